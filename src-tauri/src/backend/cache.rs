@@ -0,0 +1,145 @@
+// Session cache shared by the native backends. Sessions are indexed by their instance ID
+// and by PID, so per-app operations don't re-walk every device. The backend's change
+// notifications only flip `dirty`; the next operation on the worker thread re-enumerates.
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub struct SessionCache<S> {
+    dirty: Arc<AtomicBool>,
+    by_instance: HashMap<String, S>,
+    // Instance IDs per process, in enumeration order
+    by_pid: HashMap<u32, Vec<String>>,
+    order: Vec<(u32, String)>,
+}
+
+impl<S> Default for SessionCache<S> {
+    fn default() -> Self {
+        SessionCache {
+            dirty: Arc::new(AtomicBool::new(true)),
+            by_instance: HashMap::new(),
+            by_pid: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+}
+
+impl<S> SessionCache<S> {
+    // Handed to notification callbacks (which may run on other threads)
+    pub fn dirty_flag(&self) -> Arc<AtomicBool> {
+        self.dirty.clone()
+    }
+
+    pub fn invalidate(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    // Returns true (and clears the flag) if the cache must be rebuilt. Clearing before
+    // the re-enumeration means events arriving meanwhile trigger another rebuild.
+    pub fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::AcqRel)
+    }
+
+    // Only the WASAPI backend looks up single sessions
+    #[cfg(windows)]
+    pub fn contains(&self, instance_id: &str) -> bool {
+        self.by_instance.contains_key(instance_id)
+    }
+
+    // Replace the cache content. `seen` lists every current session as (PID, instance ID)
+    // in enumeration order; `fresh` carries the values for IDs that weren't cached yet.
+    // Cached entries missing from `seen` are dropped.
+    pub fn rebuild(&mut self, seen: &[(u32, String)], fresh: Vec<(u32, String, S)>) {
+        let keep: HashSet<&str> = seen.iter().map(|(_, id)| id.as_str()).collect();
+        self.by_instance.retain(|id, _| keep.contains(id.as_str()));
+        for (_, id, session) in fresh {
+            self.by_instance.insert(id, session);
+        }
+
+        self.by_pid.clear();
+        self.order.clear();
+        for (pid, id) in seen {
+            if self.by_instance.contains_key(id) {
+                self.by_pid.entry(*pid).or_default().push(id.clone());
+                self.order.push((*pid, id.clone()));
+            }
+        }
+    }
+
+    pub fn sessions_of(&self, pid: u32) -> Vec<&S> {
+        self.by_pid
+            .get(&pid)
+            .map(|ids| ids.iter().filter_map(|id| self.by_instance.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    // All sessions with their PID, in enumeration order
+    pub fn entries(&self) -> impl Iterator<Item = (u32, &S)> + '_ {
+        self.order
+            .iter()
+            .filter_map(|(pid, id)| self.by_instance.get(id).map(|s| (*pid, s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seen(ids: &[(u32, &str)]) -> Vec<(u32, String)> {
+        ids.iter().map(|(pid, id)| (*pid, id.to_string())).collect()
+    }
+
+    fn fresh(ids: &[(u32, &str)]) -> Vec<(u32, String, String)> {
+        ids.iter().map(|(pid, id)| (*pid, id.to_string(), format!("session {id}"))).collect()
+    }
+
+    #[test]
+    fn sessions_gone_from_the_enumeration_are_dropped() {
+        let mut cache = SessionCache::default();
+        let first = [(10, "a"), (20, "b"), (10, "c")];
+        cache.rebuild(&seen(&first), fresh(&first));
+        assert_eq!(cache.entries().count(), 3);
+
+        // "b" is gone, "d" is new; "a" and "c" are kept without being created again
+        cache.rebuild(&seen(&[(10, "a"), (10, "c"), (30, "d")]), fresh(&[(30, "d")]));
+        let entries: Vec<(u32, &str)> = cache.entries().map(|(pid, s)| (pid, s.as_str())).collect();
+        assert_eq!(entries, vec![(10, "session a"), (10, "session c"), (30, "session d")]);
+        assert!(cache.sessions_of(20).is_empty());
+        assert!(!cache.by_instance.contains_key("b"));
+
+        // An ID listed but never supplied is skipped rather than half-indexed
+        cache.rebuild(&seen(&[(10, "a"), (40, "e")]), Vec::new());
+        assert!(cache.sessions_of(40).is_empty());
+        assert_eq!(cache.entries().count(), 1);
+    }
+
+    #[test]
+    fn sessions_of_a_process_keep_enumeration_order() {
+        let mut cache = SessionCache::default();
+        let ids = [(7, "speakers"), (8, "other"), (7, "headset"), (7, "capture")];
+        cache.rebuild(&seen(&ids), fresh(&ids));
+        let of_7: Vec<&str> = cache.sessions_of(7).into_iter().map(String::as_str).collect();
+        assert_eq!(of_7, vec!["session speakers", "session headset", "session capture"]);
+
+        // A new enumeration order wins, even for cached sessions
+        let reordered = [(7, "capture"), (7, "speakers"), (8, "other"), (7, "headset")];
+        cache.rebuild(&seen(&reordered), Vec::new());
+        let of_7: Vec<&str> = cache.sessions_of(7).into_iter().map(String::as_str).collect();
+        assert_eq!(of_7, vec!["session capture", "session speakers", "session headset"]);
+    }
+
+    #[test]
+    fn dirty_flag_rearms() {
+        let cache: SessionCache<()> = SessionCache::default();
+        // A new cache has never been filled
+        assert!(cache.take_dirty());
+        assert!(!cache.take_dirty());
+        // Notifications set it from other threads through the shared flag
+        let flag = cache.dirty_flag();
+        std::thread::spawn(move || flag.store(true, Ordering::Release)).join().unwrap();
+        assert!(cache.take_dirty());
+        assert!(!cache.take_dirty());
+        cache.invalidate();
+        assert!(cache.take_dirty());
+    }
+}
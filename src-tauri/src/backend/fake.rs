@@ -0,0 +1,150 @@
+// In-memory backend without any sound card or OS mixer. Used when no native backend
+// is available and for exercising the command layer on any platform
+// (AUDIO_MIXER_BACKEND=fake).
+use std::collections::BTreeMap;
+
+use std::sync::Arc;
+
+use super::{AppSession, AudioBackend, DeviceInfo, DeviceKind, FeedOpener, SessionInfo, VirtualInput, VirtualOutput};
+
+#[derive(Debug, Clone)]
+pub struct FakeApp {
+    pub name: String,
+    pub sessions: Vec<SessionInfo>,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeBackend {
+    pub devices: Vec<DeviceInfo>,
+    pub apps: BTreeMap<u32, FakeApp>,
+    pub virtual_outputs: BTreeMap<String, VirtualOutput>,
+    pub virtual_inputs: BTreeMap<String, VirtualInput>,
+}
+
+impl FakeBackend {
+    pub fn with_demo_data() -> Self {
+        let mut backend = FakeBackend::default();
+        backend.add_device("Fake Speakers", DeviceKind::Output, true);
+        backend.add_device("Fake Headset", DeviceKind::Output, false);
+        backend.add_device("Fake Microphone", DeviceKind::Input, true);
+        backend.add_session(1001, "game.exe");
+        backend.add_session(1001, "game.exe");
+        backend.add_session(1002, "discord.exe");
+        backend.add_session(1003, "spotify.exe");
+        backend
+    }
+
+    pub fn add_device(&mut self, name: &str, kind: DeviceKind, is_default: bool) -> String {
+        let idx = self
+            .devices
+            .iter()
+            .filter(|d| d.name == name && d.kind == kind)
+            .count();
+        let id = format!("{}::{:?}#{}", name, kind, idx);
+        self.devices.push(DeviceInfo {
+            id: id.clone(),
+            name: name.into(),
+            kind,
+            is_default,
+            backend: "Fake".into(),
+            channels: 2,
+        });
+        id
+    }
+
+    // Adds a session to the process, creating the process on first use
+    pub fn add_session(&mut self, pid: u32, process_name: &str) {
+        let app = self.apps.entry(pid).or_insert_with(|| FakeApp { name: process_name.into(), sessions: Vec::new() });
+        let instance_id = format!("fake-{}-{}", pid, app.sessions.len());
+        app.sessions.push(SessionInfo {
+            instance_id,
+            device_id: String::new(),
+            display_name: String::new(),
+            icon_path: String::new(),
+            volume: 1.0,
+            muted: false,
+        });
+    }
+}
+
+impl AudioBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "Fake"
+    }
+
+    fn list_devices(&mut self) -> Result<Vec<DeviceInfo>, String> {
+        let virtual_outputs = self.virtual_outputs.values().map(|v| (v.id.clone(), v.name.clone(), DeviceKind::Output));
+        let virtual_inputs = self.virtual_inputs.values().map(|v| (v.id.clone(), v.name.clone(), DeviceKind::Input));
+        let virtual_devices = virtual_outputs.chain(virtual_inputs).map(|(id, name, kind)| DeviceInfo {
+            id,
+            name,
+            kind,
+            is_default: false,
+            backend: "Fake".into(),
+            channels: 2,
+        });
+        Ok(self.devices.iter().cloned().chain(virtual_devices).collect())
+    }
+
+    fn list_apps(&mut self) -> Result<Vec<AppSession>, String> {
+        Ok(self
+            .apps
+            .iter()
+            .map(|(pid, a)| AppSession::new(*pid, a.name.clone(), a.name.clone(), a.sessions.clone()))
+            .collect())
+    }
+
+    fn set_app_volume(&mut self, pid: u32, volume: f32) -> Result<bool, String> {
+        Ok(match self.apps.get_mut(&pid) {
+            Some(app) => {
+                for s in &mut app.sessions {
+                    s.volume = volume.clamp(0.0, 1.0);
+                }
+                true
+            }
+            None => false,
+        })
+    }
+
+    fn route_app(&mut self, pid: u32, device_id: Option<&str>) -> Result<(), String> {
+        if let Some(id) = device_id {
+            if !self.devices.iter().any(|d| d.id == id && d.kind == DeviceKind::Output) {
+                return Err(format!("Device not found: {}", id));
+            }
+        }
+        let app = self
+            .apps
+            .get_mut(&pid)
+            .ok_or_else(|| format!("No audio session found for PID {}", pid))?;
+        for s in &mut app.sessions {
+            s.device_id = device_id.unwrap_or_default().to_string();
+        }
+        Ok(())
+    }
+
+    fn set_virtual_output(&mut self, output: &VirtualOutput) -> Result<(), String> {
+        if let Some(target) = output.target.as_deref() {
+            if !self.devices.iter().any(|d| d.id == target && d.kind == DeviceKind::Output) {
+                return Err(format!("Device not found: {}", target));
+            }
+        }
+        self.virtual_outputs.insert(output.id.clone(), output.clone());
+        Ok(())
+    }
+
+    fn remove_virtual_output(&mut self, id: &str) -> Result<(), String> {
+        self.virtual_outputs.remove(id);
+        Ok(())
+    }
+
+    // The feed goes nowhere
+    fn set_virtual_input(&mut self, input: &VirtualInput) -> Result<FeedOpener, String> {
+        self.virtual_inputs.insert(input.id.clone(), input.clone());
+        Ok(Arc::new(|_, _| Ok(Box::new(std::io::sink()) as Box<dyn std::io::Write + Send>)))
+    }
+
+    fn remove_virtual_input(&mut self, id: &str) -> Result<(), String> {
+        self.virtual_inputs.remove(id);
+        Ok(())
+    }
+}
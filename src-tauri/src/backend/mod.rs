@@ -0,0 +1,223 @@
+// Platform audio backends (WASAPI on Windows, PulseAudio/PipeWire on Linux, an in-memory
+// fake everywhere). All backend calls run on a single worker thread, see `worker.rs`.
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Arc;
+
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+mod cache;
+pub mod fake;
+#[cfg(target_os = "linux")]
+pub mod pulse;
+#[cfg(target_os = "windows")]
+pub mod wasapi;
+pub mod worker;
+
+pub use worker::BackendWorker;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub kind: DeviceKind,
+    pub is_default: bool,
+    pub backend: String,
+    // Channel count of the device's mix format; 0 if unknown
+    pub channels: u16,
+}
+
+// One audio session. Browsers and games often open several per process, on
+// different devices.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub instance_id: String,
+    pub device_id: String,
+    pub display_name: String,
+    pub icon_path: String,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppSession {
+    pub pid: u32,
+    pub name: String,
+    pub process_name: String, // The actual executable name (e.g., "discord.exe")
+    // Summary over `sessions`: the loudest session's volume, muted if all are muted
+    pub volume: f32,
+    pub muted: bool,
+    pub sessions: Vec<SessionInfo>,
+}
+
+impl AppSession {
+    pub fn new(pid: u32, name: String, process_name: String, sessions: Vec<SessionInfo>) -> Self {
+        let volume = sessions.iter().map(|s| s.volume).fold(0.0, f32::max);
+        let muted = !sessions.is_empty() && sessions.iter().all(|s| s.muted);
+        AppSession { pid, name, process_name, volume, muted, sessions }
+    }
+}
+
+// Output device of our own that apps can pick, played on a real output. Its volume and
+// mute apply to everything played on it.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct VirtualOutput {
+    // Device ID; also how leftovers of an earlier run are found
+    pub id: String,
+    pub name: String,
+    // Device it plays on; `None` = default output
+    pub target: Option<String>,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+// Capture device of our own that apps can pick as a microphone
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct VirtualInput {
+    pub id: String,
+    pub name: String,
+}
+
+// Opens a pipe for interleaved little-endian f32 audio (sample rate, channels) that the
+// virtual input delivers to whoever captures from it. Usable from any thread.
+pub type FeedOpener = Arc<dyn Fn(u32, usize) -> Result<Box<dyn Write + Send>, String> + Send + Sync>;
+
+// Combine the per-session results of an operation applied to a whole process.
+// Every session is attempted; the call fails if any of them failed.
+pub fn all_sessions(pid: u32, results: Vec<Result<(), String>>) -> Result<bool, String> {
+    if results.is_empty() {
+        return Ok(false);
+    }
+    let errors: Vec<String> = results.into_iter().filter_map(Result::err).collect();
+    if errors.is_empty() {
+        Ok(true)
+    } else {
+        Err(format!("PID {}: {}", pid, errors.join("; ")))
+    }
+}
+
+// Operations the mixer needs from the OS audio stack. Implementations are created on
+// and only ever used from the worker thread, so they don't need to be Send.
+pub trait AudioBackend {
+    fn name(&self) -> &'static str;
+    fn list_devices(&mut self) -> Result<Vec<DeviceInfo>, String>;
+    fn list_apps(&mut self) -> Result<Vec<AppSession>, String>;
+    // Applies to every session of the process; Ok(false) if it has none
+    fn set_app_volume(&mut self, pid: u32, volume: f32) -> Result<bool, String>;
+    // Moves every session of the process; `None` routes the app back to the default output
+    fn route_app(&mut self, pid: u32, device_id: Option<&str>) -> Result<(), String>;
+    // Create the virtual output, or bring an existing one with the same ID up to date
+    fn set_virtual_output(&mut self, _output: &VirtualOutput) -> Result<(), String> {
+        Err(format!("The {} backend has no virtual output devices", self.name()))
+    }
+    // Ok if there is no such virtual output
+    fn remove_virtual_output(&mut self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+    // Create the virtual input unless it exists already
+    fn set_virtual_input(&mut self, _input: &VirtualInput) -> Result<FeedOpener, String> {
+        Err(format!("The {} backend has no virtual input devices", self.name()))
+    }
+    fn remove_virtual_input(&mut self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+const BACKEND_ENV_VAR: &str = "AUDIO_MIXER_BACKEND";
+
+// Pick the backend from AUDIO_MIXER_BACKEND (wasapi | pulse | fake), defaulting to the
+// native one. Falls back to the fake backend if the native one can't start.
+pub fn spawn_default_worker() -> BackendWorker {
+    let requested = std::env::var(BACKEND_ENV_VAR).unwrap_or_default().to_lowercase();
+    let worker = match requested.as_str() {
+        "fake" => BackendWorker::spawn(|| Ok(Box::new(fake::FakeBackend::with_demo_data()) as Box<dyn AudioBackend>)),
+        _ => spawn_native_worker(),
+    };
+    match worker {
+        Ok(w) => {
+            log::info!("Using {} audio backend", w.backend_name());
+            w
+        }
+        Err(e) => {
+            log::error!("Native audio backend unavailable ({}), falling back to fake backend", e);
+            BackendWorker::spawn(|| Ok(Box::new(fake::FakeBackend::default()) as Box<dyn AudioBackend>))
+                .expect("fake backend cannot fail to start")
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn spawn_native_worker() -> Result<BackendWorker, String> {
+    BackendWorker::spawn(|| Ok(Box::new(wasapi::WasapiBackend::new()?) as Box<dyn AudioBackend>))
+}
+
+#[cfg(target_os = "linux")]
+fn spawn_native_worker() -> Result<BackendWorker, String> {
+    BackendWorker::spawn(|| Ok(Box::new(pulse::PulseBackend::new()?) as Box<dyn AudioBackend>))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn spawn_native_worker() -> Result<BackendWorker, String> {
+    Err("no native audio backend for this platform".into())
+}
+
+// Enumerate devices via cpal. To provide unique IDs for React keys and routing selections,
+// we create a stable-in-session identifier of the shape: "<name>::<kind>#<n>".
+#[cfg(target_os = "windows")]
+pub fn list_cpal_devices(backend: &str) -> Vec<DeviceInfo> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+    use std::collections::HashMap;
+
+    let host = cpal::default_host();
+
+    let default_output = host.default_output_device().map(|d| d.name().unwrap_or_default());
+    let default_output_name = default_output.unwrap_or_default();
+
+    let mut out = Vec::new();
+
+    use std::collections::hash_map::Entry;
+    let mut seen: HashMap<(DeviceKind, String), usize> = HashMap::new();
+    if let Ok(devices) = host.devices() {
+        for dev in devices {
+            let name = dev.name().unwrap_or_else(|_| "Unbekannt".into());
+            // Determine kind by probing supported configs
+            let is_output = dev.supported_output_configs().is_ok();
+            let kind = if is_output { DeviceKind::Output } else { DeviceKind::Input };
+            let is_default = is_output && name == default_output_name;
+            let key = (kind.clone(), name.clone());
+            let idx = match seen.entry(key) {
+                Entry::Occupied(mut e) => {
+                    *e.get_mut() += 1;
+                    *e.get()
+                }
+                Entry::Vacant(v) => {
+                    v.insert(0);
+                    0
+                }
+            };
+            let id = format!("{}::{:?}#{}", name, kind, idx);
+
+            let config = if is_output { dev.default_output_config() } else { dev.default_input_config() };
+            let channels = config.map(|c| c.channels()).unwrap_or(0);
+
+            log::trace!("Device {}: ID='{}', Name='{}', Default={}, Channels={}", idx, id, name, is_default, channels);
+
+            out.push(DeviceInfo {
+                id,
+                name,
+                kind,
+                is_default,
+                backend: backend.into(),
+                channels,
+            });
+        }
+    }
+
+    out
+}
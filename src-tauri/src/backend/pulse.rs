@@ -0,0 +1,505 @@
+// PulseAudio / PipeWire-Pulse backend. Talks to the sound server through `pactl`
+// (JSON output, pactl >= 16), which works the same on plain PulseAudio and PipeWire.
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::cache::SessionCache;
+use super::{
+    all_sessions, AppSession, AudioBackend, DeviceInfo, DeviceKind, FeedOpener, SessionInfo, VirtualInput, VirtualOutput,
+};
+
+#[derive(Debug, Deserialize)]
+struct PaChannelVolume {
+    value: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct PaSinkInput {
+    index: u32,
+    #[serde(default)]
+    sink: u32,
+    #[serde(default)]
+    mute: bool,
+    #[serde(default)]
+    volume: HashMap<String, PaChannelVolume>,
+    #[serde(default)]
+    properties: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PaDevice {
+    index: u32,
+    name: String,
+    #[serde(default)]
+    description: String,
+    // e.g. "front-left,front-right"
+    #[serde(default)]
+    channel_map: String,
+}
+
+impl PaDevice {
+    fn channels(&self) -> u16 {
+        self.channel_map.split(',').filter(|c| !c.trim().is_empty()).count() as u16
+    }
+}
+
+// PA_VOLUME_NORM
+const PA_VOLUME_NORM: f32 = 65536.0;
+// Buffering of the loopbacks and feeds of virtual devices
+const LOOPBACK_LATENCY_MS: u32 = 20;
+
+// A virtual output is a null sink whose monitor a loopback plays on the target device
+struct VirtualSink {
+    // As last applied
+    output: VirtualOutput,
+    sink_module: u32,
+    loopback_module: u32,
+}
+
+// A virtual input is a remap of the monitor of a null sink ("<id>_feed") that the feed
+// plays into
+struct VirtualSource {
+    sink_module: u32,
+    remap_module: u32,
+}
+
+pub struct PulseBackend {
+    // Sink inputs keyed by index (the session instance ID) and PID
+    sessions: SessionCache<PaSinkInput>,
+    // `pactl subscribe`, which marks the cache dirty when sink inputs come and go
+    subscription: Option<Child>,
+    // Keyed by sink name (the device ID)
+    virtual_sinks: HashMap<String, VirtualSink>,
+    // Keyed by source name
+    virtual_sources: HashMap<String, VirtualSource>,
+}
+
+impl Drop for PulseBackend {
+    fn drop(&mut self) {
+        if let Some(child) = self.subscription.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let ids: Vec<String> = self.virtual_sinks.keys().cloned().collect();
+        for id in ids {
+            let _ = self.remove_virtual_output(&id);
+        }
+        let ids: Vec<String> = self.virtual_sources.keys().cloned().collect();
+        for id in ids {
+            let _ = self.remove_virtual_input(&id);
+        }
+    }
+}
+
+impl PulseBackend {
+    pub fn new() -> Result<Self, String> {
+        // Fail early (and let the caller fall back) if there is no reachable server
+        pactl(&["info"])?;
+        let sessions = SessionCache::default();
+        let subscription = match subscribe(sessions.dirty_flag()) {
+            Ok(child) => Some(child),
+            Err(e) => {
+                log::warn!("pactl subscribe unavailable ({}), sessions are re-listed on every call", e);
+                None
+            }
+        };
+        Ok(PulseBackend { sessions, subscription, virtual_sinks: HashMap::new(), virtual_sources: HashMap::new() })
+    }
+
+    // Always queries the server and rebuilds the cache from the result
+    fn reload_sessions(&mut self) -> Result<(), String> {
+        let out = pactl(&["-f", "json", "list", "sink-inputs"])?;
+        let inputs: Vec<PaSinkInput> =
+            serde_json::from_str(&out).map_err(|e| format!("Parse sink-inputs failed: {e}"))?;
+        let mut seen = Vec::with_capacity(inputs.len());
+        let mut fresh = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let Some(pid) = sink_input_pid(&input) else { continue };
+            let id = input.index.to_string();
+            seen.push((pid, id.clone()));
+            // Cached values are replaced too, so volumes/mutes stay current
+            fresh.push((pid, id, input));
+        }
+        self.sessions.rebuild(&seen, fresh);
+        Ok(())
+    }
+
+    fn sink_inputs_of(&mut self, pid: u32) -> Result<Vec<u32>, String> {
+        if self.sessions.take_dirty() || self.subscription.is_none() {
+            if let Err(e) = self.reload_sessions() {
+                self.sessions.invalidate();
+                return Err(e);
+            }
+        }
+        Ok(self.sessions.sessions_of(pid).iter().map(|i| i.index).collect())
+    }
+
+    fn devices(&self, what: &str) -> Result<Vec<PaDevice>, String> {
+        let out = pactl(&["-f", "json", "list", what])?;
+        serde_json::from_str(&out).map_err(|e| format!("Parse {what} failed: {e}"))
+    }
+}
+
+impl AudioBackend for PulseBackend {
+    fn name(&self) -> &'static str {
+        "PulseAudio"
+    }
+
+    // Device IDs are the sink/source names, which `move-sink-input` accepts directly
+    fn list_devices(&mut self) -> Result<Vec<DeviceInfo>, String> {
+        let default_sink = pactl(&["get-default-sink"]).map(|s| s.trim().to_string()).unwrap_or_default();
+        let default_source = pactl(&["get-default-source"]).map(|s| s.trim().to_string()).unwrap_or_default();
+
+        let mut out = Vec::new();
+        for sink in self.devices("sinks")? {
+            out.push(DeviceInfo {
+                is_default: sink.name == default_sink,
+                name: display_name(&sink),
+                channels: sink.channels(),
+                id: sink.name,
+                kind: DeviceKind::Output,
+                backend: "PulseAudio".into(),
+            });
+        }
+        for source in self.devices("sources")? {
+            // Monitors of sinks show up as sources; they aren't real inputs
+            if source.name.ends_with(".monitor") {
+                continue;
+            }
+            out.push(DeviceInfo {
+                is_default: source.name == default_source,
+                name: display_name(&source),
+                channels: source.channels(),
+                id: source.name,
+                kind: DeviceKind::Input,
+                backend: "PulseAudio".into(),
+            });
+        }
+        Ok(out)
+    }
+
+    fn list_apps(&mut self) -> Result<Vec<AppSession>, String> {
+        self.sessions.take_dirty();
+        self.reload_sessions()?;
+
+        // Sink index -> sink name, which is what we use as device ID
+        let sink_names: HashMap<u32, String> = self
+            .devices("sinks")?
+            .into_iter()
+            .map(|d| (d.index, d.name))
+            .collect();
+
+        let mut out: Vec<AppSession> = Vec::new();
+        for (pid, input) in self.sessions.entries() {
+            if pid == 0 {
+                continue;
+            }
+            let info = SessionInfo {
+                instance_id: input.index.to_string(),
+                device_id: sink_names.get(&input.sink).cloned().unwrap_or_default(),
+                display_name: property(input, "media.name").unwrap_or_default(),
+                icon_path: property(input, "application.icon_name").unwrap_or_default(),
+                volume: average_volume(input),
+                muted: input.mute,
+            };
+            match out.iter_mut().find(|a| a.pid == pid) {
+                Some(app) => {
+                    let mut sessions = std::mem::take(&mut app.sessions);
+                    sessions.push(info);
+                    *app = AppSession::new(pid, app.name.clone(), app.process_name.clone(), sessions);
+                }
+                None => {
+                    let process_name = property(input, "application.process.binary")
+                        .unwrap_or_else(|| format!("unknown_process_{pid}"));
+                    let name = property(input, "application.name").unwrap_or_else(|| process_name.clone());
+                    out.push(AppSession::new(pid, name, process_name, vec![info]));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn set_app_volume(&mut self, pid: u32, volume: f32) -> Result<bool, String> {
+        // A value with a decimal point is taken as a linear factor, like ISimpleAudioVolume
+        let vol = format!("{:.4}", volume.clamp(0.0, 1.0));
+        let results: Vec<Result<(), String>> = self
+            .sink_inputs_of(pid)?
+            .into_iter()
+            .map(|index| pactl(&["set-sink-input-volume", &index.to_string(), &vol]).map(|_| ()))
+            .collect();
+        let result = all_sessions(pid, results);
+        if result.is_err() {
+            self.sessions.invalidate();
+        }
+        result
+    }
+
+    fn route_app(&mut self, pid: u32, device_id: Option<&str>) -> Result<(), String> {
+        let inputs = self.sink_inputs_of(pid)?;
+        if inputs.is_empty() {
+            return Err(format!("No audio session found for PID {}", pid));
+        }
+        let sink = match device_id {
+            Some(id) => id.to_string(),
+            None => pactl(&["get-default-sink"])?.trim().to_string(),
+        };
+        let results = inputs
+            .into_iter()
+            .map(|index| pactl(&["move-sink-input", &index.to_string(), &sink]).map(|_| ()))
+            .collect();
+        if let Err(e) = all_sessions(pid, results) {
+            self.sessions.invalidate();
+            return Err(e);
+        }
+        log::info!("Routed PID {} to sink {}", pid, sink);
+        Ok(())
+    }
+
+    fn set_virtual_output(&mut self, output: &VirtualOutput) -> Result<(), String> {
+        if output.target.as_deref() == Some(output.id.as_str()) {
+            return Err(format!("{} can't play on itself", output.name));
+        }
+        if !self.virtual_sinks.contains_key(&output.id) {
+            // Left behind by a run that didn't exit cleanly
+            unload_modules_of(&output.id)?;
+            let description = output.name.replace(['"', '\''], "");
+            let sink_module = load_module(&[
+                "module-null-sink",
+                &format!("sink_name={}", output.id),
+                &format!("sink_properties='device.description=\"{description}\"'"),
+            ])?;
+            let loopback_module = match load_loopback(&output.id, output.target.as_deref()) {
+                Ok(module) => module,
+                Err(e) => {
+                    let _ = pactl(&["unload-module", &sink_module.to_string()]);
+                    return Err(e);
+                }
+            };
+            log::info!("Created virtual output {} playing on {:?}", output.id, output.target);
+            // A new null sink is at full volume and unmuted
+            let fresh = VirtualOutput { volume: 1.0, muted: false, ..output.clone() };
+            self.virtual_sinks.insert(output.id.clone(), VirtualSink { output: fresh, sink_module, loopback_module });
+        }
+        let Some(sink) = self.virtual_sinks.get_mut(&output.id) else { return Ok(()) };
+        if sink.output.target != output.target {
+            // New loopback first, so the virtual output never goes silent for long
+            let loopback_module = load_loopback(&output.id, output.target.as_deref())?;
+            let _ = pactl(&["unload-module", &sink.loopback_module.to_string()]);
+            sink.loopback_module = loopback_module;
+            sink.output.target = output.target.clone();
+            log::info!("Virtual output {} now plays on {:?}", output.id, output.target);
+        }
+        if sink.output.volume != output.volume {
+            pactl(&["set-sink-volume", &output.id, &format!("{:.4}", output.volume.clamp(0.0, 1.0))])?;
+            sink.output.volume = output.volume;
+        }
+        if sink.output.muted != output.muted {
+            pactl(&["set-sink-mute", &output.id, if output.muted { "1" } else { "0" }])?;
+            sink.output.muted = output.muted;
+        }
+        Ok(())
+    }
+
+    fn remove_virtual_output(&mut self, id: &str) -> Result<(), String> {
+        let Some(sink) = self.virtual_sinks.remove(id) else { return Ok(()) };
+        // Loopback first; apps on the sink move to the default output when it goes
+        let loopback = pactl(&["unload-module", &sink.loopback_module.to_string()]).map(|_| ());
+        let null_sink = pactl(&["unload-module", &sink.sink_module.to_string()]).map(|_| ());
+        log::info!("Removed virtual output {}", id);
+        loopback.and(null_sink)
+    }
+
+    fn set_virtual_input(&mut self, input: &VirtualInput) -> Result<FeedOpener, String> {
+        let feed = format!("{}_feed", input.id);
+        if !self.virtual_sources.contains_key(&input.id) {
+            unload_modules_of(&feed)?;
+            unload_modules_of(&input.id)?;
+            let description = input.name.replace(['"', '\''], "");
+            let sink_module = load_module(&[
+                "module-null-sink",
+                &format!("sink_name={feed}"),
+                &format!("sink_properties='device.description=\"{description} (feed)\"'"),
+            ])?;
+            let remap = load_module(&[
+                "module-remap-source",
+                &format!("master={feed}.monitor"),
+                &format!("source_name={}", input.id),
+                &format!("source_properties='device.description=\"{description}\"'"),
+            ]);
+            let remap_module = match remap {
+                Ok(module) => module,
+                Err(e) => {
+                    let _ = pactl(&["unload-module", &sink_module.to_string()]);
+                    return Err(e);
+                }
+            };
+            log::info!("Created virtual input {}", input.id);
+            self.virtual_sources.insert(input.id.clone(), VirtualSource { sink_module, remap_module });
+        }
+        Ok(Arc::new(move |rate, channels| play_raw(&feed, rate, channels)))
+    }
+
+    fn remove_virtual_input(&mut self, id: &str) -> Result<(), String> {
+        let Some(source) = self.virtual_sources.remove(id) else { return Ok(()) };
+        let remap = pactl(&["unload-module", &source.remap_module.to_string()]).map(|_| ());
+        let null_sink = pactl(&["unload-module", &source.sink_module.to_string()]).map(|_| ());
+        log::info!("Removed virtual input {}", id);
+        remap.and(null_sink)
+    }
+}
+
+pub(crate) fn pactl(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("pactl")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Run pactl failed: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "pactl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Returns the module index
+fn load_module(args: &[&str]) -> Result<u32, String> {
+    let mut full = vec!["load-module"];
+    full.extend_from_slice(args);
+    let out = pactl(&full)?;
+    out.trim().parse().map_err(|_| format!("pactl load-module {} returned {:?}", args[0], out.trim()))
+}
+
+// Plays the monitor of the null sink `id` on `target` (the default sink if `None`)
+fn load_loopback(id: &str, target: Option<&str>) -> Result<u32, String> {
+    let source = format!("source={id}.monitor");
+    let sink = target.map(|t| format!("sink={t}"));
+    let latency = format!("latency_msec={LOOPBACK_LATENCY_MS}");
+    let mut args = vec!["module-loopback", source.as_str(), latency.as_str()];
+    args.extend(sink.as_deref());
+    load_module(&args)
+}
+
+// Unload the null sink or remapped source `id` and loopbacks from its monitor
+fn unload_modules_of(id: &str) -> Result<(), String> {
+    let sink_arg = format!("sink_name={id}");
+    let source_arg = format!("source={id}.monitor");
+    let source_name_arg = format!("source_name={id}");
+    // "<index>\t<name>\t<argument>\t..."
+    for line in pactl(&["list", "short", "modules"])?.lines() {
+        let mut fields = line.split('\t');
+        let (Some(index), Some(name), Some(argument)) = (fields.next(), fields.next(), fields.next()) else { continue };
+        let ours = match name {
+            "module-null-sink" => argument.split_whitespace().any(|a| a == sink_arg),
+            "module-loopback" => argument.split_whitespace().any(|a| a == source_arg),
+            "module-remap-source" => argument.split_whitespace().any(|a| a == source_name_arg),
+            _ => false,
+        };
+        if ours {
+            log::info!("Unloading leftover {} #{} of {}", name, index, id);
+            pactl(&["unload-module", index])?;
+        }
+    }
+    Ok(())
+}
+
+// `pacat` playing raw audio from its stdin; killed when dropped
+struct RawPlayback {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl Write for RawPlayback {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Drop for RawPlayback {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn play_raw(sink: &str, rate: u32, channels: usize) -> Result<Box<dyn Write + Send>, String> {
+    let mut child = Command::new("pacat")
+        .args([
+            "--playback",
+            "--raw",
+            "--format=float32le",
+            &format!("--rate={rate}"),
+            &format!("--channels={channels}"),
+            &format!("--device={sink}"),
+            &format!("--latency-msec={LOOPBACK_LATENCY_MS}"),
+            "--client-name=Audio Mixer",
+            "--stream-name=Virtual microphone",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Run pacat failed: {e}"))?;
+    let stdin = child.stdin.take().ok_or("pacat has no stdin")?;
+    Ok(Box::new(RawPlayback { child, stdin }))
+}
+
+// Spawn `pactl subscribe` and flag `dirty` whenever a sink input or sink appears or
+// disappears. Volume changes ('change' events) don't invalidate the index.
+fn subscribe(dirty: Arc<AtomicBool>) -> Result<Child, String> {
+    let mut child = Command::new("pactl")
+        .arg("subscribe")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Run pactl subscribe failed: {e}"))?;
+    let stdout = child.stdout.take().ok_or("pactl subscribe has no stdout")?;
+    std::thread::Builder::new()
+        .name("pulse-subscribe".into())
+        .spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                // e.g. "Event 'new' on sink-input #42"
+                let structural = line.contains("'new'") || line.contains("'remove'");
+                if structural && (line.contains(" sink-input ") || line.contains(" sink ")) {
+                    dirty.store(true, Ordering::Release);
+                }
+            }
+            // Subscription ended (server restart, ...): at least re-list on the next call
+            dirty.store(true, Ordering::Release);
+        })
+        .map_err(|e| format!("Spawn pulse-subscribe thread failed: {e}"))?;
+    Ok(child)
+}
+
+fn property(input: &PaSinkInput, key: &str) -> Option<String> {
+    match input.properties.get(key)? {
+        serde_json::Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+fn sink_input_pid(input: &PaSinkInput) -> Option<u32> {
+    property(input, "application.process.id")?.parse().ok()
+}
+
+fn average_volume(input: &PaSinkInput) -> f32 {
+    if input.volume.is_empty() {
+        return 1.0;
+    }
+    // Raw volumes are on Pulse's cubic scale; report the linear factor
+    let sum: f32 = input.volume.values().map(|v| (v.value as f32 / PA_VOLUME_NORM).powi(3)).sum();
+    sum / input.volume.len() as f32
+}
+
+fn display_name(dev: &PaDevice) -> String {
+    if dev.description.is_empty() { dev.name.clone() } else { dev.description.clone() }
+}
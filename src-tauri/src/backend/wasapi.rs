@@ -0,0 +1,526 @@
+// WASAPI backend: per-app session enumeration, volume control and routing.
+// Lives on the backend worker thread, which owns the COM apartment for its whole lifetime.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Windows COM / WASAPI imports for per-app session enumeration and volume control
+use std::ffi::c_void;
+
+use windows::core::{implement, IInspectable, IUnknown, Interface, GUID, HRESULT, HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
+use windows::Win32::Media::Audio::{eConsole, eMultimedia, eRender, AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateExpired, EDataFlow, ERole, IAudioSessionControl, IAudioSessionControl2, IAudioSessionEnumerator, IAudioSessionEvents, IAudioSessionEvents_Impl, IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl, IMMDevice, IMMDeviceEnumerator, IMMNotificationClient, IMMNotificationClient_Impl, MMDeviceEnumerator, ISimpleAudioVolume, IMMDeviceCollection, DEVICE_STATE, DEVICE_STATE_ACTIVE};
+use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED, STGM_READ};
+use windows::Win32::System::WinRT::RoGetActivationFactory;
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+use windows::Win32::System::ProcessStatus::K32GetProcessImageFileNameW;
+use windows::Win32::Foundation::{HANDLE, BOOL, CloseHandle};
+use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
+
+use super::cache::SessionCache;
+use super::{all_sessions, list_cpal_devices, AppSession, AudioBackend, DeviceInfo, SessionInfo};
+
+// Balances the CoInitializeEx of the worker thread. Declared as the last field of
+// WasapiBackend so every COM interface is released before the apartment goes away.
+struct ComApartment {
+    initialized: bool,
+}
+
+impl Drop for ComApartment {
+    fn drop(&mut self) {
+        if self.initialized {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+// COM callback object for session and endpoint notifications. Callbacks arrive on
+// arbitrary MTA threads, so all it does is mark the session cache dirty.
+#[implement(IAudioSessionNotification, IAudioSessionEvents, IMMNotificationClient)]
+struct CacheInvalidator {
+    dirty: Arc<AtomicBool>,
+}
+
+impl CacheInvalidator {
+    fn mark(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+}
+
+impl IAudioSessionNotification_Impl for CacheInvalidator {
+    fn OnSessionCreated(&self, _newsession: Option<&IAudioSessionControl>) -> windows::core::Result<()> {
+        self.mark();
+        Ok(())
+    }
+}
+
+impl IAudioSessionEvents_Impl for CacheInvalidator {
+    fn OnDisplayNameChanged(&self, _newdisplayname: &PCWSTR, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+    fn OnIconPathChanged(&self, _newiconpath: &PCWSTR, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+    fn OnSimpleVolumeChanged(&self, _newvolume: f32, _newmute: BOOL, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+    fn OnChannelVolumeChanged(&self, _channelcount: u32, _newchannelvolumearray: *const f32, _changedchannel: u32, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+    fn OnGroupingParamChanged(&self, _newgroupingparam: *const GUID, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+    fn OnStateChanged(&self, newstate: AudioSessionState) -> windows::core::Result<()> {
+        if newstate == AudioSessionStateExpired {
+            self.mark();
+        }
+        Ok(())
+    }
+    fn OnSessionDisconnected(&self, _disconnectreason: AudioSessionDisconnectReason) -> windows::core::Result<()> {
+        self.mark();
+        Ok(())
+    }
+}
+
+impl IMMNotificationClient_Impl for CacheInvalidator {
+    fn OnDeviceStateChanged(&self, _pwstrdeviceid: &PCWSTR, _dwnewstate: DEVICE_STATE) -> windows::core::Result<()> {
+        self.mark();
+        Ok(())
+    }
+    fn OnDeviceAdded(&self, _pwstrdeviceid: &PCWSTR) -> windows::core::Result<()> {
+        self.mark();
+        Ok(())
+    }
+    fn OnDeviceRemoved(&self, _pwstrdeviceid: &PCWSTR) -> windows::core::Result<()> {
+        self.mark();
+        Ok(())
+    }
+    fn OnDefaultDeviceChanged(&self, _flow: EDataFlow, _role: ERole, _pwstrdefaultdeviceid: &PCWSTR) -> windows::core::Result<()> {
+        Ok(())
+    }
+    fn OnPropertyValueChanged(&self, _pwstrdeviceid: &PCWSTR, _key: &PROPERTYKEY) -> windows::core::Result<()> {
+        Ok(())
+    }
+}
+
+// Session manager of one render endpoint plus the notification we registered on it
+struct DeviceSessions {
+    manager: IAudioSessionManager2,
+    notifier: IAudioSessionNotification,
+}
+
+impl Drop for DeviceSessions {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.manager.UnregisterSessionNotification(&self.notifier);
+        }
+    }
+}
+
+// A cached session with the interfaces we need for O(1) operations on it
+struct CachedSession {
+    instance_id: String,
+    device_id: String,
+    control: IAudioSessionControl2,
+    volume: ISimpleAudioVolume,
+    events: IAudioSessionEvents,
+}
+
+impl CachedSession {
+    // Live values; display name and icon can change at any time
+    fn info(&self) -> Result<SessionInfo, String> {
+        unsafe {
+            let volume = self
+                .volume
+                .GetMasterVolume()
+                .map_err(|e| format!("GetMasterVolume failed: {e}"))?;
+            let muted = self
+                .volume
+                .GetMute()
+                .map_err(|e| format!("GetMute failed: {e}"))?
+                .as_bool();
+            let display_name = self.control.GetDisplayName().ok().and_then(|p| take_pwstr(p).ok()).unwrap_or_default();
+            let icon_path = self.control.GetIconPath().ok().and_then(|p| take_pwstr(p).ok()).unwrap_or_default();
+            Ok(SessionInfo {
+                instance_id: self.instance_id.clone(),
+                device_id: self.device_id.clone(),
+                display_name,
+                icon_path,
+                volume,
+                muted,
+            })
+        }
+    }
+}
+
+impl Drop for CachedSession {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.control.UnregisterAudioSessionNotification(&self.events);
+        }
+    }
+}
+
+pub struct WasapiBackend {
+    enumerator: IMMDeviceEnumerator,
+    endpoint_notifier: IMMNotificationClient,
+    // Endpoint ID -> session manager, so we only Activate() once per device
+    session_managers: HashMap<String, DeviceSessions>,
+    sessions: SessionCache<CachedSession>,
+    _com: ComApartment,
+}
+
+impl Drop for WasapiBackend {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.enumerator.UnregisterEndpointNotificationCallback(&self.endpoint_notifier);
+        }
+    }
+}
+
+impl WasapiBackend {
+    pub fn new() -> Result<Self, String> {
+        unsafe {
+            let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let com = ComApartment { initialized: hr.is_ok() };
+            let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
+                .map_err(|e| format!("Create MMDeviceEnumerator failed: {e}"))?;
+
+            let sessions = SessionCache::default();
+            let endpoint_notifier: IMMNotificationClient = CacheInvalidator { dirty: sessions.dirty_flag() }.into();
+            enumerator
+                .RegisterEndpointNotificationCallback(&endpoint_notifier)
+                .map_err(|e| format!("RegisterEndpointNotificationCallback failed: {e}"))?;
+
+            Ok(WasapiBackend {
+                enumerator,
+                endpoint_notifier,
+                session_managers: HashMap::new(),
+                sessions,
+                _com: com,
+            })
+        }
+    }
+
+    fn render_devices(&self) -> Result<IMMDeviceCollection, String> {
+        unsafe {
+            self.enumerator
+                .EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)
+                .map_err(|e| format!("EnumAudioEndpoints failed: {e}"))
+        }
+    }
+
+    // Session managers of all active render devices, activated on first use and dropped
+    // again once the endpoint disappears
+    fn session_managers(&mut self) -> Result<Vec<(String, IAudioSessionManager2)>, String> {
+        unsafe {
+            let devices = self.render_devices()?;
+            let dev_count = devices
+                .GetCount()
+                .map_err(|e| format!("GetCount(devices) failed: {e}"))?;
+
+            let mut out = Vec::with_capacity(dev_count as usize);
+            for di in 0..dev_count {
+                let device: IMMDevice = devices
+                    .Item(di)
+                    .map_err(|e| format!("Get device {di} failed: {e}"))?;
+                let endpoint_id = get_device_endpoint_id(&device)?;
+                if !self.session_managers.contains_key(&endpoint_id) {
+                    let manager: IAudioSessionManager2 = device
+                        .Activate::<IAudioSessionManager2>(CLSCTX_ALL, None)
+                        .map_err(|e| format!("Activate IAudioSessionManager2 failed: {e}"))?;
+                    let notifier: IAudioSessionNotification = CacheInvalidator { dirty: self.sessions.dirty_flag() }.into();
+                    manager
+                        .RegisterSessionNotification(&notifier)
+                        .map_err(|e| format!("RegisterSessionNotification failed: {e}"))?;
+                    self.session_managers.insert(endpoint_id.clone(), DeviceSessions { manager, notifier });
+                }
+                out.push((endpoint_id.clone(), self.session_managers[&endpoint_id].manager.clone()));
+            }
+            self.session_managers.retain(|id, _| out.iter().any(|(active, _)| active == id));
+            Ok(out)
+        }
+    }
+
+    // Re-enumerate sessions if a notification (or a failed call) marked the cache dirty
+    fn refresh_sessions(&mut self) -> Result<(), String> {
+        if !self.sessions.take_dirty() {
+            return Ok(());
+        }
+        let result = self.enumerate_sessions();
+        if result.is_err() {
+            self.sessions.invalidate();
+        }
+        result
+    }
+
+    fn enumerate_sessions(&mut self) -> Result<(), String> {
+        let mut seen = Vec::new();
+        let mut fresh = Vec::new();
+        for (device_id, mgr) in self.session_managers()? {
+            unsafe {
+                let sessions: IAudioSessionEnumerator = mgr
+                    .GetSessionEnumerator()
+                    .map_err(|e| format!("GetSessionEnumerator failed: {e}"))?;
+                let count = sessions
+                    .GetCount()
+                    .map_err(|e| format!("GetCount(sessions) failed: {e}"))?;
+
+                for i in 0..count {
+                    let ctrl: IAudioSessionControl = sessions
+                        .GetSession(i)
+                        .map_err(|e| format!("GetSession({i}) failed: {e}"))?;
+                    if ctrl.GetState().map(|st| st == AudioSessionStateExpired).unwrap_or(true) {
+                        continue;
+                    }
+                    let control: IAudioSessionControl2 = ctrl
+                        .cast()
+                        .map_err(|e| format!("Query IAudioSessionControl2 failed: {e}"))?;
+                    let pid = control
+                        .GetProcessId()
+                        .map_err(|e| format!("GetProcessId failed: {e}"))?;
+                    let instance_id = take_pwstr(
+                        control
+                            .GetSessionInstanceIdentifier()
+                            .map_err(|e| format!("GetSessionInstanceIdentifier failed: {e}"))?,
+                    )?;
+
+                    let known = self.sessions.contains(&instance_id)
+                        || fresh.iter().any(|(_, id, _): &(u32, String, CachedSession)| *id == instance_id);
+                    if !known {
+                        let volume: ISimpleAudioVolume = ctrl
+                            .cast()
+                            .map_err(|e| format!("Query ISimpleAudioVolume failed: {e}"))?;
+                        let events: IAudioSessionEvents = CacheInvalidator { dirty: self.sessions.dirty_flag() }.into();
+                        control
+                            .RegisterAudioSessionNotification(&events)
+                            .map_err(|e| format!("RegisterAudioSessionNotification failed: {e}"))?;
+                        fresh.push((pid, instance_id.clone(), CachedSession { instance_id: instance_id.clone(), device_id: device_id.clone(), control, volume, events }));
+                    }
+                    seen.push((pid, instance_id));
+                }
+            }
+        }
+        log::debug!("Session cache rebuilt: {} sessions ({} new)", seen.len(), fresh.len());
+        self.sessions.rebuild(&seen, fresh);
+        Ok(())
+    }
+
+    // Cached sessions of a process (refreshing the cache first if needed)
+    fn sessions_of(&mut self, pid: u32) -> Result<Vec<&CachedSession>, String> {
+        self.refresh_sessions()?;
+        Ok(self.sessions.sessions_of(pid))
+    }
+
+    // Render endpoint for a "<name>::Output#<n>" ID from `list_cpal_devices`: the n-th
+    // active endpoint with that friendly name, which is the name cpal reports
+    fn find_device_by_id(&self, device_id: &str) -> Result<IMMDevice, String> {
+        let (name, index) = match device_id.split_once("::") {
+            Some((name, rest)) => {
+                let (kind, idx) = rest.split_once('#').unwrap_or((rest, "0"));
+                if kind != "Output" {
+                    return Err(format!("{} is not an output device", device_id));
+                }
+                (name, idx.parse::<usize>().unwrap_or(0))
+            }
+            None => (device_id, 0),
+        };
+        unsafe {
+            let devices = self.render_devices()?;
+            let dev_count = devices
+                .GetCount()
+                .map_err(|e| format!("GetCount(devices) failed: {e}"))?;
+            let mut seen = 0;
+            for di in 0..dev_count {
+                let device: IMMDevice = devices
+                    .Item(di)
+                    .map_err(|e| format!("Get device {di} failed: {e}"))?;
+                if friendly_name(&device)? == name {
+                    if seen == index {
+                        return Ok(device);
+                    }
+                    seen += 1;
+                }
+            }
+        }
+        Err(format!("Device not found: {}", device_id))
+    }
+}
+
+impl AudioBackend for WasapiBackend {
+    fn name(&self) -> &'static str {
+        "WASAPI"
+    }
+
+    fn list_devices(&mut self) -> Result<Vec<DeviceInfo>, String> {
+        Ok(list_cpal_devices("WASAPI"))
+    }
+
+    fn list_apps(&mut self) -> Result<Vec<AppSession>, String> {
+        self.refresh_sessions()?;
+
+        // Group sessions by process, keeping enumeration order
+        let mut order: Vec<u32> = Vec::new();
+        let mut by_pid: HashMap<u32, Vec<SessionInfo>> = HashMap::new();
+        for (pid, s) in self.sessions.entries() {
+            if pid == 0 { continue; }
+            let info = s.info()?;
+            by_pid.entry(pid).or_insert_with(|| { order.push(pid); Vec::new() }).push(info);
+        }
+
+        let mut out = Vec::with_capacity(order.len());
+        for pid in order {
+            let sessions = by_pid.remove(&pid).unwrap_or_default();
+            let name = process_name_from_pid(pid).unwrap_or_else(|| format!("PID {pid}"));
+            let process_name = process_name_from_pid(pid).unwrap_or_else(|| format!("unknown_process_{pid}.exe"));
+            out.push(AppSession::new(pid, name, process_name, sessions));
+        }
+        Ok(out)
+    }
+
+    fn set_app_volume(&mut self, pid: u32, volume: f32) -> Result<bool, String> {
+        let results: Vec<Result<(), String>> = self
+            .sessions_of(pid)?
+            .into_iter()
+            .map(|s| unsafe {
+                s.volume
+                    .SetMasterVolume(volume.clamp(0.0, 1.0), std::ptr::null())
+                    .map_err(|e| format!("SetMasterVolume({}) failed: {e}", s.instance_id))
+            })
+            .collect();
+        let result = all_sessions(pid, results);
+        if result.is_err() {
+            // Most likely a session went away without us hearing about it
+            self.sessions.invalidate();
+        }
+        result
+    }
+
+    // Route a specific app (PID) to a specific audio device. The override is stored per
+    // process by Windows; `None` clears it so the app follows the default device again.
+    fn route_app(&mut self, pid: u32, device_id: Option<&str>) -> Result<(), String> {
+        let endpoint_id = match device_id {
+            Some(id) => Some(get_device_endpoint_id(&self.find_device_by_id(id)?)?),
+            None => None,
+        };
+        if self.sessions_of(pid)?.is_empty() {
+            return Err(format!("No audio session found for PID {}", pid));
+        }
+        let policy = AudioPolicyConfig::new()?;
+        // Console and multimedia are the roles apps open their streams with
+        for role in [eConsole, eMultimedia] {
+            policy.set_default_endpoint(pid, role, endpoint_id.as_deref())?;
+        }
+        match device_id {
+            Some(id) => log::info!("Routed PID {} to device {}", pid, id),
+            None => log::info!("Routed PID {} back to the default device", pid),
+        }
+        Ok(())
+    }
+}
+
+// Get device endpoint ID for policy routing
+fn get_device_endpoint_id(device: &IMMDevice) -> Result<String, String> {
+    unsafe {
+        // Get the device ID string
+        let id_ptr = device.GetId()
+            .map_err(|e| format!("GetId failed: {e}"))?;
+        take_pwstr(id_ptr)
+    }
+}
+
+// Convert a COM-allocated string and free it
+fn take_pwstr(ptr: PWSTR) -> Result<String, String> {
+    unsafe {
+        let s = ptr.to_string()
+            .map_err(|e| format!("Convert string failed: {e}"));
+        CoTaskMemFree(Some(ptr.0 as *mut _));
+        s
+    }
+}
+
+// Friendly name of an endpoint ("Speakers (Realtek Audio)")
+fn friendly_name(device: &IMMDevice) -> Result<String, String> {
+    unsafe {
+        let store = device
+            .OpenPropertyStore(STGM_READ)
+            .map_err(|e| format!("OpenPropertyStore failed: {e}"))?;
+        let value = store
+            .GetValue(&PKEY_Device_FriendlyName)
+            .map_err(|e| format!("Get friendly name failed: {e}"))?;
+        Ok(value.to_string())
+    }
+}
+
+// Undocumented IAudioPolicyConfig, the interface behind the per-app output picker in the
+// Windows settings ("App volume and device preferences"). Only the three methods after
+// the 19 we don't use are declared.
+#[repr(C)]
+struct AudioPolicyConfigVtbl {
+    base: [usize; 6],
+    _unused: [usize; 19],
+    set_persisted_default_audio_endpoint:
+        unsafe extern "system" fn(this: *mut c_void, pid: u32, flow: EDataFlow, role: ERole, device_id: *mut c_void) -> HRESULT,
+    _get_persisted_default_audio_endpoint: usize,
+    _clear_all_persisted_application_default_endpoints: usize,
+}
+
+// Windows 10 21H2 and later
+const AUDIO_POLICY_CONFIG_IID: GUID = GUID::from_u128(0xab3d4648_e242_459f_b02f_541c70306324);
+// Windows 10 before 21H2
+const AUDIO_POLICY_CONFIG_IID_DOWNLEVEL: GUID = GUID::from_u128(0x2a59116d_6c4f_45e0_a74f_707e3fef9258);
+
+// Endpoint IDs are passed as device interface paths
+const MMDEVAPI_TOKEN: &str = r"\\?\SWD#MMDEVAPI#";
+const DEVINTERFACE_AUDIO_RENDER: &str = "#{e6327cad-dcec-4949-ae8a-991e976a79d2}";
+
+struct AudioPolicyConfig {
+    // Owns the reference to the interface behind `raw`
+    _factory: IUnknown,
+    raw: *mut c_void,
+}
+
+impl AudioPolicyConfig {
+    fn new() -> Result<Self, String> {
+        unsafe {
+            let class = HSTRING::from("Windows.Media.Internal.AudioPolicyConfig");
+            let factory: IInspectable =
+                RoGetActivationFactory(&class).map_err(|e| format!("AudioPolicyConfig unavailable: {e}"))?;
+            for iid in [AUDIO_POLICY_CONFIG_IID, AUDIO_POLICY_CONFIG_IID_DOWNLEVEL] {
+                let mut raw = std::ptr::null_mut();
+                if factory.query(&iid, &mut raw).is_ok() && !raw.is_null() {
+                    return Ok(AudioPolicyConfig { _factory: IUnknown::from_raw(raw), raw });
+                }
+            }
+            Err("AudioPolicyConfig is not supported on this Windows version".to_string())
+        }
+    }
+
+    fn set_default_endpoint(&self, pid: u32, role: ERole, endpoint_id: Option<&str>) -> Result<(), String> {
+        // An empty (null) HSTRING removes the override
+        let device = match endpoint_id {
+            Some(id) => HSTRING::from(format!("{MMDEVAPI_TOKEN}{id}{DEVINTERFACE_AUDIO_RENDER}")),
+            None => HSTRING::new(),
+        };
+        unsafe {
+            let vtbl = &**(self.raw as *const *const AudioPolicyConfigVtbl);
+            (vtbl.set_persisted_default_audio_endpoint)(self.raw, pid, eRender, role, std::mem::transmute_copy(&device))
+                .ok()
+                .map_err(|e| format!("SetPersistedDefaultAudioEndpoint(PID {pid}) failed: {e}"))
+        }
+    }
+}
+
+fn process_name_from_pid(pid: u32) -> Option<String> {
+    unsafe {
+        let handle: HANDLE = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, BOOL(0), pid).ok()?;
+        if handle.is_invalid() { return None; }
+        let mut buf = [0u16; 32768];
+        let len = K32GetProcessImageFileNameW(handle, &mut buf);
+        let _ = CloseHandle(handle);
+        if len == 0 { return None; }
+        let path = String::from_utf16_lossy(&buf[..len as usize]);
+        let name = path.rsplit(['\\', '/']).next().unwrap_or(path.as_str()).to_string();
+        Some(name)
+    }
+}
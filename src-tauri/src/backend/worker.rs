@@ -0,0 +1,172 @@
+// Dedicated thread that owns the audio backend. On Windows this is the one thread that
+// initializes COM and keeps the device enumerator and session managers alive; commands
+// send closures over a channel and block on the reply.
+use std::sync::mpsc;
+use std::thread;
+
+use super::AudioBackend;
+
+type Job = Box<dyn FnOnce(&mut dyn AudioBackend) + Send>;
+
+pub struct BackendWorker {
+    jobs: mpsc::Sender<Job>,
+    backend_name: &'static str,
+}
+
+impl BackendWorker {
+    // Start the worker thread. `factory` runs on that thread, so the backend may hold
+    // thread-affine handles (COM interfaces, sockets, ...).
+    pub fn spawn<F>(factory: F) -> Result<Self, String>
+    where
+        F: FnOnce() -> Result<Box<dyn AudioBackend>, String> + Send + 'static,
+    {
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let (ready_tx, ready_rx) = mpsc::sync_channel::<Result<&'static str, String>>(1);
+
+        thread::Builder::new()
+            .name("audio-backend".into())
+            .spawn(move || {
+                let mut backend = match factory() {
+                    Ok(b) => {
+                        let _ = ready_tx.send(Ok(b.name()));
+                        b
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                // Runs until every BackendWorker handle is dropped
+                while let Ok(job) = rx.recv() {
+                    job(backend.as_mut());
+                }
+                log::debug!("Audio backend worker stopped");
+            })
+            .map_err(|e| format!("Spawn audio backend thread failed: {e}"))?;
+
+        let backend_name = ready_rx
+            .recv()
+            .map_err(|_| "Audio backend thread exited during startup".to_string())??;
+        Ok(BackendWorker { jobs, backend_name })
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.backend_name
+    }
+
+    // Run `f` on the worker thread and wait for its result
+    pub fn call<R, F>(&self, f: F) -> Result<R, String>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn AudioBackend) -> R + Send + 'static,
+    {
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::new(move |backend| {
+                let _ = reply_tx.send(f(backend));
+            }))
+            .map_err(|_| "Audio backend worker is not running".to_string())?;
+        reply_rx
+            .recv()
+            .map_err(|_| "Audio backend worker dropped the request".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::FakeBackend;
+
+    fn demo_worker() -> BackendWorker {
+        BackendWorker::spawn(|| Ok(Box::new(FakeBackend::with_demo_data()) as Box<dyn AudioBackend>)).unwrap()
+    }
+
+    #[test]
+    fn calls_run_on_the_worker_thread() {
+        let worker = demo_worker();
+        assert_eq!(worker.backend_name(), "Fake");
+        let (name, thread) = worker.call(|b| (b.name(), thread::current().name().map(String::from))).unwrap();
+        assert_eq!(name, "Fake");
+        assert_eq!(thread.as_deref(), Some("audio-backend"));
+        // Jobs run in order and see each other's changes
+        worker.call(|b| b.set_app_volume(1002, 0.25)).unwrap().unwrap();
+        let apps = worker.call(|b| b.list_apps()).unwrap().unwrap();
+        assert_eq!(apps.iter().find(|a| a.pid == 1002).unwrap().volume, 0.25);
+    }
+
+    #[test]
+    fn factory_error_fails_spawn() {
+        let err = BackendWorker::spawn(|| Err("no sound server".to_string())).err().unwrap();
+        assert_eq!(err, "no sound server");
+    }
+
+    #[test]
+    fn list_apps_groups_sessions_by_pid() {
+        let worker = demo_worker();
+        let apps = worker.call(|b| b.list_apps()).unwrap().unwrap();
+        let pids: Vec<u32> = apps.iter().map(|a| a.pid).collect();
+        assert_eq!(pids, [1001, 1002, 1003]);
+        let game = &apps[0];
+        assert_eq!(game.name, "game.exe");
+        assert_eq!(game.sessions.len(), 2);
+        assert_ne!(game.sessions[0].instance_id, game.sessions[1].instance_id);
+        assert_eq!(apps[1].sessions.len(), 1);
+    }
+
+    #[test]
+    fn route_app_rejects_unknown_devices_and_pids() {
+        let worker = demo_worker();
+        let err = worker.call(|b| b.route_app(1001, Some("Nope::Output#0"))).unwrap().unwrap_err();
+        assert!(err.contains("Device not found"), "{err}");
+        // Inputs aren't valid targets either
+        let err = worker.call(|b| b.route_app(1001, Some("Fake Microphone::Input#0"))).unwrap().unwrap_err();
+        assert!(err.contains("Device not found"), "{err}");
+        let err = worker.call(|b| b.route_app(4242, Some("Fake Headset::Output#0"))).unwrap().unwrap_err();
+        assert!(err.contains("4242"), "{err}");
+        let err = worker.call(|b| b.route_app(4242, None)).unwrap().unwrap_err();
+        assert!(err.contains("4242"), "{err}");
+
+        worker.call(|b| b.route_app(1001, Some("Fake Headset::Output#0"))).unwrap().unwrap();
+        let apps = worker.call(|b| b.list_apps()).unwrap().unwrap();
+        assert!(apps[0].sessions.iter().all(|s| s.device_id == "Fake Headset::Output#0"));
+        worker.call(|b| b.route_app(1001, None)).unwrap().unwrap();
+        let apps = worker.call(|b| b.list_apps()).unwrap().unwrap();
+        assert!(apps[0].sessions.iter().all(|s| s.device_id.is_empty()));
+    }
+
+    #[test]
+    fn set_app_volume_clamps() {
+        let worker = demo_worker();
+        let volume_of = |pid: u32| {
+            let apps = worker.call(|b| b.list_apps()).unwrap().unwrap();
+            apps.iter().find(|a| a.pid == pid).unwrap().volume
+        };
+        assert!(worker.call(|b| b.set_app_volume(1001, 1.5)).unwrap().unwrap());
+        assert_eq!(volume_of(1001), 1.0);
+        assert!(worker.call(|b| b.set_app_volume(1001, -0.5)).unwrap().unwrap());
+        assert_eq!(volume_of(1001), 0.0);
+        assert!(worker.call(|b| b.set_app_volume(1003, 0.4)).unwrap().unwrap());
+        assert_eq!(volume_of(1003), 0.4);
+        // No such process: nothing to apply it to
+        assert!(!worker.call(|b| b.set_app_volume(4242, 0.5)).unwrap().unwrap());
+    }
+
+    #[test]
+    fn per_app_calls_reach_every_session_of_the_process() {
+        let worker = demo_worker();
+        // game.exe plays through two sessions, e.g. one per output it has opened
+        worker.call(|b| b.set_app_volume(1001, 0.3)).unwrap().unwrap();
+        worker.call(|b| b.route_app(1001, Some("Fake Headset::Output#0"))).unwrap().unwrap();
+        let apps = worker.call(|b| b.list_apps()).unwrap().unwrap();
+        let game = &apps[0];
+        assert_eq!(game.sessions.len(), 2);
+        for s in &game.sessions {
+            assert_eq!(s.volume, 0.3, "{}", s.instance_id);
+            assert_eq!(s.device_id, "Fake Headset::Output#0", "{}", s.instance_id);
+        }
+        // Other processes are left alone
+        for app in &apps[1..] {
+            assert!(app.sessions.iter().all(|s| s.volume == 1.0 && s.device_id.is_empty()), "{}", app.name);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// Interleaved f32 audio blocks. Capacity is fixed at construction so nothing on the
+// audio thread has to allocate.
+#[derive(Debug, Clone)]
+pub struct AudioBuffer {
+    channels: usize,
+    capacity: usize,
+    frames: usize,
+    samples: Vec<f32>,
+}
+
+impl AudioBuffer {
+    pub fn new(channels: usize, capacity_frames: usize) -> Self {
+        let channels = channels.max(1);
+        AudioBuffer {
+            channels,
+            capacity: capacity_frames,
+            frames: capacity_frames,
+            samples: vec![0.0; channels * capacity_frames],
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    // Start a new block of `frames` (clamped to capacity), zeroed
+    pub fn begin(&mut self, frames: usize) {
+        self.frames = frames.min(self.capacity);
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        let n = self.frames * self.channels;
+        self.samples[..n].fill(0.0);
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples[..self.frames * self.channels]
+    }
+
+    pub fn samples_mut(&mut self) -> &mut [f32] {
+        let n = self.frames * self.channels;
+        &mut self.samples[..n]
+    }
+}
+
+// Add `src` (src_channels interleaved) into `dst` (dst_channels interleaved) with `gain`,
+// using a plain fold: equal layouts copy 1:1, mono feeds every channel, anything into
+// mono is averaged, otherwise extra channels wrap onto the available ones.
+pub fn mix_into(src: &[f32], src_channels: usize, dst: &mut [f32], dst_channels: usize, gain: f32) {
+    let frames = (src.len() / src_channels).min(dst.len() / dst_channels);
+    if src_channels == dst_channels {
+        for (d, s) in dst[..frames * dst_channels].iter_mut().zip(src) {
+            *d += s * gain;
+        }
+        return;
+    }
+    for f in 0..frames {
+        let s = &src[f * src_channels..(f + 1) * src_channels];
+        let d = &mut dst[f * dst_channels..(f + 1) * dst_channels];
+        if src_channels == 1 {
+            for out in d.iter_mut() {
+                *out += s[0] * gain;
+            }
+        } else if dst_channels == 1 {
+            d[0] += s.iter().sum::<f32>() / src_channels as f32 * gain;
+        } else {
+            for (c, v) in s.iter().enumerate() {
+                d[c % dst_channels] += v * gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,826 @@
+// Host for CLAP effect plugins (https://github.com/free-audio/clap). Only the parts of the
+// ABI we use are declared here: the plugin factory, the plugin itself and the audio-ports,
+// params and state extensions.
+//
+// Threading follows the CLAP rules: `ClapInstance` is created, activated, queried and
+// destroyed on a control thread (the Tauri command threads stand in for the main thread),
+// `ClapProcessor` runs `process` on the audio thread. The two share the instance via Arc;
+// the control side keeps the last reference so destruction never happens in the callback.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use libloading::Library;
+use rtrb::Consumer;
+
+use super::MAX_BLOCK_FRAMES;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ClapVersion {
+    major: u32,
+    minor: u32,
+    revision: u32,
+}
+
+const CLAP_VERSION: ClapVersion = ClapVersion { major: 1, minor: 2, revision: 0 };
+
+#[repr(C)]
+struct ClapPluginEntry {
+    clap_version: ClapVersion,
+    init: unsafe extern "C" fn(plugin_path: *const c_char) -> bool,
+    deinit: unsafe extern "C" fn(),
+    get_factory: unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void,
+}
+
+#[repr(C)]
+struct ClapPluginFactory {
+    get_plugin_count: unsafe extern "C" fn(factory: *const ClapPluginFactory) -> u32,
+    get_plugin_descriptor:
+        unsafe extern "C" fn(factory: *const ClapPluginFactory, index: u32) -> *const ClapPluginDescriptor,
+    create_plugin: unsafe extern "C" fn(
+        factory: *const ClapPluginFactory,
+        host: *const ClapHost,
+        plugin_id: *const c_char,
+    ) -> *const ClapPlugin,
+}
+
+#[repr(C)]
+struct ClapPluginDescriptor {
+    clap_version: ClapVersion,
+    id: *const c_char,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    manual_url: *const c_char,
+    support_url: *const c_char,
+    version: *const c_char,
+    description: *const c_char,
+    // NULL terminated
+    features: *const *const c_char,
+}
+
+#[repr(C)]
+struct ClapHost {
+    clap_version: ClapVersion,
+    host_data: *mut c_void,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    version: *const c_char,
+    get_extension: unsafe extern "C" fn(host: *const ClapHost, extension_id: *const c_char) -> *const c_void,
+    request_restart: unsafe extern "C" fn(host: *const ClapHost),
+    request_process: unsafe extern "C" fn(host: *const ClapHost),
+    request_callback: unsafe extern "C" fn(host: *const ClapHost),
+}
+
+#[repr(C)]
+struct ClapPlugin {
+    desc: *const ClapPluginDescriptor,
+    plugin_data: *mut c_void,
+    init: unsafe extern "C" fn(plugin: *const ClapPlugin) -> bool,
+    destroy: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    activate: unsafe extern "C" fn(plugin: *const ClapPlugin, sample_rate: f64, min_frames: u32, max_frames: u32) -> bool,
+    deactivate: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    start_processing: unsafe extern "C" fn(plugin: *const ClapPlugin) -> bool,
+    stop_processing: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    reset: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    process: unsafe extern "C" fn(plugin: *const ClapPlugin, process: *const ClapProcess) -> i32,
+    get_extension: unsafe extern "C" fn(plugin: *const ClapPlugin, id: *const c_char) -> *const c_void,
+    on_main_thread: unsafe extern "C" fn(plugin: *const ClapPlugin),
+}
+
+const CLAP_PROCESS_ERROR: i32 = 0;
+
+#[repr(C)]
+struct ClapAudioBuffer {
+    data32: *mut *mut f32,
+    data64: *mut *mut f64,
+    channel_count: u32,
+    latency: u32,
+    constant_mask: u64,
+}
+
+#[repr(C)]
+struct ClapProcess {
+    steady_time: i64,
+    frames_count: u32,
+    transport: *const c_void,
+    audio_inputs: *const ClapAudioBuffer,
+    audio_outputs: *mut ClapAudioBuffer,
+    audio_inputs_count: u32,
+    audio_outputs_count: u32,
+    in_events: *const ClapInputEvents,
+    out_events: *const ClapOutputEvents,
+}
+
+#[repr(C)]
+struct ClapEventHeader {
+    size: u32,
+    time: u32,
+    space_id: u16,
+    kind: u16,
+    flags: u32,
+}
+
+const CLAP_CORE_EVENT_SPACE_ID: u16 = 0;
+const CLAP_EVENT_PARAM_VALUE: u16 = 5;
+
+#[repr(C)]
+struct ClapEventParamValue {
+    header: ClapEventHeader,
+    param_id: u32,
+    cookie: *mut c_void,
+    note_id: i32,
+    port_index: i16,
+    channel: i16,
+    key: i16,
+    value: f64,
+}
+
+#[repr(C)]
+struct ClapInputEvents {
+    ctx: *mut c_void,
+    size: unsafe extern "C" fn(list: *const ClapInputEvents) -> u32,
+    get: unsafe extern "C" fn(list: *const ClapInputEvents, index: u32) -> *const ClapEventHeader,
+}
+
+#[repr(C)]
+struct ClapOutputEvents {
+    ctx: *mut c_void,
+    try_push: unsafe extern "C" fn(list: *const ClapOutputEvents, event: *const ClapEventHeader) -> bool,
+}
+
+const CLAP_NAME_SIZE: usize = 256;
+const CLAP_PATH_SIZE: usize = 1024;
+
+#[repr(C)]
+struct ClapAudioPortInfo {
+    id: u32,
+    name: [c_char; CLAP_NAME_SIZE],
+    flags: u32,
+    channel_count: u32,
+    port_type: *const c_char,
+    in_place_pair: u32,
+}
+
+#[repr(C)]
+struct ClapPluginAudioPorts {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin, is_input: bool) -> u32,
+    get: unsafe extern "C" fn(plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapAudioPortInfo) -> bool,
+}
+
+#[repr(C)]
+struct ClapParamInfo {
+    id: u32,
+    flags: u32,
+    cookie: *mut c_void,
+    name: [c_char; CLAP_NAME_SIZE],
+    module: [c_char; CLAP_PATH_SIZE],
+    min_value: f64,
+    max_value: f64,
+    default_value: f64,
+}
+
+const CLAP_PARAM_IS_STEPPED: u32 = 1 << 0;
+const CLAP_PARAM_IS_HIDDEN: u32 = 1 << 2;
+const CLAP_PARAM_IS_READONLY: u32 = 1 << 3;
+
+#[repr(C)]
+struct ClapPluginParams {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin) -> u32,
+    get_info: unsafe extern "C" fn(plugin: *const ClapPlugin, index: u32, info: *mut ClapParamInfo) -> bool,
+    get_value: unsafe extern "C" fn(plugin: *const ClapPlugin, id: u32, value: *mut f64) -> bool,
+    value_to_text: unsafe extern "C" fn(plugin: *const ClapPlugin, id: u32, value: f64, out: *mut c_char, capacity: u32) -> bool,
+    text_to_value: unsafe extern "C" fn(plugin: *const ClapPlugin, id: u32, text: *const c_char, value: *mut f64) -> bool,
+    flush: unsafe extern "C" fn(plugin: *const ClapPlugin, in_events: *const ClapInputEvents, out_events: *const ClapOutputEvents),
+}
+
+#[repr(C)]
+struct ClapOstream {
+    ctx: *mut c_void,
+    write: unsafe extern "C" fn(stream: *const ClapOstream, buffer: *const c_void, size: u64) -> i64,
+}
+
+#[repr(C)]
+struct ClapIstream {
+    ctx: *mut c_void,
+    read: unsafe extern "C" fn(stream: *const ClapIstream, buffer: *mut c_void, size: u64) -> i64,
+}
+
+#[repr(C)]
+struct ClapPluginState {
+    save: unsafe extern "C" fn(plugin: *const ClapPlugin, stream: *const ClapOstream) -> bool,
+    load: unsafe extern "C" fn(plugin: *const ClapPlugin, stream: *const ClapIstream) -> bool,
+}
+
+const PLUGIN_FACTORY_ID: &CStr = c"clap.plugin-factory";
+const EXT_AUDIO_PORTS: &CStr = c"clap.audio-ports";
+const EXT_PARAMS: &CStr = c"clap.params";
+const EXT_STATE: &CStr = c"clap.state";
+
+// Turn a possibly-NULL C string into an owned string
+fn c_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+}
+
+fn c_array_string(chars: &[c_char]) -> String {
+    let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    pub path: String,
+    pub id: String,
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub features: Vec<String>,
+}
+
+// A loaded .clap file. Libraries stay loaded (and initialized) for the whole session:
+// unloading plugin code is a classic source of crashes.
+struct Bundle {
+    path: PathBuf,
+    _library: Library,
+    factory: *const ClapPluginFactory,
+}
+
+// The factory may be used from any thread
+unsafe impl Send for Bundle {}
+unsafe impl Sync for Bundle {}
+
+fn bundles() -> &'static Mutex<HashMap<PathBuf, Arc<Bundle>>> {
+    static BUNDLES: OnceLock<Mutex<HashMap<PathBuf, Arc<Bundle>>>> = OnceLock::new();
+    BUNDLES.get_or_init(Default::default)
+}
+
+impl Bundle {
+    fn open(path: &Path) -> Result<Arc<Bundle>, String> {
+        let mut cache = bundles().lock().unwrap();
+        if let Some(b) = cache.get(path) {
+            return Ok(b.clone());
+        }
+        let fail = |e: String| format!("{}: {}", path.display(), e);
+        // The loader's message already names the file
+        let library = unsafe { Library::new(path) }.map_err(|e| e.to_string())?;
+        let entry = unsafe { library.get::<*const ClapPluginEntry>(b"clap_entry\0") }
+            .map(|s| *s)
+            .map_err(|_| fail("not a CLAP plugin (no clap_entry)".into()))?;
+        let entry = unsafe { &*entry };
+        if entry.clap_version.major < 1 {
+            return Err(fail(format!("unsupported CLAP version {}", entry.clap_version.major)));
+        }
+        let c_path = CString::new(path.to_string_lossy().as_bytes()).map_err(|e| fail(e.to_string()))?;
+        if !unsafe { (entry.init)(c_path.as_ptr()) } {
+            return Err(fail("plugin init failed".into()));
+        }
+        let factory = unsafe { (entry.get_factory)(PLUGIN_FACTORY_ID.as_ptr()) } as *const ClapPluginFactory;
+        if factory.is_null() {
+            unsafe { (entry.deinit)() };
+            return Err(fail("no plugin factory".into()));
+        }
+        let bundle = Arc::new(Bundle { path: path.to_path_buf(), _library: library, factory });
+        cache.insert(path.to_path_buf(), bundle.clone());
+        Ok(bundle)
+    }
+
+    fn descriptors(&self) -> Vec<&ClapPluginDescriptor> {
+        let factory = unsafe { &*self.factory };
+        let count = unsafe { (factory.get_plugin_count)(self.factory) };
+        (0..count)
+            .filter_map(|i| unsafe { (factory.get_plugin_descriptor)(self.factory, i).as_ref() })
+            .collect()
+    }
+
+    fn info(&self) -> Vec<PluginInfo> {
+        self.descriptors()
+            .into_iter()
+            .map(|d| {
+                let mut features = Vec::new();
+                let mut f = d.features;
+                while !f.is_null() && !unsafe { *f }.is_null() {
+                    features.push(c_string(unsafe { *f }));
+                    f = unsafe { f.add(1) };
+                }
+                PluginInfo {
+                    path: self.path.to_string_lossy().into_owned(),
+                    id: c_string(d.id),
+                    name: c_string(d.name),
+                    vendor: c_string(d.vendor),
+                    version: c_string(d.version),
+                    features,
+                }
+            })
+            .collect()
+    }
+}
+
+// Standard CLAP search paths plus CLAP_PATH
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(extra) = std::env::var_os("CLAP_PATH") {
+        paths.extend(std::env::split_paths(&extra));
+    }
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(common) = std::env::var_os("COMMONPROGRAMFILES") {
+            paths.push(PathBuf::from(common).join("CLAP"));
+        }
+        if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+            paths.push(PathBuf::from(local).join("Programs").join("Common").join("CLAP"));
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        if let Some(home) = std::env::var_os("HOME") {
+            paths.push(PathBuf::from(home).join(".clap"));
+        }
+        paths.push(PathBuf::from("/usr/lib/clap"));
+        paths.push(PathBuf::from("/usr/local/lib/clap"));
+    }
+    paths
+}
+
+fn find_clap_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+        if path.is_dir() {
+            find_clap_files(&path, out);
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("clap")) {
+            out.push(path);
+        }
+    }
+}
+
+// Every plugin in the search paths; files that fail to load are skipped with a warning
+pub fn scan() -> Vec<PluginInfo> {
+    let mut files = Vec::new();
+    for dir in search_paths() {
+        find_clap_files(&dir, &mut files);
+    }
+    files.sort();
+    files.dedup();
+    let mut plugins = Vec::new();
+    for file in files {
+        match Bundle::open(&file) {
+            Ok(bundle) => plugins.extend(bundle.info()),
+            Err(e) => log::warn!("Skipping plugin {}", e),
+        }
+    }
+    plugins
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamInfo {
+    pub id: u32,
+    pub name: String,
+    // Group path within the plugin, e.g. "Filter/Env"
+    pub module: String,
+    pub min: f64,
+    pub max: f64,
+    pub default: f64,
+    pub value: f64,
+    pub stepped: bool,
+    pub read_only: bool,
+}
+
+// Set from any plugin thread, handled by the control side
+#[derive(Default)]
+struct HostFlags {
+    callback: AtomicBool,
+    restart: AtomicBool,
+}
+
+unsafe extern "C" fn host_get_extension(_host: *const ClapHost, _id: *const c_char) -> *const c_void {
+    std::ptr::null()
+}
+
+unsafe extern "C" fn host_request_restart(host: *const ClapHost) {
+    let flags = &*((*host).host_data as *const HostFlags);
+    flags.restart.store(true, Ordering::Relaxed);
+}
+
+// The engine processes continuously, nothing to wake up
+unsafe extern "C" fn host_request_process(_host: *const ClapHost) {}
+
+unsafe extern "C" fn host_request_callback(host: *const ClapHost) {
+    let flags = &*((*host).host_data as *const HostFlags);
+    flags.callback.store(true, Ordering::Relaxed);
+}
+
+const HOST_NAME: &CStr = c"Audio Mixer";
+const HOST_VENDOR: &CStr = c"audio-mixer";
+const HOST_URL: &CStr = c"";
+const HOST_VERSION: &CStr = c"0.2";
+
+// Parameter change for the audio thread
+#[derive(Debug, Clone, Copy)]
+pub struct ParamChange {
+    pub id: u32,
+    pub value: f64,
+}
+
+// Event list handed to the plugin; `ctx` points to a slice of param value events
+unsafe extern "C" fn events_size(list: *const ClapInputEvents) -> u32 {
+    (*((*list).ctx as *const Vec<ClapEventParamValue>)).len() as u32
+}
+
+unsafe extern "C" fn events_get(list: *const ClapInputEvents, index: u32) -> *const ClapEventHeader {
+    let events = &*((*list).ctx as *const Vec<ClapEventParamValue>);
+    events.get(index as usize).map_or(std::ptr::null(), |e| &e.header as *const ClapEventHeader)
+}
+
+// Output events (parameter gestures etc.) are not used
+unsafe extern "C" fn events_try_push(_list: *const ClapOutputEvents, _event: *const ClapEventHeader) -> bool {
+    true
+}
+
+fn param_event(change: ParamChange) -> ClapEventParamValue {
+    ClapEventParamValue {
+        header: ClapEventHeader {
+            size: std::mem::size_of::<ClapEventParamValue>() as u32,
+            time: 0,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            kind: CLAP_EVENT_PARAM_VALUE,
+            flags: 0,
+        },
+        param_id: change.id,
+        cookie: std::ptr::null_mut(),
+        note_id: -1,
+        port_index: -1,
+        channel: -1,
+        key: -1,
+        value: change.value,
+    }
+}
+
+unsafe extern "C" fn ostream_write(stream: *const ClapOstream, buffer: *const c_void, size: u64) -> i64 {
+    let out = &mut *((*stream).ctx as *mut Vec<u8>);
+    out.extend_from_slice(std::slice::from_raw_parts(buffer as *const u8, size as usize));
+    size as i64
+}
+
+unsafe extern "C" fn istream_read(stream: *const ClapIstream, buffer: *mut c_void, size: u64) -> i64 {
+    let input = &mut *((*stream).ctx as *mut &[u8]);
+    let n = input.len().min(size as usize);
+    std::ptr::copy_nonoverlapping(input.as_ptr(), buffer as *mut u8, n);
+    *input = &input[n..];
+    n as i64
+}
+
+pub struct ClapInstance {
+    name: String,
+    plugin: *const ClapPlugin,
+    // The plugin keeps pointers to both for its whole life
+    _host: Box<ClapHost>,
+    flags: Box<HostFlags>,
+    _bundle: Arc<Bundle>,
+    audio_ports: *const ClapPluginAudioPorts,
+    params: *const ClapPluginParams,
+    state: *const ClapPluginState,
+    // Sample rate while active
+    active: Mutex<Option<u32>>,
+    bypassed: AtomicBool,
+}
+
+// See the thread rules at the top of the file
+unsafe impl Send for ClapInstance {}
+unsafe impl Sync for ClapInstance {}
+
+impl ClapInstance {
+    pub fn new(path: &Path, id: &str) -> Result<Self, String> {
+        let bundle = Bundle::open(path)?;
+        let c_id = CString::new(id).map_err(|e| e.to_string())?;
+        let flags = Box::<HostFlags>::default();
+        let host = Box::new(ClapHost {
+            clap_version: CLAP_VERSION,
+            host_data: &*flags as *const HostFlags as *mut c_void,
+            name: HOST_NAME.as_ptr(),
+            vendor: HOST_VENDOR.as_ptr(),
+            url: HOST_URL.as_ptr(),
+            version: HOST_VERSION.as_ptr(),
+            get_extension: host_get_extension,
+            request_restart: host_request_restart,
+            request_process: host_request_process,
+            request_callback: host_request_callback,
+        });
+        let factory = unsafe { &*bundle.factory };
+        let plugin = unsafe { (factory.create_plugin)(bundle.factory, &*host, c_id.as_ptr()) };
+        if plugin.is_null() {
+            return Err(format!("{}: plugin '{}' could not be created", path.display(), id));
+        }
+        let p = unsafe { &*plugin };
+        if !unsafe { (p.init)(plugin) } {
+            unsafe { (p.destroy)(plugin) };
+            return Err(format!("{}: plugin '{}' failed to initialize", path.display(), id));
+        }
+        let name = unsafe { p.desc.as_ref() }.map(|d| c_string(d.name)).unwrap_or_else(|| id.to_string());
+        let extension = |ext: &CStr| unsafe { (p.get_extension)(plugin, ext.as_ptr()) };
+        let instance = ClapInstance {
+            name,
+            plugin,
+            _host: host,
+            flags,
+            _bundle: bundle,
+            audio_ports: extension(EXT_AUDIO_PORTS) as *const ClapPluginAudioPorts,
+            params: extension(EXT_PARAMS) as *const ClapPluginParams,
+            state: extension(EXT_STATE) as *const ClapPluginState,
+            active: Mutex::new(None),
+            bypassed: AtomicBool::new(false),
+        };
+        let (inputs, outputs) = (instance.port_channels(true), instance.port_channels(false));
+        if inputs.first().is_none_or(|&c| c == 0) || outputs.first().is_none_or(|&c| c == 0) {
+            return Err(format!("'{}' is not an effect (no audio input/output)", instance.name));
+        }
+        Ok(instance)
+    }
+
+    fn plugin(&self) -> &ClapPlugin {
+        unsafe { &*self.plugin }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Channel count per audio port; plugins without the extension get one stereo port
+    fn port_channels(&self, input: bool) -> Vec<u32> {
+        let Some(ports) = (unsafe { self.audio_ports.as_ref() }) else { return vec![2] };
+        let count = unsafe { (ports.count)(self.plugin, input) };
+        (0..count)
+            .map(|i| {
+                let mut info: ClapAudioPortInfo = unsafe { std::mem::zeroed() };
+                if unsafe { (ports.get)(self.plugin, i, input, &mut info) } {
+                    info.channel_count
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    // Runs pending main-thread work the plugin asked for
+    pub fn idle(&self) {
+        if self.flags.callback.swap(false, Ordering::Relaxed) {
+            unsafe { (self.plugin().on_main_thread)(self.plugin) };
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.lock().unwrap().is_some()
+    }
+
+    // (Re)activate at `sample_rate`; no-op if already active at that rate unless the
+    // plugin asked for a restart
+    pub fn activate(&self, sample_rate: u32) -> Result<(), String> {
+        let mut active = self.active.lock().unwrap();
+        let restart = self.flags.restart.swap(false, Ordering::Relaxed);
+        if *active == Some(sample_rate) && !restart {
+            return Ok(());
+        }
+        if active.take().is_some() {
+            unsafe { (self.plugin().deactivate)(self.plugin) };
+        }
+        if !unsafe { (self.plugin().activate)(self.plugin, sample_rate as f64, 1, MAX_BLOCK_FRAMES as u32) } {
+            return Err(format!("'{}' failed to activate at {} Hz", self.name, sample_rate));
+        }
+        *active = Some(sample_rate);
+        Ok(())
+    }
+
+    // Only once no processor uses the instance any more
+    pub fn deactivate(&self) {
+        if self.active.lock().unwrap().take().is_some() {
+            unsafe { (self.plugin().deactivate)(self.plugin) };
+        }
+    }
+
+    pub fn set_bypassed(&self, bypassed: bool) {
+        self.bypassed.store(bypassed, Ordering::Relaxed);
+    }
+
+    pub fn params(&self) -> Vec<ParamInfo> {
+        let Some(params) = (unsafe { self.params.as_ref() }) else { return Vec::new() };
+        let count = unsafe { (params.count)(self.plugin) };
+        let mut out = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let mut info: ClapParamInfo = unsafe { std::mem::zeroed() };
+            if !unsafe { (params.get_info)(self.plugin, i, &mut info) } || info.flags & CLAP_PARAM_IS_HIDDEN != 0 {
+                continue;
+            }
+            let mut value = info.default_value;
+            unsafe { (params.get_value)(self.plugin, info.id, &mut value) };
+            out.push(ParamInfo {
+                id: info.id,
+                name: c_array_string(&info.name),
+                module: c_array_string(&info.module),
+                min: info.min_value,
+                max: info.max_value,
+                default: info.default_value,
+                value,
+                stepped: info.flags & CLAP_PARAM_IS_STEPPED != 0,
+                read_only: info.flags & CLAP_PARAM_IS_READONLY != 0,
+            });
+        }
+        out
+    }
+
+    // Apply parameter changes directly; only while inactive; otherwise they go through
+    // the processor
+    pub fn flush_params(&self, changes: &[ParamChange]) {
+        let Some(params) = (unsafe { self.params.as_ref() }) else { return };
+        if self.is_active() {
+            return;
+        }
+        let mut events: Vec<ClapEventParamValue> = changes.iter().map(|&c| param_event(c)).collect();
+        let input = ClapInputEvents { ctx: &mut events as *mut _ as *mut c_void, size: events_size, get: events_get };
+        let output = ClapOutputEvents { ctx: std::ptr::null_mut(), try_push: events_try_push };
+        unsafe { (params.flush)(self.plugin, &input, &output) };
+    }
+
+    // Plugin state as an opaque blob; `None` if the plugin has no state extension
+    pub fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        let Some(state) = (unsafe { self.state.as_ref() }) else { return Ok(None) };
+        let mut data: Vec<u8> = Vec::new();
+        let stream = ClapOstream { ctx: &mut data as *mut Vec<u8> as *mut c_void, write: ostream_write };
+        if !unsafe { (state.save)(self.plugin, &stream) } {
+            return Err(format!("'{}' failed to save its state", self.name));
+        }
+        Ok(Some(data))
+    }
+
+    pub fn load_state(&self, data: &[u8]) -> Result<(), String> {
+        let Some(state) = (unsafe { self.state.as_ref() }) else { return Ok(()) };
+        let mut cursor: &[u8] = data;
+        let stream = ClapIstream { ctx: &mut cursor as *mut &[u8] as *mut c_void, read: istream_read };
+        if !unsafe { (state.load)(self.plugin, &stream) } {
+            return Err(format!("'{}' rejected its saved state", self.name));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ClapInstance {
+    fn drop(&mut self) {
+        self.deactivate();
+        unsafe { (self.plugin().destroy)(self.plugin) };
+    }
+}
+
+// Parameter changes queued per block at most
+const MAX_EVENTS: usize = 256;
+
+// Audio-thread side of an instance: planar port buffers and the event list, all
+// allocated up front
+pub struct ClapProcessor {
+    instance: Arc<ClapInstance>,
+    changes: Consumer<ParamChange>,
+    events: Vec<ClapEventParamValue>,
+    // Channel counts per port, planar data and the channel pointer arrays into it
+    input_channels: Vec<u32>,
+    output_channels: Vec<u32>,
+    input_data: Vec<f32>,
+    output_data: Vec<f32>,
+    _input_ptrs: Vec<*mut f32>,
+    _output_ptrs: Vec<*mut f32>,
+    inputs: Vec<ClapAudioBuffer>,
+    outputs: Vec<ClapAudioBuffer>,
+    started: bool,
+    steady_time: i64,
+}
+
+// Raw pointers into its own buffers; moved to the audio thread once
+unsafe impl Send for ClapProcessor {}
+
+fn port_buffers(channels: &[u32], data: &mut [f32], ptrs: &mut Vec<*mut f32>) -> Vec<ClapAudioBuffer> {
+    let base = data.as_mut_ptr();
+    ptrs.clear();
+    for c in 0..channels.iter().sum::<u32>() as usize {
+        ptrs.push(unsafe { base.add(c * MAX_BLOCK_FRAMES) });
+    }
+    let mut offset = 0;
+    channels
+        .iter()
+        .map(|&n| {
+            let buffer = ClapAudioBuffer {
+                data32: unsafe { ptrs.as_mut_ptr().add(offset) },
+                data64: std::ptr::null_mut(),
+                channel_count: n,
+                latency: 0,
+                constant_mask: 0,
+            };
+            offset += n as usize;
+            buffer
+        })
+        .collect()
+}
+
+impl ClapProcessor {
+    // The instance must be active
+    pub fn new(instance: Arc<ClapInstance>, changes: Consumer<ParamChange>) -> Self {
+        let input_channels = instance.port_channels(true);
+        let output_channels = instance.port_channels(false);
+        let total = |c: &[u32]| c.iter().sum::<u32>() as usize * MAX_BLOCK_FRAMES;
+        let mut input_data = vec![0.0; total(&input_channels)];
+        let mut output_data = vec![0.0; total(&output_channels)];
+        let mut input_ptrs = Vec::new();
+        let mut output_ptrs = Vec::new();
+        let inputs = port_buffers(&input_channels, &mut input_data, &mut input_ptrs);
+        let outputs = port_buffers(&output_channels, &mut output_data, &mut output_ptrs);
+        ClapProcessor {
+            instance,
+            changes,
+            events: Vec::with_capacity(MAX_EVENTS),
+            input_channels,
+            output_channels,
+            input_data,
+            output_data,
+            _input_ptrs: input_ptrs,
+            _output_ptrs: output_ptrs,
+            inputs,
+            outputs,
+            started: false,
+            steady_time: 0,
+        }
+    }
+
+    // Process interleaved `samples` in place through the main ports. Bus channels the
+    // plugin doesn't have pass through unchanged.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        let frames = samples.len().checked_div(channels).unwrap_or(0).min(MAX_BLOCK_FRAMES);
+        // Plugins without a main output port have nothing to give back to the bus
+        let ins = (self.input_channels.first().copied().unwrap_or(0) as usize).min(channels);
+        let outs = (self.output_channels.first().copied().unwrap_or(0) as usize).min(channels);
+        if frames == 0 || outs == 0 || self.instance.bypassed.load(Ordering::Relaxed) {
+            return;
+        }
+        let plugin = self.instance.plugin();
+        if !self.started {
+            if !unsafe { (plugin.start_processing)(self.instance.plugin) } {
+                return;
+            }
+            self.started = true;
+        }
+
+        self.events.clear();
+        while self.events.len() < MAX_EVENTS {
+            match self.changes.pop() {
+                Ok(change) => self.events.push(param_event(change)),
+                Err(_) => break,
+            }
+        }
+
+        for c in 0..ins {
+            let plane = &mut self.input_data[c * MAX_BLOCK_FRAMES..c * MAX_BLOCK_FRAMES + frames];
+            for (f, v) in plane.iter_mut().enumerate() {
+                *v = samples[f * channels + c];
+            }
+        }
+        let in_events = ClapInputEvents {
+            ctx: &mut self.events as *mut _ as *mut c_void,
+            size: events_size,
+            get: events_get,
+        };
+        let out_events = ClapOutputEvents { ctx: std::ptr::null_mut(), try_push: events_try_push };
+        let process = ClapProcess {
+            steady_time: self.steady_time,
+            frames_count: frames as u32,
+            transport: std::ptr::null(),
+            audio_inputs: self.inputs.as_ptr(),
+            audio_outputs: self.outputs.as_mut_ptr(),
+            audio_inputs_count: self.inputs.len() as u32,
+            audio_outputs_count: self.outputs.len() as u32,
+            in_events: &in_events,
+            out_events: &out_events,
+        };
+        let status = unsafe { (plugin.process)(self.instance.plugin, &process) };
+        self.steady_time += frames as i64;
+        if status == CLAP_PROCESS_ERROR {
+            return;
+        }
+
+        for c in 0..outs {
+            let plane = &self.output_data[c * MAX_BLOCK_FRAMES..c * MAX_BLOCK_FRAMES + frames];
+            for (f, v) in plane.iter().enumerate() {
+                samples[f * channels + c] = *v;
+            }
+        }
+    }
+}
+
+impl Drop for ClapProcessor {
+    // Runs on the audio thread, where stop_processing belongs
+    fn drop(&mut self) {
+        if self.started {
+            unsafe { (self.instance.plugin().stop_processing)(self.instance.plugin) };
+        }
+    }
+}
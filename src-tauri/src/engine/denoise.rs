@@ -0,0 +1,372 @@
+// Spectral noise suppression for the microphone. STFT with 50% overlapping sqrt-Hann
+// windows; the noise spectrum is tracked per bin as a slowly rising minimum of the
+// smoothed power, and every bin gets a spectral-subtraction gain with a floor.
+// All buffers and FFT plans are created up front; `process` doesn't allocate.
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+
+const FFT_SIZE: usize = 512;
+const HOP: usize = FFT_SIZE / 2;
+const BINS: usize = FFT_SIZE / 2 + 1;
+const MAX_CHANNELS: usize = 8;
+// Power smoothing over frames before the minimum search
+const POWER_SMOOTHING: f32 = 0.7;
+// The tracked minimum sits well below the mean noise power; without this factor (6 dB)
+// steady noise was only lowered by about 5 dB
+const NOISE_BIAS: f32 = 4.0;
+// How fast the noise estimate may rise, in dB per second
+const NOISE_RISE_DB_PER_SECOND: f32 = 3.0;
+// Gain smoothing against "musical noise": gains may drop at once but recover slowly
+const GAIN_RECOVERY: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NoiseSuppressionSettings {
+    pub enabled: bool,
+    // 0 = gentle, 1 = aggressive
+    pub strength: f32,
+}
+
+impl Default for NoiseSuppressionSettings {
+    fn default() -> Self {
+        NoiseSuppressionSettings { enabled: false, strength: 0.6 }
+    }
+}
+
+impl NoiseSuppressionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.strength.is_finite() && (0.0..=1.0).contains(&self.strength)) {
+            return Err(format!("Noise suppression strength {} out of range (0..1)", self.strength));
+        }
+        Ok(())
+    }
+}
+
+// Over-subtraction factor and gain floor derived from the strength
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseParams {
+    over_subtraction: f32,
+    floor: f32,
+}
+
+impl DenoiseParams {
+    pub fn new(strength: f32) -> Self {
+        let s = strength.clamp(0.0, 1.0);
+        let floor_db = -6.0 - 24.0 * s;
+        DenoiseParams { over_subtraction: 1.0 + 2.0 * s, floor: 10f32.powf(floor_db / 20.0) }
+    }
+}
+
+struct ChannelState {
+    // Last FFT_SIZE input samples; `pos` is where the next one goes
+    input: Vec<f32>,
+    // Overlap-add accumulator and the finished hop being played out
+    overlap: Vec<f32>,
+    ready: Vec<f32>,
+    power: Vec<f32>,
+    noise: Vec<f32>,
+    gain: Vec<f32>,
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        ChannelState {
+            input: vec![0.0; FFT_SIZE],
+            overlap: vec![0.0; FFT_SIZE],
+            ready: vec![0.0; HOP],
+            power: vec![0.0; BINS],
+            noise: vec![0.0; BINS],
+            gain: vec![1.0; BINS],
+        }
+    }
+}
+
+pub struct Denoiser {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    window: Vec<f32>,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch_fwd: Vec<Complex<f32>>,
+    scratch_inv: Vec<Complex<f32>>,
+    channels: Vec<ChannelState>,
+    pos: usize,
+    noise_rise: f32,
+}
+
+impl Denoiser {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(FFT_SIZE);
+        let inverse = planner.plan_fft_inverse(FFT_SIZE);
+        // sqrt-Hann (periodic) for analysis and synthesis: sums to 1 at 50% overlap
+        let window = (0..FFT_SIZE)
+            .map(|i| (0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / FFT_SIZE as f32).cos()).sqrt())
+            .collect();
+        let frames_per_second = sample_rate.max(1) as f32 / HOP as f32;
+        Denoiser {
+            frame: forward.make_input_vec(),
+            spectrum: forward.make_output_vec(),
+            scratch_fwd: forward.make_scratch_vec(),
+            scratch_inv: inverse.make_scratch_vec(),
+            forward,
+            inverse,
+            window,
+            channels: (0..MAX_CHANNELS).map(|_| ChannelState::new()).collect(),
+            pos: FFT_SIZE - HOP,
+            noise_rise: 10f32.powf(NOISE_RISE_DB_PER_SECOND / 10.0 / frames_per_second),
+        }
+    }
+
+    // Delay introduced by the STFT, in frames: a full window has to be collected, and
+    // its first hop is played out while the next one comes in
+    pub fn latency() -> usize {
+        FFT_SIZE
+    }
+
+    pub fn process(&mut self, p: &DenoiseParams, samples: &mut [f32], channels: usize) {
+        if channels == 0 {
+            return;
+        }
+        // Channels beyond MAX_CHANNELS pass through undelayed (no such devices in practice)
+        let processed = channels.min(MAX_CHANNELS);
+        for frame in samples.chunks_mut(channels) {
+            let out_index = self.pos - (FFT_SIZE - HOP);
+            for (ch, s) in frame.iter_mut().enumerate().take(processed) {
+                let state = &mut self.channels[ch];
+                state.input[self.pos] = *s;
+                *s = state.ready[out_index];
+            }
+            self.pos += 1;
+            if self.pos == FFT_SIZE {
+                for ch in 0..processed {
+                    self.process_frame(p, ch);
+                }
+                self.pos = FFT_SIZE - HOP;
+            }
+        }
+    }
+
+    fn process_frame(&mut self, p: &DenoiseParams, ch: usize) {
+        let state = &mut self.channels[ch];
+        for ((f, x), w) in self.frame.iter_mut().zip(&state.input).zip(&self.window) {
+            *f = x * w;
+        }
+        if self.forward.process_with_scratch(&mut self.frame, &mut self.spectrum, &mut self.scratch_fwd).is_err() {
+            return;
+        }
+
+        for (i, bin) in self.spectrum.iter_mut().enumerate() {
+            let power = bin.norm_sqr();
+            // Start from the first frame rather than from zero, or the noise estimate starts
+            // too low and takes seconds to rise to the real floor
+            let smoothed = if state.power[i] > 0.0 {
+                POWER_SMOOTHING * state.power[i] + (1.0 - POWER_SMOOTHING) * power
+            } else {
+                power
+            };
+            state.power[i] = smoothed;
+            // Minimum tracking: follow drops at once, rise slowly (speech doesn't pull the
+            // estimate up, changing background noise eventually does)
+            let noise = &mut state.noise[i];
+            *noise = if *noise <= 0.0 { smoothed } else { (*noise * self.noise_rise).min(smoothed) };
+
+            let wanted = if smoothed > 0.0 {
+                (1.0 - p.over_subtraction * NOISE_BIAS * *noise / smoothed).max(p.floor * p.floor).sqrt()
+            } else {
+                p.floor
+            };
+            let gain = wanted.max(state.gain[i] * GAIN_RECOVERY).min(1.0);
+            state.gain[i] = gain;
+            *bin *= gain;
+        }
+        // The inverse transform requires real DC and Nyquist bins
+        self.spectrum[0].im = 0.0;
+        self.spectrum[BINS - 1].im = 0.0;
+        if self.inverse.process_with_scratch(&mut self.spectrum, &mut self.frame, &mut self.scratch_inv).is_err() {
+            return;
+        }
+
+        let scale = 1.0 / FFT_SIZE as f32;
+        for ((acc, y), w) in state.overlap.iter_mut().zip(&self.frame).zip(&self.window) {
+            *acc += y * w * scale;
+        }
+        state.ready.copy_from_slice(&state.overlap[..HOP]);
+        state.overlap.copy_within(HOP.., 0);
+        state.overlap[FFT_SIZE - HOP..].fill(0.0);
+        state.input.copy_within(HOP.., 0);
+    }
+}
+
+// Noise floor estimate of a signal: 10th percentile of 20 ms block RMS, in dBFS
+fn noise_floor_db(samples: &[f32], channels: usize, sample_rate: u32) -> f32 {
+    let block = (sample_rate as usize / 50).max(1) * channels.max(1);
+    let mut levels: Vec<f32> = samples
+        .chunks(block)
+        .filter(|c| c.len() == block)
+        .map(|c| {
+            let ms = c.iter().map(|s| s * s).sum::<f32>() / c.len() as f32;
+            10.0 * ms.max(1e-12).log10()
+        })
+        .collect();
+    if levels.is_empty() {
+        return -120.0;
+    }
+    levels.sort_by(|a, b| a.total_cmp(b));
+    levels[levels.len() / 10]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DenoiseReport {
+    pub input: String,
+    pub output: String,
+    pub noise_floor_before_db: f32,
+    pub noise_floor_after_db: f32,
+}
+
+// Offline run over a WAV file, latency compensated, written as 32-bit float WAV
+pub fn process_wav(
+    input: &std::path::Path,
+    output: &std::path::Path,
+    strength: f32,
+) -> Result<DenoiseReport, String> {
+    let (samples, channels, sample_rate) = super::wav::read(input)?;
+    let params = DenoiseParams::new(strength);
+    let mut denoiser = Denoiser::new(sample_rate);
+
+    // Feed trailing silence so the tail comes out, then drop the latency at the start
+    let latency = Denoiser::latency() * channels;
+    let mut processed = samples.clone();
+    processed.resize(samples.len() + latency, 0.0);
+    for block in processed.chunks_mut(super::MAX_BLOCK_FRAMES * channels) {
+        denoiser.process(&params, block, channels);
+    }
+    let processed = &processed[latency..];
+
+    super::wav::write_f32(output, processed, channels, sample_rate)?;
+    Ok(DenoiseReport {
+        input: input.display().to_string(),
+        output: output.display().to_string(),
+        noise_floor_before_db: noise_floor_db(&samples, channels, sample_rate),
+        noise_floor_after_db: noise_floor_db(processed, channels, sample_rate),
+    })
+}
+
+// Test harness: process one WAV, or every WAV in a fixture directory into `<dir>/denoised`
+pub fn run_harness(path: &std::path::Path, strength: f32) -> Result<Vec<DenoiseReport>, String> {
+    if path.is_file() {
+        let out = path.with_extension("denoised.wav");
+        return Ok(vec![process_wav(path, &out, strength)?]);
+    }
+    let out_dir = path.join("denoised");
+    std::fs::create_dir_all(&out_dir).map_err(|e| format!("Create {} failed: {e}", out_dir.display()))?;
+    let mut entries: Vec<_> = std::fs::read_dir(path)
+        .map_err(|e| format!("Read {} failed: {e}", path.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav")))
+        .collect();
+    entries.sort();
+    entries
+        .iter()
+        .map(|p| process_wav(p, &out_dir.join(p.file_name().unwrap_or_default()), strength))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // 1 kHz tone bursts (0.5 s every second, from 1.5 s on) and white noise about 30 dB
+    // below them, as separate mono signals
+    fn tone_and_noise(seconds: usize) -> (Vec<f32>, Vec<f32>) {
+        let frames = seconds * RATE as usize;
+        let mut seed = 0x2545_f491_u32;
+        let noise = (0..frames)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed as f32 / u32::MAX as f32 - 0.5) * 0.03
+            })
+            .collect();
+        let tone = (0..frames)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                let on = t >= 1.5 && t.fract() >= 0.5;
+                if on { 0.3 * (std::f32::consts::TAU * 1000.0 * t).sin() } else { 0.0 }
+            })
+            .collect();
+        (tone, noise)
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32
+    }
+
+    fn db(ratio: f32) -> f32 {
+        10.0 * ratio.log10()
+    }
+
+    // Sample range of `from..to` seconds
+    fn span(from: f32, to: f32) -> std::ops::Range<usize> {
+        (from * RATE as f32) as usize..(to * RATE as f32) as usize
+    }
+
+    #[test]
+    fn removes_noise_and_keeps_the_tone() {
+        let (tone, noise) = tone_and_noise(6);
+        let input: Vec<f32> = tone.iter().zip(&noise).map(|(t, n)| t + n).collect();
+        let mut output = input.clone();
+        output.resize(input.len() + Denoiser::latency(), 0.0);
+        let mut denoiser = Denoiser::new(RATE);
+        let params = DenoiseParams::new(0.6);
+        for block in output.chunks_mut(crate::engine::MAX_BLOCK_FRAMES) {
+            denoiser.process(&params, block, 1);
+        }
+        let output = &output[Denoiser::latency()..];
+
+        // Between the bursts only noise is left
+        for gap in [span(2.1, 2.4), span(4.1, 4.4)] {
+            let reduction = db(energy(&input[gap.clone()]) / energy(&output[gap]));
+            assert!(reduction > 12.0, "noise only down {reduction:.1} dB");
+        }
+        // The bursts come through at their level, and closer to the clean tone than before
+        for burst in [span(3.6, 3.9), span(5.6, 5.9)] {
+            let level = db(energy(&output[burst.clone()]) / energy(&tone[burst.clone()]));
+            assert!(level.abs() < 1.0, "tone level changed by {level:.2} dB");
+            let error = |signal: &[f32]| {
+                let diff: Vec<f32> = signal[burst.clone()].iter().zip(&tone[burst.clone()]).map(|(s, t)| s - t).collect();
+                energy(&diff)
+            };
+            assert!(error(output) < error(&input), "burst got noisier");
+        }
+    }
+
+    #[test]
+    fn process_wav_lowers_the_noise_floor() {
+        let (tone, noise) = tone_and_noise(4);
+        // Stereo, the right channel with the noise reversed
+        let samples: Vec<f32> = (0..tone.len())
+            .flat_map(|i| [tone[i] + noise[i], tone[i] + noise[noise.len() - 1 - i]])
+            .collect();
+        let dir = std::env::temp_dir().join(format!("denoise-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("noisy.wav"), dir.join("clean.wav"));
+        crate::engine::wav::write_f32(&input, &samples, 2, RATE).unwrap();
+
+        let report = process_wav(&input, &output, 0.6).unwrap();
+        let (processed, channels, rate) = crate::engine::wav::read(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((channels, rate, processed.len()), (2, RATE, samples.len()));
+        assert!(
+            report.noise_floor_after_db < report.noise_floor_before_db - 12.0,
+            "{} -> {} dBFS",
+            report.noise_floor_before_db,
+            report.noise_floor_after_db
+        );
+    }
+}
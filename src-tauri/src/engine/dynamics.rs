@@ -0,0 +1,427 @@
+// Dynamics processors for buses and outputs: a feed-forward compressor followed by a
+// lookahead brickwall limiter. Settings are turned into `DynamicsParams` (coefficients
+// at the engine rate) off the audio thread; all buffers are allocated up front.
+use serde::{Deserialize, Serialize};
+
+// Longest lookahead we allocate for (at 192 kHz)
+const MAX_LOOKAHEAD_MS: f32 = 20.0;
+const MAX_SAMPLE_RATE: f32 = 192_000.0;
+const MAX_CHANNELS: usize = 8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CompressorSettings {
+    pub enabled: bool,
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub knee_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub makeup_db: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        CompressorSettings {
+            enabled: false,
+            threshold_db: -18.0,
+            ratio: 4.0,
+            knee_db: 6.0,
+            attack_ms: 10.0,
+            release_ms: 150.0,
+            makeup_db: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LimiterSettings {
+    pub enabled: bool,
+    pub ceiling_db: f32,
+    pub lookahead_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        LimiterSettings { enabled: false, ceiling_db: -1.0, lookahead_ms: 5.0, release_ms: 100.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct DynamicsSettings {
+    pub compressor: CompressorSettings,
+    pub limiter: LimiterSettings,
+}
+
+fn check(name: &str, value: f32, min: f32, max: f32) -> Result<(), String> {
+    if value.is_finite() && (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} {} out of range ({}..{})", name, value, min, max))
+    }
+}
+
+impl DynamicsSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        let c = &self.compressor;
+        check("Threshold", c.threshold_db, -60.0, 0.0)?;
+        check("Ratio", c.ratio, 1.0, 100.0)?;
+        check("Knee", c.knee_db, 0.0, 24.0)?;
+        check("Attack", c.attack_ms, 0.1, 500.0)?;
+        check("Release", c.release_ms, 1.0, 5000.0)?;
+        check("Makeup", c.makeup_db, -12.0, 24.0)?;
+        let l = &self.limiter;
+        check("Ceiling", l.ceiling_db, -24.0, 0.0)?;
+        check("Lookahead", l.lookahead_ms, 0.0, MAX_LOOKAHEAD_MS)?;
+        check("Limiter release", l.release_ms, 1.0, 5000.0)?;
+        Ok(())
+    }
+}
+
+// One-pole smoothing coefficient for a time constant
+fn time_coef(ms: f32, sample_rate: u32) -> f32 {
+    let samples = ms * 0.001 * sample_rate as f32;
+    if samples < 1.0 { 0.0 } else { (-1.0 / samples).exp() }
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DynamicsParams {
+    compressor: Option<CompressorParams>,
+    limiter: Option<LimiterParams>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CompressorParams {
+    threshold_db: f32,
+    slope: f32,
+    knee_db: f32,
+    attack: f32,
+    release: f32,
+    makeup_db: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LimiterParams {
+    ceiling: f32,
+    lookahead: usize,
+    release: f32,
+}
+
+impl DynamicsParams {
+    pub fn new(settings: &DynamicsSettings, sample_rate: u32) -> Self {
+        let c = &settings.compressor;
+        let l = &settings.limiter;
+        DynamicsParams {
+            compressor: c.enabled.then(|| CompressorParams {
+                threshold_db: c.threshold_db,
+                slope: 1.0 / c.ratio.max(1.0) - 1.0,
+                knee_db: c.knee_db.max(0.0),
+                attack: time_coef(c.attack_ms, sample_rate),
+                release: time_coef(c.release_ms, sample_rate),
+                makeup_db: c.makeup_db,
+            }),
+            limiter: l.enabled.then(|| LimiterParams {
+                ceiling: db_to_linear(l.ceiling_db),
+                lookahead: ((l.lookahead_ms.min(MAX_LOOKAHEAD_MS) * 0.001 * sample_rate as f32) as usize)
+                    .min(max_lookahead_frames()),
+                release: time_coef(l.release_ms, sample_rate),
+            }),
+        }
+    }
+}
+
+fn max_lookahead_frames() -> usize {
+    (MAX_LOOKAHEAD_MS * 0.001 * MAX_SAMPLE_RATE) as usize
+}
+
+struct Compressor {
+    // Smoothed gain change in dB (<= 0 without makeup)
+    envelope_db: f32,
+}
+
+impl Compressor {
+    // Static curve with a quadratic soft knee
+    fn gain_db(p: &CompressorParams, level_db: f32) -> f32 {
+        let over = level_db - p.threshold_db;
+        if 2.0 * over < -p.knee_db {
+            0.0
+        } else if 2.0 * over.abs() <= p.knee_db && p.knee_db > 0.0 {
+            p.slope * (over + p.knee_db / 2.0).powi(2) / (2.0 * p.knee_db)
+        } else {
+            p.slope * over
+        }
+    }
+
+    // Returns the largest gain reduction in this block (dB, positive)
+    fn process(&mut self, p: &CompressorParams, samples: &mut [f32], channels: usize) -> f32 {
+        let mut max_reduction = 0.0f32;
+        for frame in samples.chunks_mut(channels) {
+            // Linked detector: all channels get the same gain so the image doesn't shift
+            let peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            let level_db = 20.0 * peak.max(1e-9).log10();
+            let target = Self::gain_db(p, level_db);
+            let coef = if target < self.envelope_db { p.attack } else { p.release };
+            self.envelope_db = target + coef * (self.envelope_db - target);
+            max_reduction = max_reduction.max(-self.envelope_db);
+            let gain = db_to_linear(self.envelope_db + p.makeup_db);
+            for s in frame.iter_mut() {
+                *s *= gain;
+            }
+        }
+        max_reduction
+    }
+}
+
+// Lookahead limiter. The required gain of every frame is held for the lookahead window
+// (sliding minimum) and then averaged over the same window, so the gain has fully ramped
+// down by the time a peak leaves the delay line. A final clip catches rounding.
+struct Limiter {
+    delay: Vec<f32>,
+    delay_pos: usize,
+    // Monotonic queue of (frame, required gain) for the sliding minimum
+    queue: Vec<(u64, f32)>,
+    queue_head: usize,
+    queue_len: usize,
+    // Ring of held minimums for the moving average
+    held: Vec<f32>,
+    held_sum: f64,
+    frame: u64,
+    gain: f32,
+    lookahead: usize,
+}
+
+impl Limiter {
+    fn new() -> Self {
+        let max = max_lookahead_frames() + 1;
+        Limiter {
+            delay: vec![0.0; max * MAX_CHANNELS],
+            delay_pos: 0,
+            queue: vec![(0, 1.0); max],
+            queue_head: 0,
+            queue_len: 0,
+            held: vec![1.0; max],
+            held_sum: max as f64,
+            frame: 0,
+            gain: 1.0,
+            lookahead: usize::MAX,
+        }
+    }
+
+    fn reset(&mut self, lookahead: usize) {
+        self.lookahead = lookahead;
+        self.delay.fill(0.0);
+        self.delay_pos = 0;
+        self.queue_head = 0;
+        self.queue_len = 0;
+        self.held[..lookahead + 1].fill(1.0);
+        self.held_sum = (lookahead + 1) as f64;
+        self.gain = 1.0;
+    }
+
+    fn process(&mut self, p: &LimiterParams, samples: &mut [f32], channels: usize) -> f32 {
+        if p.lookahead != self.lookahead {
+            // Lookahead changed: the delay line length changes, start over
+            self.reset(p.lookahead);
+        }
+        let window = p.lookahead + 1;
+        let cap = self.queue.len();
+        let ch = channels.min(MAX_CHANNELS);
+        let mut max_reduction = 0.0f32;
+
+        for frame in samples.chunks_mut(channels) {
+            let peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            let required = if peak > p.ceiling { p.ceiling / peak } else { 1.0 };
+
+            // Sliding minimum over the last `window` frames
+            while self.queue_len > 0 {
+                let back = (self.queue_head + self.queue_len - 1) % cap;
+                if self.queue[back].1 >= required {
+                    self.queue_len -= 1;
+                } else {
+                    break;
+                }
+            }
+            self.queue[(self.queue_head + self.queue_len) % cap] = (self.frame, required);
+            self.queue_len += 1;
+            while self.queue[self.queue_head].0 + (window as u64) <= self.frame {
+                self.queue_head = (self.queue_head + 1) % cap;
+                self.queue_len -= 1;
+            }
+            let held = self.queue[self.queue_head].1;
+
+            // Moving average of the held minimum
+            let slot = (self.frame % window as u64) as usize;
+            self.held_sum += held as f64 - self.held[slot] as f64;
+            self.held[slot] = held;
+            let smoothed = (self.held_sum / window as f64) as f32;
+
+            self.gain = if smoothed < self.gain { smoothed } else { smoothed + p.release * (self.gain - smoothed) };
+            max_reduction = max_reduction.max(-20.0 * self.gain.max(1e-9).log10());
+
+            // Swap the frame with the delayed one and apply the gain
+            let base = self.delay_pos * MAX_CHANNELS;
+            for (c, s) in frame.iter_mut().enumerate().take(ch) {
+                let delayed = if p.lookahead == 0 { *s } else { std::mem::replace(&mut self.delay[base + c], *s) };
+                *s = (delayed * self.gain).clamp(-p.ceiling, p.ceiling);
+            }
+            for s in frame.iter_mut().skip(ch) {
+                *s = 0.0;
+            }
+            self.delay_pos = (self.delay_pos + 1) % p.lookahead.max(1);
+            self.frame += 1;
+        }
+        max_reduction
+    }
+}
+
+// Compressor -> limiter, as inserted on a bus or an output
+pub struct Dynamics {
+    params: DynamicsParams,
+    compressor: Compressor,
+    limiter: Limiter,
+}
+
+impl Dynamics {
+    pub fn new(params: DynamicsParams) -> Self {
+        Dynamics { params, compressor: Compressor { envelope_db: 0.0 }, limiter: Limiter::new() }
+    }
+
+    pub fn set_params(&mut self, params: DynamicsParams) {
+        if params.compressor.is_none() {
+            self.compressor.envelope_db = 0.0;
+        }
+        if params.limiter.is_none() {
+            // Flush the delay line when it is switched on again
+            self.limiter.lookahead = usize::MAX;
+        }
+        self.params = params;
+    }
+
+    // Process in place; returns the total gain reduction of the block in dB
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) -> f32 {
+        if channels == 0 {
+            return 0.0;
+        }
+        let mut reduction = 0.0;
+        if let Some(p) = &self.params.compressor {
+            reduction += self.compressor.process(p, samples, channels);
+        }
+        if let Some(p) = &self.params.limiter {
+            reduction += self.limiter.process(p, samples, channels);
+        }
+        reduction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn limiter(ceiling_db: f32, lookahead_ms: f32) -> Dynamics {
+        let settings = DynamicsSettings {
+            limiter: LimiterSettings { enabled: true, ceiling_db, lookahead_ms, release_ms: 100.0 },
+            ..Default::default()
+        };
+        Dynamics::new(DynamicsParams::new(&settings, RATE))
+    }
+
+    fn compressor(threshold_db: f32, ratio: f32, knee_db: f32) -> CompressorParams {
+        let settings = CompressorSettings { enabled: true, threshold_db, ratio, knee_db, ..Default::default() };
+        DynamicsParams::new(&DynamicsSettings { compressor: settings, ..Default::default() }, RATE).compressor.unwrap()
+    }
+
+    // Runs the limiter in engine-sized blocks over a stereo signal
+    fn run(dynamics: &mut Dynamics, input: &[f32]) -> Vec<f32> {
+        let mut out = input.to_vec();
+        for block in out.chunks_mut(256 * 2) {
+            dynamics.process(block, 2);
+        }
+        out
+    }
+
+    #[test]
+    fn limiter_holds_the_ceiling_on_a_step() {
+        let ceiling = db_to_linear(-1.0);
+        let lookahead = (0.005 * RATE as f32) as usize;
+        let input: Vec<f32> = (0..RATE as usize).flat_map(|i| {
+            let s = if i < 10_000 { 0.1 } else { 1.0 };
+            [s, -s]
+        }).collect();
+        let out = run(&mut limiter(-1.0, 5.0), &input);
+        // Quiet part comes through untouched, just delayed, until the gain ramps down ahead of the step
+        for i in lookahead..10_000 - lookahead {
+            assert!((out[i * 2] - 0.1).abs() < 1e-6, "frame {} = {}", i, out[i * 2]);
+        }
+        assert!(out[(10_000 + lookahead / 2) * 2] < 0.1 * 0.95);
+        // The gain is already down when the step leaves the delay line, so nothing relies on the clip
+        for (i, s) in out.iter().enumerate().skip(lookahead * 2) {
+            assert!(s.abs() <= ceiling * 1.0001, "sample {} = {}", i, s);
+        }
+        let settled = out[(RATE as usize - 1) * 2];
+        assert!((settled - ceiling).abs() < 1e-3, "settled at {}", settled);
+    }
+
+    #[test]
+    fn limiter_catches_a_single_transient() {
+        let ceiling = db_to_linear(-3.0);
+        let lookahead = (0.002 * RATE as f32) as usize;
+        let mut input = vec![0.2f32; 8192 * 2];
+        input[5000 * 2] = 2.0;
+        input[5000 * 2 + 1] = -1.5;
+        let out = run(&mut limiter(-3.0, 2.0), &input);
+        let peak = out.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak <= ceiling * 1.0001, "peak {}", peak);
+        // The transient is scaled by the ramped gain, not flattened by the clip
+        let (l, r) = (out[(5000 + lookahead) * 2], out[(5000 + lookahead) * 2 + 1]);
+        assert!((l / r + 2.0 / 1.5).abs() < 1e-3, "{} / {}", l, r);
+        // Background before the ramp starts is left alone
+        assert!((out[4000 * 2] - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn compressor_static_curve_above_the_knee() {
+        for &(threshold, ratio, knee) in &[(-18.0, 4.0, 6.0), (-30.0, 2.0, 0.0), (-10.0, 10.0, 12.0)] {
+            let p = compressor(threshold, ratio, knee);
+            for x in [threshold + knee / 2.0 + 0.5, threshold + 10.0, 0.0f32] {
+                if x - threshold <= knee / 2.0 {
+                    continue;
+                }
+                let out = x + Compressor::gain_db(&p, x);
+                let expected = threshold + (x - threshold) / ratio;
+                assert!((out - expected).abs() < 1e-4, "{}:1 at {} dB: {} vs {}", ratio, x, out, expected);
+            }
+            // Untouched below the knee, and the knee joins both lines
+            assert_eq!(Compressor::gain_db(&p, threshold - knee / 2.0 - 1.0), 0.0);
+            let edge = threshold + knee / 2.0;
+            assert!((Compressor::gain_db(&p, edge - 1e-3) - Compressor::gain_db(&p, edge + 1e-3)).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn compressor_settles_on_the_static_curve() {
+        let settings = DynamicsSettings {
+            compressor: CompressorSettings {
+                enabled: true,
+                threshold_db: -20.0,
+                ratio: 4.0,
+                knee_db: 6.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut dynamics = Dynamics::new(DynamicsParams::new(&settings, RATE));
+        let level = db_to_linear(-4.0);
+        let out = run(&mut dynamics, &vec![level; RATE as usize * 2]);
+        let settled = 20.0 * out[out.len() - 1].log10();
+        assert!((settled - (-20.0 + 16.0 / 4.0)).abs() < 0.01, "settled at {} dB", settled);
+    }
+}
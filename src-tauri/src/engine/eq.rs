@@ -0,0 +1,353 @@
+// Parametric EQ: a chain of biquads per bus (RBJ audio EQ cookbook formulas).
+// Coefficients are computed off the audio thread into a fixed-size `EqDesign`; the
+// callback only swaps it in, so parameter changes never do math or resize on that thread.
+use serde::{Deserialize, Serialize};
+
+pub const MAX_EQ_BANDS: usize = 16;
+// Channels with their own filter state; wider buses share the last state (never happens
+// with the current device layouts)
+const MAX_CHANNELS: usize = 8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EqBandType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+    Notch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EqBand {
+    pub kind: EqBandType,
+    pub frequency: f32,
+    // Ignored by pass and notch filters
+    #[serde(default)]
+    pub gain_db: f32,
+    pub q: f32,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+impl EqBand {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.frequency.is_finite() && (10.0..=24_000.0).contains(&self.frequency)) {
+            return Err(format!("Frequency {} Hz out of range (10-24000)", self.frequency));
+        }
+        if !(self.q.is_finite() && (0.05..=30.0).contains(&self.q)) {
+            return Err(format!("Q {} out of range (0.05-30)", self.q));
+        }
+        if !(self.gain_db.is_finite() && (-24.0..=24.0).contains(&self.gain_db)) {
+            return Err(format!("Gain {} dB out of range (-24..24)", self.gain_db));
+        }
+        Ok(())
+    }
+}
+
+// EQ of one bus: bands plus a broadband gain, which correction curves (AutoEQ) use to
+// leave headroom for their boosts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct EqSettings {
+    #[serde(default)]
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+}
+
+impl EqSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.preamp_db.is_finite() && (-30.0..=12.0).contains(&self.preamp_db)) {
+            return Err(format!("Preamp {} dB out of range (-30..12)", self.preamp_db));
+        }
+        if self.bands.len() > MAX_EQ_BANDS {
+            return Err(format!("At most {} EQ bands supported", MAX_EQ_BANDS));
+        }
+        for (i, band) in self.bands.iter().enumerate() {
+            band.validate().map_err(|e| format!("Band {}: {}", i + 1, e))?;
+        }
+        Ok(())
+    }
+}
+
+// Normalized biquad coefficients (a0 = 1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    const IDENTITY: Coefficients = Coefficients { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0 };
+
+    pub fn design(band: &EqBand, sample_rate: u32) -> Coefficients {
+        let fs = sample_rate as f64;
+        // Keep the center below Nyquist so the design stays stable at low sample rates
+        let f0 = (band.frequency as f64).clamp(10.0, fs * 0.49);
+        let q = (band.q as f64).max(0.05);
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+        let w0 = std::f64::consts::TAU * f0 / fs;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            EqBandType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            EqBandType::LowShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + k),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - k),
+                    (a + 1.0) + (a - 1.0) * cos + k,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - k,
+                )
+            }
+            EqBandType::HighShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + k),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - k),
+                    (a + 1.0) - (a - 1.0) * cos + k,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - k,
+                )
+            }
+            EqBandType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            EqBandType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            EqBandType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+        };
+        Coefficients {
+            b0: (b0 / a0) as f32,
+            b1: (b1 / a0) as f32,
+            b2: (b2 / a0) as f32,
+            a1: (a1 / a0) as f32,
+            a2: (a2 / a0) as f32,
+        }
+    }
+
+    // Magnitude response in dB at `freq`
+    fn response_db(&self, freq: f64, sample_rate: u32) -> f64 {
+        let w = std::f64::consts::TAU * freq / sample_rate as f64;
+        let (s1, c1) = w.sin_cos();
+        let (s2, c2) = (2.0 * w).sin_cos();
+        let (b0, b1, b2) = (self.b0 as f64, self.b1 as f64, self.b2 as f64);
+        let (a1, a2) = (self.a1 as f64, self.a2 as f64);
+        let num = (b0 + b1 * c1 + b2 * c2).powi(2) + (b1 * s1 + b2 * s2).powi(2);
+        let den = (1.0 + a1 * c1 + a2 * c2).powi(2) + (a1 * s1 + a2 * s2).powi(2);
+        10.0 * (num / den).max(1e-24).log10()
+    }
+}
+
+// Coefficients for a whole chain, computed from the bands at one sample rate
+#[derive(Debug, Clone, Copy)]
+pub struct EqDesign {
+    stages: [Coefficients; MAX_EQ_BANDS],
+    len: usize,
+    preamp: f32,
+}
+
+impl EqDesign {
+    pub fn flat() -> Self {
+        EqDesign { stages: [Coefficients::IDENTITY; MAX_EQ_BANDS], len: 0, preamp: 1.0 }
+    }
+
+    // Stage i is band i, so every band keeps its filter state when another one is toggled;
+    // disabled bands pass through. Bands beyond MAX_EQ_BANDS are ignored.
+    pub fn new(settings: &EqSettings, sample_rate: u32) -> Self {
+        let mut design = EqDesign::flat();
+        design.preamp = 10f32.powf(settings.preamp_db / 20.0);
+        for (stage, band) in design.stages.iter_mut().zip(&settings.bands) {
+            if band.enabled {
+                *stage = Coefficients::design(band, sample_rate);
+            }
+        }
+        design.len = settings.bands.len().min(MAX_EQ_BANDS);
+        design
+    }
+}
+
+// Magnitude response (dB) of `settings` at each of `freqs`, for drawing the EQ curve
+pub fn response(settings: &EqSettings, sample_rate: u32, freqs: &[f32]) -> Vec<f32> {
+    let design = EqDesign::new(settings, sample_rate);
+    freqs
+        .iter()
+        .map(|&f| {
+            let f = (f as f64).clamp(1.0, sample_rate as f64 * 0.5);
+            let bands: f64 = design.stages[..design.len].iter().map(|c| c.response_db(f, sample_rate)).sum();
+            bands as f32 + settings.preamp_db
+        })
+        .collect()
+}
+
+// Transposed direct form II state
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    z1: f32,
+    z2: f32,
+}
+
+pub struct EqChain {
+    design: EqDesign,
+    states: [[State; MAX_CHANNELS]; MAX_EQ_BANDS],
+}
+
+impl EqChain {
+    pub fn new(design: EqDesign) -> Self {
+        EqChain { design, states: [[State::default(); MAX_CHANNELS]; MAX_EQ_BANDS] }
+    }
+
+    // Swap in new coefficients. Filter state is kept so parameter tweaks don't click;
+    // stages that were disabled or removed start from silence when they come back.
+    pub fn set_design(&mut self, design: EqDesign) {
+        for (i, states) in self.states.iter_mut().enumerate() {
+            if i >= design.len || design.stages[i] == Coefficients::IDENTITY {
+                *states = [State::default(); MAX_CHANNELS];
+            }
+        }
+        self.design = design;
+    }
+
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if self.design.preamp != 1.0 {
+            for s in samples.iter_mut() {
+                *s *= self.design.preamp;
+            }
+        }
+        if self.design.len == 0 || channels == 0 {
+            return;
+        }
+        for (c, states) in self.design.stages[..self.design.len].iter().zip(self.states.iter_mut()) {
+            if *c == Coefficients::IDENTITY {
+                continue;
+            }
+            for frame in samples.chunks_mut(channels) {
+                for (ch, x) in frame.iter_mut().enumerate() {
+                    let s = &mut states[ch.min(MAX_CHANNELS - 1)];
+                    let y = c.b0 * *x + s.z1;
+                    s.z1 = c.b1 * *x - c.a1 * y + s.z2;
+                    s.z2 = c.b2 * *x - c.a2 * y;
+                    *x = y;
+                }
+            }
+        }
+        // Flush denormals and recover from blow-ups (NaN from a broken input)
+        for states in self.states[..self.design.len].iter_mut() {
+            for s in states.iter_mut() {
+                if !s.z1.is_finite() || !s.z2.is_finite() {
+                    *s = State::default();
+                } else {
+                    if s.z1.abs() < 1e-20 {
+                        s.z1 = 0.0;
+                    }
+                    if s.z2.abs() < 1e-20 {
+                        s.z2 = 0.0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn band(kind: EqBandType, frequency: f32, gain_db: f32, q: f32) -> EqBand {
+        EqBand { kind, frequency, gain_db, q, enabled: true }
+    }
+
+    fn response_at(band: EqBand, freq: f32) -> f32 {
+        response(&EqSettings { preamp_db: 0.0, bands: vec![band] }, RATE, &[freq])[0]
+    }
+
+    #[test]
+    fn bands_hit_their_level_at_the_center() {
+        use EqBandType::*;
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        for gain in [-12.0, 6.0] {
+            assert!((response_at(band(Peaking, 1000.0, gain, 1.0), 1000.0) - gain).abs() < 0.01);
+            // Shelves are halfway at f0 and reach the gain far from it
+            assert!((response_at(band(LowShelf, 1000.0, gain, q), 1000.0) - gain / 2.0).abs() < 0.01);
+            assert!((response_at(band(LowShelf, 1000.0, gain, q), 20.0) - gain).abs() < 0.1);
+            assert!((response_at(band(HighShelf, 1000.0, gain, q), 1000.0) - gain / 2.0).abs() < 0.01);
+            assert!((response_at(band(HighShelf, 1000.0, gain, q), 20_000.0) - gain).abs() < 0.1);
+        }
+        // Pass filters are at 20 log10(Q) at f0, -3 dB for Butterworth
+        assert!((response_at(band(LowPass, 1000.0, 0.0, q), 1000.0) + 3.01).abs() < 0.01);
+        assert!((response_at(band(HighPass, 1000.0, 0.0, q), 1000.0) + 3.01).abs() < 0.01);
+        assert!((response_at(band(HighPass, 1000.0, 0.0, 2.0), 1000.0) - 6.02).abs() < 0.01);
+        assert!(response_at(band(Notch, 1000.0, 0.0, 2.0), 1000.0) < -60.0);
+        // Unaffected far from f0
+        assert!(response_at(band(Notch, 1000.0, 0.0, 2.0), 100.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn narrow_peak_near_nyquist_is_stable() {
+        let c = Coefficients::design(&band(EqBandType::Peaking, 23_000.0, 24.0, 30.0), RATE);
+        // Poles inside the unit circle
+        assert!(c.a2.abs() < 1.0 && c.a1.abs() < 1.0 + c.a2, "{c:?}");
+        let settings = EqSettings { preamp_db: 0.0, bands: vec![band(EqBandType::Peaking, 23_000.0, 24.0, 30.0)] };
+        let mut chain = EqChain::new(EqDesign::new(&settings, RATE));
+        let mut samples = vec![0.0f32; RATE as usize * 2];
+        samples[0] = 1.0;
+        samples[1] = 1.0;
+        chain.process(&mut samples, 2);
+        assert!(samples.iter().all(|s| s.is_finite()));
+        assert!(samples[samples.len() - 200..].iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn toggling_a_band_keeps_the_others_in_place() {
+        let mut settings = EqSettings {
+            preamp_db: 0.0,
+            bands: vec![band(EqBandType::Peaking, 200.0, 6.0, 1.0), band(EqBandType::Peaking, 3000.0, -6.0, 1.0)],
+        };
+        let mut chain = EqChain::new(EqDesign::new(&settings, RATE));
+        let mut noise: Vec<f32> = (0..2048).map(|i| ((i * 7919) % 200) as f32 / 200.0 - 0.5).collect();
+        chain.process(&mut noise, 2);
+        let second = chain.states[1];
+
+        settings.bands[0].enabled = false;
+        let design = EqDesign::new(&settings, RATE);
+        assert_eq!(design.stages[0], Coefficients::IDENTITY);
+        assert_eq!(design.stages[1], Coefficients::design(&settings.bands[1], RATE));
+        chain.set_design(design);
+        // The second band keeps its state, the disabled one starts over
+        assert_eq!(chain.states[1][0].z1, second[0].z1);
+        assert_eq!(chain.states[0][0].z1, 0.0);
+        // Response as if the band weren't there
+        assert!(response(&settings, RATE, &[200.0])[0].abs() < 0.1);
+    }
+}
@@ -0,0 +1,436 @@
+// Minimal streaming FLAC encoder for recordings: fixed-size blocks, each channel coded
+// with the best of the fixed predictors (orders 0-4) and Rice-coded residuals, or as a
+// constant/verbatim subframe where that is smaller. No stereo decorrelation or LPC, so
+// files come out larger than with the reference encoder, but every decoder reads them.
+// The MD5 in STREAMINFO is left at zero, which the format defines as "not computed".
+use std::io::{self, Seek, SeekFrom, Write};
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+// Rice parameters are coded in 5 bits (RICE2); 31 is the escape code
+const MAX_RICE_PARAM: u32 = 30;
+const STREAMINFO_OFFSET: u64 = 8;
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    // Lowest `bits` (at most 32) of `value`, MSB first
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+// Frame numbers are coded like UTF-8
+fn write_utf8(w: &mut BitWriter, v: u32) {
+    if v < 0x80 {
+        w.write(v as u64, 8);
+        return;
+    }
+    let extra = match v {
+        0..=0x7ff => 1,
+        0x800..=0xffff => 2,
+        0x1_0000..=0x1f_ffff => 3,
+        0x20_0000..=0x3ff_ffff => 4,
+        _ => 5,
+    };
+    let lead = (0xff00u32 >> (extra + 1)) as u8;
+    w.write((lead | (v >> (6 * extra)) as u8) as u64, 8);
+    for i in (0..extra).rev() {
+        w.write((0x80 | ((v >> (6 * i)) & 0x3f)) as u64, 8);
+    }
+}
+
+fn fixed_residual(x: &[i32], order: usize, out: &mut Vec<u64>) {
+    out.clear();
+    for i in order..x.len() {
+        let s = |k: usize| x[i - k] as i64;
+        let r = match order {
+            0 => s(0),
+            1 => s(0) - s(1),
+            2 => s(0) - 2 * s(1) + s(2),
+            3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+            _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+        };
+        // Zigzag, as Rice coding wants it
+        out.push(((r << 1) ^ (r >> 63)) as u64);
+    }
+}
+
+// Cheapest Rice parameter for one partition and its size in bits
+fn rice_param(u: &[u64]) -> (u32, u64) {
+    if u.is_empty() {
+        return (0, 0);
+    }
+    let mean = u.iter().sum::<u64>() / u.len() as u64;
+    let estimate = if mean > 0 { 63 - mean.leading_zeros() } else { 0 };
+    (estimate.saturating_sub(1)..=(estimate + 1).min(MAX_RICE_PARAM))
+        .map(|k| (k, u.len() as u64 * (k as u64 + 1) + u.iter().map(|v| v >> k).sum::<u64>()))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+struct RicePlan {
+    partition_order: u32,
+    params: Vec<u32>,
+    // Residual section without the method/order fields
+    bits: u64,
+}
+
+// Partition `i` of a residual that starts after `order` warm-up samples
+fn partition(u: &[u64], block: usize, order: usize, partition_order: u32, i: usize) -> &[u64] {
+    let size = block >> partition_order;
+    let start = if i == 0 { 0 } else { i * size - order };
+    &u[start..(i + 1) * size - order]
+}
+
+fn rice_plan(u: &[u64], block: usize, order: usize) -> RicePlan {
+    let mut best: Option<RicePlan> = None;
+    for p in 0..=MAX_PARTITION_ORDER {
+        if block & ((1 << p) - 1) != 0 || (block >> p) < order {
+            break;
+        }
+        let (params, bits) = (0..1 << p)
+            .map(|i| rice_param(partition(u, block, order, p, i)))
+            .fold((Vec::new(), 0), |(mut params, bits), (k, b)| {
+                params.push(k);
+                (params, bits + 5 + b)
+            });
+        if best.as_ref().is_none_or(|b| bits < b.bits) {
+            best = Some(RicePlan { partition_order: p, params, bits });
+        }
+    }
+    best.unwrap_or(RicePlan { partition_order: 0, params: vec![0], bits: 5 })
+}
+
+fn encode_subframe(w: &mut BitWriter, x: &[i32], bps: u32, residual: &mut Vec<u64>) {
+    let n = x.len();
+    // Header: zero pad bit, 6 bit type, no wasted bits
+    if x.iter().all(|&v| v == x[0]) {
+        w.write(0, 8);
+        w.write_signed(x[0] as i64, bps);
+        return;
+    }
+    let verbatim = n as u64 * bps as u64;
+    let mut best: Option<(usize, RicePlan, u64)> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(n - 1) {
+        fixed_residual(x, order, residual);
+        let plan = rice_plan(residual, n, order);
+        let bits = (order as u64) * bps as u64 + 6 + plan.bits;
+        if best.as_ref().is_none_or(|b| bits < b.2) {
+            best = Some((order, plan, bits));
+        }
+    }
+    match best {
+        Some((order, plan, bits)) if bits < verbatim => {
+            w.write((0b001000 | order as u64) << 1, 8);
+            for &v in &x[..order] {
+                w.write_signed(v as i64, bps);
+            }
+            fixed_residual(x, order, residual);
+            // RICE2: 5 bit parameters
+            w.write(1, 2);
+            w.write(plan.partition_order as u64, 4);
+            for (i, &k) in plan.params.iter().enumerate() {
+                w.write(k as u64, 5);
+                for &u in partition(residual, n, order, plan.partition_order, i) {
+                    w.write_unary(u >> k);
+                    w.write(u, k);
+                }
+            }
+        }
+        _ => {
+            w.write(0b000001 << 1, 8);
+            for &v in x {
+                w.write_signed(v as i64, bps);
+            }
+        }
+    }
+}
+
+pub struct FlacWriter<W: Write + Seek> {
+    out: W,
+    channels: usize,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    // Block being filled, one quantized vector per channel
+    block: Vec<Vec<i32>>,
+    residual: Vec<u64>,
+    frame_number: u32,
+    total_samples: u64,
+    min_frame: u32,
+    max_frame: u32,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    // `bits_per_sample` is 16 or 24
+    pub fn new(mut out: W, channels: usize, sample_rate: u32, bits_per_sample: u32) -> io::Result<Self> {
+        if !(1..=8).contains(&channels) || !matches!(bits_per_sample, 16 | 24) || sample_rate == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported FLAC format"));
+        }
+        out.write_all(b"fLaC")?;
+        let mut writer = FlacWriter {
+            out,
+            channels,
+            sample_rate,
+            bits_per_sample,
+            block: vec![Vec::with_capacity(BLOCK_SIZE); channels],
+            residual: Vec::with_capacity(BLOCK_SIZE),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame: 0,
+            max_frame: 0,
+        };
+        let info = writer.streaminfo();
+        writer.out.write_all(&info)?;
+        Ok(writer)
+    }
+
+    // Metadata block header (last block, type 0) plus STREAMINFO
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.write(1, 1);
+        w.write(0, 7);
+        w.write(34, 24);
+        w.write(BLOCK_SIZE as u64, 16);
+        w.write(BLOCK_SIZE as u64, 16);
+        w.write(self.min_frame as u64, 24);
+        w.write(self.max_frame as u64, 24);
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bits_per_sample as u64 - 1, 5);
+        w.write(self.total_samples >> 32, 4);
+        w.write(self.total_samples & 0xffff_ffff, 32);
+        for _ in 0..4 {
+            w.write(0, 32);
+        }
+        w.bytes
+    }
+
+    // Interleaved samples; a trailing partial frame is ignored
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let scale = ((1i64 << (self.bits_per_sample - 1)) - 1) as f64;
+        for frame in samples.chunks_exact(self.channels) {
+            for (ch, &s) in self.block.iter_mut().zip(frame) {
+                ch.push((s.clamp(-1.0, 1.0) as f64 * scale).round() as i32);
+            }
+            if self.block[0].len() == BLOCK_SIZE {
+                self.encode_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_frame(&mut self) -> io::Result<()> {
+        let n = self.block[0].len();
+        if n == 0 {
+            return Ok(());
+        }
+        let mut w = BitWriter { bytes: Vec::with_capacity(n * self.channels * 3), ..Default::default() };
+        // Sync code, fixed block size
+        w.write(0xfff8, 16);
+        w.write(if n == BLOCK_SIZE { 12 } else { 7 }, 4);
+        // Sample rate from STREAMINFO, independent channels
+        w.write(0, 4);
+        w.write(self.channels as u64 - 1, 4);
+        w.write(if self.bits_per_sample == 16 { 0b100 } else { 0b110 }, 3);
+        w.write(0, 1);
+        write_utf8(&mut w, self.frame_number);
+        if n != BLOCK_SIZE {
+            w.write(n as u64 - 1, 16);
+        }
+        let crc = crc8(&w.bytes);
+        w.write(crc as u64, 8);
+        for ch in &self.block {
+            encode_subframe(&mut w, ch, self.bits_per_sample, &mut self.residual);
+        }
+        w.align();
+        let crc = crc16(&w.bytes);
+        w.write(crc as u64, 16);
+        self.out.write_all(&w.bytes)?;
+
+        let size = w.bytes.len() as u32;
+        self.min_frame = if self.frame_number == 0 { size } else { self.min_frame.min(size) };
+        self.max_frame = self.max_frame.max(size);
+        self.frame_number += 1;
+        self.total_samples += n as u64;
+        for ch in self.block.iter_mut() {
+            ch.clear();
+        }
+        Ok(())
+    }
+
+    // Write the last partial block and the final STREAMINFO
+    pub fn finalize(mut self) -> io::Result<W> {
+        self.encode_frame()?;
+        let info = self.streaminfo();
+        self.out.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.out.write_all(&info[4..])?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    const RATE: u32 = 48_000;
+    // Three full blocks and a partial one
+    const FRAMES: usize = 3 * BLOCK_SIZE + 1234;
+
+    // Stereo test signal: sine plus noise, a constant second block, and clipped peaks in the third
+    fn signal() -> Vec<f32> {
+        let mut seed = 0x1234_5678u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        let mut out = Vec::with_capacity(FRAMES * 2);
+        for i in 0..FRAMES {
+            let t = i as f32 / RATE as f32;
+            let frame = match i / BLOCK_SIZE {
+                1 => [0.25, -0.5],
+                2 if i % 500 == 0 => [1.5, -1.5],
+                _ => [
+                    0.5 * (std::f32::consts::TAU * 440.0 * t).sin() + 0.05 * noise(),
+                    0.3 * (std::f32::consts::TAU * 97.0 * t).sin() + 0.2 * noise(),
+                ],
+            };
+            out.extend(frame);
+        }
+        out
+    }
+
+    fn quantize(samples: &[f32], bits: u32) -> Vec<i32> {
+        let scale = ((1i64 << (bits - 1)) - 1) as f64;
+        samples.iter().map(|&s| (s.clamp(-1.0, 1.0) as f64 * scale).round() as i32).collect()
+    }
+
+    fn encode(samples: &[f32], bits: u32) -> Vec<u8> {
+        let mut writer = FlacWriter::new(Cursor::new(Vec::new()), 2, RATE, bits).unwrap();
+        // Uneven writes, so blocks fill across calls
+        for chunk in samples.chunks(1000 * 2) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finalize().unwrap().into_inner()
+    }
+
+    // Interleaved samples at the file's bit depth, plus the frame count STREAMINFO reports
+    fn decode(bytes: Vec<u8>, bits: u32) -> (Vec<i32>, Option<u64>) {
+        let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let mut format = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+            .unwrap()
+            .format;
+        let params = format.default_track().unwrap().codec_params.clone();
+        assert_eq!(params.sample_rate, Some(RATE));
+        assert_eq!(params.bits_per_sample, Some(bits));
+        assert_eq!(params.channels.map(|c| c.count()), Some(2));
+        let mut decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default()).unwrap();
+        let mut out = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("{e}"),
+            };
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buf = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            // The decoder scales to the full i32 range
+            out.extend(buf.samples().iter().map(|s| s >> (32 - bits)));
+        }
+        (out, params.n_frames)
+    }
+
+    #[test]
+    fn round_trips_bit_exact() {
+        let samples = signal();
+        for bits in [16, 24] {
+            let (decoded, frames) = decode(encode(&samples, bits), bits);
+            assert_eq!(frames, Some(FRAMES as u64), "{bits} bit");
+            let expected = quantize(&samples, bits);
+            assert_eq!(decoded.len(), expected.len(), "{bits} bit");
+            if let Some(i) = decoded.iter().zip(&expected).position(|(a, b)| a != b) {
+                panic!("{bits} bit: sample {i} is {} instead of {}", decoded[i], expected[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn constant_blocks_are_small() {
+        let bytes = encode(&vec![0.25; BLOCK_SIZE * 2], 24);
+        // Marker, STREAMINFO, and a frame of two constant subframes
+        assert!(bytes.len() < 4 + 38 + 32, "{} bytes", bytes.len());
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        assert!(FlacWriter::new(Cursor::new(Vec::new()), 2, RATE, 32).is_err());
+        assert!(FlacWriter::new(Cursor::new(Vec::new()), 0, RATE, 16).is_err());
+        assert!(FlacWriter::new(Cursor::new(Vec::new()), 2, 0, 16).is_err());
+    }
+}
@@ -0,0 +1,987 @@
+// Minimal read-only HDF5 reader, just enough for SOFA files (which are netCDF-4, i.e.
+// HDF5, containers): superblock v0-v3, v1/v2 object headers, symbol-table groups and
+// link-message groups including dense link storage (fractal heap + v2 B-tree leaf),
+// integer/float datasets in compact, contiguous or chunked layout with the deflate,
+// shuffle and fletcher32 filters, and fixed-length string attributes. Only members of
+// the root group are looked up, which is where SOFA keeps all its variables.
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, String>;
+
+const SIGNATURE: &[u8; 8] = b"\x89HDF\r\n\x1a\n";
+// "Undefined address" (all bits set)
+const UNDEFINED: u64 = u64::MAX;
+// Refuse datasets larger than this instead of trying to allocate them
+const MAX_DATASET_BYTES: usize = 512 << 20;
+
+#[derive(Clone)]
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    offset_size: usize,
+    length_size: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or("HDF5: unexpected end of data")?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn uint(&mut self, n: usize) -> Result<u64> {
+        if n > 8 {
+            return Err(format!("HDF5: {n}-byte integer field not supported"));
+        }
+        Ok(self.bytes(n)?.iter().rev().fold(0u64, |v, &b| (v << 8) | b as u64))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    fn offset(&mut self) -> Result<u64> {
+        let raw = self.bytes(self.offset_size)?;
+        if raw.iter().all(|&b| b == 0xff) {
+            return Ok(UNDEFINED);
+        }
+        Ok(raw.iter().rev().fold(0u64, |v, &b| (v << 8) | b as u64))
+    }
+
+    fn length(&mut self) -> Result<u64> {
+        self.uint(self.length_size)
+    }
+
+    fn expect(&mut self, signature: &[u8]) -> Result<()> {
+        if self.bytes(signature.len())? != signature {
+            return Err(format!("HDF5: expected {} block", String::from_utf8_lossy(signature)));
+        }
+        Ok(())
+    }
+
+    // Cursor over `n` bytes from here, with the same field sizes
+    fn sub(&mut self, n: usize) -> Result<Cursor<'a>> {
+        let data = self.bytes(n)?;
+        Ok(Cursor { data, pos: 0, offset_size: self.offset_size, length_size: self.length_size })
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Datatype {
+    Integer { size: usize, signed: bool, big_endian: bool },
+    Float { size: usize, big_endian: bool },
+    String { size: usize },
+    Other { size: usize },
+}
+
+impl Datatype {
+    fn parse(c: &mut Cursor) -> Result<Datatype> {
+        let class = c.u8()? & 0x0f;
+        let bits = c.u8()?;
+        c.skip(2)?;
+        let size = c.u32()? as usize;
+        let big_endian = bits & 0x01 != 0;
+        Ok(match class {
+            0 => Datatype::Integer { size, signed: bits & 0x08 != 0, big_endian },
+            1 => Datatype::Float { size, big_endian },
+            3 => Datatype::String { size },
+            _ => Datatype::Other { size },
+        })
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Datatype::Integer { size, .. }
+            | Datatype::Float { size, .. }
+            | Datatype::String { size }
+            | Datatype::Other { size } => size,
+        }
+    }
+
+    fn decode(&self, raw: &[u8]) -> Result<Vec<f64>> {
+        let size = self.size();
+        if size == 0 {
+            return Err("HDF5: zero-sized datatype".into());
+        }
+        let mut out = Vec::with_capacity(raw.len() / size);
+        for element in raw.chunks_exact(size) {
+            let mut b = [0u8; 8];
+            if size > 8 {
+                return Err(format!("HDF5: {size}-byte numbers not supported"));
+            }
+            b[..size].copy_from_slice(element);
+            let big_endian = matches!(
+                *self,
+                Datatype::Integer { big_endian: true, .. } | Datatype::Float { big_endian: true, .. }
+            );
+            if big_endian {
+                b[..size].reverse();
+            }
+            let value = match (*self, size) {
+                (Datatype::Float { .. }, 4) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                (Datatype::Float { .. }, 8) => f64::from_le_bytes(b),
+                (Datatype::Integer { signed: false, .. }, _) => u64::from_le_bytes(b) as f64,
+                (Datatype::Integer { signed: true, .. }, _) => {
+                    // Sign-extend from `size` bytes
+                    let shift = 64 - 8 * size as u32;
+                    ((u64::from_le_bytes(b) << shift) as i64 >> shift) as f64
+                }
+                _ => return Err("HDF5: dataset is not numeric".into()),
+            };
+            out.push(value);
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Layout {
+    Compact(Vec<u8>),
+    Contiguous { address: u64 },
+    // Chunk extent in elements per dimension
+    Chunked { btree: u64, chunk: Vec<u64> },
+    SingleChunk { address: u64, size: u64, mask: u32, chunk: Vec<u64> },
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    id: u16,
+    values: Vec<u32>,
+}
+
+// The messages of an object header we care about
+#[derive(Default)]
+struct Object {
+    dims: Option<Vec<u64>>,
+    datatype: Option<Datatype>,
+    layout: Option<Layout>,
+    filters: Vec<Filter>,
+    // (name, type, raw value)
+    attributes: Vec<(String, Datatype, Vec<u8>)>,
+    links: Vec<(String, u64)>,
+    // v1 B-tree and local heap of an old-style group
+    symbol_table: Option<(u64, u64)>,
+    // Fractal heap and name index of a new-style group with dense link storage
+    link_info: Option<(u64, u64)>,
+}
+
+fn parse_dataspace(c: &mut Cursor) -> Result<Vec<u64>> {
+    let version = c.u8()?;
+    let rank = c.u8()? as usize;
+    let flags = c.u8()?;
+    if version == 1 {
+        c.skip(5)?;
+    } else if c.u8()? == 2 {
+        // Null dataspace
+        return Ok(vec![0]);
+    }
+    let dims = (0..rank).map(|_| c.length()).collect::<Result<Vec<_>>>()?;
+    if flags & 0x01 != 0 {
+        c.skip(rank * c.length_size)?;
+    }
+    Ok(dims)
+}
+
+fn parse_layout(c: &mut Cursor) -> Result<Layout> {
+    let version = c.u8()?;
+    if version < 3 {
+        return Err(format!("HDF5: data layout version {version} not supported"));
+    }
+    match c.u8()? {
+        0 => {
+            let size = c.u16()? as usize;
+            Ok(Layout::Compact(c.bytes(size)?.to_vec()))
+        }
+        1 => Ok(Layout::Contiguous { address: c.offset()? }),
+        2 if version == 3 => {
+            let rank = c.u8()? as usize;
+            let btree = c.offset()?;
+            let mut chunk = (0..rank).map(|_| c.u32().map(u64::from)).collect::<Result<Vec<_>>>()?;
+            // The last "dimension" is the element size
+            chunk.pop();
+            Ok(Layout::Chunked { btree, chunk })
+        }
+        2 => {
+            let flags = c.u8()?;
+            let rank = c.u8()? as usize;
+            let dim_bytes = c.u8()? as usize;
+            let mut chunk = (0..rank).map(|_| c.uint(dim_bytes)).collect::<Result<Vec<_>>>()?;
+            chunk.pop();
+            match c.u8()? {
+                // Single chunk index: the whole dataset is one chunk
+                1 => {
+                    let (size, mask) = if flags & 0x02 != 0 { (c.length()?, c.u32()?) } else { (0, 0) };
+                    Ok(Layout::SingleChunk { address: c.offset()?, size, mask, chunk })
+                }
+                index => Err(format!("HDF5: chunk index type {index} not supported")),
+            }
+        }
+        class => Err(format!("HDF5: layout class {class} not supported")),
+    }
+}
+
+fn parse_filters(c: &mut Cursor) -> Result<Vec<Filter>> {
+    let version = c.u8()?;
+    let count = c.u8()?;
+    if version == 1 {
+        c.skip(6)?;
+    }
+    let mut filters = Vec::new();
+    for _ in 0..count {
+        let id = c.u16()?;
+        let name_len = if version == 1 || id >= 256 { c.u16()? as usize } else { 0 };
+        c.skip(2)?;
+        let n_values = c.u16()? as usize;
+        c.skip(if version == 1 { name_len.div_ceil(8) * 8 } else { name_len })?;
+        let values = (0..n_values).map(|_| c.u32()).collect::<Result<Vec<_>>>()?;
+        if version == 1 && n_values % 2 == 1 {
+            c.skip(4)?;
+        }
+        filters.push(Filter { id, values });
+    }
+    Ok(filters)
+}
+
+fn parse_attribute(c: &mut Cursor) -> Result<(String, Datatype, Vec<u8>)> {
+    let version = c.u8()?;
+    c.skip(1)?;
+    let name_size = c.u16()? as usize;
+    let type_size = c.u16()? as usize;
+    let space_size = c.u16()? as usize;
+    if version >= 3 {
+        c.skip(1)?;
+    }
+    // Version 1 pads every field to 8 bytes
+    let padded = |n: usize| if version == 1 { n.div_ceil(8) * 8 } else { n };
+    let name = c.bytes(padded(name_size))?;
+    let name = String::from_utf8_lossy(&name[..name_size.min(name.len())]).trim_end_matches('\0').to_string();
+    let datatype = Datatype::parse(&mut c.sub(padded(type_size))?)?;
+    let dims = parse_dataspace(&mut c.sub(padded(space_size))?)?;
+    let count = dims.iter().fold(1u64, |n, &d| n.saturating_mul(d)) as usize;
+    let len = count.saturating_mul(datatype.size()).min(c.remaining());
+    Ok((name, datatype, c.bytes(len)?.to_vec()))
+}
+
+// Hard links only; soft and external links are ignored
+fn parse_link(c: &mut Cursor) -> Result<Option<(String, u64)>> {
+    c.skip(1)?;
+    let flags = c.u8()?;
+    let link_type = if flags & 0x08 != 0 { c.u8()? } else { 0 };
+    if flags & 0x04 != 0 {
+        c.skip(8)?;
+    }
+    if flags & 0x10 != 0 {
+        c.skip(1)?;
+    }
+    let name_len = c.uint(1 << (flags & 0x03))? as usize;
+    let name = String::from_utf8_lossy(c.bytes(name_len)?).into_owned();
+    if link_type != 0 {
+        return Ok(None);
+    }
+    Ok(Some((name, c.offset()?)))
+}
+
+fn unshuffle(data: &[u8], element_size: usize) -> Vec<u8> {
+    if element_size <= 1 {
+        return data.to_vec();
+    }
+    let n = data.len() / element_size;
+    let mut out = data.to_vec();
+    for i in 0..n {
+        for b in 0..element_size {
+            out[i * element_size + b] = data[b * n + i];
+        }
+    }
+    out
+}
+
+// Managed objects of a fractal heap, located through its direct blocks
+struct FractalHeap {
+    offset_bytes: usize,
+    length_bytes: usize,
+    // (heap offset, file position, size) of every direct block
+    blocks: Vec<(u64, usize, u64)>,
+}
+
+// Doubling table of a fractal heap
+struct HeapTable {
+    width: u64,
+    start_size: u64,
+    max_direct: u64,
+    offset_bytes: usize,
+}
+
+pub struct File {
+    data: Vec<u8>,
+    offset_size: usize,
+    length_size: usize,
+    base: u64,
+    root: u64,
+}
+
+impl File {
+    pub fn open(path: &Path) -> Result<File> {
+        let data = std::fs::read(path).map_err(|e| format!("Reading {} failed: {e}", path.display()))?;
+        File::parse(data)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<File> {
+        // The superblock sits at 0 or after a user block of 512, 1024, 2048, ... bytes
+        let mut start = 0usize;
+        while data.get(start..start + 8) != Some(&SIGNATURE[..]) {
+            start = if start == 0 { 512 } else { start * 2 };
+            if start + 8 > data.len() {
+                return Err("Not an HDF5 file".into());
+            }
+        }
+        let mut c = Cursor { data: &data, pos: start + 8, offset_size: 8, length_size: 8 };
+        let version = c.u8()?;
+        let (offset_size, length_size);
+        match version {
+            0 | 1 => {
+                // Free-space, root group and shared header versions, reserved
+                c.skip(4)?;
+                offset_size = c.u8()? as usize;
+                length_size = c.u8()? as usize;
+                // Reserved, group K values, consistency flags (+ indexed storage K)
+                c.skip(if version == 1 { 13 } else { 9 })?;
+            }
+            2 | 3 => {
+                offset_size = c.u8()? as usize;
+                length_size = c.u8()? as usize;
+                c.skip(1)?;
+            }
+            v => return Err(format!("HDF5: superblock version {v} not supported")),
+        }
+        if ![2, 4, 8].contains(&offset_size) || ![2, 4, 8].contains(&length_size) {
+            return Err("HDF5: invalid offset/length sizes".into());
+        }
+        c.offset_size = offset_size;
+        c.length_size = length_size;
+        let base = c.offset()?;
+        let root = if version < 2 {
+            // Free-space, end-of-file and driver addresses, then the root group's
+            // symbol table entry (link name offset, object header address)
+            for _ in 0..4 {
+                c.offset()?;
+            }
+            c.offset()?
+        } else {
+            // Superblock extension and end-of-file addresses
+            c.offset()?;
+            c.offset()?;
+            c.offset()?
+        };
+        Ok(File { data, offset_size, length_size, base, root })
+    }
+
+    fn at(&self, address: u64) -> Result<Cursor<'_>> {
+        let pos = address
+            .checked_add(self.base)
+            .filter(|&p| address != UNDEFINED && p < self.data.len() as u64)
+            .ok_or("HDF5: address out of range")?;
+        Ok(Cursor { data: &self.data, pos: pos as usize, offset_size: self.offset_size, length_size: self.length_size })
+    }
+
+    fn object(&self, address: u64) -> Result<Object> {
+        let mut obj = Object::default();
+        let mut c = self.at(address)?;
+        // Message blocks still to parse: (file position, length)
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        let v2 = c.data.get(c.pos..c.pos + 4) == Some(&b"OHDR"[..]);
+        let mut creation_order = false;
+        if v2 {
+            c.skip(5)?;
+            let flags = c.u8()?;
+            if flags & 0x20 != 0 {
+                c.skip(16)?;
+            }
+            if flags & 0x10 != 0 {
+                c.skip(4)?;
+            }
+            creation_order = flags & 0x04 != 0;
+            let size = c.uint(1 << (flags & 0x03))? as usize;
+            blocks.push((c.pos, size));
+        } else {
+            if c.u8()? != 1 {
+                return Err("HDF5: unknown object header version".into());
+            }
+            c.skip(7)?;
+            let size = c.u32()? as usize;
+            c.skip(4)?;
+            blocks.push((c.pos, size));
+        }
+
+        let mut visited = 0;
+        while let Some((pos, size)) = blocks.pop() {
+            visited += 1;
+            if visited > 1000 {
+                return Err("HDF5: object header continuation loop".into());
+            }
+            let mut block = Cursor { data: &self.data, pos, offset_size: self.offset_size, length_size: self.length_size }
+                .sub(size)?;
+            let header_len = if v2 { 4 + if creation_order { 2 } else { 0 } } else { 8 };
+            while block.remaining() >= header_len {
+                let (kind, len, flags) = if v2 {
+                    let kind = block.u8()? as u16;
+                    let len = block.u16()? as usize;
+                    let flags = block.u8()?;
+                    block.skip(header_len - 4)?;
+                    (kind, len, flags)
+                } else {
+                    let kind = block.u16()?;
+                    let len = block.u16()? as usize;
+                    let flags = block.u8()?;
+                    block.skip(3)?;
+                    (kind, len, flags)
+                };
+                let mut m = block.sub(len)?;
+                // Shared (committed) messages only hold a reference; SOFA writers don't
+                // use them for anything we read
+                if flags & 0x02 != 0 {
+                    continue;
+                }
+                match kind {
+                    0x01 => obj.dims = Some(parse_dataspace(&mut m)?),
+                    0x02 => {
+                        m.skip(1)?;
+                        let flags = m.u8()?;
+                        if flags & 0x01 != 0 {
+                            m.skip(8)?;
+                        }
+                        let heap = m.offset()?;
+                        obj.link_info = Some((heap, m.offset()?));
+                    }
+                    0x03 => obj.datatype = Some(Datatype::parse(&mut m)?),
+                    0x06 => obj.links.extend(parse_link(&mut m)?),
+                    0x08 => obj.layout = Some(parse_layout(&mut m)?),
+                    0x0B => obj.filters = parse_filters(&mut m)?,
+                    0x0C => obj.attributes.push(parse_attribute(&mut m)?),
+                    0x10 => {
+                        let address = m.offset()?;
+                        let len = m.length()? as usize;
+                        let mut cont = self.at(address)?;
+                        if v2 {
+                            // "OCHK" signature ... checksum
+                            cont.expect(b"OCHK")?;
+                            blocks.push((cont.pos, len.saturating_sub(8)));
+                        } else {
+                            blocks.push((cont.pos, len));
+                        }
+                    }
+                    0x11 => {
+                        let btree = m.offset()?;
+                        obj.symbol_table = Some((btree, m.offset()?));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(obj)
+    }
+
+    fn c_string(&self, address: u64) -> Result<String> {
+        let c = self.at(address)?;
+        let rest = &c.data[c.pos..];
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    // Old-style group: v1 B-tree of symbol table nodes, names in a local heap
+    fn symbol_table_links(&self, btree: u64, heap: u64, out: &mut Vec<(String, u64)>) -> Result<()> {
+        let mut h = self.at(heap)?;
+        h.expect(b"HEAP")?;
+        h.skip(4)?;
+        h.length()?;
+        h.length()?;
+        let names = h.offset()?;
+        self.group_node(btree, names, out, 0)
+    }
+
+    fn group_node(&self, address: u64, names: u64, out: &mut Vec<(String, u64)>, depth: usize) -> Result<()> {
+        if depth > 32 {
+            return Err("HDF5: group B-tree too deep".into());
+        }
+        let mut c = self.at(address)?;
+        c.expect(b"TREE")?;
+        c.skip(1)?;
+        let level = c.u8()?;
+        let entries = c.u16()?;
+        c.offset()?;
+        c.offset()?;
+        for _ in 0..entries {
+            c.length()?;
+            let child = c.offset()?;
+            if level > 0 {
+                self.group_node(child, names, out, depth + 1)?;
+                continue;
+            }
+            let mut s = self.at(child)?;
+            s.expect(b"SNOD")?;
+            s.skip(2)?;
+            for _ in 0..s.u16()? {
+                let name = s.offset()?;
+                let header = s.offset()?;
+                s.skip(24)?;
+                out.push((self.c_string(names.saturating_add(name))?, header));
+            }
+        }
+        Ok(())
+    }
+
+    fn fractal_heap(&self, address: u64) -> Result<FractalHeap> {
+        let mut c = self.at(address)?;
+        c.expect(b"FRHP")?;
+        c.skip(1)?;
+        c.u16()?;
+        let filters_len = c.u16()?;
+        c.skip(1)?;
+        let max_managed = c.u32()? as u64;
+        // Huge object bookkeeping, free space and object counters
+        c.length()?;
+        c.offset()?;
+        c.length()?;
+        c.offset()?;
+        for _ in 0..8 {
+            c.length()?;
+        }
+        let width = c.u16()? as u64;
+        let start_size = c.length()?;
+        let max_direct = c.length()?;
+        let max_heap_bits = c.u16()? as usize;
+        c.u16()?;
+        let root = c.offset()?;
+        let rows = c.u16()? as u64;
+        if filters_len != 0 {
+            return Err("HDF5: filtered fractal heaps not supported".into());
+        }
+        // Same field sizes as the HDF5 library derives them
+        let mut heap = FractalHeap {
+            offset_bytes: max_heap_bits.div_ceil(8),
+            length_bytes: (max_direct.max(1).ilog2() as usize)
+                .div_ceil(8)
+                .min(max_managed.max(1).ilog2() as usize / 8 + 1),
+            blocks: Vec::new(),
+        };
+        if root == UNDEFINED {
+            return Ok(heap);
+        }
+        let mut direct = Vec::new();
+        if rows == 0 {
+            direct.push((root, start_size));
+        } else {
+            let table = HeapTable { width, start_size, max_direct, offset_bytes: heap.offset_bytes };
+            self.indirect_block(&table, root, rows, &mut direct, 0)?;
+        }
+        // Every direct block stores its own heap offset
+        for (addr, size) in direct {
+            let mut d = self.at(addr)?;
+            d.expect(b"FHDB")?;
+            d.skip(1)?;
+            d.offset()?;
+            let heap_offset = d.uint(heap.offset_bytes)?;
+            heap.blocks.push((heap_offset, self.at(addr)?.pos, size));
+        }
+        Ok(heap)
+    }
+
+    // Collect (address, size) of the direct blocks below an indirect block
+    fn indirect_block(
+        &self,
+        table: &HeapTable,
+        address: u64,
+        rows: u64,
+        out: &mut Vec<(u64, u64)>,
+        depth: usize,
+    ) -> Result<()> {
+        if depth > 16 || rows > 48 || table.width == 0 || table.start_size == 0 {
+            return Err("HDF5: malformed fractal heap".into());
+        }
+        let mut c = self.at(address)?;
+        c.expect(b"FHIB")?;
+        c.skip(1)?;
+        c.offset()?;
+        c.skip(table.offset_bytes)?;
+        for row in 0..rows {
+            // Rows 0 and 1 hold blocks of the starting size, then sizes double
+            let size = if row < 2 { table.start_size } else { table.start_size << (row - 1) };
+            for _ in 0..table.width {
+                let child = c.offset()?;
+                if child == UNDEFINED {
+                    continue;
+                }
+                if size <= table.max_direct {
+                    out.push((child, size));
+                } else {
+                    // Enough rows for blocks up to `size`; a bogus table can't get there
+                    let child_rows = table
+                        .start_size
+                        .checked_mul(table.width)
+                        .and_then(|row| size.checked_ilog2()?.checked_sub(row.checked_ilog2()?))
+                        .ok_or("HDF5: malformed fractal heap")?;
+                    self.indirect_block(table, child, child_rows as u64 + 1, out, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn heap_object(&self, heap: &FractalHeap, id: &[u8]) -> Result<Vec<u8>> {
+        let first = *id.first().ok_or("HDF5: empty heap ID")?;
+        match (first >> 4) & 0x03 {
+            0 => {
+                let mut c = Cursor { data: &id[1..], pos: 0, offset_size: 8, length_size: 8 };
+                let offset = c.uint(heap.offset_bytes)?;
+                let len = c.uint(heap.length_bytes)? as usize;
+                let &(block_offset, pos, _) = heap
+                    .blocks
+                    .iter()
+                    .find(|(start, _, size)| offset >= *start && offset - start < *size)
+                    .ok_or("HDF5: heap object outside of the heap")?;
+                let start = pos.saturating_add((offset - block_offset) as usize);
+                self.data
+                    .get(start..start.saturating_add(len))
+                    .map(|b| b.to_vec())
+                    .ok_or_else(|| "HDF5: heap object out of range".into())
+            }
+            // Tiny object stored in the ID itself
+            2 => {
+                let len = (first & 0x0f) as usize + 1;
+                id.get(1..1 + len).map(|b| b.to_vec()).ok_or_else(|| "HDF5: malformed tiny heap object".into())
+            }
+            _ => Err("HDF5: huge heap objects not supported".into()),
+        }
+    }
+
+    // New-style group with dense link storage: links are heap objects, listed by the
+    // name index (v2 B-tree). Only single-leaf indexes are supported, which covers
+    // groups with up to a few dozen members.
+    fn dense_links(&self, heap: u64, name_index: u64, out: &mut Vec<(String, u64)>) -> Result<()> {
+        if heap == UNDEFINED || name_index == UNDEFINED {
+            return Ok(());
+        }
+        let heap = self.fractal_heap(heap)?;
+        let mut c = self.at(name_index)?;
+        c.expect(b"BTHD")?;
+        c.skip(2)?;
+        c.u32()?;
+        let record_size = c.u16()? as usize;
+        let depth = c.u16()?;
+        c.skip(2)?;
+        let root = c.offset()?;
+        let records = c.u16()?;
+        if root == UNDEFINED {
+            return Ok(());
+        }
+        if depth != 0 {
+            return Err("HDF5: multi-level link index not supported".into());
+        }
+        // Records are a 4-byte hash plus a heap ID
+        if record_size <= 4 {
+            return Err("HDF5: malformed link index".into());
+        }
+        let mut leaf = self.at(root)?;
+        leaf.expect(b"BTLF")?;
+        leaf.skip(2)?;
+        for _ in 0..records {
+            // Name hash, then the heap ID of the link message
+            let record = leaf.bytes(record_size)?;
+            let message = self.heap_object(&heap, &record[4..])?;
+            let mut m = Cursor { data: &message, pos: 0, offset_size: self.offset_size, length_size: self.length_size };
+            out.extend(parse_link(&mut m)?);
+        }
+        Ok(())
+    }
+
+    fn root_member(&self, name: &str) -> Result<Object> {
+        let root = self.object(self.root)?;
+        let mut links = root.links.clone();
+        if let Some((btree, heap)) = root.symbol_table {
+            self.symbol_table_links(btree, heap, &mut links)?;
+        }
+        if let Some((heap, name_index)) = root.link_info {
+            self.dense_links(heap, name_index, &mut links)?;
+        }
+        let (_, address) = links.iter().find(|(n, _)| n == name).ok_or_else(|| format!("'{}' not found", name))?;
+        self.object(*address)
+    }
+
+    // Read a numeric dataset of the root group as f64, row-major
+    pub fn dataset(&self, name: &str) -> Result<Dataset> {
+        let obj = self.root_member(name)?;
+        let dims = obj.dims.clone().ok_or_else(|| format!("'{}' has no dataspace", name))?;
+        let datatype = obj.datatype.ok_or_else(|| format!("'{}' has no datatype", name))?;
+        let count = dims.iter().try_fold(1u64, |n, &d| n.checked_mul(d)).ok_or("HDF5: dataset too large")? as usize;
+        let bytes = count.checked_mul(datatype.size()).filter(|&b| b <= MAX_DATASET_BYTES).ok_or("HDF5: dataset too large")?;
+
+        let raw = match obj.layout.as_ref().ok_or_else(|| format!("'{}' has no data layout", name))? {
+            Layout::Compact(data) => data.clone(),
+            Layout::Contiguous { address } if *address == UNDEFINED => vec![0; bytes],
+            Layout::Contiguous { address } => {
+                let pos = self.at(*address)?.pos;
+                self.data.get(pos..pos + bytes).ok_or("HDF5: dataset out of range")?.to_vec()
+            }
+            Layout::Chunked { btree, chunk } => {
+                let mut out = vec![0; bytes];
+                if *btree != UNDEFINED {
+                    let mut chunks = Vec::new();
+                    self.chunk_node(*btree, dims.len(), &mut chunks, 0)?;
+                    for (offsets, size, mask, address) in chunks {
+                        let pos = self.at(address)?.pos;
+                        let stored = self.data.get(pos..pos.saturating_add(size)).ok_or("HDF5: chunk out of range")?;
+                        let data = unfilter(&obj.filters, mask, stored.to_vec(), datatype.size())?;
+                        copy_chunk(&data, &offsets, chunk, &dims, datatype.size(), &mut out);
+                    }
+                }
+                out
+            }
+            Layout::SingleChunk { address, size, mask, chunk } => {
+                let pos = self.at(*address)?.pos;
+                let len = if obj.filters.is_empty() { bytes } else { *size as usize };
+                let stored = self.data.get(pos..pos.saturating_add(len)).ok_or("HDF5: chunk out of range")?;
+                let data = unfilter(&obj.filters, *mask, stored.to_vec(), datatype.size())?;
+                let mut out = vec![0; bytes];
+                copy_chunk(&data, &vec![0; dims.len()], chunk, &dims, datatype.size(), &mut out);
+                out
+            }
+        };
+        if raw.len() < bytes {
+            return Err(format!("'{}': short data", name));
+        }
+        Ok(Dataset { values: datatype.decode(&raw[..bytes])?, dims })
+    }
+
+    // Fixed-length string attribute of a root group member, if present
+    pub fn attribute_string(&self, object: &str, attribute: &str) -> Result<Option<String>> {
+        let obj = self.root_member(object)?;
+        Ok(obj.attributes.iter().find(|(n, _, _)| n == attribute).and_then(|(_, datatype, raw)| match datatype {
+            Datatype::String { .. } => {
+                let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+                Some(String::from_utf8_lossy(&raw[..end]).trim().to_string())
+            }
+            _ => None,
+        }))
+    }
+
+    // Chunk B-tree (v1, type 1): (chunk offsets in elements, stored size, filter mask, address)
+    fn chunk_node(&self, address: u64, rank: usize, out: &mut Vec<(Vec<u64>, usize, u32, u64)>, depth: usize) -> Result<()> {
+        if depth > 32 {
+            return Err("HDF5: chunk B-tree too deep".into());
+        }
+        let mut c = self.at(address)?;
+        c.expect(b"TREE")?;
+        if c.u8()? != 1 {
+            return Err("HDF5: expected a chunk B-tree".into());
+        }
+        let level = c.u8()?;
+        let entries = c.u16()?;
+        c.offset()?;
+        c.offset()?;
+        for _ in 0..entries {
+            let size = c.u32()? as usize;
+            let mask = c.u32()?;
+            let offsets = (0..=rank).map(|_| c.uint(8)).collect::<Result<Vec<_>>>()?;
+            let child = c.offset()?;
+            if level > 0 {
+                self.chunk_node(child, rank, out, depth + 1)?;
+            } else {
+                out.push((offsets[..rank].to_vec(), size, mask, child));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Undo the filter pipeline (in reverse order), skipping filters set in `mask`
+fn unfilter(filters: &[Filter], mask: u32, mut data: Vec<u8>, element_size: usize) -> Result<Vec<u8>> {
+    for (i, filter) in filters.iter().enumerate().rev() {
+        if mask.checked_shr(i as u32).is_some_and(|m| m & 1 != 0) {
+            continue;
+        }
+        data = match filter.id {
+            1 => miniz_oxide::inflate::decompress_to_vec_zlib(&data)
+                .map_err(|e| format!("HDF5: inflate failed ({:?})", e.status))?,
+            2 => unshuffle(&data, filter.values.first().map(|&v| v as usize).unwrap_or(element_size)),
+            3 => {
+                data.truncate(data.len().saturating_sub(4));
+                data
+            }
+            id => return Err(format!("HDF5: filter {id} not supported")),
+        };
+    }
+    Ok(data)
+}
+
+// Copy a decoded chunk into the full row-major array, clipping at the dataset edges
+fn copy_chunk(data: &[u8], offsets: &[u64], chunk: &[u64], dims: &[u64], element_size: usize, out: &mut [u8]) {
+    let rank = dims.len();
+    if chunk.len() != rank || offsets.len() != rank {
+        return;
+    }
+    // Elements of the chunk, but never more than the decoded data holds
+    let count = chunk.iter().try_fold(1u64, |n, &c| n.checked_mul(c)).unwrap_or(u64::MAX);
+    let count = count.min((data.len() / element_size.max(1)) as u64);
+    let mut index = vec![0u64; rank];
+    for n in 0..count as usize {
+        // Chunk-local multi-index of element n
+        let mut rest = n as u64;
+        for d in (0..rank).rev() {
+            index[d] = rest % chunk[d];
+            rest /= chunk[d];
+        }
+        // Position in the dataset, if the element lies inside it
+        let linear = (0..rank).try_fold(0u64, |linear, d| {
+            let g = offsets[d].checked_add(index[d]).filter(|&g| g < dims[d])?;
+            Some(linear * dims[d] + g)
+        });
+        let Some(linear) = linear else { continue };
+        let src = n * element_size;
+        let dst = linear as usize * element_size;
+        if dst + element_size <= out.len() {
+            out[dst..dst + element_size].copy_from_slice(&data[src..src + element_size]);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    pub dims: Vec<u64>,
+    pub values: Vec<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written by tests/fixtures/make_sofa.py: the same SOFA content with two HDF5 layouts
+    const V0: &[u8] = include_bytes!("../../tests/fixtures/tiny-v0.sofa");
+    const V2: &[u8] = include_bytes!("../../tests/fixtures/tiny-v2.sofa");
+
+    fn expected_ir() -> Vec<f64> {
+        (0..4).flat_map(|m| (0..2).flat_map(move |r| (0..8).map(move |n| (m * 100 + r * 10 + n) as f64 * 0.001))).collect()
+    }
+
+    // Read everything a SOFA load reads. Damaged dimensions can describe a valid but huge,
+    // mostly unwritten dataset; reading those would only time the allocator.
+    fn read_all(file: &File) -> Result<()> {
+        for name in ["Data.IR", "Data.SamplingRate", "SourcePosition"] {
+            let dims = file.root_member(name)?.dims.unwrap_or_default();
+            if dims.iter().try_fold(1u64, |n, &d| n.checked_mul(d)).is_some_and(|n| n <= 1 << 16) {
+                file.dataset(name)?;
+            }
+        }
+        file.attribute_string("SourcePosition", "Type")?;
+        Ok(())
+    }
+
+    // File with the bytes at address 0 and no superblock, for calling the readers directly
+    fn raw(data: Vec<u8>) -> File {
+        File { data, offset_size: 8, length_size: 8, base: 0, root: 0 }
+    }
+
+    #[test]
+    fn reads_superblock_0_with_chunked_filtered_data() {
+        let file = File::parse(V0.to_vec()).unwrap();
+        let ir = file.dataset("Data.IR").unwrap();
+        assert_eq!(ir.dims, vec![4, 2, 8]);
+        // Stored as float32
+        for (got, want) in ir.values.iter().zip(expected_ir()) {
+            assert!((got - want).abs() < 1e-7, "{got} != {want}");
+        }
+        assert_eq!(file.dataset("Data.SamplingRate").unwrap().values, vec![44100.0]);
+        let pos = file.dataset("SourcePosition").unwrap();
+        assert_eq!(pos.dims, vec![4, 3]);
+        assert_eq!(pos.values[..6], [0.0, 0.0, 1.2, 90.0, 0.0, 1.2]);
+        assert_eq!(file.attribute_string("SourcePosition", "Type").unwrap().as_deref(), Some("spherical"));
+        assert_eq!(file.attribute_string("SourcePosition", "Units").unwrap(), None);
+    }
+
+    #[test]
+    fn reads_superblock_2_with_dense_links() {
+        let file = File::parse(V2.to_vec()).unwrap();
+        let ir = file.dataset("Data.IR").unwrap();
+        assert_eq!(ir.dims, vec![4, 2, 8]);
+        assert_eq!(ir.values, expected_ir());
+        // Compact layout
+        assert_eq!(file.dataset("Data.SamplingRate").unwrap().values, vec![48000.0]);
+        let pos = file.dataset("SourcePosition").unwrap();
+        assert!((pos.values[3] - 0.0).abs() < 1e-12 && (pos.values[4] - 1.0).abs() < 1e-12);
+        assert_eq!(file.attribute_string("SourcePosition", "Type").unwrap().as_deref(), Some("cartesian"));
+    }
+
+    #[test]
+    fn rejects_missing_members_and_other_files() {
+        let file = File::parse(V2.to_vec()).unwrap();
+        assert!(file.dataset("Data.Delay").unwrap_err().contains("not found"));
+        assert!(File::parse(b"RIFF\0\0\0\0WAVE".to_vec()).is_err());
+        assert!(File::parse(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn damaged_files_fail_without_panicking() {
+        for original in [V0, V2] {
+            for len in 0..original.len() {
+                if let Ok(file) = File::parse(original[..len].to_vec()) {
+                    let _ = read_all(&file);
+                }
+            }
+            for i in 0..original.len() {
+                for flip in [0x01, 0x80, 0xff] {
+                    let mut data = original.to_vec();
+                    data[i] ^= flip;
+                    if let Ok(file) = File::parse(data) {
+                        let _ = read_all(&file);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn short_link_index_records_are_an_error() {
+        // BTHD: signature, version, type, node size, then the record size
+        let mut data = V2.to_vec();
+        let at = data.windows(4).position(|w| w == b"BTHD").unwrap() + 10;
+        data[at..at + 2].copy_from_slice(&2u16.to_le_bytes());
+        let file = File::parse(data).unwrap();
+        assert_eq!(file.dataset("Data.IR").unwrap_err(), "HDF5: malformed link index");
+    }
+
+    #[test]
+    fn bad_doubling_tables_are_an_error() {
+        // Indirect block at 0 with one child, for a heap with 4-byte block offsets
+        let mut block = b"FHIB\0".to_vec();
+        block.extend([0; 8 + 4]);
+        block.extend(0u64.to_le_bytes());
+        let file = raw(block);
+        let mut out = Vec::new();
+        // Direct blocks can't be smaller than the starting size
+        let table = HeapTable { width: 4, start_size: 512, max_direct: 256, offset_bytes: 4 };
+        assert!(file.indirect_block(&table, 0, 1, &mut out, 0).is_err());
+        // Row size overflows
+        let table = HeapTable { width: 2, start_size: 1 << 63, max_direct: 1, offset_bytes: 4 };
+        assert!(file.indirect_block(&table, 0, 1, &mut out, 0).is_err());
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+// Output latency measurement: plays a train of clicks on an output device and listens
+// for them on a capture device (a microphone in front of the speaker, a loopback cable,
+// or loopback capture of the output itself). The time from writing a click to hearing it
+// is the round trip. The capture side is the same for every output measured, so the
+// differences between outputs are what their delays have to make up.
+use rtrb::RingBuffer;
+use serde::Serialize;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+
+use super::live::{find_device, resolve_output, stream_config};
+use super::routing::MAX_DELAY_MS;
+use super::OutputKey;
+use crate::backend::DeviceKind;
+
+// Silence before the first click, to measure the noise floor
+const LEAD_SECONDS: f64 = 0.5;
+const CLICKS: usize = 5;
+// Also the longest round trip that can be measured
+const CLICK_INTERVAL_SECONDS: f64 = 0.6;
+// Hann-windowed tone burst; survives Bluetooth codecs better than a single-sample impulse
+const CLICK_SECONDS: f64 = 0.005;
+const CLICK_HZ: f64 = 2000.0;
+const CLICK_LEVEL: f32 = 0.5;
+// A click must peak this far above the noise floor (and above -40 dBFS) to count
+const MIN_SNR: f32 = 4.0;
+const MIN_PEAK: f32 = 0.01;
+// Rate asked of both devices; others run at their own default rate
+const PREFERRED_RATE: u32 = 48_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyMeasurement {
+    pub output: OutputKey,
+    // Median over the clicks heard
+    pub round_trip_ms: f32,
+    pub clicks_heard: usize,
+    // Delay that lines this output up with the slowest one measured with it
+    pub delay_ms: f32,
+}
+
+fn click(sample_rate: f64) -> Vec<f32> {
+    let n = (CLICK_SECONDS * sample_rate).round().max(1.0) as usize;
+    (0..n)
+        .map(|i| {
+            let t = i as f64 / sample_rate;
+            let window = (std::f64::consts::PI * i as f64 / n as f64).sin().powi(2);
+            (CLICK_LEVEL as f64 * window * (std::f64::consts::TAU * CLICK_HZ * t).sin()) as f32
+        })
+        .collect()
+}
+
+// Time from `a` to `b` in seconds, negative if `b` is earlier
+fn seconds_between(a: Instant, b: Instant) -> f64 {
+    if b >= a {
+        (b - a).as_secs_f64()
+    } else {
+        -(a - b).as_secs_f64()
+    }
+}
+
+// Play the clicks on `output` and time them as heard on `input`. Takes a few seconds;
+// the running engine can stay up, but whatever it plays makes the clicks harder to hear.
+pub fn measure(output: &OutputKey, input: &str) -> Result<LatencyMeasurement, String> {
+    let host = cpal::default_host();
+    let out_device = resolve_output(&host, output)?;
+    // Real capture devices first, then output devices for loopback capture
+    let (in_device, loopback) = match find_device(&host, input, DeviceKind::Input) {
+        Some(d) => (d, false),
+        None => (find_device(&host, input, DeviceKind::Output).ok_or_else(|| format!("Input {input} not found"))?, true),
+    };
+    let out_cfg = stream_config(&out_device, PREFERRED_RATE, true)?;
+    let in_cfg = stream_config(&in_device, PREFERRED_RATE, loopback)?;
+    let (out_rate, out_channels) = (out_cfg.sample_rate.0 as f64, out_cfg.channels as usize);
+    let (in_rate, in_channels) = (in_cfg.sample_rate.0 as f64, in_cfg.channels as usize);
+
+    let total_seconds = LEAD_SECONDS + (CLICKS + 1) as f64 * CLICK_INTERVAL_SECONDS;
+    let first = (LEAD_SECONDS * out_rate) as usize;
+    let interval = (CLICK_INTERVAL_SECONDS * out_rate) as usize;
+    let burst = click(out_rate);
+    // Where the burst first reaches half its peak, which is what the detection finds
+    let onset_bias = burst.iter().position(|s| s.abs() >= 0.5 * CLICK_LEVEL).unwrap_or(0) as f64 / out_rate;
+
+    // When the first frame was written / captured
+    let out_start = Arc::new(OnceLock::new());
+    let in_start = Arc::new(OnceLock::new());
+
+    let (mut producer, mut consumer) = RingBuffer::<f32>::new(((total_seconds + 1.0) * in_rate) as usize);
+    let started = in_start.clone();
+    let input_stream = in_device
+        .build_input_stream(
+            &in_cfg,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                // The block's first frame was captured a block ago
+                let frames = data.len() / in_channels;
+                started.get_or_init(|| Instant::now() - Duration::from_secs_f64(frames as f64 / in_rate));
+                for f in data.chunks_exact(in_channels) {
+                    let _ = producer.push(f.iter().sum::<f32>() / in_channels as f32);
+                }
+            },
+            |e| log::error!("Latency measurement input error: {}", e),
+            None,
+        )
+        .map_err(|e| format!("Build input stream failed: {e}"))?;
+    let started = out_start.clone();
+    let mut frame = 0usize;
+    let output_stream = out_device
+        .build_output_stream(
+            &out_cfg,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                started.get_or_init(Instant::now);
+                for f in data.chunks_exact_mut(out_channels) {
+                    let v = frame
+                        .checked_sub(first)
+                        .filter(|d| d / interval < CLICKS)
+                        .and_then(|d| burst.get(d % interval))
+                        .copied()
+                        .unwrap_or(0.0);
+                    f.fill(v);
+                    frame += 1;
+                }
+            },
+            |e| log::error!("Latency measurement output error: {}", e),
+            None,
+        )
+        .map_err(|e| format!("Build output stream failed: {e}"))?;
+    input_stream.play().map_err(|e| format!("Start input failed: {e}"))?;
+    output_stream.play().map_err(|e| format!("Start output failed: {e}"))?;
+    std::thread::sleep(Duration::from_secs_f64(total_seconds));
+    drop(output_stream);
+    drop(input_stream);
+
+    let (Some(&out_start), Some(&in_start)) = (out_start.get(), in_start.get()) else {
+        return Err("The devices delivered no audio".into());
+    };
+    let mut recorded = Vec::with_capacity(consumer.slots());
+    while let Ok(s) = consumer.pop() {
+        recorded.push(s);
+    }
+    // Input index of a moment given in seconds after the first output frame
+    let offset = seconds_between(in_start, out_start);
+    let at_input = |seconds: f64| (((offset + seconds) * in_rate).round().max(0.0) as usize).min(recorded.len());
+    let peak = |s: &[f32]| s.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+
+    let noise = peak(&recorded[..at_input(LEAD_SECONDS)]);
+    let mut trips = Vec::new();
+    for k in 0..CLICKS {
+        let emitted = (first + k * interval) as f64 / out_rate;
+        let window = &recorded[at_input(emitted)..at_input(emitted + CLICK_INTERVAL_SECONDS)];
+        let loudest = peak(window);
+        if loudest < (noise * MIN_SNR).max(MIN_PEAK) {
+            continue;
+        }
+        let threshold = noise + 0.5 * (loudest - noise);
+        if let Some(onset) = window.iter().position(|s| s.abs() >= threshold) {
+            trips.push(onset as f64 / in_rate - onset_bias);
+        }
+    }
+    if trips.len() < CLICKS.div_ceil(2) {
+        return Err(format!(
+            "Heard {} of {} clicks; check that {} picks up {} and turn the volume up",
+            trips.len(),
+            CLICKS,
+            input,
+            output.as_deref().unwrap_or("the default output")
+        ));
+    }
+    trips.sort_by(f64::total_cmp);
+    let round_trip_ms = (trips[trips.len() / 2].max(0.0) * 1000.0) as f32;
+    log::info!("Latency of {:?}: {:.1} ms round trip ({} clicks)", output, round_trip_ms, trips.len());
+    Ok(LatencyMeasurement { output: output.clone(), round_trip_ms, clicks_heard: trips.len(), delay_ms: 0.0 })
+}
+
+// Fill in the delays that make every measured output as late as the slowest one
+pub fn compensate(measurements: &mut [LatencyMeasurement]) {
+    let slowest = measurements.iter().map(|m| m.round_trip_ms).fold(0.0f32, f32::max);
+    for m in measurements.iter_mut() {
+        m.delay_ms = (slowest - m.round_trip_ms).clamp(0.0, MAX_DELAY_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{BusSpec, Engine, EngineSpec, Meters, Source};
+    use crate::StreamId;
+    use std::collections::BTreeMap;
+
+    fn measured(output: &str, round_trip_ms: f32) -> LatencyMeasurement {
+        LatencyMeasurement { output: Some(output.into()), round_trip_ms, clicks_heard: CLICKS, delay_ms: -1.0 }
+    }
+
+    // Full scale from the first frame on
+    struct Dc;
+
+    impl Source for Dc {
+        fn channels(&self) -> usize {
+            2
+        }
+
+        fn read(&mut self, out: &mut [f32]) {
+            out.fill(1.0);
+        }
+    }
+
+    #[test]
+    fn every_output_waits_for_the_slowest() {
+        let mut m = vec![measured("wired", 40.0), measured("bluetooth", 190.0), measured("hdmi", 12.5)];
+        compensate(&mut m);
+        let delays: Vec<f32> = m.iter().map(|m| m.delay_ms).collect();
+        assert_eq!(delays, [150.0, 0.0, 177.5]);
+        assert!(m.iter().all(|m| m.round_trip_ms + m.delay_ms == 190.0));
+
+        // Beyond what a delay line holds, the gap is closed as far as possible
+        let mut m = vec![measured("wired", 5.0), measured("network", 5.0 + MAX_DELAY_MS + 300.0)];
+        compensate(&mut m);
+        assert_eq!(m[0].delay_ms, MAX_DELAY_MS);
+        compensate(&mut []);
+    }
+
+    #[test]
+    fn compensated_outputs_line_up() {
+        let mut m = vec![measured("wired", 40.0), measured("bluetooth", 190.0)];
+        compensate(&mut m);
+        let bus = |stream, output: &str| BusSpec {
+            stream,
+            channels: 2,
+            gain: 1.0,
+            muted: false,
+            output: Some(output.into()),
+            eq: Default::default(),
+            dynamics: Default::default(),
+            auto_gain: Default::default(),
+            mic: None,
+            direct_out: None,
+            spatial: None,
+            matrix: Default::default(),
+            broadcast: None,
+            extra_outputs: Vec::new(),
+        };
+        let spec = EngineSpec {
+            sample_rate: PREFERRED_RATE,
+            buses: vec![bus(StreamId::Game, "wired"), bus(StreamId::Music, "bluetooth")],
+            outputs: BTreeMap::new(),
+            master: Default::default(),
+            aux: Vec::new(),
+            output_delays: m.iter().map(|m| (m.output.clone(), m.delay_ms)).collect(),
+        };
+        let (mut engine, _control) = Engine::new(spec, Arc::new(Meters::new(&[])));
+        engine.add_source(&StreamId::Game, Box::new(Dc));
+        engine.add_source(&StreamId::Music, Box::new(Dc));
+        let out = engine.render_offline(PREFERRED_RATE as usize / 2);
+
+        // Where each output starts playing, plus the latency of its device
+        for m in &m {
+            let start = out[&m.output].iter().position(|s| *s != 0.0).unwrap() / 2;
+            let heard_ms = start as f32 * 1000.0 / PREFERRED_RATE as f32 + m.round_trip_ms;
+            assert!((heard_ms - 190.0).abs() < 0.05, "{:?} heard at {} ms", m.output, heard_ms);
+        }
+    }
+}
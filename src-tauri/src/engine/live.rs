@@ -0,0 +1,426 @@
+// Live driver for the engine: opens cpal streams for every routed output and every bus
+// input, and runs `Engine::render` inside the callback of the primary output device.
+// Other outputs are fed through ring buffers. cpal streams aren't Send on every
+// platform, so they're created and kept on a dedicated thread.
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, SampleRate, StreamConfig, SupportedStreamConfigRange};
+
+use super::resample::ResamplerQuality;
+use super::source::{push_samples, ring_pair, RingSource, Source};
+use super::{BusSpec, Engine, EngineCommand, EngineSpec, Meters, OutputKey, RecordSource, MAX_BLOCK_FRAMES};
+use crate::backend::DeviceKind;
+use crate::StreamId;
+
+// Ring buffer size and the latency we allow to build up before dropping samples
+const RING_SECONDS: f32 = 0.5;
+const MAX_LATENCY_SECONDS: f32 = 0.05;
+
+#[derive(Debug, Clone)]
+pub struct BusConfig {
+    pub stream: StreamId,
+    // Capture device feeding the bus; an output device means loopback capture
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub gain: f32,
+    pub muted: bool,
+    pub eq: super::EqSettings,
+    pub dynamics: super::DynamicsSettings,
+    pub auto_gain: super::AutoGainSettings,
+    pub mic: Option<super::MicSettings>,
+    pub direct_out: Option<String>,
+    pub spatial: Option<super::SpatialConfig>,
+    pub matrix: super::MatrixSettings,
+    pub broadcast: Option<super::TapSpec>,
+    pub extra_outputs: Vec<super::TapSpec>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    pub buses: Vec<BusConfig>,
+    // Dynamics on every output device
+    pub master: super::DynamicsSettings,
+    // Conversion for devices not running at the engine rate, and drift compensation
+    // between device clocks
+    pub resampler: ResamplerQuality,
+    // Buses fed by sends from the stream buses
+    pub aux: Vec<super::AuxBusSettings>,
+    // Delay per output in ms
+    pub output_delays: BTreeMap<OutputKey, f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct EngineStatus {
+    pub running: bool,
+    pub sample_rate: u32,
+    pub outputs: Vec<String>,
+    pub inputs: Vec<String>,
+    // Devices running at another rate than the engine, "<device>: <rate> Hz"
+    pub resampled: Vec<String>,
+    // Non-fatal problems (input or output skipped, ...)
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+pub struct LiveEngine {
+    control: mpsc::Sender<EngineCommand>,
+    meters: Arc<Meters>,
+    status: EngineStatus,
+    // Recordable signals and their channel counts
+    recordable: BTreeMap<RecordSource, usize>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LiveEngine {
+    pub fn start(config: EngineConfig) -> Result<Self, String> {
+        let meters = Arc::new(Meters::new(&config.aux));
+        let thread_meters = meters.clone();
+        let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        let thread = thread::Builder::new()
+            .name("audio-engine".into())
+            .spawn(move || match open_streams(&config, thread_meters) {
+                Ok((streams, control, status, recordable)) => {
+                    let _ = ready_tx.send(Ok((control, status, recordable)));
+                    // Keep the streams alive until stop() or drop
+                    let _ = stop_rx.recv();
+                    drop(streams);
+                    log::info!("Audio engine stopped");
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                }
+            })
+            .map_err(|e| format!("Spawn audio engine thread failed: {e}"))?;
+
+        let (control, status, recordable) = ready_rx
+            .recv()
+            .map_err(|_| "Audio engine thread exited during startup".to_string())??;
+        log::info!("Audio engine running at {} Hz, outputs {:?}", status.sample_rate, status.outputs);
+        Ok(LiveEngine { control, meters, status, recordable, stop: Some(stop_tx), thread: Some(thread) })
+    }
+
+    pub fn send(&self, cmd: EngineCommand) {
+        let _ = self.control.send(cmd);
+    }
+
+    pub fn meters(&self) -> &Meters {
+        &self.meters
+    }
+
+    pub fn status(&self) -> EngineStatus {
+        self.status.clone()
+    }
+
+    pub fn record_channels(&self, source: &RecordSource) -> Option<usize> {
+        self.recordable.get(source).copied()
+    }
+}
+
+impl Drop for LiveEngine {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn describe(key: &OutputKey) -> String {
+    key.clone().unwrap_or_else(|| "default".into())
+}
+
+type Opened = (Vec<cpal::Stream>, mpsc::Sender<EngineCommand>, EngineStatus, BTreeMap<RecordSource, usize>);
+
+fn open_streams(config: &EngineConfig, meters: Arc<Meters>) -> Result<Opened, String> {
+    let host = cpal::default_host();
+    let mut status = EngineStatus { running: true, ..Default::default() };
+
+    let first = config.buses.first().ok_or("No buses configured")?;
+    let output_keys: BTreeSet<OutputKey> = config
+        .buses
+        .iter()
+        .map(|b| b.output.clone())
+        .chain(config.buses.iter().filter_map(|b| b.direct_out.clone().map(Some)))
+        .chain(config.aux.iter().map(|a| a.output.clone()))
+        .chain(config.buses.iter().filter_map(|b| b.broadcast.as_ref().map(|t| t.output.clone())))
+        .chain(config.buses.iter().flat_map(|b| b.extra_outputs.iter().map(|t| t.output.clone())))
+        .collect();
+
+    // The primary output's clock drives the whole graph
+    let primary_key = first.output.clone();
+    let primary_device = resolve_output(&host, &primary_key)?;
+    let primary_default = primary_device
+        .default_output_config()
+        .map_err(|e| format!("Default output config failed: {e}"))?;
+    let sample_rate = primary_default.sample_rate().0;
+    status.sample_rate = sample_rate;
+
+    // Output devices and their configs, at the engine rate where they support it
+    let mut outputs = Vec::new();
+    for key in &output_keys {
+        let device = if *key == primary_key {
+            primary_device.clone()
+        } else {
+            match resolve_output(&host, key) {
+                Ok(d) => d,
+                Err(e) => {
+                    status.warnings.push(format!("Output {} skipped: {}", describe(key), e));
+                    continue;
+                }
+            }
+        };
+        match stream_config(&device, sample_rate, true) {
+            Ok(cfg) => outputs.push((key.clone(), device, cfg)),
+            Err(e) => status.warnings.push(format!("Output {} skipped: {}", describe(key), e)),
+        }
+    }
+    if !outputs.iter().any(|(k, _, _)| *k == primary_key) {
+        return Err(format!("Primary output {} could not be opened", describe(&primary_key)));
+    }
+
+    // Bus inputs
+    let mut streams = Vec::new();
+    let mut sources: Vec<(StreamId, Box<dyn Source>)> = Vec::new();
+    let mut bus_channels = std::collections::HashMap::new();
+    for bus in &config.buses {
+        let Some(input_id) = &bus.input else { continue };
+        match open_input(&host, input_id, sample_rate, config.resampler) {
+            Ok((stream, source, rate)) => {
+                if rate != sample_rate {
+                    status.resampled.push(format!("{}: {} Hz", input_id, rate));
+                }
+                bus_channels.insert(bus.stream.clone(), source.channels());
+                sources.push((bus.stream.clone(), Box::new(source)));
+                streams.push(stream);
+                status.inputs.push(input_id.clone());
+            }
+            Err(e) => status.warnings.push(format!("Input {} for {:?} skipped: {}", input_id, bus.stream, e)),
+        }
+    }
+
+    let spec = EngineSpec {
+        sample_rate,
+        buses: config
+            .buses
+            .iter()
+            .map(|b| BusSpec {
+                stream: b.stream.clone(),
+                channels: bus_channels.get(&b.stream).copied().unwrap_or(2),
+                gain: b.gain,
+                muted: b.muted,
+                output: b.output.clone(),
+                eq: b.eq.clone(),
+                dynamics: b.dynamics,
+                auto_gain: b.auto_gain,
+                mic: b.mic,
+                direct_out: b.direct_out.clone(),
+                spatial: b.spatial.clone(),
+                matrix: b.matrix.clone(),
+                broadcast: b.broadcast.clone(),
+                extra_outputs: b.extra_outputs.clone(),
+            })
+            .collect(),
+        outputs: outputs.iter().map(|(k, _, cfg)| (k.clone(), cfg.channels as usize)).collect(),
+        master: config.master,
+        aux: config.aux.clone(),
+        output_delays: config.output_delays.clone(),
+    };
+    let (mut engine, control) = Engine::new(spec, meters);
+    for (stream, source) in sources {
+        engine.add_source(&stream, source);
+    }
+    let recordable = engine.recordable();
+
+    // Secondary outputs read what the primary callback renders for them
+    let mut feeds = Vec::new();
+    let mut primary = None;
+    for (key, device, cfg) in outputs {
+        let channels = cfg.channels as usize;
+        if key == primary_key {
+            primary = Some((device, cfg));
+            continue;
+        }
+        let rate = cfg.sample_rate.0;
+        if rate != sample_rate {
+            status.resampled.push(format!("{}: {} Hz", describe(&key), rate));
+        }
+        let (producer, mut source) = ring(channels, sample_rate, rate, config.resampler);
+        let name = describe(&key);
+        let built = device.build_output_stream(
+            &cfg,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| source.read(data),
+            move |e| log::error!("Output stream {} error: {}", name, e),
+            None,
+        );
+        match built {
+            Ok(stream) => {
+                streams.push(stream);
+                status.outputs.push(describe(&key));
+                feeds.push((key, producer));
+            }
+            Err(e) => status.warnings.push(format!("Output {} skipped: {}", describe(&key), e)),
+        }
+    }
+
+    let (device, cfg) = primary.ok_or("Primary output missing")?;
+    let channels = cfg.channels as usize;
+    let render_key = primary_key.clone();
+    let stream = device
+        .build_output_stream(
+            &cfg,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let total = data.len() / channels;
+                let mut offset = 0;
+                while offset < total {
+                    let n = (total - offset).min(MAX_BLOCK_FRAMES);
+                    engine.render(n);
+                    let dst = &mut data[offset * channels..(offset + n) * channels];
+                    match engine.output(&render_key) {
+                        Some(buf) => dst.copy_from_slice(buf.samples()),
+                        None => dst.fill(0.0),
+                    }
+                    for (key, producer) in feeds.iter_mut() {
+                        if let Some(buf) = engine.output(key) {
+                            push_samples(producer, buf.samples());
+                        }
+                    }
+                    offset += n;
+                }
+            },
+            |e| log::error!("Primary output stream error: {}", e),
+            None,
+        )
+        .map_err(|e| format!("Build primary output stream failed: {e}"))?;
+    streams.push(stream);
+    status.outputs.insert(0, describe(&primary_key));
+
+    for s in &streams {
+        s.play().map_err(|e| format!("Start stream failed: {e}"))?;
+    }
+    for w in &status.warnings {
+        log::warn!("Audio engine: {}", w);
+    }
+    Ok((streams, control, status, recordable))
+}
+
+// Ring buffer from a device running at `from` to one at `to`
+fn ring(channels: usize, from: u32, to: u32, quality: ResamplerQuality) -> (rtrb::Producer<f32>, RingSource) {
+    let frames = |seconds: f32| (from as f32 * seconds) as usize;
+    ring_pair(channels, from, to, quality, frames(RING_SECONDS), frames(MAX_LATENCY_SECONDS))
+}
+
+// Capture stream plus the source the engine reads it from, and the device's rate
+fn open_input(
+    host: &cpal::Host,
+    id: &str,
+    sample_rate: u32,
+    quality: ResamplerQuality,
+) -> Result<(cpal::Stream, RingSource, u32), String> {
+    // Real capture devices first, then output devices for loopback capture
+    let (device, loopback) = match find_device(host, id, DeviceKind::Input) {
+        Some(d) => (d, false),
+        None => (find_device(host, id, DeviceKind::Output).ok_or("device not found")?, true),
+    };
+    let cfg = stream_config(&device, sample_rate, loopback)?;
+    let rate = cfg.sample_rate.0;
+    let (mut producer, source) = ring(cfg.channels as usize, rate, sample_rate, quality);
+    let name = id.to_string();
+    let stream = device
+        .build_input_stream(
+            &cfg,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                push_samples(&mut producer, data);
+            },
+            move |e| log::error!("Input stream {} error: {}", name, e),
+            None,
+        )
+        .map_err(|e| format!("Build input stream failed: {e}"))?;
+    Ok((stream, source, rate))
+}
+
+// Device the engine plays an output route on. IDs cpal doesn't know (e.g. PulseAudio sink
+// names) are an error rather than the default device, which would play the stream in the
+// wrong place.
+pub fn resolve_output(host: &cpal::Host, key: &OutputKey) -> Result<cpal::Device, String> {
+    match key {
+        Some(id) => find_device(host, id, DeviceKind::Output)
+            .ok_or_else(|| format!("{} is not a device the engine can open", id)),
+        None => host.default_output_device().ok_or_else(|| "No default output device".to_string()),
+    }
+}
+
+// Find a device by the "<name>::<kind>#<n>" ID produced by `list_cpal_devices`, or by
+// plain name
+pub fn find_device(host: &cpal::Host, id: &str, kind: DeviceKind) -> Option<cpal::Device> {
+    let (name, wanted_kind, index) = match id.split_once("::") {
+        Some((name, rest)) => {
+            let (kind_str, idx) = rest.split_once('#').unwrap_or((rest, "0"));
+            let k = if kind_str == "Output" { DeviceKind::Output } else { DeviceKind::Input };
+            (name, k, idx.parse::<usize>().unwrap_or(0))
+        }
+        None => (id, kind.clone(), 0),
+    };
+    if wanted_kind != kind {
+        return None;
+    }
+    let mut seen = 0;
+    for dev in host.devices().ok()? {
+        let dev_name = dev.name().unwrap_or_default();
+        // Same classification as the device list
+        let is_output = dev.supported_output_configs().is_ok();
+        let dev_kind = if is_output { DeviceKind::Output } else { DeviceKind::Input };
+        if dev_name == name && dev_kind == kind {
+            if seen == index {
+                return Some(dev);
+            }
+            seen += 1;
+        }
+    }
+    None
+}
+
+// f32 stream config at `sample_rate`, preferring the device's default channel count.
+// Devices that can't run at that rate get their default rate and are resampled.
+pub(super) fn stream_config(device: &cpal::Device, sample_rate: u32, output: bool) -> Result<StreamConfig, String> {
+    let default = if output { device.default_output_config() } else { device.default_input_config() }
+        .map_err(|e| format!("Default config failed: {e}"))?;
+    if default.sample_rate().0 == sample_rate && default.sample_format() == SampleFormat::F32 {
+        return Ok(default.config());
+    }
+
+    let ranges: Vec<SupportedStreamConfigRange> = if output {
+        device.supported_output_configs().map(|r| r.collect())
+    } else {
+        device.supported_input_configs().map(|r| r.collect())
+    }
+    .map_err(|e| format!("Query supported configs failed: {e}"))?;
+
+    let at = |rate: u32| {
+        let fits = |r: &&SupportedStreamConfigRange| {
+            r.sample_format() == SampleFormat::F32 && r.min_sample_rate().0 <= rate && rate <= r.max_sample_rate().0
+        };
+        ranges
+            .iter()
+            .filter(fits)
+            .find(|r| r.channels() == default.channels())
+            .or_else(|| ranges.iter().find(fits))
+            .map(|r| (*r).with_sample_rate(SampleRate(rate)).config())
+    };
+    at(sample_rate).or_else(|| at(default.sample_rate().0)).ok_or_else(|| {
+        format!(
+            "no f32 format at {} Hz or the device default {} Hz",
+            sample_rate,
+            default.sample_rate().0
+        )
+    })
+}
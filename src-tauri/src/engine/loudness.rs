@@ -0,0 +1,340 @@
+// Loudness per ITU-R BS.1770 / EBU R128: K-weighted mean square over 100 ms blocks,
+// momentary (400 ms) and short-term (3 s) loudness in LUFS. `AutoGain` uses the
+// short-term value to pull a bus towards a target loudness.
+use serde::{Deserialize, Serialize};
+
+const MAX_CHANNELS: usize = 8;
+const BLOCK_SECONDS: f64 = 0.1;
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+// Reported for silence instead of -inf
+pub const SILENCE_LUFS: f32 = -120.0;
+// Below this the content is treated as a pause and auto-gain holds its value
+const AUTO_GAIN_GATE_LUFS: f32 = -50.0;
+// How fast auto-gain may move
+const AUTO_GAIN_SLEW_DB_PER_SECOND: f32 = 3.0;
+// BS.1770 weight of the surround channels (+1.5 dB)
+const SURROUND_WEIGHT: f64 = 1.41;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AutoGainSettings {
+    pub enabled: bool,
+    pub target_lufs: f32,
+    // Limits of the correction in dB
+    pub max_boost_db: f32,
+    pub max_cut_db: f32,
+}
+
+impl Default for AutoGainSettings {
+    fn default() -> Self {
+        // -14 LUFS is what the big streaming services normalize to
+        AutoGainSettings { enabled: false, target_lufs: -14.0, max_boost_db: 12.0, max_cut_db: 20.0 }
+    }
+}
+
+impl AutoGainSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.target_lufs.is_finite() && (-40.0..=-5.0).contains(&self.target_lufs)) {
+            return Err(format!("Target {} LUFS out of range (-40..-5)", self.target_lufs));
+        }
+        if !(self.max_boost_db.is_finite() && (0.0..=24.0).contains(&self.max_boost_db)) {
+            return Err(format!("Max boost {} dB out of range (0..24)", self.max_boost_db));
+        }
+        if !(self.max_cut_db.is_finite() && (0.0..=40.0).contains(&self.max_cut_db)) {
+            return Err(format!("Max cut {} dB out of range (0..40)", self.max_cut_db));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn run(&self, s: &mut State, x: f64) -> f64 {
+        let y = self.b0 * x + s.z1;
+        s.z1 = self.b1 * x - self.a1 * y + s.z2;
+        s.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+// K-weighting filters for any sample rate (BS.1770 stage parameters, bilinear transform)
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    // Stage 1: high shelf modelling the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    };
+
+    // Stage 2: RLB high-pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    };
+    [shelf, highpass]
+}
+
+// Channel weights for the layouts of `matrix`: the surrounds count 1.41, the LFE not at all
+fn channel_weights(channels: usize) -> [f64; MAX_CHANNELS] {
+    let mut w = [1.0; MAX_CHANNELS];
+    match channels {
+        4 => w[2..4].fill(SURROUND_WEIGHT),
+        5 => w[3..5].fill(SURROUND_WEIGHT),
+        6 | 8 => {
+            w[3] = 0.0;
+            w[4..channels].fill(SURROUND_WEIGHT);
+        }
+        _ => {}
+    }
+    w
+}
+
+fn to_lufs(mean_square: f64) -> f32 {
+    if mean_square <= 0.0 {
+        SILENCE_LUFS
+    } else {
+        ((-0.691 + 10.0 * mean_square.log10()) as f32).max(SILENCE_LUFS)
+    }
+}
+
+pub struct LoudnessMeter {
+    filters: [Biquad; 2],
+    states: [[State; 2]; MAX_CHANNELS],
+    block_frames: usize,
+    block_sum: f64,
+    block_count: usize,
+    // Mean square (summed over channels) of the last blocks
+    history: [f64; SHORT_TERM_BLOCKS],
+    history_pos: usize,
+    history_len: usize,
+    momentary: f32,
+    short_term: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        LoudnessMeter {
+            filters: k_weighting(sample_rate),
+            states: [[State::default(); 2]; MAX_CHANNELS],
+            block_frames: ((sample_rate as f64 * BLOCK_SECONDS) as usize).max(1),
+            block_sum: 0.0,
+            block_count: 0,
+            history: [0.0; SHORT_TERM_BLOCKS],
+            history_pos: 0,
+            history_len: 0,
+            momentary: SILENCE_LUFS,
+            short_term: SILENCE_LUFS,
+        }
+    }
+
+    pub fn momentary(&self) -> f32 {
+        self.momentary
+    }
+
+    pub fn short_term(&self) -> f32 {
+        self.short_term
+    }
+
+    // Short-term loudness is only meaningful once a few blocks are in
+    fn settled(&self) -> bool {
+        self.history_len >= MOMENTARY_BLOCKS
+    }
+
+    pub fn measure(&mut self, samples: &[f32], channels: usize) {
+        if channels == 0 {
+            return;
+        }
+        let weights = channel_weights(channels);
+        for frame in samples.chunks(channels) {
+            for (ch, &x) in frame.iter().enumerate().take(MAX_CHANNELS) {
+                let [shelf, highpass] = &mut self.states[ch];
+                let y = self.filters[1].run(highpass, self.filters[0].run(shelf, x as f64));
+                self.block_sum += weights[ch] * y * y;
+            }
+            self.block_count += 1;
+            if self.block_count == self.block_frames {
+                self.finish_block();
+            }
+        }
+    }
+
+    fn finish_block(&mut self) {
+        self.history[self.history_pos] = self.block_sum / self.block_frames as f64;
+        self.history_pos = (self.history_pos + 1) % SHORT_TERM_BLOCKS;
+        self.history_len = (self.history_len + 1).min(SHORT_TERM_BLOCKS);
+        self.block_sum = 0.0;
+        self.block_count = 0;
+
+        let recent = |n: usize| {
+            let n = n.min(self.history_len);
+            let sum: f64 = (1..=n).map(|i| self.history[(self.history_pos + SHORT_TERM_BLOCKS - i) % SHORT_TERM_BLOCKS]).sum();
+            sum / n as f64
+        };
+        self.momentary = to_lufs(recent(MOMENTARY_BLOCKS));
+        self.short_term = to_lufs(recent(SHORT_TERM_BLOCKS));
+
+        // Flush denormals in long silences
+        for st in self.states.iter_mut().flatten() {
+            if st.z1.abs() < 1e-30 && st.z2.abs() < 1e-30 {
+                *st = State::default();
+            }
+        }
+    }
+}
+
+pub struct AutoGain {
+    settings: AutoGainSettings,
+    slew_db_per_frame: f32,
+    gain_db: f32,
+    gain: f32,
+}
+
+impl AutoGain {
+    pub fn new(settings: AutoGainSettings, sample_rate: u32) -> Self {
+        AutoGain {
+            settings,
+            slew_db_per_frame: AUTO_GAIN_SLEW_DB_PER_SECOND / sample_rate.max(1) as f32,
+            gain_db: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: AutoGainSettings) {
+        self.settings = settings;
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    // Move towards the correction for the measured loudness and apply it, ramping
+    // linearly across the block
+    pub fn process(&mut self, meter: &LoudnessMeter, samples: &mut [f32], channels: usize) {
+        let frames = samples.len() / channels.max(1);
+        let s = &self.settings;
+        let wanted = if !s.enabled {
+            0.0
+        } else if meter.settled() && meter.short_term() > AUTO_GAIN_GATE_LUFS {
+            (s.target_lufs - meter.short_term()).clamp(-s.max_cut_db, s.max_boost_db)
+        } else {
+            // Pause or start-up: hold
+            self.gain_db
+        };
+        let max_step = self.slew_db_per_frame * frames as f32;
+        let next_db = self.gain_db + (wanted - self.gain_db).clamp(-max_step, max_step);
+        let next = 10f32.powf(next_db / 20.0);
+
+        if self.gain != 1.0 || next != 1.0 {
+            let step = (next - self.gain) / frames.max(1) as f32;
+            let mut g = self.gain;
+            for frame in samples.chunks_mut(channels.max(1)) {
+                g += step;
+                for x in frame.iter_mut() {
+                    *x *= g;
+                }
+            }
+        }
+        self.gain_db = next_db;
+        self.gain = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // `seconds` of a 997 Hz sine at `dbfs` (peak) on the channels in `on`
+    fn sine(dbfs: f32, seconds: f32, channels: usize, on: &[usize]) -> Vec<f32> {
+        let amplitude = 10f32.powf(dbfs / 20.0);
+        let frames = (seconds * RATE as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let x = amplitude * (std::f32::consts::TAU * 997.0 * i as f32 / RATE as f32).sin();
+                (0..channels).map(move |c| if on.contains(&c) { x } else { 0.0 })
+            })
+            .collect()
+    }
+
+    fn loudness(samples: &[f32], channels: usize) -> f32 {
+        let mut meter = LoudnessMeter::new(RATE);
+        meter.measure(samples, channels);
+        meter.short_term()
+    }
+
+    #[test]
+    fn stereo_reference_tone_reads_minus_23_lufs() {
+        let lufs = loudness(&sine(-23.0, 4.0, 2, &[0, 1]), 2);
+        assert!((lufs + 23.0).abs() < 0.1, "{lufs}");
+        // Momentary agrees on a steady tone
+        let mut meter = LoudnessMeter::new(RATE);
+        meter.measure(&sine(-23.0, 1.0, 2, &[0, 1]), 2);
+        assert!((meter.momentary() + 23.0).abs() < 0.1, "{}", meter.momentary());
+    }
+
+    #[test]
+    fn surrounds_weigh_more_and_lfe_not_at_all() {
+        let front = loudness(&sine(-20.0, 4.0, 6, &[0]), 6);
+        let surround = loudness(&sine(-20.0, 4.0, 6, &[4]), 6);
+        assert!((surround - front - 10.0 * 1.41f32.log10()).abs() < 0.01, "{front} {surround}");
+        assert_eq!(loudness(&sine(-20.0, 4.0, 6, &[3]), 6), SILENCE_LUFS);
+        // Same in 7.1, and stereo buses are unaffected
+        assert_eq!(loudness(&sine(-20.0, 4.0, 8, &[3]), 8), SILENCE_LUFS);
+        assert!((loudness(&sine(-20.0, 4.0, 2, &[0]), 2) - front).abs() < 0.01);
+    }
+
+    #[test]
+    fn auto_gain_holds_through_pauses() {
+        let settings = AutoGainSettings { enabled: true, ..Default::default() };
+        let mut meter = LoudnessMeter::new(RATE);
+        let mut gain = AutoGain::new(settings, RATE);
+        let block = |meter: &mut LoudnessMeter, gain: &mut AutoGain, mut samples: Vec<f32>| {
+            for chunk in samples.chunks_mut(1024 * 2) {
+                meter.measure(chunk, 2);
+                gain.process(meter, chunk, 2);
+            }
+        };
+        // 6 s at -26 LUFS: pulled up towards -14 at the slew rate
+        block(&mut meter, &mut gain, sine(-26.0, 6.0, 2, &[0, 1]));
+        let boosted = gain.gain_db();
+        assert!(boosted > 10.0 && boosted <= 12.0, "{boosted}");
+        // A pause below the gate keeps the gain where it was
+        block(&mut meter, &mut gain, sine(-80.0, 5.0, 2, &[0, 1]));
+        assert!(meter.short_term() < AUTO_GAIN_GATE_LUFS);
+        assert!((gain.gain_db() - boosted).abs() < 1e-3, "{}", gain.gain_db());
+    }
+}
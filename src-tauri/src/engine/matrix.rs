@@ -0,0 +1,256 @@
+// Channel matrix between a bus and the device it plays on. Without explicit gains the
+// standard down/upmix for the two layouts is used (ITU-R BS.775 coefficients, LFE
+// dropped), so the center of 5.1 game audio ends up on both sides of a stereo device
+// and in a mono speaker instead of wrapping onto the wrong channel.
+use serde::{Deserialize, Serialize};
+
+pub const MAX_CHANNELS: usize = 8;
+// -3 dB, for a channel folded onto two speakers or a neighbouring one
+const FOLD: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MatrixSettings {
+    // Gains as [output channel][bus channel]; empty = standard down/upmix. Ignored if
+    // the dimensions don't match the current bus/device.
+    pub gains: Vec<Vec<f32>>,
+    // Sum everything to mono and feed it to every output channel (mono speakers that
+    // report themselves as stereo)
+    pub mono: bool,
+    pub swap_left_right: bool,
+    // Trim per output channel; missing entries are 0 dB
+    pub channel_gains_db: Vec<f32>,
+}
+
+impl MatrixSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.gains.len() > MAX_CHANNELS || self.gains.iter().any(|r| r.len() > MAX_CHANNELS) {
+            return Err(format!("Channel matrix larger than {MAX_CHANNELS}x{MAX_CHANNELS}"));
+        }
+        if self.gains.iter().any(|r| r.len() != self.gains[0].len()) {
+            return Err("Channel matrix rows differ in length".into());
+        }
+        if let Some(g) = self.gains.iter().flatten().find(|g| !(g.is_finite() && (-4.0..=4.0).contains(*g))) {
+            return Err(format!("Matrix gain {g} out of range (-4..4)"));
+        }
+        if self.channel_gains_db.len() > MAX_CHANNELS {
+            return Err(format!("More than {MAX_CHANNELS} channel gains"));
+        }
+        if let Some(g) = self.channel_gains_db.iter().find(|g| !(g.is_finite() && (-60.0..=12.0).contains(*g))) {
+            return Err(format!("Channel gain {g} dB out of range (-60..12)"));
+        }
+        Ok(())
+    }
+
+    // Whether the explicit gains (if any) are usable for this bus/device pair
+    pub fn fits(&self, inputs: usize, outputs: usize) -> bool {
+        self.gains.is_empty() || (self.gains.len() == outputs && self.gains.iter().all(|r| r.len() == inputs))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Speaker {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    // Surround pair of 4.0/5.x, back pair of 7.1
+    RearLeft,
+    RearRight,
+    SideLeft,
+    SideRight,
+    Other(usize),
+}
+
+// Channel order as in WAVEFORMATEXTENSIBLE / PulseAudio defaults
+fn layout(channels: usize) -> Vec<Speaker> {
+    use Speaker::*;
+    match channels {
+        1 => vec![Center],
+        2 => vec![Left, Right],
+        3 => vec![Left, Right, Center],
+        4 => vec![Left, Right, RearLeft, RearRight],
+        5 => vec![Left, Right, Center, RearLeft, RearRight],
+        6 => vec![Left, Right, Center, Lfe, RearLeft, RearRight],
+        8 => vec![Left, Right, Center, Lfe, RearLeft, RearRight, SideLeft, SideRight],
+        n => (0..n).map(|c| [Left, Right].get(c).copied().unwrap_or(Other(c))).collect(),
+    }
+}
+
+type Gains = [[f32; MAX_CHANNELS]; MAX_CHANNELS];
+
+// Standard matrix from `inputs` to `outputs` channels
+fn standard(inputs: usize, outputs: usize) -> Gains {
+    use Speaker::*;
+    let mut m = [[0.0; MAX_CHANNELS]; MAX_CHANNELS];
+    if inputs == outputs {
+        for (c, row) in m.iter_mut().enumerate() {
+            row[c] = 1.0;
+        }
+        return m;
+    }
+    if outputs == 1 {
+        // Mono: average of the stereo downmix
+        let stereo = standard(inputs, 2);
+        for i in 0..inputs {
+            m[0][i] = 0.5 * (stereo[0][i] + stereo[1][i]);
+        }
+        return m;
+    }
+    let src = layout(inputs);
+    let dst = layout(outputs);
+    let find = |s: Speaker| dst.iter().position(|&d| d == s);
+    for (i, &s) in src.iter().enumerate() {
+        if let Some(o) = find(s) {
+            m[o][i] = 1.0;
+            continue;
+        }
+        // Fallbacks for a speaker the device doesn't have
+        let targets: &[(Speaker, f32)] = match s {
+            // Mono sources play at full level on both sides, like before
+            Center if inputs == 1 => &[(Left, 1.0), (Right, 1.0)],
+            Center => &[(Left, FOLD), (Right, FOLD)],
+            SideLeft if find(RearLeft).is_some() => &[(RearLeft, FOLD)],
+            SideRight if find(RearRight).is_some() => &[(RearRight, FOLD)],
+            RearLeft if find(SideLeft).is_some() => &[(SideLeft, FOLD)],
+            RearRight if find(SideRight).is_some() => &[(SideRight, FOLD)],
+            SideLeft | RearLeft => &[(Left, FOLD)],
+            SideRight | RearRight => &[(Right, FOLD)],
+            Lfe | Left | Right | Other(_) => &[],
+        };
+        for &(t, g) in targets {
+            if let Some(o) = find(t) {
+                m[o][i] += g;
+            }
+        }
+    }
+    m
+}
+
+// Gains as the frontend shows them, [output][input]
+pub fn standard_gains(inputs: usize, outputs: usize) -> Vec<Vec<f32>> {
+    let (inputs, outputs) = (inputs.clamp(1, MAX_CHANNELS), outputs.clamp(1, MAX_CHANNELS));
+    let m = standard(inputs, outputs);
+    m[..outputs].iter().map(|row| row[..inputs].to_vec()).collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelMatrix {
+    gains: Gains,
+    inputs: usize,
+    outputs: usize,
+}
+
+impl ChannelMatrix {
+    // Channels beyond MAX_CHANNELS are dropped. Allocation free, so it can be rebuilt on
+    // the audio thread.
+    pub fn new(settings: &MatrixSettings, inputs: usize, outputs: usize) -> Self {
+        let (inputs, outputs) = (inputs.clamp(1, MAX_CHANNELS), outputs.clamp(1, MAX_CHANNELS));
+        let mut gains = standard(inputs, outputs);
+        if !settings.gains.is_empty() && settings.fits(inputs, outputs) {
+            for (o, row) in settings.gains.iter().enumerate() {
+                gains[o][..inputs].copy_from_slice(&row[..inputs]);
+            }
+        }
+        if settings.mono {
+            let mut mono = [0.0; MAX_CHANNELS];
+            for i in 0..inputs {
+                mono[i] = (0..outputs).map(|o| gains[o][i]).sum::<f32>() / outputs as f32;
+            }
+            for row in gains[..outputs].iter_mut() {
+                *row = mono;
+            }
+        }
+        if settings.swap_left_right && outputs >= 2 {
+            gains.swap(0, 1);
+        }
+        for (row, db) in gains.iter_mut().zip(&settings.channel_gains_db) {
+            let g = 10f32.powf(db / 20.0);
+            for v in row.iter_mut() {
+                *v *= g;
+            }
+        }
+        ChannelMatrix { gains, inputs, outputs }
+    }
+
+    // Add `src` (interleaved, `src_channels` wide) into `dst`
+    pub fn mix_into(&self, src: &[f32], src_channels: usize, dst: &mut [f32], dst_channels: usize) {
+        let inputs = self.inputs.min(src_channels);
+        let outputs = self.outputs.min(dst_channels);
+        for (s, d) in src.chunks_exact(src_channels).zip(dst.chunks_exact_mut(dst_channels)) {
+            for (o, out) in d[..outputs].iter_mut().enumerate() {
+                let row = &self.gains[o];
+                *out += s[..inputs].iter().zip(row).map(|(v, g)| v * g).sum::<f32>();
+            }
+        }
+    }
+
+    // Same with an extra gain moving linearly from `from` to `to` over the block
+    pub fn mix_into_ramped(&self, src: &[f32], src_channels: usize, dst: &mut [f32], dst_channels: usize, from: f32, to: f32) {
+        let inputs = self.inputs.min(src_channels);
+        let outputs = self.outputs.min(dst_channels);
+        let frames = (src.len() / src_channels).max(1);
+        let step = (to - from) / frames as f32;
+        let mut gain = from;
+        for (s, d) in src.chunks_exact(src_channels).zip(dst.chunks_exact_mut(dst_channels)) {
+            gain += step;
+            for (o, out) in d[..outputs].iter_mut().enumerate() {
+                let row = &self.gains[o];
+                *out += gain * s[..inputs].iter().zip(row).map(|(v, g)| v * g).sum::<f32>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One frame through the matrix
+    fn mix(settings: &MatrixSettings, frame: &[f32], outputs: usize) -> Vec<f32> {
+        let mut out = vec![0.0; outputs];
+        ChannelMatrix::new(settings, frame.len(), outputs).mix_into(frame, frame.len(), &mut out, outputs);
+        out
+    }
+
+    #[test]
+    fn surround_to_stereo_folds_the_center_at_minus_3_db() {
+        let gains = standard_gains(6, 2);
+        // L R C LFE Ls Rs
+        assert_eq!(gains[0], [1.0, 0.0, FOLD, 0.0, FOLD, 0.0]);
+        assert_eq!(gains[1], [0.0, 1.0, FOLD, 0.0, 0.0, FOLD]);
+        let out = mix(&MatrixSettings::default(), &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0], 2);
+        for v in out {
+            assert!((20.0 * v.log10() + 3.01).abs() < 0.01, "{v}");
+        }
+    }
+
+    #[test]
+    fn surround_to_mono_keeps_the_center() {
+        let out = mix(&MatrixSettings::default(), &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0], 1);
+        assert!((out[0] - FOLD).abs() < 1e-6, "{out:?}");
+        // LFE stays out
+        assert_eq!(mix(&MatrixSettings::default(), &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0], 1), [0.0]);
+    }
+
+    #[test]
+    fn swap_and_mono_fold_down() {
+        let swap = MatrixSettings { swap_left_right: true, ..Default::default() };
+        assert_eq!(mix(&swap, &[1.0, 0.25], 2), [0.25, 1.0]);
+        let mono = MatrixSettings { mono: true, ..Default::default() };
+        assert_eq!(mix(&mono, &[1.0, 0.0], 2), [0.5, 0.5]);
+        assert_eq!(mix(&mono, &[0.5, 0.5], 2), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn ragged_gains_are_rejected_and_ignored() {
+        let ragged = MatrixSettings { gains: vec![vec![1.0, 0.0], vec![1.0]], ..Default::default() };
+        assert!(ragged.validate().is_err());
+        assert!(!ragged.fits(2, 2));
+        // Falls back to the standard mix instead of panicking
+        assert_eq!(mix(&ragged, &[1.0, 0.25], 2), [1.0, 0.25]);
+        let explicit = MatrixSettings { gains: vec![vec![0.0, 1.0], vec![1.0, 0.0]], ..Default::default() };
+        assert!(explicit.validate().is_ok() && explicit.fits(2, 2));
+        assert_eq!(mix(&explicit, &[1.0, 0.25], 2), [0.25, 1.0]);
+    }
+}
@@ -0,0 +1,127 @@
+// Level metering shared between the audio thread (writer) and commands (reader).
+// Values are f32 bit patterns in atomics; for non-negative floats the bit order matches
+// the numeric order, so `fetch_max` works for peak hold.
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use super::{AuxBusSettings, BusId};
+use crate::StreamId;
+
+// RMS integration time
+const RMS_WINDOW_SECONDS: f32 = 0.3;
+
+#[derive(Default)]
+pub struct BusMeter {
+    // Highest sample since the last read
+    peak: AtomicU32,
+    // Exponentially averaged mean square
+    mean_square: AtomicU32,
+    // Highest compressor/limiter gain reduction (dB) since the last read
+    gain_reduction: AtomicU32,
+    // Latest EBU R128 values (LUFS) and the auto-gain correction (dB)
+    momentary: AtomicU32,
+    short_term: AtomicU32,
+    auto_gain: AtomicU32,
+    // Noise gate state of the mic chain (always open without one)
+    gate_open: AtomicBool,
+}
+
+impl BusMeter {
+    // Called once per block from the audio thread
+    pub fn update(&self, samples: &[f32], frames: usize, sample_rate: u32) {
+        if samples.is_empty() || frames == 0 {
+            return;
+        }
+        let mut peak = 0.0f32;
+        let mut sum = 0.0f32;
+        for s in samples {
+            peak = peak.max(s.abs());
+            sum += s * s;
+        }
+        let block_ms = sum / samples.len() as f32;
+        let alpha = (frames as f32 / (RMS_WINDOW_SECONDS * sample_rate as f32)).min(1.0);
+        let prev = f32::from_bits(self.mean_square.load(Ordering::Relaxed));
+        let ms = prev + alpha * (block_ms - prev);
+
+        self.peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+        self.mean_square.store(ms.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_gain_reduction(&self, db: f32) {
+        self.gain_reduction.fetch_max(db.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_loudness(&self, momentary: f32, short_term: f32, auto_gain_db: f32) {
+        self.momentary.store(momentary.to_bits(), Ordering::Relaxed);
+        self.short_term.store(short_term.to_bits(), Ordering::Relaxed);
+        self.auto_gain.store(auto_gain_db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_gate_open(&self, open: bool) {
+        self.gate_open.store(open, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> (f32, f32) {
+        let peak = f32::from_bits(self.peak.swap(0, Ordering::Relaxed));
+        let rms = f32::from_bits(self.mean_square.load(Ordering::Relaxed)).sqrt();
+        (peak, rms)
+    }
+}
+
+pub struct Meters {
+    buses: BTreeMap<BusId, BusMeter>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MeterReading {
+    pub bus: BusId,
+    pub peak_db: f32,
+    pub rms_db: f32,
+    pub gain_reduction_db: f32,
+    pub momentary_lufs: f32,
+    pub short_term_lufs: f32,
+    pub auto_gain_db: f32,
+    pub gate_open: bool,
+}
+
+// Floor for silent signals so the UI doesn't get -inf
+const SILENCE_DB: f32 = -120.0;
+
+pub fn to_db(linear: f32) -> f32 {
+    if linear <= 0.0 { SILENCE_DB } else { (20.0 * linear.log10()).max(SILENCE_DB) }
+}
+
+impl Meters {
+    // One meter per stream bus and per aux bus
+    pub fn new(aux: &[AuxBusSettings]) -> Self {
+        let streams = StreamId::ALL.iter().map(|s| BusId::Stream(s.clone()));
+        let aux = aux.iter().map(|a| BusId::Aux(a.id.clone()));
+        Meters { buses: streams.chain(aux).map(|id| (id, BusMeter::default())).collect() }
+    }
+
+    pub fn bus(&self, id: &BusId) -> &BusMeter {
+        &self.buses[id]
+    }
+
+    pub fn readings(&self) -> Vec<MeterReading> {
+        self.buses
+            .iter()
+            .map(|(bus, m)| {
+                let (peak, rms) = m.read();
+                let gain_reduction_db = f32::from_bits(m.gain_reduction.swap(0, Ordering::Relaxed));
+                let load = |a: &AtomicU32| f32::from_bits(a.load(Ordering::Relaxed));
+                MeterReading {
+                    bus: bus.clone(),
+                    peak_db: to_db(peak),
+                    rms_db: to_db(rms),
+                    gain_reduction_db,
+                    momentary_lufs: load(&m.momentary),
+                    short_term_lufs: load(&m.short_term),
+                    auto_gain_db: load(&m.auto_gain),
+                    gate_open: m.gate_open.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
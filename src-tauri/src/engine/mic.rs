@@ -0,0 +1,434 @@
+// Microphone chain for the Voice capture path: high-pass -> noise suppression ->
+// noise gate -> de-esser.
+// Every stage can be bypassed. Runs on the bus input before EQ and dynamics.
+use serde::{Deserialize, Serialize};
+
+use super::denoise::{DenoiseParams, Denoiser, NoiseSuppressionSettings};
+use super::eq::{EqBand, EqBandType, EqChain, EqDesign, EqSettings};
+use super::MAX_BLOCK_FRAMES;
+
+const MAX_CHANNELS: usize = 8;
+// Detector time constants of the gate
+const GATE_DETECT_ATTACK_MS: f32 = 1.0;
+const GATE_DETECT_RELEASE_MS: f32 = 30.0;
+// De-esser detector and gain smoothing
+const DEESS_ATTACK_MS: f32 = 1.0;
+const DEESS_RELEASE_MS: f32 = 60.0;
+const DEESS_RATIO: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HighPassSettings {
+    pub enabled: bool,
+    pub frequency: f32,
+}
+
+impl Default for HighPassSettings {
+    fn default() -> Self {
+        HighPassSettings { enabled: true, frequency: 80.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GateSettings {
+    pub enabled: bool,
+    // Opens above the threshold, closes below threshold - hysteresis
+    pub threshold_db: f32,
+    pub hysteresis_db: f32,
+    pub hold_ms: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    // Attenuation while closed
+    pub range_db: f32,
+}
+
+impl Default for GateSettings {
+    fn default() -> Self {
+        GateSettings {
+            enabled: false,
+            threshold_db: -45.0,
+            hysteresis_db: 6.0,
+            hold_ms: 150.0,
+            attack_ms: 2.0,
+            release_ms: 120.0,
+            range_db: -80.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DeEsserSettings {
+    pub enabled: bool,
+    // Lower edge of the sibilance band
+    pub frequency: f32,
+    pub threshold_db: f32,
+    pub max_reduction_db: f32,
+}
+
+impl Default for DeEsserSettings {
+    fn default() -> Self {
+        DeEsserSettings { enabled: false, frequency: 6000.0, threshold_db: -30.0, max_reduction_db: 10.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct MicSettings {
+    pub high_pass: HighPassSettings,
+    pub noise_suppression: NoiseSuppressionSettings,
+    pub gate: GateSettings,
+    pub de_esser: DeEsserSettings,
+}
+
+fn check(name: &str, value: f32, min: f32, max: f32) -> Result<(), String> {
+    if value.is_finite() && (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} {} out of range ({}..{})", name, value, min, max))
+    }
+}
+
+impl MicSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        check("High-pass frequency", self.high_pass.frequency, 20.0, 400.0)?;
+        self.noise_suppression.validate()?;
+        let g = &self.gate;
+        check("Gate threshold", g.threshold_db, -90.0, 0.0)?;
+        check("Gate hysteresis", g.hysteresis_db, 0.0, 30.0)?;
+        check("Gate hold", g.hold_ms, 0.0, 2000.0)?;
+        check("Gate attack", g.attack_ms, 0.1, 200.0)?;
+        check("Gate release", g.release_ms, 1.0, 2000.0)?;
+        check("Gate range", g.range_db, -120.0, 0.0)?;
+        let d = &self.de_esser;
+        check("De-esser frequency", d.frequency, 2000.0, 16000.0)?;
+        check("De-esser threshold", d.threshold_db, -60.0, 0.0)?;
+        check("De-esser max reduction", d.max_reduction_db, 0.0, 24.0)?;
+        Ok(())
+    }
+}
+
+fn time_coef(ms: f32, sample_rate: u32) -> f32 {
+    let samples = ms * 0.001 * sample_rate as f32;
+    if samples < 1.0 { 0.0 } else { (-1.0 / samples).exp() }
+}
+
+fn high_pass(frequency: f32, sample_rate: u32) -> EqDesign {
+    let band = EqBand { kind: EqBandType::HighPass, frequency, gain_db: 0.0, q: 0.707, enabled: true };
+    EqDesign::new(&EqSettings { preamp_db: 0.0, bands: vec![band] }, sample_rate)
+}
+
+// Settings turned into coefficients at the engine rate; built off the audio thread
+#[derive(Debug, Clone, Copy)]
+pub struct MicParams {
+    high_pass: Option<EqDesign>,
+    denoise: Option<DenoiseParams>,
+    gate: Option<GateParams>,
+    de_esser: Option<DeEsserParams>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GateParams {
+    open_db: f32,
+    close_db: f32,
+    hold_frames: u32,
+    attack: f32,
+    release: f32,
+    floor: f32,
+    detect_attack: f32,
+    detect_release: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DeEsserParams {
+    sidechain: EqDesign,
+    threshold_db: f32,
+    max_reduction_db: f32,
+    attack: f32,
+    release: f32,
+}
+
+impl MicParams {
+    pub fn new(settings: &MicSettings, sample_rate: u32) -> Self {
+        let g = &settings.gate;
+        let d = &settings.de_esser;
+        MicParams {
+            high_pass: settings.high_pass.enabled.then(|| high_pass(settings.high_pass.frequency, sample_rate)),
+            denoise: settings.noise_suppression.enabled.then(|| DenoiseParams::new(settings.noise_suppression.strength)),
+            gate: g.enabled.then(|| GateParams {
+                open_db: g.threshold_db,
+                close_db: g.threshold_db - g.hysteresis_db,
+                hold_frames: (g.hold_ms * 0.001 * sample_rate as f32) as u32,
+                attack: time_coef(g.attack_ms, sample_rate),
+                release: time_coef(g.release_ms, sample_rate),
+                floor: 10f32.powf(g.range_db / 20.0),
+                detect_attack: time_coef(GATE_DETECT_ATTACK_MS, sample_rate),
+                detect_release: time_coef(GATE_DETECT_RELEASE_MS, sample_rate),
+            }),
+            de_esser: d.enabled.then(|| DeEsserParams {
+                sidechain: high_pass(d.frequency, sample_rate),
+                threshold_db: d.threshold_db,
+                max_reduction_db: d.max_reduction_db,
+                attack: time_coef(DEESS_ATTACK_MS, sample_rate),
+                release: time_coef(DEESS_RELEASE_MS, sample_rate),
+            }),
+        }
+    }
+}
+
+struct Gate {
+    envelope: f32,
+    open: bool,
+    hold_left: u32,
+    gain: f32,
+}
+
+impl Gate {
+    fn process(&mut self, p: &GateParams, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_mut(channels) {
+            let peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            let coef = if peak > self.envelope { p.detect_attack } else { p.detect_release };
+            self.envelope = peak + coef * (self.envelope - peak);
+            let level_db = 20.0 * self.envelope.max(1e-9).log10();
+
+            // Hysteresis plus hold keeps the gate from chattering on word endings
+            if level_db >= p.open_db {
+                self.open = true;
+                self.hold_left = p.hold_frames;
+            } else if self.open && level_db < p.close_db {
+                if self.hold_left > 0 {
+                    self.hold_left -= 1;
+                } else {
+                    self.open = false;
+                }
+            }
+
+            let target = if self.open { 1.0 } else { p.floor };
+            let coef = if target > self.gain { p.attack } else { p.release };
+            self.gain = target + coef * (self.gain - target);
+            for s in frame.iter_mut() {
+                *s *= self.gain;
+            }
+        }
+    }
+}
+
+// Split-band de-esser: the band above `frequency` is turned down while its level is
+// over the threshold, the rest passes untouched (output = x - high * (1 - gain))
+struct DeEsser {
+    sidechain: EqChain,
+    high: Vec<f32>,
+    envelope: f32,
+    reduction_db: f32,
+}
+
+impl DeEsser {
+    fn process(&mut self, p: &DeEsserParams, samples: &mut [f32], channels: usize) {
+        let high = &mut self.high[..samples.len()];
+        high.copy_from_slice(samples);
+        self.sidechain.process(high, channels);
+
+        for (frame, band) in samples.chunks_mut(channels).zip(high.chunks(channels)) {
+            let peak = band.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            let coef = if peak > self.envelope { p.attack } else { p.release };
+            self.envelope = peak + coef * (self.envelope - peak);
+            let over = 20.0 * self.envelope.max(1e-9).log10() - p.threshold_db;
+            let wanted = (over * (1.0 - 1.0 / DEESS_RATIO)).clamp(0.0, p.max_reduction_db);
+            // Smooth the gain as well so the band doesn't flutter
+            let coef = if wanted > self.reduction_db { p.attack } else { p.release };
+            self.reduction_db = wanted + coef * (self.reduction_db - wanted);
+            let cut = 1.0 - 10f32.powf(-self.reduction_db / 20.0);
+            for (s, h) in frame.iter_mut().zip(band) {
+                *s -= h * cut;
+            }
+        }
+    }
+}
+
+pub struct MicChain {
+    params: MicParams,
+    high_pass: EqChain,
+    denoiser: Denoiser,
+    gate: Gate,
+    de_esser: DeEsser,
+}
+
+impl MicChain {
+    pub fn new(params: MicParams, sample_rate: u32) -> Self {
+        let mut chain = MicChain {
+            params,
+            high_pass: EqChain::new(EqDesign::flat()),
+            denoiser: Denoiser::new(sample_rate),
+            gate: Gate { envelope: 0.0, open: false, hold_left: 0, gain: 1.0 },
+            de_esser: DeEsser {
+                sidechain: EqChain::new(EqDesign::flat()),
+                high: vec![0.0; MAX_BLOCK_FRAMES * MAX_CHANNELS],
+                envelope: 0.0,
+                reduction_db: 0.0,
+            },
+        };
+        chain.set_params(params);
+        chain
+    }
+
+    pub fn set_params(&mut self, params: MicParams) {
+        self.high_pass.set_design(params.high_pass.unwrap_or_else(EqDesign::flat));
+        if let Some(d) = &params.de_esser {
+            self.de_esser.sidechain.set_design(d.sidechain);
+        }
+        if params.gate.is_none() {
+            self.gate.gain = 1.0;
+        }
+        self.params = params;
+    }
+
+    pub fn gate_open(&self) -> bool {
+        self.params.gate.is_none() || self.gate.open
+    }
+
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if channels == 0 || samples.len() > self.de_esser.high.len() {
+            return;
+        }
+        self.high_pass.process(samples, channels);
+        if let Some(p) = &self.params.denoise {
+            self.denoiser.process(p, samples, channels);
+        }
+        if let Some(p) = &self.params.gate {
+            self.gate.process(p, samples, channels);
+        }
+        if let Some(p) = &self.params.de_esser {
+            self.de_esser.process(p, samples, channels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // Only the stages a test turns on; the high-pass is on by default
+    fn chain(f: impl FnOnce(&mut MicSettings)) -> MicChain {
+        let high_pass = HighPassSettings { enabled: false, frequency: 80.0 };
+        let mut settings = MicSettings { high_pass, ..Default::default() };
+        f(&mut settings);
+        settings.validate().unwrap();
+        MicChain::new(MicParams::new(&settings, RATE), RATE)
+    }
+
+    fn gate(threshold_db: f32, hysteresis_db: f32, hold_ms: f32, range_db: f32) -> MicChain {
+        let times = GateSettings { attack_ms: 1.0, release_ms: 5.0, ..Default::default() };
+        chain(|s| s.gate = GateSettings { enabled: true, threshold_db, hysteresis_db, hold_ms, range_db, ..times })
+    }
+
+    // Mono, in engine-sized blocks
+    fn run(chain: &mut MicChain, input: &[f32]) -> Vec<f32> {
+        let mut out = input.to_vec();
+        for block in out.chunks_mut(512) {
+            chain.process(block, 1);
+        }
+        out
+    }
+
+    fn db(level: f32) -> f32 {
+        20.0 * level.abs().max(1e-9).log10()
+    }
+
+    fn level(db: f32, frames: usize) -> Vec<f32> {
+        vec![10f32.powf(db / 20.0); frames]
+    }
+
+    fn sine(freq: f32, amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames).map(|i| amplitude * (2.0 * std::f32::consts::PI * freq * i as f32 / RATE as f32).sin()).collect()
+    }
+
+    // Amplitude of one frequency, over a whole number of its periods
+    fn amplitude_at(signal: &[f32], freq: f32) -> f32 {
+        let (mut re, mut im) = (0.0f64, 0.0f64);
+        for (i, s) in signal.iter().enumerate() {
+            let phase = 2.0 * std::f64::consts::PI * freq as f64 * i as f64 / RATE as f64;
+            re += *s as f64 * phase.cos();
+            im += *s as f64 * phase.sin();
+        }
+        (2.0 * (re * re + im * im).sqrt() / signal.len() as f64) as f32
+    }
+
+    #[test]
+    fn gate_stays_open_between_its_thresholds() {
+        let mut mic = gate(-30.0, 10.0, 50.0, -80.0);
+        run(&mut mic, &level(-20.0, 4800));
+        assert!(mic.gate_open());
+        // Below the open threshold but above the close one: the level passes untouched for good
+        let out = run(&mut mic, &level(-35.0, RATE as usize));
+        assert!(mic.gate_open());
+        assert!(out.iter().all(|s| (db(*s) + 35.0).abs() < 0.01));
+        // Below the close threshold it still waits for the hold time
+        run(&mut mic, &level(-50.0, 40 * RATE as usize / 1000));
+        assert!(mic.gate_open());
+        run(&mut mic, &level(-50.0, 100 * RATE as usize / 1000));
+        assert!(!mic.gate_open());
+        // Coming back between the thresholds doesn't reopen it
+        run(&mut mic, &level(-35.0, RATE as usize));
+        assert!(!mic.gate_open());
+    }
+
+    #[test]
+    fn hold_restarts_on_every_opening() {
+        let mut mic = gate(-30.0, 6.0, 100.0, -80.0);
+        for _ in 0..10 {
+            run(&mut mic, &level(-20.0, 480));
+            run(&mut mic, &level(-60.0, 80 * RATE as usize / 1000));
+            assert!(mic.gate_open());
+        }
+    }
+
+    #[test]
+    fn range_sets_the_closed_attenuation() {
+        for range in [-80.0, -20.0, -6.0] {
+            let mut mic = gate(-30.0, 6.0, 0.0, range);
+            let out = run(&mut mic, &level(-50.0, RATE as usize / 2));
+            assert!(!mic.gate_open());
+            let settled = db(out[out.len() - 1]);
+            assert!((settled - (-50.0 + range)).abs() < 0.05, "range {} gave {} dB", range, settled);
+        }
+    }
+
+    #[test]
+    fn high_pass_is_3db_down_at_its_cutoff() {
+        for cutoff in [80.0, 150.0, 400.0] {
+            let mut mic = chain(|s| s.high_pass = HighPassSettings { enabled: true, frequency: cutoff });
+            let out = run(&mut mic, &sine(cutoff, 0.5, RATE as usize * 2));
+            let settled = &out[RATE as usize..];
+            let peak = settled.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            assert!((db(peak / 0.5) + 3.01).abs() < 0.05, "{} Hz: {} dB", cutoff, db(peak / 0.5));
+            // And passes speech well above it
+            let out = run(&mut mic, &sine(cutoff * 8.0, 0.5, RATE as usize));
+            let peak = out[RATE as usize / 2..].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            assert!(db(peak / 0.5).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn de_esser_only_turns_down_the_sibilance_band() {
+        let de_esser = DeEsserSettings { enabled: true, threshold_db: -30.0, ..Default::default() };
+        let mut mic = chain(|s| s.de_esser = de_esser);
+        // Loud voice fundamental plus loud sibilance; 4800 frames hold whole periods of both
+        let frames = RATE as usize;
+        let input: Vec<f32> =
+            sine(480.0, 0.4, frames).iter().zip(sine(12_000.0, 0.2, frames)).map(|(a, b)| a + b).collect();
+        let out = run(&mut mic, &input);
+        let tail = &out[frames - 4800..];
+        let voice = db(amplitude_at(tail, 480.0) / 0.4);
+        assert!(voice.abs() < 0.1, "voice at {} dB", voice);
+        let cut = db(amplitude_at(tail, 12_000.0) / 0.2);
+        assert!(cut < -3.0 && cut > -10.5, "sibilance at {} dB", cut);
+
+        // Without sibilance the voice passes as is
+        let mut mic = chain(|s| s.de_esser.enabled = true);
+        let out = run(&mut mic, &sine(480.0, 0.8, frames));
+        assert!(db(amplitude_at(&out[frames - 4800..], 480.0) / 0.8).abs() < 0.05);
+    }
+}
@@ -0,0 +1,980 @@
+// Real-time mixing engine: one bus per StreamId sums its inputs, applies gain and is
+// mixed into the output of the device it is routed to; aux buses collect sends from the
+// stream buses and are rendered after them. `Engine` itself knows nothing
+// about sound cards; `live.rs` drives it from cpal callbacks, and `render_offline`
+// runs the same graph without any device.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use crate::StreamId;
+
+pub mod buffer;
+pub mod clap;
+pub mod denoise;
+pub mod dynamics;
+pub mod eq;
+pub mod flac;
+pub mod hdf5;
+pub mod latency;
+pub mod live;
+pub mod loudness;
+pub mod matrix;
+pub mod meter;
+pub mod mic;
+pub mod record;
+pub mod replay;
+pub mod resample;
+pub mod routing;
+pub mod sends;
+pub mod soundboard;
+pub mod source;
+pub mod spatial;
+pub mod virtual_mic;
+pub mod wav;
+
+pub use buffer::AudioBuffer;
+pub use clap::{ClapInstance, ClapProcessor};
+pub use dynamics::{Dynamics, DynamicsParams, DynamicsSettings};
+pub use eq::{EqChain, EqDesign, EqSettings};
+pub use live::{EngineConfig, EngineStatus, LiveEngine};
+pub use loudness::{AutoGain, AutoGainSettings, LoudnessMeter};
+pub use matrix::{ChannelMatrix, MatrixSettings};
+pub use meter::{MeterReading, Meters};
+pub use mic::{MicChain, MicParams, MicSettings};
+pub use record::{RecordFormat, RecordSource};
+pub use resample::ResamplerQuality;
+pub use routing::{MixLevel, TapSpec};
+pub use sends::{AuxBusSettings, SendSettings};
+pub use source::{SineSource, Source};
+pub use spatial::{SpatialConfig, SpatialParams, SpatialSettings, Virtualizer};
+
+// Largest block rendered in one go; device callbacks asking for more are split
+pub const MAX_BLOCK_FRAMES: usize = 1024;
+
+// Gain changes are ramped over this long to avoid zipper noise
+const GAIN_RAMP_SECONDS: f32 = 0.02;
+
+// Output key: device ID, `None` being the default output (same as `Routes`)
+pub type OutputKey = Option<String>;
+
+// A node of the routing graph: a stream bus, or an aux bus by ID. Serialized as the
+// plain stream name or aux ID.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BusId {
+    Stream(StreamId),
+    Aux(String),
+}
+
+// Control messages, drained by the audio thread at the start of every block
+#[allow(clippy::enum_variant_names)]
+pub enum EngineCommand {
+    SetGain(StreamId, f32),
+    SetMuted(StreamId, bool),
+    SetEq(StreamId, Box<EqDesign>),
+    SetDynamics(StreamId, DynamicsParams),
+    SetAutoGain(StreamId, AutoGainSettings),
+    SetMic(StreamId, Box<MicParams>),
+    SetSpatial(StreamId, Box<SpatialParams>),
+    // Bus -> output channel matrix; built against the device's channel count
+    SetMatrix(StreamId, Box<MatrixSettings>),
+    // Replaces the bus's plugin chain; instances must be active
+    SetPlugins(BusId, Vec<ClapProcessor>),
+    // Send from a stream bus to the aux bus with that ID
+    SetSend(StreamId, String, SendSettings),
+    SetAuxGain(String, f32),
+    SetAuxMuted(String, bool),
+    // Level of a stream in the broadcast mix
+    SetBroadcast(StreamId, MixLevel),
+    // Level, delay and tap point of the extra output of a stream on that device
+    SetTap(StreamId, TapSpec),
+    // Applied to every output
+    SetMasterDynamics(DynamicsParams),
+    // Hold back everything played on that device, in ms
+    SetOutputDelay(OutputKey, f32),
+    // Replaces the recording taps; empty ones end the take
+    SetRecording(record::RecordTaps),
+    // Same for the replay buffer
+    SetReplay(record::RecordTaps),
+    // And for the virtual microphone feed
+    SetVirtualMic(record::RecordTaps),
+    // Soundboard: start a clip, or fade out the clips of a slot (all if `None`)
+    PlayClip(soundboard::Trigger),
+    StopClips(Option<usize>),
+}
+
+#[derive(Debug, Clone)]
+pub struct BusSpec {
+    pub stream: StreamId,
+    pub channels: usize,
+    pub gain: f32,
+    pub muted: bool,
+    pub output: OutputKey,
+    pub eq: EqSettings,
+    pub dynamics: DynamicsSettings,
+    pub auto_gain: AutoGainSettings,
+    // Input processing, used on the Voice bus
+    pub mic: Option<MicSettings>,
+    // Device that gets the processed signal before the fader (e.g. a virtual cable
+    // other apps record as microphone)
+    pub direct_out: Option<String>,
+    // HRTF virtualizer, used on the Game bus; the bus itself is stereo then and
+    // `channels` is the width of its input
+    pub spatial: Option<SpatialConfig>,
+    // Mix from the bus into its output device
+    pub matrix: MatrixSettings,
+    // Broadcast mix: the processed bus before the fader, at its own level
+    pub broadcast: Option<TapSpec>,
+    // Further devices playing the bus besides `output`
+    pub extra_outputs: Vec<TapSpec>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineSpec {
+    pub sample_rate: u32,
+    pub buses: Vec<BusSpec>,
+    // Channel count per output; outputs only referenced by a bus default to stereo
+    pub outputs: BTreeMap<OutputKey, usize>,
+    pub master: DynamicsSettings,
+    pub aux: Vec<AuxBusSettings>,
+    // Delay per output in ms, to line up devices with different latency
+    pub output_delays: BTreeMap<OutputKey, f32>,
+}
+
+// Output buffer plus its master processing
+struct Output {
+    buffer: AudioBuffer,
+    dynamics: Dynamics,
+    delay: routing::DelayLine,
+}
+
+// Channels preallocated per source read; wider sources grow the scratch once
+const SCRATCH_CHANNELS: usize = 8;
+
+// Aux buses are stereo
+const AUX_CHANNELS: usize = 2;
+
+// A stream bus's feed into one aux bus. Every bus has one per aux bus, silent unless
+// configured, so sends can change without allocating on the audio thread.
+struct AuxSend {
+    aux: usize,
+    pre_fader: bool,
+    level: f32,
+    target: f32,
+    matrix: ChannelMatrix,
+}
+
+struct AuxBus {
+    id: BusId,
+    buffer: AudioBuffer,
+    plugins: Vec<ClapProcessor>,
+    gain: f32,
+    target_gain: f32,
+    muted: bool,
+    output: OutputKey,
+    matrix: ChannelMatrix,
+}
+
+struct Bus {
+    stream: StreamId,
+    sources: Vec<Box<dyn Source>>,
+    scratch: Vec<f32>,
+    buffer: AudioBuffer,
+    spatial: Option<Virtualizer>,
+    mic: Option<MicChain>,
+    eq: EqChain,
+    plugins: Vec<ClapProcessor>,
+    dynamics: Dynamics,
+    loudness: LoudnessMeter,
+    auto_gain: AutoGain,
+    gain: f32,
+    target_gain: f32,
+    muted: bool,
+    output: OutputKey,
+    matrix: ChannelMatrix,
+    direct_out: Option<OutputKey>,
+    // Always the standard down/upmix
+    direct_matrix: ChannelMatrix,
+    sends: Vec<AuxSend>,
+    broadcast: Option<routing::Tap>,
+    extra_outputs: Vec<routing::Tap>,
+}
+
+// Add every source into `dst`, whose block has been started
+fn sum_sources(sources: &mut [Box<dyn Source>], scratch: &mut Vec<f32>, dst: &mut AudioBuffer) {
+    let frames = dst.samples().len() / dst.channels();
+    let channels = dst.channels();
+    for source in sources.iter_mut() {
+        let ch = source.channels();
+        let n = frames * ch;
+        if n > scratch.len() {
+            scratch.resize(n, 0.0);
+        }
+        let scratch = &mut scratch[..n];
+        source.read(scratch);
+        buffer::mix_into(scratch, ch, dst.samples_mut(), channels, 1.0);
+    }
+}
+
+impl Bus {
+    fn effective_target(&self) -> f32 {
+        if self.muted { 0.0 } else { self.target_gain }
+    }
+
+    // Sum the sources and run the processing chain, up to (not including) the fader.
+    // Returns the gain reduction of the dynamics section in dB.
+    fn process(&mut self, frames: usize) -> f32 {
+        self.buffer.begin(frames);
+        let channels = self.buffer.channels();
+        match self.spatial.as_mut() {
+            Some(v) => {
+                sum_sources(&mut self.sources, &mut self.scratch, v.begin(frames));
+                v.process(self.buffer.samples_mut());
+            }
+            None => sum_sources(&mut self.sources, &mut self.scratch, &mut self.buffer),
+        }
+
+        if let Some(mic) = self.mic.as_mut() {
+            mic.process(self.buffer.samples_mut(), channels);
+        }
+        self.eq.process(self.buffer.samples_mut(), channels);
+        for plugin in self.plugins.iter_mut() {
+            plugin.process(self.buffer.samples_mut(), channels);
+        }
+        let reduction = self.dynamics.process(self.buffer.samples_mut(), channels);
+        // Loudness is measured before auto-gain, so it shows what the source delivers
+        self.loudness.measure(self.buffer.samples(), channels);
+        self.auto_gain.process(&self.loudness, self.buffer.samples_mut(), channels);
+        reduction
+    }
+
+    fn apply_fader(&mut self, ramp_step: f32) {
+        let channels = self.buffer.channels();
+        let target = self.effective_target();
+        ramp_gain(self.buffer.samples_mut(), channels, &mut self.gain, target, ramp_step);
+    }
+
+    // Mix the bus as it is now into the extra outputs tapped before or after the fader
+    fn feed_extra_outputs(&mut self, outputs: &mut BTreeMap<OutputKey, Output>, pre_fader: bool) {
+        let channels = self.buffer.channels();
+        for tap in self.extra_outputs.iter_mut().filter(|t| t.pre_fader == pre_fader) {
+            if let Some(out) = outputs.get_mut(&tap.output) {
+                let ch = out.buffer.channels();
+                tap.mix_into(self.buffer.samples(), channels, out.buffer.samples_mut(), ch);
+            }
+        }
+    }
+
+    // Mix the bus as it is now into the aux buses of the pre- or post-fader sends
+    fn feed_sends(&mut self, aux: &mut [AuxBus], pre_fader: bool) {
+        let channels = self.buffer.channels();
+        for send in self.sends.iter_mut().filter(|s| s.pre_fader == pre_fader) {
+            if send.level == 0.0 && send.target == 0.0 {
+                continue;
+            }
+            let dst = &mut aux[send.aux].buffer;
+            let ch = dst.channels();
+            send.matrix.mix_into_ramped(self.buffer.samples(), channels, dst.samples_mut(), ch, send.level, send.target);
+            send.level = send.target;
+        }
+    }
+}
+
+// Linear ramp from `gain` towards `target`, multiplied into the block
+fn ramp_gain(samples: &mut [f32], channels: usize, gain: &mut f32, target: f32, ramp_step: f32) {
+    let mut g = *gain;
+    for frame in samples.chunks_mut(channels) {
+        if g != target {
+            g = if g < target { (g + ramp_step).min(target) } else { (g - ramp_step).max(target) };
+        }
+        for s in frame.iter_mut() {
+            *s *= g;
+        }
+    }
+    *gain = g;
+}
+
+pub struct Engine {
+    sample_rate: u32,
+    buses: Vec<Bus>,
+    aux: Vec<AuxBus>,
+    outputs: BTreeMap<OutputKey, Output>,
+    commands: mpsc::Receiver<EngineCommand>,
+    meters: Arc<Meters>,
+    ramp_step: f32,
+    capture: record::Captures,
+    soundboard: soundboard::Player,
+}
+
+impl Engine {
+    // Returns the engine and the sender for control messages
+    pub fn new(spec: EngineSpec, meters: Arc<Meters>) -> (Self, mpsc::Sender<EngineCommand>) {
+        let (tx, rx) = mpsc::channel();
+        let master = DynamicsParams::new(&spec.master, spec.sample_rate);
+        let new_output = |key: &OutputKey, channels: usize| {
+            let mut delay = routing::DelayLine::new(channels, spec.sample_rate);
+            delay.set_delay_ms(spec.output_delays.get(key).copied().unwrap_or(0.0));
+            Output { buffer: AudioBuffer::new(channels, MAX_BLOCK_FRAMES), dynamics: Dynamics::new(master), delay }
+        };
+        let mut outputs: BTreeMap<OutputKey, Output> =
+            spec.outputs.iter().map(|(key, ch)| (key.clone(), new_output(key, *ch))).collect();
+        let aux: Vec<AuxBus> = spec
+            .aux
+            .iter()
+            .map(|a| {
+                let output = outputs.entry(a.output.clone()).or_insert_with(|| new_output(&a.output, 2));
+                let gain = a.gain.clamp(0.0, 1.0);
+                AuxBus {
+                    id: BusId::Aux(a.id.clone()),
+                    buffer: AudioBuffer::new(AUX_CHANNELS, MAX_BLOCK_FRAMES),
+                    plugins: Vec::new(),
+                    gain: if a.muted { 0.0 } else { gain },
+                    target_gain: gain,
+                    muted: a.muted,
+                    output: a.output.clone(),
+                    matrix: ChannelMatrix::new(&MatrixSettings::default(), AUX_CHANNELS, output.buffer.channels()),
+                }
+            })
+            .collect();
+        let buses = spec
+            .buses
+            .into_iter()
+            .map(|b| {
+                outputs.entry(b.output.clone()).or_insert_with(|| new_output(&b.output, 2));
+                let direct_out = b.direct_out.map(Some);
+                if let Some(key) = &direct_out {
+                    outputs.entry(key.clone()).or_insert_with(|| new_output(key, 2));
+                }
+                let gain = b.gain.clamp(0.0, 1.0);
+                // Bypassed spatial processing leaves the bus out of the virtualizer entirely
+                let spatial = b
+                    .spatial
+                    .filter(|s| s.enabled)
+                    .map(|s| Virtualizer::new(SpatialParams::new(&s, spec.sample_rate), b.channels));
+                let bus_channels = if spatial.is_some() { 2 } else { b.channels };
+                let out_channels = |key: &OutputKey| outputs.get(key).map_or(2, |o| o.buffer.channels());
+                let output_channels = out_channels(&b.output);
+                if !b.matrix.fits(bus_channels, output_channels) {
+                    log::warn!(
+                        "Channel matrix of {:?} doesn't fit {} -> {} channels, using the standard mix",
+                        b.stream,
+                        bus_channels,
+                        output_channels
+                    );
+                }
+                let matrix = ChannelMatrix::new(&b.matrix, bus_channels, output_channels);
+                let direct_matrix = ChannelMatrix::new(
+                    &MatrixSettings::default(),
+                    bus_channels,
+                    direct_out.as_ref().map_or(2, out_channels),
+                );
+                let mut tap = |t: &TapSpec, delay: Option<(usize, u32)>| {
+                    let channels = outputs.entry(t.output.clone()).or_insert_with(|| new_output(&t.output, 2)).buffer.channels();
+                    routing::Tap::new(t, ChannelMatrix::new(&MatrixSettings::default(), bus_channels, channels), delay)
+                };
+                let broadcast = b.broadcast.as_ref().map(|t| tap(t, None));
+                let extra_outputs =
+                    b.extra_outputs.iter().map(|t| tap(t, Some((bus_channels, spec.sample_rate)))).collect();
+                let send_matrix = ChannelMatrix::new(&MatrixSettings::default(), bus_channels, AUX_CHANNELS);
+                let sends = spec
+                    .aux
+                    .iter()
+                    .enumerate()
+                    .map(|(i, a)| {
+                        let send = a.send(&b.stream);
+                        let level = send.level.clamp(0.0, 1.0);
+                        AuxSend { aux: i, pre_fader: send.pre_fader, level, target: level, matrix: send_matrix }
+                    })
+                    .collect();
+                Bus {
+                    stream: b.stream,
+                    sources: Vec::new(),
+                    scratch: vec![0.0; SCRATCH_CHANNELS * MAX_BLOCK_FRAMES],
+                    buffer: AudioBuffer::new(bus_channels, MAX_BLOCK_FRAMES),
+                    spatial,
+                    mic: b.mic.map(|m| MicChain::new(MicParams::new(&m, spec.sample_rate), spec.sample_rate)),
+                    eq: EqChain::new(EqDesign::new(&b.eq, spec.sample_rate)),
+                    plugins: Vec::new(),
+                    dynamics: Dynamics::new(DynamicsParams::new(&b.dynamics, spec.sample_rate)),
+                    loudness: LoudnessMeter::new(spec.sample_rate),
+                    auto_gain: AutoGain::new(b.auto_gain, spec.sample_rate),
+                    gain: if b.muted { 0.0 } else { gain },
+                    target_gain: gain,
+                    muted: b.muted,
+                    output: b.output,
+                    matrix,
+                    direct_out,
+                    direct_matrix,
+                    sends,
+                    broadcast,
+                    extra_outputs,
+                }
+            })
+            .collect();
+        let ramp_step = 1.0 / (GAIN_RAMP_SECONDS * spec.sample_rate as f32).max(1.0);
+        let engine = Engine {
+            sample_rate: spec.sample_rate,
+            buses,
+            aux,
+            outputs,
+            commands: rx,
+            meters,
+            ramp_step,
+            capture: Default::default(),
+            soundboard: soundboard::Player::new(spec.sample_rate),
+        };
+        (engine, tx)
+    }
+
+    pub fn add_source(&mut self, stream: &StreamId, source: Box<dyn Source>) {
+        if let Some(bus) = self.buses.iter_mut().find(|b| b.stream == *stream) {
+            bus.sources.push(source);
+        }
+    }
+
+    // Everything that can be recorded, with its channel count
+    pub fn recordable(&self) -> BTreeMap<RecordSource, usize> {
+        let buses = self.buses.iter().map(|b| (RecordSource::Bus(BusId::Stream(b.stream.clone())), b.buffer.channels()));
+        let mic = self.buses.iter().filter(|b| b.stream == StreamId::Voice).map(|b| (RecordSource::Mic, b.buffer.channels()));
+        let aux = self.aux.iter().map(|a| (RecordSource::Bus(a.id.clone()), a.buffer.channels()));
+        let outputs = self.outputs.iter().map(|(key, o)| (RecordSource::Output(key.clone()), o.buffer.channels()));
+        let soundboard = std::iter::once((RecordSource::Soundboard, soundboard::MIC_CHANNELS));
+        buses.chain(mic).chain(aux).chain(outputs).chain(soundboard).collect()
+    }
+
+    fn bus_mut(&mut self, stream: &StreamId) -> Option<&mut Bus> {
+        self.buses.iter_mut().find(|b| b.stream == *stream)
+    }
+
+    fn aux_index(&self, id: &str) -> Option<usize> {
+        self.aux.iter().position(|a| matches!(&a.id, BusId::Aux(aux_id) if aux_id == id))
+    }
+
+    fn aux_mut(&mut self, id: &str) -> Option<&mut AuxBus> {
+        self.aux_index(id).map(|i| &mut self.aux[i])
+    }
+
+    fn apply_commands(&mut self) {
+        while let Ok(cmd) = self.commands.try_recv() {
+            match cmd {
+                EngineCommand::SetGain(stream, gain) => {
+                    if let Some(bus) = self.bus_mut(&stream) {
+                        bus.target_gain = gain.clamp(0.0, 1.0);
+                    }
+                }
+                EngineCommand::SetMuted(stream, muted) => {
+                    if let Some(bus) = self.bus_mut(&stream) {
+                        bus.muted = muted;
+                    }
+                }
+                EngineCommand::SetEq(stream, design) => {
+                    if let Some(bus) = self.bus_mut(&stream) {
+                        bus.eq.set_design(*design);
+                    }
+                }
+                EngineCommand::SetDynamics(stream, params) => {
+                    if let Some(bus) = self.bus_mut(&stream) {
+                        bus.dynamics.set_params(params);
+                    }
+                }
+                EngineCommand::SetAutoGain(stream, settings) => {
+                    if let Some(bus) = self.bus_mut(&stream) {
+                        bus.auto_gain.set_settings(settings);
+                    }
+                }
+                EngineCommand::SetMic(stream, params) => {
+                    if let Some(mic) = self.bus_mut(&stream).and_then(|b| b.mic.as_mut()) {
+                        mic.set_params(*params);
+                    }
+                }
+                EngineCommand::SetSpatial(stream, params) => {
+                    if let Some(v) = self.bus_mut(&stream).and_then(|b| b.spatial.as_mut()) {
+                        v.set_params(*params);
+                    }
+                }
+                EngineCommand::SetMatrix(stream, settings) => {
+                    let Some(bus) = self.buses.iter_mut().find(|b| b.stream == stream) else { continue };
+                    let outputs = self.outputs.get(&bus.output).map_or(2, |o| o.buffer.channels());
+                    bus.matrix = ChannelMatrix::new(&settings, bus.buffer.channels(), outputs);
+                }
+                EngineCommand::SetPlugins(BusId::Stream(stream), plugins) => {
+                    if let Some(bus) = self.bus_mut(&stream) {
+                        bus.plugins = plugins;
+                    }
+                }
+                EngineCommand::SetPlugins(BusId::Aux(id), plugins) => {
+                    if let Some(aux) = self.aux_mut(&id) {
+                        aux.plugins = plugins;
+                    }
+                }
+                EngineCommand::SetSend(stream, id, settings) => {
+                    let Some(index) = self.aux_index(&id) else { continue };
+                    let Some(bus) = self.bus_mut(&stream) else { continue };
+                    if let Some(send) = bus.sends.iter_mut().find(|s| s.aux == index) {
+                        send.target = settings.level.clamp(0.0, 1.0);
+                        send.pre_fader = settings.pre_fader;
+                    }
+                }
+                EngineCommand::SetAuxGain(id, gain) => {
+                    if let Some(aux) = self.aux_mut(&id) {
+                        aux.target_gain = gain.clamp(0.0, 1.0);
+                    }
+                }
+                EngineCommand::SetAuxMuted(id, muted) => {
+                    if let Some(aux) = self.aux_mut(&id) {
+                        aux.muted = muted;
+                    }
+                }
+                EngineCommand::SetBroadcast(stream, level) => {
+                    if let Some(tap) = self.bus_mut(&stream).and_then(|b| b.broadcast.as_mut()) {
+                        tap.set_level(level);
+                    }
+                }
+                EngineCommand::SetTap(stream, spec) => {
+                    let Some(bus) = self.bus_mut(&stream) else { continue };
+                    if let Some(tap) = bus.extra_outputs.iter_mut().find(|t| t.output == spec.output) {
+                        tap.update(&spec);
+                    }
+                }
+                EngineCommand::SetMasterDynamics(params) => {
+                    for out in self.outputs.values_mut() {
+                        out.dynamics.set_params(params);
+                    }
+                }
+                EngineCommand::SetOutputDelay(key, ms) => {
+                    if let Some(out) = self.outputs.get_mut(&key) {
+                        out.delay.set_delay_ms(ms);
+                    }
+                }
+                EngineCommand::SetRecording(taps) => self.capture.recording = taps,
+                EngineCommand::SetReplay(taps) => self.capture.replay = taps,
+                EngineCommand::SetVirtualMic(taps) => self.capture.virtual_mic = taps,
+                EngineCommand::PlayClip(trigger) => self.soundboard.play(trigger),
+                EngineCommand::StopClips(slot) => self.soundboard.stop(slot),
+            }
+        }
+    }
+
+    // Render one block of `frames` (at most MAX_BLOCK_FRAMES) into the output buffers
+    pub fn render(&mut self, frames: usize) {
+        let frames = frames.min(MAX_BLOCK_FRAMES);
+        self.apply_commands();
+        self.capture.begin(frames);
+        self.soundboard.begin(frames);
+
+        for out in self.outputs.values_mut() {
+            out.buffer.begin(frames);
+        }
+        for aux in self.aux.iter_mut() {
+            aux.buffer.begin(frames);
+        }
+        for bus in self.buses.iter_mut() {
+            let reduction = bus.process(frames);
+            if bus.stream == StreamId::Voice {
+                self.capture.push(|s| *s == RecordSource::Mic, bus.buffer.samples());
+            }
+            if let Some(out) = bus.direct_out.as_ref().and_then(|key| self.outputs.get_mut(key)) {
+                let ch = out.buffer.channels();
+                bus.direct_matrix.mix_into(bus.buffer.samples(), bus.buffer.channels(), out.buffer.samples_mut(), ch);
+            }
+            // Clips skip the bus's processing but go through everything from here on
+            let channels = bus.buffer.channels();
+            self.soundboard.mix_bus(&bus.stream, frames, bus.buffer.samples_mut(), channels);
+            if let Some(tap) = bus.broadcast.as_mut() {
+                if let Some(out) = self.outputs.get_mut(&tap.output) {
+                    let ch = out.buffer.channels();
+                    tap.mix_into(bus.buffer.samples(), bus.buffer.channels(), out.buffer.samples_mut(), ch);
+                }
+            }
+            bus.feed_extra_outputs(&mut self.outputs, true);
+            bus.feed_sends(&mut self.aux, true);
+            bus.apply_fader(self.ramp_step);
+            bus.feed_extra_outputs(&mut self.outputs, false);
+            bus.feed_sends(&mut self.aux, false);
+            self.capture
+                .push(|s| matches!(s, RecordSource::Bus(BusId::Stream(st)) if *st == bus.stream), bus.buffer.samples());
+
+            let meter = self.meters.bus(&BusId::Stream(bus.stream.clone()));
+            meter.update(bus.buffer.samples(), frames, self.sample_rate);
+            meter.set_gain_reduction(reduction);
+            meter.set_loudness(bus.loudness.momentary(), bus.loudness.short_term(), bus.auto_gain.gain_db());
+            meter.set_gate_open(bus.mic.as_ref().is_none_or(|m| m.gate_open()));
+            if let Some(out) = self.outputs.get_mut(&bus.output) {
+                let ch = out.buffer.channels();
+                bus.matrix.mix_into(bus.buffer.samples(), bus.buffer.channels(), out.buffer.samples_mut(), ch);
+            }
+        }
+        self.capture.push(|s| *s == RecordSource::Soundboard, self.soundboard.finish(frames));
+        for aux in self.aux.iter_mut() {
+            let channels = aux.buffer.channels();
+            for plugin in aux.plugins.iter_mut() {
+                plugin.process(aux.buffer.samples_mut(), channels);
+            }
+            let target = if aux.muted { 0.0 } else { aux.target_gain };
+            ramp_gain(aux.buffer.samples_mut(), channels, &mut aux.gain, target, self.ramp_step);
+            self.capture.push(|s| matches!(s, RecordSource::Bus(id) if *id == aux.id), aux.buffer.samples());
+            self.meters.bus(&aux.id).update(aux.buffer.samples(), frames, self.sample_rate);
+            if let Some(out) = self.outputs.get_mut(&aux.output) {
+                let ch = out.buffer.channels();
+                aux.matrix.mix_into(aux.buffer.samples(), channels, out.buffer.samples_mut(), ch);
+            }
+        }
+        for (key, out) in self.outputs.iter_mut() {
+            let ch = out.buffer.channels();
+            out.dynamics.process(out.buffer.samples_mut(), ch);
+            self.capture.push(|s| matches!(s, RecordSource::Output(k) if k == key), out.buffer.samples());
+            let delayed = out.delay.process(out.buffer.samples());
+            out.buffer.samples_mut().copy_from_slice(delayed);
+        }
+    }
+
+    pub fn output(&self, key: &OutputKey) -> Option<&AudioBuffer> {
+        self.outputs.get(key).map(|o| &o.buffer)
+    }
+
+    // Render `frames` frames without any audio device and return every output's
+    // interleaved samples
+    pub fn render_offline(&mut self, frames: usize) -> BTreeMap<OutputKey, Vec<f32>> {
+        let mut rendered: BTreeMap<OutputKey, Vec<f32>> = self
+            .outputs
+            .iter()
+            .map(|(key, out)| (key.clone(), Vec::with_capacity(frames * out.buffer.channels())))
+            .collect();
+        let mut remaining = frames;
+        while remaining > 0 {
+            let n = remaining.min(MAX_BLOCK_FRAMES);
+            self.render(n);
+            for (key, out) in self.outputs.iter() {
+                if let Some(samples) = rendered.get_mut(key) {
+                    samples.extend_from_slice(out.buffer.samples());
+                }
+            }
+            remaining -= n;
+        }
+        rendered
+    }
+}
+
+// Render a second of test tone through every bus of `config` without touching any
+// device, and report the peak level (dBFS) reaching each output. Lets users check the
+// routing graph separately from driver problems.
+pub fn self_test(config: &EngineConfig, sample_rate: u32) -> BTreeMap<String, f32> {
+    let spec = EngineSpec {
+        sample_rate,
+        buses: config
+            .buses
+            .iter()
+            .map(|b| BusSpec {
+                stream: b.stream.clone(),
+                channels: 2,
+                gain: b.gain,
+                muted: b.muted,
+                output: b.output.clone(),
+                eq: b.eq.clone(),
+                dynamics: b.dynamics,
+                auto_gain: b.auto_gain,
+                mic: b.mic,
+                direct_out: b.direct_out.clone(),
+                spatial: b.spatial.clone(),
+                matrix: b.matrix.clone(),
+                broadcast: b.broadcast.clone(),
+                extra_outputs: b.extra_outputs.clone(),
+            })
+            .collect(),
+        outputs: BTreeMap::new(),
+        master: config.master,
+        aux: config.aux.clone(),
+        output_delays: config.output_delays.clone(),
+    };
+    let (mut engine, _control) = Engine::new(spec, Arc::new(Meters::new(&config.aux)));
+    for (i, bus) in config.buses.iter().enumerate() {
+        // A different tone per bus, -6 dBFS each
+        let tone = SineSource::new(2, sample_rate, 220.0 * (i + 1) as f64, 0.5);
+        engine.add_source(&bus.stream, Box::new(tone));
+    }
+    engine
+        .render_offline(sample_rate as usize)
+        .into_iter()
+        .map(|(key, samples)| {
+            let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            (key.unwrap_or_else(|| "default".into()), meter::to_db(peak))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // Stereo source holding `left`/`right` forever
+    struct Constant(f32, f32);
+
+    impl Source for Constant {
+        fn channels(&self) -> usize {
+            2
+        }
+
+        fn read(&mut self, out: &mut [f32]) {
+            for frame in out.chunks_exact_mut(2) {
+                frame[0] = self.0;
+                frame[1] = self.1;
+            }
+        }
+    }
+
+    fn bus(stream: StreamId, output: OutputKey) -> BusSpec {
+        BusSpec {
+            stream,
+            channels: 2,
+            gain: 1.0,
+            muted: false,
+            output,
+            eq: EqSettings::default(),
+            dynamics: DynamicsSettings::default(),
+            auto_gain: AutoGainSettings::default(),
+            mic: None,
+            direct_out: None,
+            spatial: None,
+            matrix: MatrixSettings::default(),
+            broadcast: None,
+            extra_outputs: Vec::new(),
+        }
+    }
+
+    fn engine(buses: Vec<BusSpec>, outputs: &[OutputKey]) -> (Engine, mpsc::Sender<EngineCommand>) {
+        let spec = EngineSpec {
+            sample_rate: RATE,
+            buses,
+            outputs: outputs.iter().map(|key| (key.clone(), 2)).collect(),
+            master: DynamicsSettings::default(),
+            aux: Vec::new(),
+            output_delays: BTreeMap::new(),
+        };
+        Engine::new(spec, Arc::new(Meters::new(&[])))
+    }
+
+    fn engine_with_aux(buses: Vec<BusSpec>, aux: Vec<AuxBusSettings>) -> (Engine, mpsc::Sender<EngineCommand>) {
+        let meters = Arc::new(Meters::new(&aux));
+        let spec = EngineSpec {
+            sample_rate: RATE,
+            buses,
+            outputs: BTreeMap::new(),
+            master: DynamicsSettings::default(),
+            aux,
+            output_delays: BTreeMap::new(),
+        };
+        Engine::new(spec, meters)
+    }
+
+    // Aux bus on its own device, fed by a single send
+    fn aux(output: &str, stream: StreamId, send: SendSettings) -> AuxBusSettings {
+        AuxBusSettings {
+            id: "aux-1".into(),
+            name: "Reverb".into(),
+            output: device(output),
+            gain: 1.0,
+            muted: false,
+            sends: BTreeMap::from([(stream, send)]),
+        }
+    }
+
+    fn spatial(enabled: bool) -> Option<SpatialConfig> {
+        Some(SpatialConfig { enabled, hrirs: Arc::new(spatial::HrirSet::builtin()) })
+    }
+
+    fn device(id: &str) -> OutputKey {
+        Some(id.to_string())
+    }
+
+    // Ramp from full gain to `target` takes this many frames
+    fn ramp_frames(from: f32, to: f32) -> usize {
+        ((from - to).abs() * GAIN_RAMP_SECONDS * RATE as f32).round() as usize
+    }
+
+    #[test]
+    fn buses_sum_into_their_output() {
+        let (mut engine, _control) = engine(vec![bus(StreamId::Game, None), bus(StreamId::Music, None)], &[]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.25, -0.25)));
+        // Two sources on one bus add up as well
+        engine.add_source(&StreamId::Music, Box::new(Constant(0.125, 0.0)));
+        engine.add_source(&StreamId::Music, Box::new(Constant(0.125, 0.5)));
+        let out = engine.render_offline(3000);
+        assert_eq!(out.len(), 1);
+        let samples = &out[&None];
+        assert_eq!(samples.len(), 6000);
+        for frame in samples.chunks_exact(2) {
+            assert!((frame[0] - 0.5).abs() < 1e-6, "{frame:?}");
+            assert!((frame[1] - 0.25).abs() < 1e-6, "{frame:?}");
+        }
+    }
+
+    #[test]
+    fn buses_reach_only_their_own_output() {
+        let buses = vec![
+            bus(StreamId::Game, device("speakers")),
+            bus(StreamId::Voice, device("headset")),
+            bus(StreamId::Music, device("speakers")),
+        ];
+        let (mut engine, _control) = engine(buses, &[None]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.5, 0.5)));
+        engine.add_source(&StreamId::Voice, Box::new(Constant(0.25, 0.25)));
+        let out = engine.render_offline(2048);
+        let keys: Vec<&OutputKey> = out.keys().collect();
+        assert_eq!(keys, [&None, &device("headset"), &device("speakers")]);
+        assert!(out[&device("speakers")].iter().all(|s| (s - 0.5).abs() < 1e-6));
+        assert!(out[&device("headset")].iter().all(|s| (s - 0.25).abs() < 1e-6));
+        // No bus plays on the default output
+        assert!(out[&None].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn buses_without_sources_are_silent() {
+        let buses = vec![bus(StreamId::Game, device("speakers")), bus(StreamId::Music, device("headset"))];
+        let (mut engine, _control) = engine(buses, &[]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.5, 0.5)));
+        let out = engine.render_offline(1500);
+        assert!(out[&device("headset")].iter().all(|s| *s == 0.0));
+        assert!(out[&device("speakers")].iter().all(|s| *s != 0.0));
+    }
+
+    #[test]
+    fn gain_changes_are_ramped() {
+        let (mut engine, control) = engine(vec![bus(StreamId::Game, None)], &[]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(1.0, 1.0)));
+        control.send(EngineCommand::SetGain(StreamId::Game, 0.5)).unwrap();
+        let out = engine.render_offline(2 * MAX_BLOCK_FRAMES);
+        let left: Vec<f32> = out[&None].chunks_exact(2).map(|f| f[0]).collect();
+        let ramp = ramp_frames(1.0, 0.5);
+        // No jump at the start, steady descent, then the new gain exactly
+        assert!(left[0] > 0.99, "{}", left[0]);
+        assert!(left[..ramp].windows(2).all(|w| w[1] < w[0]));
+        assert!((left[ramp / 2] - 0.75).abs() < 0.01, "{}", left[ramp / 2]);
+        assert!(left[ramp..].iter().all(|s| *s == 0.5));
+    }
+
+    #[test]
+    fn mute_ramps_down_and_back_up() {
+        let (mut engine, control) = engine(vec![bus(StreamId::Game, None)], &[]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.5, 0.5)));
+        control.send(EngineCommand::SetMuted(StreamId::Game, true)).unwrap();
+        let muted = engine.render_offline(MAX_BLOCK_FRAMES);
+        let ramp = ramp_frames(1.0, 0.0);
+        assert!(muted[&None][0] > 0.49);
+        assert!(muted[&None][..ramp * 2].windows(2).all(|w| w[1] <= w[0]));
+        assert!(muted[&None][ramp * 2..].iter().all(|s| *s == 0.0));
+
+        // The gain set while muted is what unmuting ramps back to
+        control.send(EngineCommand::SetGain(StreamId::Game, 0.5)).unwrap();
+        control.send(EngineCommand::SetMuted(StreamId::Game, false)).unwrap();
+        let unmuted = engine.render_offline(MAX_BLOCK_FRAMES);
+        let ramp = ramp_frames(0.0, 0.5);
+        assert!(unmuted[&None][0] < 0.01);
+        assert!(unmuted[&None][..ramp * 2].windows(2).all(|w| w[1] >= w[0]));
+        assert!(unmuted[&None][ramp * 2..].iter().all(|s| *s == 0.25));
+    }
+
+    #[test]
+    fn muted_bus_starts_silent() {
+        let mut game = bus(StreamId::Game, None);
+        game.muted = true;
+        let (mut engine, _control) = engine(vec![game], &[]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.5, 0.5)));
+        assert!(engine.render_offline(512)[&None].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn spatial_is_only_installed_when_enabled() {
+        let mut game = bus(StreamId::Game, None);
+        game.spatial = spatial(false);
+        let (mut disabled, _control) = engine(vec![game.clone()], &[]);
+        assert!(disabled.buses[0].spatial.is_none());
+        // The bus passes its input straight through
+        disabled.add_source(&StreamId::Game, Box::new(Constant(0.25, -0.5)));
+        let out = disabled.render_offline(1000);
+        assert!(out[&None].chunks_exact(2).all(|f| f == [0.25, -0.5]));
+
+        game.spatial = spatial(true);
+        let (enabled, _control) = engine(vec![game], &[]);
+        assert!(enabled.buses[0].spatial.is_some());
+    }
+
+    #[test]
+    fn pre_fader_sends_ignore_the_fader() {
+        let mut game = bus(StreamId::Game, device("speakers"));
+        game.gain = 0.25;
+        let send = SendSettings { level: 0.5, pre_fader: true };
+        let (mut engine, control) = engine_with_aux(vec![game], vec![aux("cable", StreamId::Game, send)]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.8, -0.8)));
+        let out = engine.render_offline(2048);
+        assert!(out[&device("speakers")].chunks_exact(2).all(|f| f == [0.2, -0.2]));
+        assert!(out[&device("cable")].chunks_exact(2).all(|f| f == [0.4, -0.4]));
+
+        // Turning the stream down or muting it leaves the send alone
+        control.send(EngineCommand::SetGain(StreamId::Game, 1.0)).unwrap();
+        control.send(EngineCommand::SetMuted(StreamId::Game, true)).unwrap();
+        let out = engine.render_offline(2048);
+        assert!(out[&device("speakers")][2048..].iter().all(|s| *s == 0.0));
+        assert!(out[&device("cable")].chunks_exact(2).all(|f| f == [0.4, -0.4]));
+    }
+
+    #[test]
+    fn post_fader_sends_follow_the_fader() {
+        let mut game = bus(StreamId::Game, device("speakers"));
+        game.gain = 0.25;
+        let send = SendSettings { level: 0.5, pre_fader: false };
+        let (mut engine, control) = engine_with_aux(vec![game], vec![aux("cable", StreamId::Game, send)]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.8, -0.8)));
+        let out = engine.render_offline(2048);
+        assert!(out[&device("cable")].chunks_exact(2).all(|f| f == [0.1, -0.1]));
+
+        control.send(EngineCommand::SetGain(StreamId::Game, 0.5)).unwrap();
+        let out = engine.render_offline(2048);
+        let ramp = ramp_frames(0.25, 0.5);
+        let cable: Vec<f32> = out[&device("cable")].chunks_exact(2).map(|f| f[0]).collect();
+        assert!(cable[..ramp].windows(2).all(|w| w[1] > w[0]));
+        assert!(cable[ramp..].iter().all(|s| *s == 0.2));
+
+        control.send(EngineCommand::SetMuted(StreamId::Game, true)).unwrap();
+        let out = engine.render_offline(2048);
+        let ramp = ramp_frames(0.5, 0.0);
+        assert!(out[&device("cable")][ramp * 2..].iter().all(|s| *s == 0.0));
+
+        // Moving the send before the fader brings it back while the stream stays muted
+        let pre = SendSettings { pre_fader: true, ..send };
+        control.send(EngineCommand::SetSend(StreamId::Game, "aux-1".into(), pre)).unwrap();
+        let out = engine.render_offline(2048);
+        assert!(out[&device("cable")].chunks_exact(2).all(|f| f == [0.4, -0.4]));
+        assert!(out[&device("speakers")].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn broadcast_mix_is_independent_of_the_personal_mix() {
+        let mut game = bus(StreamId::Game, device("headphones"));
+        let level = MixLevel { gain: 0.5, muted: false };
+        game.broadcast = Some(TapSpec { output: device("obs"), level, delay_ms: 0.0, pre_fader: true });
+        let (mut engine, control) = engine(vec![game], &[]);
+        engine.add_source(&StreamId::Game, Box::new(Constant(0.5, -0.5)));
+        let out = engine.render_offline(1024);
+        assert!(out[&device("obs")].chunks_exact(2).all(|f| f == [0.25, -0.25]));
+
+        // Muting the stream for yourself keeps it on stream
+        control.send(EngineCommand::SetMuted(StreamId::Game, true)).unwrap();
+        control.send(EngineCommand::SetGain(StreamId::Game, 0.1)).unwrap();
+        let out = engine.render_offline(2048);
+        assert!(out[&device("headphones")][ramp_frames(1.0, 0.0) * 2..].iter().all(|s| *s == 0.0));
+        assert!(out[&device("obs")].chunks_exact(2).all(|f| f == [0.25, -0.25]));
+
+        // And the other way round
+        control.send(EngineCommand::SetMuted(StreamId::Game, false)).unwrap();
+        control.send(EngineCommand::SetBroadcast(StreamId::Game, MixLevel { muted: true, ..level })).unwrap();
+        let out = engine.render_offline(2048);
+        let ramp = ramp_frames(0.0, 0.1);
+        assert!(out[&device("headphones")][ramp * 2..].chunks_exact(2).all(|f| f == [0.05, -0.05]));
+        // The broadcast level ramps within the first block
+        assert!(out[&device("obs")][MAX_BLOCK_FRAMES * 2..].iter().all(|s| *s == 0.0));
+    }
+}
@@ -0,0 +1,404 @@
+// Recording of buses to WAV or FLAC files. The audio thread copies every recorded signal
+// into a ring buffer; a writer thread drains the rings into the files. All tracks of a
+// take are installed with one command and fed from the same blocks, and a block that
+// doesn't fit into every ring is left out of all of them, so multitrack files stay
+// sample-aligned.
+use rtrb::{Consumer, Producer, RingBuffer};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::flac::FlacWriter;
+use super::source::push_samples;
+use super::{BusId, OutputKey};
+
+// Audio the writer may fall behind by before blocks are dropped
+const RING_SECONDS: usize = 2;
+const WRITER_INTERVAL: Duration = Duration::from_millis(50);
+// How long a stopped take waits for the audio thread to let go of its rings
+const STOP_TIMEOUT: Duration = Duration::from_millis(500);
+const FLAC_BITS: u32 = 24;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordFormat {
+    // 32-bit float, so levels above full scale survive
+    #[default]
+    Wav,
+    // 24-bit
+    Flac,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum RecordSource {
+    // A stream or aux bus after its fader
+    Bus(BusId),
+    // What an output device plays after the master dynamics
+    Output(OutputKey),
+    // The processed microphone: the Voice bus before its fader
+    Mic,
+    // Soundboard clips sent to the virtual mic
+    Soundboard,
+}
+
+impl RecordSource {
+    // Used in file names
+    pub(super) fn label(&self) -> String {
+        let raw = match self {
+            RecordSource::Bus(BusId::Stream(stream)) => format!("{stream:?}").to_lowercase(),
+            RecordSource::Bus(BusId::Aux(id)) => id.clone(),
+            RecordSource::Output(None) => "master".into(),
+            RecordSource::Output(Some(device)) => format!("master {device}"),
+            RecordSource::Mic => "mic".into(),
+            RecordSource::Soundboard => "soundboard".into(),
+        };
+        raw.chars().map(|c| if c.is_alphanumeric() || " -_".contains(c) { c } else { '_' }).collect()
+    }
+}
+
+struct RecordTap {
+    source: RecordSource,
+    channels: usize,
+    producer: Producer<f32>,
+}
+
+// Audio-thread side of a take
+#[derive(Default)]
+pub struct RecordTaps {
+    taps: Vec<RecordTap>,
+    // Frames left out because the writer fell behind
+    dropped: Arc<AtomicU64>,
+    // Whether the current block goes into the files
+    active: bool,
+}
+
+impl RecordTaps {
+    // Sources and channel counts of the tracks, with the consumer side of their rings
+    pub(super) fn new(tracks: &[(RecordSource, usize)], ring_frames: usize) -> (Self, Vec<Consumer<f32>>) {
+        let mut taps = Vec::new();
+        let mut consumers = Vec::new();
+        for (source, channels) in tracks {
+            let (producer, consumer) = RingBuffer::new(ring_frames * channels);
+            taps.push(RecordTap { source: source.clone(), channels: *channels, producer });
+            consumers.push(consumer);
+        }
+        (RecordTaps { taps, dropped: Default::default(), active: false }, consumers)
+    }
+
+    pub(super) fn dropped(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
+
+    // Start of a block: record it only if every track has room
+    pub fn begin(&mut self, frames: usize) {
+        if self.taps.is_empty() {
+            self.active = false;
+            return;
+        }
+        self.active = self.taps.iter().all(|t| t.producer.slots() >= frames * t.channels);
+        if !self.active {
+            self.dropped.fetch_add(frames as u64, Ordering::Relaxed);
+        }
+    }
+
+    // Copy the block of every track recording a source that `is` picks
+    pub fn push(&mut self, is: impl Fn(&RecordSource) -> bool, samples: &[f32]) {
+        if !self.active {
+            return;
+        }
+        for tap in self.taps.iter_mut().filter(|t| is(&t.source)) {
+            push_samples(&mut tap.producer, samples);
+        }
+    }
+}
+
+// Everything the audio thread copies out of the graph: the recording take, the replay
+// buffer and the virtual microphone feed
+#[derive(Default)]
+pub struct Captures {
+    pub recording: RecordTaps,
+    pub replay: RecordTaps,
+    pub virtual_mic: RecordTaps,
+}
+
+impl Captures {
+    pub fn begin(&mut self, frames: usize) {
+        self.recording.begin(frames);
+        self.replay.begin(frames);
+        self.virtual_mic.begin(frames);
+    }
+
+    pub fn push(&mut self, is: impl Fn(&RecordSource) -> bool, samples: &[f32]) {
+        self.recording.push(&is, samples);
+        self.replay.push(&is, samples);
+        self.virtual_mic.push(&is, samples);
+    }
+}
+
+pub(super) enum TrackWriter {
+    Wav(hound::WavWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+}
+
+impl TrackWriter {
+    pub(super) fn create(path: &Path, format: RecordFormat, channels: usize, sample_rate: u32) -> Result<Self, String> {
+        let err = |e: String| format!("Create {} failed: {e}", path.display());
+        Ok(match format {
+            RecordFormat::Wav => {
+                let spec = hound::WavSpec {
+                    channels: channels as u16,
+                    sample_rate,
+                    bits_per_sample: 32,
+                    sample_format: hound::SampleFormat::Float,
+                };
+                TrackWriter::Wav(hound::WavWriter::create(path, spec).map_err(|e| err(e.to_string()))?)
+            }
+            RecordFormat::Flac => {
+                let file = File::create(path).map_err(|e| err(e.to_string()))?;
+                let writer = FlacWriter::new(BufWriter::new(file), channels, sample_rate, FLAC_BITS);
+                TrackWriter::Flac(writer.map_err(|e| err(e.to_string()))?)
+            }
+        })
+    }
+
+    pub(super) fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        match self {
+            TrackWriter::Wav(w) => samples.iter().try_for_each(|&s| w.write_sample(s)).map_err(|e| e.to_string()),
+            TrackWriter::Flac(w) => w.write(samples).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub(super) fn finalize(self) -> Result<(), String> {
+        match self {
+            TrackWriter::Wav(w) => w.finalize().map_err(|e| e.to_string()),
+            TrackWriter::Flac(w) => w.finalize().map(|_| ()).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+struct Track {
+    path: PathBuf,
+    consumer: Consumer<f32>,
+    writer: TrackWriter,
+}
+
+impl Track {
+    fn drain(&mut self) -> Result<(), String> {
+        let Ok(chunk) = self.consumer.read_chunk(self.consumer.slots()) else { return Ok(()) };
+        let (a, b) = chunk.as_slices();
+        let written = self.writer.write(a).and_then(|_| self.writer.write(b));
+        chunk.commit_all();
+        written.map_err(|e| format!("Write {} failed: {e}", self.path.display()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct RecordingStatus {
+    pub active: bool,
+    pub sources: Vec<RecordSource>,
+    pub files: Vec<String>,
+    pub seconds: f64,
+    // Audio missing from the files because the disk couldn't keep up
+    pub dropped_seconds: f64,
+    // Why the writer gave up, if it did
+    pub error: Option<String>,
+}
+
+// A take in progress
+pub struct Recording {
+    sources: Vec<RecordSource>,
+    files: Vec<PathBuf>,
+    sample_rate: u32,
+    started: Instant,
+    dropped: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+// "YYYY-MM-DDTHH-MM-SS" (UTC), shared by the files of one take
+pub(super) fn file_stamp() -> String {
+    crate::logging::format_timestamp(std::time::SystemTime::now())[..19].replace(':', "-")
+}
+
+pub(super) fn extension(format: RecordFormat) -> &'static str {
+    match format {
+        RecordFormat::Wav => "wav",
+        RecordFormat::Flac => "flac",
+    }
+}
+
+// One file per source, named after the take's start time. `tracks` are the sources with
+// their channel counts.
+pub fn start(
+    folder: &Path,
+    format: RecordFormat,
+    tracks: &[(RecordSource, usize)],
+    sample_rate: u32,
+) -> Result<(Recording, RecordTaps), String> {
+    std::fs::create_dir_all(folder).map_err(|e| format!("Create {} failed: {e}", folder.display()))?;
+    let stamp = file_stamp();
+    let (taps, consumers) = RecordTaps::new(tracks, RING_SECONDS * sample_rate as usize);
+    let mut writers = Vec::new();
+    for ((source, channels), consumer) in tracks.iter().zip(consumers) {
+        let path = folder.join(format!("{} {}.{}", stamp, source.label(), extension(format)));
+        let writer = TrackWriter::create(&path, format, *channels, sample_rate)?;
+        writers.push(Track { path, consumer, writer });
+    }
+    let files = writers.iter().map(|t| t.path.clone()).collect();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopping = stop.clone();
+    let thread = thread::Builder::new()
+        .name("recorder".into())
+        .spawn(move || write_tracks(writers, stopping))
+        .map_err(|e| format!("Spawn recorder thread failed: {e}"))?;
+    log::info!("Recording {:?} to {}", tracks.iter().map(|(s, _)| s).collect::<Vec<_>>(), folder.display());
+    let recording = Recording {
+        sources: tracks.iter().map(|(s, _)| s.clone()).collect(),
+        files,
+        sample_rate,
+        started: Instant::now(),
+        dropped: taps.dropped(),
+        stop,
+        thread: Some(thread),
+    };
+    Ok((recording, taps))
+}
+
+// Writer thread: runs until the audio thread has dropped the rings (or, once stopped,
+// for at most STOP_TIMEOUT), then finalizes every file
+fn write_tracks(mut tracks: Vec<Track>, stop: Arc<AtomicBool>) -> Result<(), String> {
+    let mut stopped_at = None;
+    let mut result = Ok(());
+    loop {
+        let abandoned = tracks.iter().all(|t| t.consumer.is_abandoned());
+        for track in tracks.iter_mut() {
+            if let Err(e) = track.drain() {
+                log::error!("Recording: {}", e);
+                result = Err(e);
+            }
+        }
+        if stop.load(Ordering::Relaxed) && stopped_at.is_none() {
+            stopped_at = Some(Instant::now());
+        }
+        if abandoned || result.is_err() || stopped_at.is_some_and(|t| t.elapsed() > STOP_TIMEOUT) {
+            break;
+        }
+        thread::sleep(WRITER_INTERVAL);
+    }
+    for track in tracks {
+        let path = track.path.display().to_string();
+        if let Err(e) = track.writer.finalize() {
+            log::error!("Recording: finishing {} failed: {}", path, e);
+            result = result.and(Err(format!("Finish {path} failed: {e}")));
+        }
+    }
+    result
+}
+
+impl Recording {
+    pub fn status(&self) -> RecordingStatus {
+        let finished = self.thread.as_ref().is_none_or(|t| t.is_finished());
+        RecordingStatus {
+            active: !finished,
+            sources: self.sources.clone(),
+            files: self.files.iter().map(|p| p.display().to_string()).collect(),
+            seconds: self.started.elapsed().as_secs_f64(),
+            dropped_seconds: self.dropped.load(Ordering::Relaxed) as f64 / self.sample_rate as f64,
+            error: finished.then(|| "Recording stopped unexpectedly".to_string()),
+        }
+    }
+
+    // Wait for the writer to finish the files; the engine should have dropped the taps
+    // already, otherwise the last moments may be cut off
+    pub fn finish(mut self) -> Result<Vec<String>, String> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().map_err(|_| "Recorder thread panicked".to_string())??;
+        }
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("Recording lost {} frames; the disk couldn't keep up", dropped);
+        }
+        Ok(self.files.iter().map(|p| p.display().to_string()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StreamId;
+
+    // One block for every track: the Game bus in stereo, the mic in mono, both counting up
+    fn block(taps: &mut RecordTaps, start: usize, frames: usize) {
+        taps.begin(frames);
+        let stereo: Vec<f32> = (start..start + frames).flat_map(|i| [i as f32, -(i as f32)]).collect();
+        let mono: Vec<f32> = (start..start + frames).map(|i| i as f32).collect();
+        taps.push(|s| *s == RecordSource::Bus(BusId::Stream(StreamId::Game)), &stereo);
+        taps.push(|s| *s == RecordSource::Mic, &mono);
+    }
+
+    fn read_all(consumer: &mut Consumer<f32>) -> Vec<f32> {
+        let chunk = consumer.read_chunk(consumer.slots()).unwrap();
+        let (a, b) = chunk.as_slices();
+        let samples = [a, b].concat();
+        chunk.commit_all();
+        samples
+    }
+
+    #[test]
+    fn tracks_stay_aligned_when_a_ring_is_full() {
+        let tracks = [(RecordSource::Bus(BusId::Stream(StreamId::Game)), 2), (RecordSource::Mic, 1)];
+        let (mut taps, mut consumers) = RecordTaps::new(&tracks, 100);
+        let dropped = taps.dropped();
+        block(&mut taps, 0, 40);
+        // The mic track is written out, the Game track lags behind
+        let mut mic = read_all(&mut consumers[1]);
+        // Room for 60 more Game frames: the next block is left out of both tracks
+        block(&mut taps, 40, 70);
+        assert_eq!(dropped.load(Ordering::Relaxed), 70);
+        assert_eq!(consumers[1].slots(), 0);
+        let mut game = read_all(&mut consumers[0]);
+        block(&mut taps, 110, 30);
+        game.extend(read_all(&mut consumers[0]));
+        mic.extend(read_all(&mut consumers[1]));
+
+        let frames: Vec<f32> = (0..40).chain(110..140).map(|i| i as f32).collect();
+        assert_eq!(mic, frames);
+        assert_eq!(game.chunks_exact(2).map(|f| f[0]).collect::<Vec<_>>(), frames);
+        assert!(game.chunks_exact(2).all(|f| f[1] == -f[0]));
+        assert_eq!(dropped.load(Ordering::Relaxed), 70);
+    }
+
+    #[test]
+    fn empty_taps_record_nothing() {
+        let mut taps = RecordTaps::default();
+        taps.begin(64);
+        assert!(!taps.active);
+        assert_eq!(taps.dropped().load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn finished_takes_are_complete_files() {
+        let dir = std::env::temp_dir().join(format!("record-test-{}", std::process::id()));
+        let tracks = [(RecordSource::Bus(BusId::Stream(StreamId::Game)), 2), (RecordSource::Mic, 1)];
+        let (take, mut taps) = start(&dir, RecordFormat::Wav, &tracks, 48_000).unwrap();
+        block(&mut taps, 0, 500);
+        block(&mut taps, 500, 1000);
+        // The engine lets go of the taps before the take is finished
+        drop(taps);
+        let files = take.finish().unwrap();
+        for (file, channels) in files.iter().zip([2, 1]) {
+            let (samples, file_channels, rate) = crate::engine::wav::read(Path::new(file)).unwrap();
+            assert_eq!((file_channels, rate), (channels, 48_000));
+            assert_eq!(samples.len(), 1500 * channels);
+            assert_eq!(samples[channels * 1499], 1499.0);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
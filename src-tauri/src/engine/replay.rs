@@ -0,0 +1,243 @@
+// Instant replay: a rolling history of some buses, filled by a background thread through
+// the same taps as recordings and saved as one stem file per bus on demand. Memory is
+// bounded by the seconds kept per bus; the files end at the same moment, so stems of
+// different lengths still line up at the end.
+use rtrb::Consumer;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::record::{self, RecordFormat, RecordSource, RecordTaps, TrackWriter};
+
+// Longest history per bus; 46 MB for a stereo bus at 48 kHz
+pub const MAX_REPLAY_SECONDS: f32 = 120.0;
+// Taps -> keeper thread
+const RING_SECONDS: usize = 1;
+const KEEPER_INTERVAL: Duration = Duration::from_millis(50);
+
+struct History {
+    source: RecordSource,
+    channels: usize,
+    consumer: Consumer<f32>,
+    // Interleaved and circular; `write` is the next sample to fill
+    samples: Vec<f32>,
+    write: usize,
+    filled: usize,
+}
+
+impl History {
+    fn drain(&mut self) {
+        let Ok(chunk) = self.consumer.read_chunk(self.consumer.slots()) else { return };
+        let (a, b) = chunk.as_slices();
+        for part in [a, b] {
+            // Only the newest samples matter if the chunk is longer than the history
+            let part = &part[part.len().saturating_sub(self.samples.len())..];
+            let first = part.len().min(self.samples.len() - self.write);
+            self.samples[self.write..self.write + first].copy_from_slice(&part[..first]);
+            self.samples[..part.len() - first].copy_from_slice(&part[first..]);
+            self.write = (self.write + part.len()) % self.samples.len();
+            self.filled = (self.filled + part.len()).min(self.samples.len());
+        }
+        chunk.commit_all();
+    }
+
+    // The newest `frames` frames, or less if not that much is buffered yet
+    fn last(&self, frames: usize) -> Vec<f32> {
+        let n = (frames * self.channels).min(self.filled);
+        let start = (self.write + self.samples.len() - n) % self.samples.len();
+        let first = n.min(self.samples.len() - start);
+        let mut out = Vec::with_capacity(n);
+        out.extend_from_slice(&self.samples[start..start + first]);
+        out.extend_from_slice(&self.samples[..n - first]);
+        out
+    }
+}
+
+// Handle to a running replay buffer; clones share the same history
+#[derive(Clone)]
+pub struct ReplayBuffer {
+    histories: Arc<Mutex<Vec<History>>>,
+    sample_rate: u32,
+}
+
+// `tracks` are the buses with their channel counts and seconds to keep. The keeper
+// thread ends by itself once the engine drops the taps.
+pub fn start(tracks: &[(RecordSource, usize, f32)], sample_rate: u32) -> Result<(ReplayBuffer, RecordTaps), String> {
+    let sources: Vec<(RecordSource, usize)> = tracks.iter().map(|(s, c, _)| (s.clone(), *c)).collect();
+    let (taps, consumers) = RecordTaps::new(&sources, RING_SECONDS * sample_rate as usize);
+    let histories: Vec<History> = tracks
+        .iter()
+        .zip(consumers)
+        .map(|((source, channels, seconds), consumer)| {
+            let frames = (seconds.clamp(1.0, MAX_REPLAY_SECONDS) * sample_rate as f32) as usize;
+            History {
+                source: source.clone(),
+                channels: *channels,
+                consumer,
+                samples: vec![0.0; frames * channels],
+                write: 0,
+                filled: 0,
+            }
+        })
+        .collect();
+    let histories = Arc::new(Mutex::new(histories));
+    let kept = histories.clone();
+    thread::Builder::new()
+        .name("replay-buffer".into())
+        .spawn(move || loop {
+            {
+                let mut histories = kept.lock().unwrap();
+                let abandoned = histories.iter().all(|h| h.consumer.is_abandoned());
+                for h in histories.iter_mut() {
+                    h.drain();
+                }
+                if abandoned {
+                    break;
+                }
+            }
+            thread::sleep(KEEPER_INTERVAL);
+        })
+        .map_err(|e| format!("Spawn replay buffer thread failed: {e}"))?;
+    Ok((ReplayBuffer { histories, sample_rate }, taps))
+}
+
+impl ReplayBuffer {
+    // Write the last `seconds` of every bus (everything buffered if `None`), one file each
+    pub fn save(&self, folder: &Path, format: RecordFormat, seconds: Option<f32>) -> Result<Vec<String>, String> {
+        let frames = seconds.map_or(usize::MAX / 64, |s| (s.max(0.0) * self.sample_rate as f32) as usize);
+        // Copy out under the lock and write without it, so the keeper isn't held up
+        let snapshot: Vec<(RecordSource, usize, Vec<f32>)> = {
+            let mut histories = self.histories.lock().unwrap();
+            histories
+                .iter_mut()
+                .map(|h| {
+                    h.drain();
+                    (h.source.clone(), h.channels, h.last(frames))
+                })
+                .collect()
+        };
+        if snapshot.iter().all(|(_, _, samples)| samples.is_empty()) {
+            return Err("The replay buffer is empty".into());
+        }
+        std::fs::create_dir_all(folder).map_err(|e| format!("Create {} failed: {e}", folder.display()))?;
+        let stamp = record::file_stamp();
+        let mut files = Vec::new();
+        for (source, channels, samples) in snapshot {
+            let path = folder.join(format!("{} replay {}.{}", stamp, source.label(), record::extension(format)));
+            let mut writer = TrackWriter::create(&path, format, channels, self.sample_rate)?;
+            writer
+                .write(&samples)
+                .and_then(|_| writer.finalize())
+                .map_err(|e| format!("Write {} failed: {e}", path.display()))?;
+            files.push(path.display().to_string());
+        }
+        log::info!("Saved replay to {}", folder.display());
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::BusId;
+    use crate::StreamId;
+    use rtrb::RingBuffer;
+
+    fn history(channels: usize, frames: usize, ring: usize) -> (History, rtrb::Producer<f32>) {
+        let (producer, consumer) = RingBuffer::new(ring * channels);
+        let history = History {
+            source: RecordSource::Mic,
+            channels,
+            consumer,
+            samples: vec![0.0; frames * channels],
+            write: 0,
+            filled: 0,
+        };
+        (history, producer)
+    }
+
+    // Stereo frames counting up from `start`, right channel negated
+    fn push(producer: &mut rtrb::Producer<f32>, start: usize, frames: usize) {
+        for i in start..start + frames {
+            producer.push(i as f32).unwrap();
+            producer.push(-(i as f32)).unwrap();
+        }
+    }
+
+    fn frame_numbers(samples: &[f32]) -> Vec<usize> {
+        assert!(samples.chunks_exact(2).all(|f| f[1] == -f[0]), "channels out of step");
+        samples.chunks_exact(2).map(|f| f[0] as usize).collect()
+    }
+
+    #[test]
+    fn history_keeps_the_newest_frames_across_the_wrap() {
+        let (mut h, mut producer) = history(2, 100, 64);
+        // Not full yet: only what came in
+        push(&mut producer, 0, 30);
+        h.drain();
+        assert_eq!(frame_numbers(&h.last(1000)), (0..30).collect::<Vec<_>>());
+        // Blocks that don't divide the history, wrapping several times
+        let mut next = 30;
+        for _ in 0..20 {
+            push(&mut producer, next, 37);
+            next += 37;
+            h.drain();
+        }
+        assert_eq!(frame_numbers(&h.last(1000)), (next - 100..next).collect::<Vec<_>>());
+        assert_eq!(frame_numbers(&h.last(7)), (next - 7..next).collect::<Vec<_>>());
+        assert!(h.last(0).is_empty());
+        // Memory stays at the configured length
+        assert_eq!(h.samples.len(), 200);
+        assert_eq!(h.filled, 200);
+    }
+
+    #[test]
+    fn chunks_longer_than_the_history_keep_their_end() {
+        let (mut h, mut producer) = history(2, 50, 400);
+        // Lands split in two slices of the ring
+        push(&mut producer, 0, 300);
+        h.drain();
+        push(&mut producer, 300, 330);
+        h.drain();
+        assert_eq!(frame_numbers(&h.last(usize::MAX / 64)), (580..630).collect::<Vec<_>>());
+        assert_eq!(h.samples.len(), 100);
+    }
+
+    #[test]
+    fn stems_end_together() {
+        const RATE: u32 = 1000;
+        let game = RecordSource::Bus(BusId::Stream(StreamId::Game));
+        let tracks = [(game.clone(), 2, 3.0), (RecordSource::Mic, 1, 1.0)];
+        let (replay, mut taps) = start(&tracks, RATE).unwrap();
+        for block in 0..60 {
+            let start = block * 100;
+            taps.begin(100);
+            let stereo: Vec<f32> = (start..start + 100).flat_map(|i| [i as f32, -(i as f32)]).collect();
+            let mono: Vec<f32> = (start..start + 100).map(|i| i as f32).collect();
+            taps.push(|s| *s == game, &stereo);
+            taps.push(|s| *s == RecordSource::Mic, &mono);
+            // Faster than the keeper, so drain here before the rings fill
+            for h in replay.histories.lock().unwrap().iter_mut() {
+                h.drain();
+            }
+        }
+        assert_eq!(taps.dropped().load(std::sync::atomic::Ordering::Relaxed), 0);
+
+        let dir = std::env::temp_dir().join(format!("replay-test-{}", std::process::id()));
+        for (i, seconds, game_frames, mic_frames) in [(0, None, 3000, 1000), (1, Some(0.5), 500, 500)] {
+            let files = replay.save(&dir.join(i.to_string()), RecordFormat::Wav, seconds).unwrap();
+            assert_eq!(files.len(), 2);
+            for (file, frames) in files.iter().zip([game_frames, mic_frames]) {
+                let (samples, channels, rate) = crate::engine::wav::read(Path::new(file)).unwrap();
+                assert_eq!(rate, RATE);
+                assert_eq!(samples.len(), frames * channels);
+                // Same last frame in every stem
+                assert_eq!(samples[samples.len() - channels], 5999.0, "{}", file);
+                assert_eq!(samples[0], (6000 - frames) as f32, "{}", file);
+            }
+        }
+        drop(taps);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
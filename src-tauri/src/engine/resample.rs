@@ -0,0 +1,431 @@
+// Band-limited polyphase resampler (Kaiser-windowed sinc, linear interpolation between
+// table phases) and the drift control that keeps a ring buffer between two independent
+// device clocks at its target fill level by nudging the ratio. `run_harness` checks both
+// offline with sine sweeps and simulated clock drift (`audio-mixer --resample-test`).
+use realfft::RealFftPlanner;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+use super::source::{ring_pair, Source};
+use super::MAX_BLOCK_FRAMES;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResamplerQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+struct Design {
+    // Kernel half length in taps at the lower of the two rates
+    half_taps: usize,
+    stopband_db: f64,
+    // Table resolution; the interpolation error has to stay below the stopband
+    phases: usize,
+}
+
+impl ResamplerQuality {
+    fn design(self) -> Design {
+        match self {
+            ResamplerQuality::Low => Design { half_taps: 12, stopband_db: 60.0, phases: 64 },
+            ResamplerQuality::Medium => Design { half_taps: 32, stopband_db: 100.0, phases: 256 },
+            ResamplerQuality::High => Design { half_taps: 80, stopband_db: 130.0, phases: 2048 },
+        }
+    }
+
+    pub fn stopband_db(self) -> f64 {
+        self.design().stopband_db
+    }
+}
+
+// Largest ratio correction of the drift control (±0.2 %, far beyond what crystals drift)
+pub const MAX_CORRECTION: f64 = 0.002;
+// Drift control: smoothing of the measured fill level and the PI gains (per second)
+const LEVEL_SMOOTHING_SECONDS: f64 = 1.0;
+const DRIFT_KP: f64 = 0.1;
+const DRIFT_KI: f64 = 0.005;
+
+fn bessel_i0(x: f64) -> f64 {
+    let q = x * x / 4.0;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for k in 1..100 {
+        term *= q / (k * k) as f64;
+        sum += term;
+        if term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
+
+fn kaiser_beta(stopband_db: f64) -> f64 {
+    if stopband_db > 50.0 {
+        0.1102 * (stopband_db - 8.7)
+    } else {
+        0.5842 * (stopband_db - 21.0).powf(0.4) + 0.07886 * (stopband_db - 21.0)
+    }
+}
+
+pub struct Resampler {
+    channels: usize,
+    half: usize,
+    taps: usize,
+    phases: usize,
+    // (phases + 1) rows of `taps` coefficients
+    table: Vec<f32>,
+    coeffs: Vec<f32>,
+    nominal: f64,
+    // Input frames per output frame, including the drift correction
+    step: f64,
+    // Pending input frames, interleaved; starts with `half - 1` frames of silence
+    input: Vec<f32>,
+    frames: usize,
+    // Read position in `input` in frames; the kernel is centred on it
+    position: f64,
+    // Passband edge in Hz, for reporting
+    passband: f64,
+}
+
+impl Resampler {
+    pub fn new(channels: usize, from: u32, to: u32, quality: ResamplerQuality) -> Self {
+        let channels = channels.max(1);
+        let design = quality.design();
+        let nominal = from as f64 / to.max(1) as f64;
+        // Downsampling stretches the kernel so it cuts at the output's Nyquist frequency
+        let scale = nominal.max(1.0);
+        let half = (design.half_taps as f64 * scale).ceil() as usize;
+        let taps = 2 * half;
+        // Kaiser's estimate of the transition width, as a fraction of the lower Nyquist;
+        // the stopband starts right at Nyquist
+        let transition = (design.stopband_db - 8.0) / (2.285 * 2.0 * design.half_taps as f64 * PI);
+        let cutoff = (1.0 - transition / 2.0) / scale;
+        let beta = kaiser_beta(design.stopband_db);
+        let norm = bessel_i0(beta);
+
+        let phases = design.phases;
+        let mut table = Vec::with_capacity((phases + 1) * taps);
+        for p in 0..=phases {
+            let frac = p as f64 / phases as f64;
+            for j in 0..taps {
+                let d = frac + (half - 1) as f64 - j as f64;
+                let x = d / half as f64;
+                let window = if x.abs() >= 1.0 { 0.0 } else { bessel_i0(beta * (1.0 - x * x).sqrt()) / norm };
+                let arg = PI * cutoff * d;
+                let sinc = if arg == 0.0 { 1.0 } else { arg.sin() / arg };
+                table.push((cutoff * sinc * window) as f32);
+            }
+        }
+
+        let capacity = taps + (MAX_BLOCK_FRAMES as f64 * nominal * (1.0 + MAX_CORRECTION)).ceil() as usize + 2;
+        let lower_nyquist = from.min(to) as f64 / 2.0;
+        Resampler {
+            channels,
+            half,
+            taps,
+            phases,
+            table,
+            coeffs: vec![0.0; taps],
+            nominal,
+            step: nominal,
+            input: vec![0.0; capacity * channels],
+            frames: half - 1,
+            position: (half - 1) as f64,
+            passband: lower_nyquist * (1.0 - transition),
+        }
+    }
+
+    pub fn passband_hz(&self) -> f64 {
+        self.passband
+    }
+
+    // Relative ratio change from the drift control; + consumes input faster
+    pub fn set_correction(&mut self, correction: f64) {
+        self.step = self.nominal * (1.0 + correction.clamp(-MAX_CORRECTION, MAX_CORRECTION));
+    }
+
+    // Input frames that producing `frames` output frames will pull
+    pub fn input_needed(&self, frames: usize) -> usize {
+        if frames == 0 {
+            return 0;
+        }
+        let last = self.position + (frames - 1) as f64 * self.step;
+        (last.floor() as usize + self.half + 1).saturating_sub(self.frames)
+    }
+
+    // Fill `out` (interleaved, whole frames). `pull` gets the exact number of input
+    // samples needed and has to fill all of them (zeros if there are none).
+    pub fn process(&mut self, out: &mut [f32], mut pull: impl FnMut(&mut [f32])) {
+        let ch = self.channels;
+        let taps = self.taps;
+        for block in out.chunks_mut(MAX_BLOCK_FRAMES * ch) {
+            let frames = block.len() / ch;
+            // Drop input the kernel can't reach any more
+            let first = (self.position.floor() as usize + 1).saturating_sub(self.half);
+            if first > 0 {
+                self.input.copy_within(first * ch..self.frames * ch, 0);
+                self.frames -= first;
+                self.position -= first as f64;
+            }
+            let need = self.input_needed(frames);
+            if need > 0 {
+                let end = self.frames + need;
+                pull(&mut self.input[self.frames * ch..end * ch]);
+                self.frames = end;
+            }
+
+            for frame in block.chunks_exact_mut(ch) {
+                let n = self.position.floor();
+                let p = (self.position - n) * self.phases as f64;
+                let row = (p as usize).min(self.phases - 1);
+                let t = (p - row as f64) as f32;
+                let a = &self.table[row * taps..(row + 1) * taps];
+                let b = &self.table[(row + 1) * taps..(row + 2) * taps];
+                for ((c, &a), &b) in self.coeffs.iter_mut().zip(a).zip(b) {
+                    *c = a + t * (b - a);
+                }
+                let start = n as usize + 1 - self.half;
+                let x = &self.input[start * ch..(start + taps) * ch];
+                for (c, o) in frame.iter_mut().enumerate() {
+                    *o = x.iter().skip(c).step_by(ch).zip(&self.coeffs).map(|(x, h)| x * h).sum();
+                }
+                self.position += self.step;
+            }
+        }
+    }
+}
+
+// PI control of a ring buffer's fill level through the resampling ratio
+#[derive(Debug, Clone)]
+pub struct DriftControl {
+    // Levels in seconds
+    target: f64,
+    level: f64,
+    integral: f64,
+    correction: f64,
+}
+
+impl DriftControl {
+    pub fn new(target_seconds: f64) -> Self {
+        DriftControl { target: target_seconds, level: target_seconds, integral: 0.0, correction: 0.0 }
+    }
+
+    // Restart the level average, e.g. after the buffer was refilled. The integral is the
+    // clock offset estimate and survives.
+    pub fn reset(&mut self, buffered_seconds: f64) {
+        self.level = buffered_seconds;
+    }
+
+    // `buffered` before a read of `dt` seconds; returns the correction for that read
+    pub fn update(&mut self, buffered_seconds: f64, dt: f64) -> f64 {
+        self.level += (buffered_seconds - self.level) * (dt / LEVEL_SMOOTHING_SECONDS).min(1.0);
+        let error = self.level - self.target;
+        let limit = MAX_CORRECTION / DRIFT_KI;
+        self.integral = (self.integral + error * dt).clamp(-limit, limit);
+        self.correction = (DRIFT_KP * error + DRIFT_KI * self.integral).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.correction
+    }
+
+    pub fn correction(&self) -> f64 {
+        self.correction
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepReport {
+    pub from: u32,
+    pub to: u32,
+    pub passband_hz: f64,
+    // Largest deviation from unity gain inside the passband
+    pub ripple_db: f64,
+    // Worst energy that isn't the sweep (aliases, images, noise) relative to the sweep
+    pub alias_db: f64,
+    pub passed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftReport {
+    pub from: u32,
+    pub to: u32,
+    pub drift_ppm: f64,
+    pub seconds: f64,
+    pub underruns: u64,
+    pub dropped: u64,
+    // Correction the control settled on, should mirror the drift; averaged with the
+    // buffer latency over the last third of the run
+    pub correction_ppm: f64,
+    pub latency_ms: f64,
+    pub passed: bool,
+}
+
+const SWEEP_SECONDS: f64 = 4.0;
+const SWEEP_AMPLITUDE: f64 = 0.5;
+const SWEEP_START_HZ: f64 = 20.0;
+const ANALYSIS_FRAMES: usize = 4096;
+// Measurement window; its sidelobes (~-150 dB) sit below every quality's stopband
+const ANALYSIS_BETA: f64 = 20.0;
+const ANALYSIS_GUARD_BINS: usize = 8;
+
+// Exponential sine sweep from 20 Hz to just below the input's Nyquist frequency through
+// the resampler, analysed in windows at the output rate
+pub fn sweep_test(quality: ResamplerQuality, from: u32, to: u32) -> SweepReport {
+    let end_hz = 0.48 * from as f64;
+    let rate = (end_hz / SWEEP_START_HZ).ln() / SWEEP_SECONDS;
+    let freq_at = |t: f64| SWEEP_START_HZ * (rate * t).exp();
+    let input: Vec<f32> = (0..(SWEEP_SECONDS * from as f64) as usize)
+        .map(|n| {
+            let t = n as f64 / from as f64;
+            let phase = 2.0 * PI * SWEEP_START_HZ * ((rate * t).exp() - 1.0) / rate;
+            (SWEEP_AMPLITUDE * phase.sin()) as f32
+        })
+        .collect();
+
+    let mut resampler = Resampler::new(1, from, to, quality);
+    let mut output = vec![0.0f32; (SWEEP_SECONDS * to as f64) as usize];
+    let mut read = 0;
+    resampler.process(&mut output, |buf| {
+        for v in buf.iter_mut() {
+            *v = input.get(read).copied().unwrap_or(0.0);
+            read += 1;
+        }
+    });
+
+    let n = ANALYSIS_FRAMES;
+    let norm = bessel_i0(ANALYSIS_BETA);
+    let window: Vec<f64> = (0..n)
+        .map(|i| {
+            let x = 2.0 * i as f64 / (n - 1) as f64 - 1.0;
+            bessel_i0(ANALYSIS_BETA * (1.0 - x * x).max(0.0).sqrt()) / norm
+        })
+        .collect();
+    // One-sided spectrum energy of a full-scale sine of the sweep's amplitude
+    let reference = n as f64 * SWEEP_AMPLITUDE * SWEEP_AMPLITUDE / 4.0 * window.iter().map(|w| w * w).sum::<f64>();
+    let bin_hz = to as f64 / n as f64;
+    let passband = resampler.passband_hz();
+    let nyquist = from.min(to) as f64 / 2.0;
+
+    let fft = RealFftPlanner::<f64>::new().plan_fft_forward(n);
+    let mut frame = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let mut ripple: f64 = 0.0;
+    let mut alias = f64::NEG_INFINITY;
+    // Skip the first window: the kernel starts on silence
+    let mut start = n;
+    while start + n <= output.len() {
+        let block = &output[start..start + n];
+        let f_lo = freq_at(start as f64 / to as f64);
+        let f_hi = freq_at((start + n) as f64 / to as f64);
+        start += n / 2;
+        if f_lo < 200.0 {
+            continue;
+        }
+        for ((d, &s), w) in frame.iter_mut().zip(block).zip(&window) {
+            *d = s as f64 * w;
+        }
+        if fft.process(&mut frame, &mut spectrum).is_err() {
+            continue;
+        }
+        let power: Vec<f64> = spectrum.iter().map(|c| c.norm_sqr()).collect();
+        let lo = ((f_lo / bin_hz) as usize).saturating_sub(ANALYSIS_GUARD_BINS);
+        let hi = ((f_hi / bin_hz) as usize + ANALYSIS_GUARD_BINS).min(power.len() - 1);
+        let total: f64 = power.iter().sum();
+        if f_hi <= passband {
+            let signal: f64 = power[lo..=hi].iter().sum();
+            ripple = ripple.max((10.0 * (signal / reference).log10()).abs());
+            alias = alias.max(10.0 * ((total - signal).max(1e-30) / signal).log10());
+        } else if f_lo >= nyquist {
+            // Nothing of the sweep may come through
+            alias = alias.max(10.0 * (total.max(1e-30) / reference).log10());
+        }
+    }
+    let passed = ripple <= 0.1 && alias <= -(quality.stopband_db() - 10.0).min(110.0);
+    SweepReport { from, to, passband_hz: passband, ripple_db: ripple, alias_db: alias, passed }
+}
+
+// Two device clocks `drift_ppm` apart, exchanging blocks through a ring buffer the
+// way the live engine does for secondary devices
+pub fn drift_test(quality: ResamplerQuality, from: u32, to: u32, drift_ppm: f64, seconds: f64) -> DriftReport {
+    const RING_SECONDS: f64 = 0.5;
+    const MAX_LATENCY_SECONDS: f64 = 0.05;
+    let (mut producer, mut source) = ring_pair(
+        1,
+        from,
+        to,
+        quality,
+        (RING_SECONDS * from as f64) as usize,
+        (MAX_LATENCY_SECONDS * from as f64) as usize,
+    );
+    // Different block sizes on both sides, like real devices
+    let push_block = (from as f64 / 100.0).round() as usize;
+    let push_period = push_block as f64 / (from as f64 * (1.0 + drift_ppm * 1e-6));
+    let pull_block = 512;
+    let pull_period = pull_block as f64 / to as f64;
+
+    let tone: Vec<f32> = (0..push_block).map(|i| (0.25 * (2.0 * PI * i as f64 / 48.0).sin()) as f32).collect();
+    let mut out = vec![0.0f32; pull_block];
+    let (mut next_push, mut next_pull) = (0.0, pull_period / 2.0);
+    let mut dropped = 0;
+    let (mut correction_sum, mut latency_sum, mut reads) = (0.0, 0.0, 0);
+    while next_pull < seconds {
+        if next_push <= next_pull {
+            dropped += (push_block - super::source::push_samples(&mut producer, &tone)) as u64;
+            next_push += push_period;
+        } else {
+            source.read(&mut out);
+            next_pull += pull_period;
+            if next_pull > seconds * 2.0 / 3.0 {
+                correction_sum += source.correction();
+                latency_sum += producer.buffer().capacity().saturating_sub(producer.slots()) as f64;
+                reads += 1;
+            }
+        }
+    }
+    let reads = reads.max(1) as f64;
+    let correction_ppm = correction_sum / reads * 1e6;
+    let latency_ms = latency_sum / reads / from as f64 * 1000.0;
+    let underruns = source.underruns();
+    let dropped = dropped + source.dropped();
+    let passed = underruns == 0 && dropped == 0 && (correction_ppm - drift_ppm).abs() < 10.0;
+    DriftReport { from, to, drift_ppm, seconds, underruns, dropped, correction_ppm, latency_ms, passed }
+}
+
+// Test harness: sweeps over the usual rate pairs, plus drift between independent clocks
+pub fn run_harness(quality: ResamplerQuality) -> (Vec<SweepReport>, Vec<DriftReport>) {
+    let pairs = [(48_000, 44_100), (44_100, 48_000), (96_000, 44_100), (44_100, 96_000), (48_000, 48_000)];
+    let sweeps = pairs.iter().map(|&(from, to)| sweep_test(quality, from, to)).collect();
+    let drifts = [(48_000, 48_000, 150.0), (48_000, 44_100, -150.0), (44_100, 48_000, 80.0)]
+        .iter()
+        .map(|&(from, to, ppm)| drift_test(quality, from, to, ppm, 300.0))
+        .collect();
+    (sweeps, drifts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [ResamplerQuality; 3] = [ResamplerQuality::Low, ResamplerQuality::Medium, ResamplerQuality::High];
+
+    #[test]
+    fn sweeps_pass_at_every_quality() {
+        let pairs =
+            [(48_000, 44_100), (44_100, 48_000), (96_000, 44_100), (44_100, 96_000), (96_000, 48_000), (48_000, 96_000)];
+        for quality in QUALITIES {
+            for (from, to) in pairs {
+                let report = sweep_test(quality, from, to);
+                assert!(report.passed, "{quality:?}: {report:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn drift_control_follows_the_clocks() {
+        // The control settles within about 100 s; the harness runs 300
+        for (from, to, ppm) in [(48_000, 48_000, 150.0), (48_000, 44_100, -150.0), (44_100, 48_000, 80.0)] {
+            let report = drift_test(ResamplerQuality::Low, from, to, ppm, 120.0);
+            assert!(report.passed, "{report:?}");
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// Output taps of a stream bus besides its main route: the broadcast mix (what goes to
+// OBS) hears every stream after its processing but before the personal fader, at a level
+// and mute of its own. Extra outputs play a stream on further devices (headphones plus
+// a recording interface), each with its own level and a delay to line up devices with
+// different latency. The same delay line also holds back whole output devices.
+use serde::{Deserialize, Serialize};
+
+use super::{ChannelMatrix, OutputKey, MAX_BLOCK_FRAMES};
+
+// Longest delay an output can get
+pub const MAX_DELAY_MS: f32 = 1000.0;
+// Extra outputs per stream
+pub const MAX_EXTRA_OUTPUTS: usize = 4;
+
+// Level of a stream in one mix
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MixLevel {
+    // Linear, 0..1
+    pub gain: f32,
+    pub muted: bool,
+}
+
+impl Default for MixLevel {
+    fn default() -> Self {
+        MixLevel { gain: 1.0, muted: false }
+    }
+}
+
+impl MixLevel {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.gain.is_finite() && (0.0..=1.0).contains(&self.gain)) {
+            return Err(format!("Mix level {} out of range (0..1)", self.gain));
+        }
+        Ok(())
+    }
+
+    fn target(&self) -> f32 {
+        if self.muted { 0.0 } else { self.gain.clamp(0.0, 1.0) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TapSpec {
+    // Device; `None` is the default output
+    pub output: OutputKey,
+    #[serde(default)]
+    pub level: MixLevel,
+    #[serde(default)]
+    pub delay_ms: f32,
+    // Tap before the stream's fader and mute instead of after
+    #[serde(default)]
+    pub pre_fader: bool,
+}
+
+impl TapSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        self.level.validate()?;
+        if !(self.delay_ms.is_finite() && (0.0..=MAX_DELAY_MS).contains(&self.delay_ms)) {
+            return Err(format!("Delay {} ms out of range (0..{MAX_DELAY_MS})", self.delay_ms));
+        }
+        Ok(())
+    }
+}
+
+// Fixed-size delay, allocated for the longest delay up front so it can change live
+pub(super) struct DelayLine {
+    samples: Vec<f32>,
+    channels: usize,
+    frames: usize,
+    write: usize,
+    delay: usize,
+    sample_rate: u32,
+    // Delayed block handed to the matrix
+    out: Vec<f32>,
+}
+
+impl DelayLine {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let frames = (MAX_DELAY_MS / 1000.0 * sample_rate as f32) as usize + 1;
+        DelayLine {
+            samples: vec![0.0; frames * channels],
+            channels,
+            frames,
+            write: 0,
+            delay: 0,
+            sample_rate,
+            out: vec![0.0; MAX_BLOCK_FRAMES * channels],
+        }
+    }
+
+    pub fn set_delay_ms(&mut self, ms: f32) {
+        let frames = (ms.clamp(0.0, MAX_DELAY_MS) / 1000.0 * self.sample_rate as f32).round() as usize;
+        self.delay = frames.min(self.frames - 1);
+    }
+
+    pub fn process(&mut self, src: &[f32]) -> &[f32] {
+        let ch = self.channels;
+        let n = src.len().min(self.out.len());
+        for (s, o) in src[..n].chunks_exact(ch).zip(self.out.chunks_exact_mut(ch)) {
+            let w = self.write * ch;
+            self.samples[w..w + ch].copy_from_slice(s);
+            let r = (self.write + self.frames - self.delay) % self.frames * ch;
+            o.copy_from_slice(&self.samples[r..r + ch]);
+            self.write = (self.write + 1) % self.frames;
+        }
+        &self.out[..n]
+    }
+}
+
+pub(super) struct Tap {
+    pub output: OutputKey,
+    pub pre_fader: bool,
+    level: MixLevel,
+    // Gain reached at the end of the last block
+    gain: f32,
+    matrix: ChannelMatrix,
+    delay: Option<DelayLine>,
+}
+
+impl Tap {
+    // `channels` and `sample_rate` describe the bus, for taps that may be delayed
+    pub fn new(spec: &TapSpec, matrix: ChannelMatrix, delay: Option<(usize, u32)>) -> Self {
+        let delay = delay.map(|(channels, rate)| {
+            let mut line = DelayLine::new(channels, rate);
+            line.set_delay_ms(spec.delay_ms);
+            line
+        });
+        Tap {
+            output: spec.output.clone(),
+            pre_fader: spec.pre_fader,
+            level: spec.level,
+            gain: spec.level.target(),
+            matrix,
+            delay,
+        }
+    }
+
+    pub fn set_level(&mut self, level: MixLevel) {
+        self.level = level;
+    }
+
+    pub fn update(&mut self, spec: &TapSpec) {
+        self.level = spec.level;
+        self.pre_fader = spec.pre_fader;
+        if let Some(line) = self.delay.as_mut() {
+            line.set_delay_ms(spec.delay_ms);
+        }
+    }
+
+    // Add the bus block into `dst`, ramping level changes over the block
+    pub fn mix_into(&mut self, src: &[f32], src_channels: usize, dst: &mut [f32], dst_channels: usize) {
+        // The delay keeps running while muted, so unmuting doesn't replay old audio
+        let src = match self.delay.as_mut() {
+            Some(line) => line.process(src),
+            None => src,
+        };
+        let target = self.level.target();
+        if self.gain == 0.0 && target == 0.0 {
+            return;
+        }
+        self.matrix.mix_into_ramped(src, src_channels, dst, dst_channels, self.gain, target);
+        self.gain = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::MatrixSettings;
+
+    const RATE: u32 = 48_000;
+
+    // Stereo frames numbered from 1, left positive and right negative
+    fn frames(range: std::ops::Range<usize>) -> Vec<f32> {
+        range.flat_map(|i| [i as f32 + 1.0, -(i as f32 + 1.0)]).collect()
+    }
+
+    // Run `input` through in blocks of `block` frames
+    fn run(line: &mut DelayLine, input: &[f32], block: usize) -> Vec<f32> {
+        input.chunks(block * 2).flat_map(|b| line.process(b).to_vec()).collect()
+    }
+
+    #[test]
+    fn delay_line_delays_by_whole_frames() {
+        // 10 ms and 250 ms at 48 kHz, across blocks that don't divide the delay
+        for (ms, delay) in [(10.0, 480), (250.0, 12_000), (0.0, 0)] {
+            for block in [MAX_BLOCK_FRAMES, 333, 1] {
+                let mut line = DelayLine::new(2, RATE);
+                line.set_delay_ms(ms);
+                let input = frames(0..20_000);
+                let out = run(&mut line, &input, block);
+                assert_eq!(out.len(), input.len());
+                assert!(out[..delay * 2].iter().all(|s| *s == 0.0), "{} ms", ms);
+                assert_eq!(&out[delay * 2..], &input[..input.len() - delay * 2], "{} ms in blocks of {}", ms, block);
+            }
+        }
+    }
+
+    #[test]
+    fn delay_line_is_capped_and_changes_live() {
+        let mut line = DelayLine::new(2, RATE);
+        line.set_delay_ms(5000.0);
+        assert_eq!(line.delay, (MAX_DELAY_MS / 1000.0 * RATE as f32) as usize);
+        line.set_delay_ms(-3.0);
+        assert_eq!(line.delay, 0);
+
+        // Shortening the delay skips ahead; no change reads outside the line
+        line.set_delay_ms(20.0);
+        let a = run(&mut line, &frames(0..4800), 512);
+        assert_eq!(a[960 * 2], 1.0);
+        line.set_delay_ms(1.0);
+        let b = run(&mut line, &frames(4800..9600), 512);
+        assert_eq!(b[0], 4800.0 - 48.0 + 1.0);
+        line.set_delay_ms(MAX_DELAY_MS);
+        let c = run(&mut line, &frames(9600..9700), 100);
+        assert_eq!(c.len(), 200);
+    }
+
+    #[test]
+    fn extra_outputs_play_delayed_at_their_level() {
+        let level = MixLevel { gain: 0.5, muted: false };
+        let spec = TapSpec { output: None, level, delay_ms: 2.0, pre_fader: false };
+        let matrix = ChannelMatrix::new(&MatrixSettings::default(), 2, 2);
+        let mut tap = Tap::new(&spec, matrix, Some((2, RATE)));
+        let input = frames(0..1000);
+        let mut out = vec![0.0; input.len()];
+        tap.mix_into(&input, 2, &mut out, 2);
+        assert!(out[..96 * 2].iter().all(|s| *s == 0.0));
+        assert_eq!(out[96 * 2..98 * 2], [0.5, -0.5, 1.0, -1.0]);
+
+        // Muted, the delay keeps running, so unmuting picks up current audio
+        tap.set_level(MixLevel { muted: true, ..level });
+        tap.mix_into(&frames(1000..2000), 2, &mut vec![0.0; 2000], 2);
+        tap.mix_into(&frames(2000..3000), 2, &mut vec![0.0; 2000], 2);
+        tap.set_level(level);
+        let mut out = vec![0.0; 2000];
+        tap.mix_into(&frames(3000..4000), 2, &mut out, 2);
+        // Ramped back up over the block, so compare the level at its end
+        assert!((out[1998] / (4000.0 - 96.0) - 0.5).abs() < 1e-3, "{}", out[1998]);
+    }
+}
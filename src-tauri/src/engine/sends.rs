@@ -0,0 +1,78 @@
+// Aux buses: buses fed by sends from the stream buses instead of a capture device, e.g.
+// one reverb shared by Game, Voice and Music, or a "stream mix" played on a virtual
+// cable. Sends only run from stream buses to aux buses, so the routing graph can't loop
+// and the engine renders every aux bus after the stream buses.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::StreamId;
+
+pub const MAX_AUX_BUSES: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SendSettings {
+    // Linear, 0..1
+    pub level: f32,
+    // Tap the stream before its fader, so the send ignores the stream volume and mute
+    pub pre_fader: bool,
+}
+
+impl SendSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.level.is_finite() && (0.0..=1.0).contains(&self.level)) {
+            return Err(format!("Send level {} out of range (0..1)", self.level));
+        }
+        Ok(())
+    }
+}
+
+fn unity() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuxBusSettings {
+    // "aux-<n>", never a stream name, so both share one namespace (`BusId`)
+    pub id: String,
+    pub name: String,
+    // Device the bus plays on; `None` is the default output
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default = "unity")]
+    pub gain: f32,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub sends: BTreeMap<StreamId, SendSettings>,
+}
+
+impl AuxBusSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.id.starts_with("aux-") {
+            return Err(format!("Invalid aux bus ID '{}'", self.id));
+        }
+        if self.name.trim().is_empty() || self.name.len() > 64 {
+            return Err("Aux bus name must be 1-64 characters".into());
+        }
+        if !(self.gain.is_finite() && (0.0..=1.0).contains(&self.gain)) {
+            return Err(format!("Aux bus gain {} out of range (0..1)", self.gain));
+        }
+        for send in self.sends.values() {
+            send.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn send(&self, stream: &StreamId) -> SendSettings {
+        self.sends.get(stream).copied().unwrap_or_default()
+    }
+}
+
+// First free ID for a new aux bus
+pub fn next_id(existing: &[AuxBusSettings]) -> String {
+    (1..)
+        .map(|n| format!("aux-{n}"))
+        .find(|id| existing.iter().all(|a| a.id != *id))
+        .unwrap_or_default()
+}
@@ -0,0 +1,319 @@
+// Soundboard: short clips decoded into memory up front and played by the audio thread into
+// a stream bus (after its effects, before the fader) and, if wanted, into the mix the
+// virtual microphone picks up. Clips come in already at the engine rate, so playing one is
+// just reading samples; voices live in a fixed pool so triggering doesn't allocate.
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::buffer::{self, AudioBuffer};
+use super::resample::{Resampler, ResamplerQuality};
+use super::MAX_BLOCK_FRAMES;
+use crate::StreamId;
+
+// Longer files are music, not clips, and would sit in memory for nothing
+pub const MAX_CLIP_SECONDS: f32 = 30.0;
+// Clips playing at once over all slots; past that the oldest is cut
+pub const MAX_VOICES: usize = 32;
+// Width of the soundboard's share of the virtual mic
+pub const MIC_CHANNELS: usize = 2;
+// Stopped and cut voices fade out over this long instead of clicking
+const FADE_SECONDS: f32 = 0.005;
+
+// Decoded clip, interleaved
+pub struct ClipAudio {
+    pub channels: usize,
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl ClipAudio {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    pub fn seconds(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+}
+
+// Decode a WAV, FLAC or Ogg Vorbis file
+pub fn decode(path: &Path) -> Result<ClipAudio, String> {
+    let err = |e: Error| format!("Decode {} failed: {e}", path.display());
+    let file = File::open(path).map_err(|e| format!("Open {} failed: {e}", path.display()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(err)?;
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| format!("{} has no audio track", path.display()))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default()).map_err(err)?;
+    let mut channels = track.codec_params.channels.map_or(0, |c| c.count());
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(err(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged packet only costs its own samples
+            Err(Error::DecodeError(e)) => {
+                log::warn!("Skipping bad packet in {}: {}", path.display(), e);
+                continue;
+            }
+            Err(e) => return Err(err(e)),
+        };
+        let spec = *decoded.spec();
+        channels = spec.channels.count();
+        sample_rate = spec.rate;
+        let mut interleaved = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        interleaved.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(interleaved.samples());
+        if samples.len() as f32 > (MAX_CLIP_SECONDS + 1.0) * sample_rate as f32 * channels as f32 {
+            return Err(format!("{} is longer than {} s", path.display(), MAX_CLIP_SECONDS));
+        }
+    }
+    if channels == 0 || sample_rate == 0 || samples.is_empty() {
+        return Err(format!("{} has no audio", path.display()));
+    }
+    let clip = ClipAudio { channels, sample_rate, samples };
+    if clip.seconds() > MAX_CLIP_SECONDS {
+        return Err(format!("{} is longer than {} s", path.display(), MAX_CLIP_SECONDS));
+    }
+    Ok(clip)
+}
+
+// Convert a clip to `sample_rate` in one go (High quality; this isn't on the audio thread)
+pub fn resample(clip: &ClipAudio, sample_rate: u32) -> ClipAudio {
+    let ch = clip.channels;
+    let frames = (clip.frames() as u64 * sample_rate as u64).div_ceil(clip.sample_rate as u64) as usize;
+    let mut resampler = Resampler::new(ch, clip.sample_rate, sample_rate, ResamplerQuality::High);
+    let mut samples = vec![0.0; frames * ch];
+    let mut read = 0;
+    resampler.process(&mut samples, |input| {
+        let n = input.len().min(clip.samples.len() - read);
+        input[..n].copy_from_slice(&clip.samples[read..read + n]);
+        input[n..].fill(0.0);
+        read += n;
+    });
+    ClipAudio { channels: ch, sample_rate, samples }
+}
+
+// One press of a slot. The clip has to be at the engine rate.
+#[derive(Clone)]
+pub struct Trigger {
+    pub clip: Arc<ClipAudio>,
+    pub slot: usize,
+    pub stream: StreamId,
+    pub to_mic: bool,
+    pub gain: f32,
+    // Voices of this slot at once; a further press cuts the oldest
+    pub polyphony: usize,
+}
+
+struct Voice {
+    trigger: Trigger,
+    // Next frame of the clip
+    position: usize,
+    // Frames left of the fade-out once stopped
+    release: Option<usize>,
+}
+
+impl Voice {
+    fn done(&self) -> bool {
+        self.position >= self.trigger.clip.frames() || self.release == Some(0)
+    }
+
+    fn mix_into(&self, frames: usize, fade: usize, dst: &mut [f32], dst_channels: usize) {
+        let clip = &self.trigger.clip;
+        let ch = clip.channels;
+        let end = (self.position + frames).min(clip.frames());
+        let src = &clip.samples[self.position * ch..end * ch];
+        let gain = self.trigger.gain;
+        match self.release {
+            None => buffer::mix_into(src, ch, dst, dst_channels, gain),
+            Some(left) => {
+                for (i, (s, d)) in src.chunks_exact(ch).zip(dst.chunks_exact_mut(dst_channels)).enumerate() {
+                    let g = gain * left.saturating_sub(i) as f32 / fade as f32;
+                    buffer::mix_into(s, ch, d, dst_channels, g);
+                }
+            }
+        }
+    }
+}
+
+// Audio-thread side, owned by the engine
+pub struct Player {
+    voices: Vec<Voice>,
+    fade: usize,
+    mic: AudioBuffer,
+}
+
+impl Player {
+    pub fn new(sample_rate: u32) -> Self {
+        Player {
+            voices: Vec::with_capacity(MAX_VOICES),
+            fade: ((FADE_SECONDS * sample_rate as f32) as usize).max(1),
+            mic: AudioBuffer::new(MIC_CHANNELS, MAX_BLOCK_FRAMES),
+        }
+    }
+
+    pub fn play(&mut self, trigger: Trigger) {
+        let polyphony = trigger.polyphony.max(1);
+        let mut playing = self.voices.iter().filter(|v| v.trigger.slot == trigger.slot && v.release.is_none()).count();
+        for v in self.voices.iter_mut().filter(|v| v.trigger.slot == trigger.slot && v.release.is_none()) {
+            if playing < polyphony {
+                break;
+            }
+            v.release = Some(self.fade);
+            playing -= 1;
+        }
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(Voice { trigger, position: 0, release: None });
+    }
+
+    // Fade out the voices of `slot`, or all of them
+    pub fn stop(&mut self, slot: Option<usize>) {
+        for v in self.voices.iter_mut().filter(|v| slot.is_none_or(|s| v.trigger.slot == s)) {
+            v.release.get_or_insert(self.fade);
+        }
+    }
+
+    pub fn begin(&mut self, frames: usize) {
+        self.mic.begin(frames);
+    }
+
+    // Add the voices playing into `stream` to that bus's block
+    pub fn mix_bus(&self, stream: &StreamId, frames: usize, dst: &mut [f32], channels: usize) {
+        for v in self.voices.iter().filter(|v| v.trigger.stream == *stream) {
+            v.mix_into(frames, self.fade, dst, channels);
+        }
+    }
+
+    // Mix the voices sent to the virtual mic, move every voice on by `frames` and return
+    // the mic share of this block
+    pub fn finish(&mut self, frames: usize) -> &[f32] {
+        for v in self.voices.iter().filter(|v| v.trigger.to_mic) {
+            v.mix_into(frames, self.fade, self.mic.samples_mut(), MIC_CHANNELS);
+        }
+        for v in self.voices.iter_mut() {
+            v.position += frames;
+            v.release = v.release.map(|r| r.saturating_sub(frames));
+        }
+        self.voices.retain(|v| !v.done());
+        self.mic.samples()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // Mono clip of a constant level
+    fn clip(frames: usize, level: f32) -> Arc<ClipAudio> {
+        Arc::new(ClipAudio { channels: 1, sample_rate: RATE, samples: vec![level; frames] })
+    }
+
+    fn trigger(slot: usize, clip: &Arc<ClipAudio>, to_mic: bool, polyphony: usize) -> Trigger {
+        Trigger { clip: clip.clone(), slot, stream: StreamId::Game, to_mic, gain: 1.0, polyphony }
+    }
+
+    // One block: the Game bus share (stereo) and the mic share
+    fn render(player: &mut Player, frames: usize) -> (Vec<f32>, Vec<f32>) {
+        player.begin(frames);
+        let mut bus = vec![0.0; frames * 2];
+        player.mix_bus(&StreamId::Game, frames, &mut bus, 2);
+        let mic = player.finish(frames).to_vec();
+        (bus, mic)
+    }
+
+    #[test]
+    fn polyphony_cuts_the_oldest_voice_of_the_slot() {
+        let mut player = Player::new(RATE);
+        let long = clip(RATE as usize, 0.1);
+        player.play(trigger(0, &long, false, 2));
+        player.play(trigger(1, &long, false, 1));
+        player.play(trigger(0, &long, false, 2));
+        assert!(player.voices.iter().all(|v| v.release.is_none()));
+        player.play(trigger(0, &long, false, 2));
+        let releases: Vec<_> = player.voices.iter().map(|v| (v.trigger.slot, v.release.is_some())).collect();
+        assert_eq!(releases, [(0, true), (1, false), (0, false), (0, false)]);
+    }
+
+    #[test]
+    fn voices_are_capped() {
+        let mut player = Player::new(RATE);
+        let long = clip(RATE as usize, 0.1);
+        for slot in 0..MAX_VOICES + 5 {
+            player.play(trigger(slot, &long, false, 1));
+        }
+        assert_eq!(player.voices.len(), MAX_VOICES);
+        // The oldest went first
+        assert_eq!(player.voices[0].trigger.slot, 5);
+        // Never grown on the audio thread
+        assert_eq!(player.voices.capacity(), MAX_VOICES);
+    }
+
+    #[test]
+    fn stopped_voices_fade_to_silence() {
+        let mut player = Player::new(RATE);
+        let fade = player.fade;
+        player.play(trigger(0, &clip(RATE as usize, 0.5), false, 1));
+        let (bus, _) = render(&mut player, 64);
+        assert!(bus.iter().all(|&s| s == 0.5));
+
+        player.stop(Some(0));
+        let mut left = Vec::new();
+        while !player.voices.is_empty() {
+            left.extend(render(&mut player, 64).0.chunks_exact(2).map(|f| f[0]));
+            assert!(left.len() <= fade + 64, "fade doesn't end");
+        }
+        assert!(left[0] <= 0.5 && left.windows(2).all(|w| w[1] <= w[0]));
+        assert!(left[fade..].iter().all(|&s| s == 0.0));
+        assert!(left[fade - 1] > 0.0 && left[fade - 1] < 0.01);
+    }
+
+    #[test]
+    fn only_mic_voices_reach_the_mic() {
+        let mut player = Player::new(RATE);
+        player.play(trigger(0, &clip(1000, 0.25), true, 1));
+        player.play(trigger(1, &clip(1000, 0.5), false, 1));
+        let (bus, mic) = render(&mut player, 128);
+        // Both play on the bus, the mic only gets the first
+        assert!(bus.iter().all(|&s| s == 0.75));
+        assert_eq!(mic.len(), 128 * MIC_CHANNELS);
+        assert!(mic.iter().all(|&s| s == 0.25));
+        // Finished voices are gone
+        render(&mut player, 1000);
+        assert!(player.voices.is_empty());
+        assert!(render(&mut player, 64).1.iter().all(|&s| s == 0.0));
+    }
+}
@@ -0,0 +1,177 @@
+// Signal sources feeding the buses. Live capture arrives through lock-free ring buffers
+// filled by the cpal input callbacks; test tones drive offline renders.
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use super::resample::{DriftControl, Resampler, ResamplerQuality};
+
+pub trait Source: Send {
+    fn channels(&self) -> usize;
+    // Fill `out` (interleaved, a whole number of frames). Missing data must be zeroed.
+    fn read(&mut self, out: &mut [f32]);
+}
+
+// Consumer side of a ring buffer between two clock domains (capture device -> engine,
+// engine -> secondary output). The consumer resamples to its own rate and steers the
+// ratio so the fill level stays put while the two clocks drift apart.
+pub struct RingSource {
+    consumer: Consumer<f32>,
+    channels: usize,
+    // Above this many buffered samples the oldest are dropped, so latency can't creep up
+    max_buffered: usize,
+    // Fill level the drift control aims for, and waited for after an underrun
+    target: usize,
+    from_rate: f64,
+    to_rate: f64,
+    resampler: Resampler,
+    drift: DriftControl,
+    primed: bool,
+    underruns: u64,
+    dropped: u64,
+}
+
+// Create a ring buffer holding `capacity_frames` at `from_rate`; the source keeps at most
+// `max_latency_frames` queued and delivers at `to_rate`.
+pub fn ring_pair(
+    channels: usize,
+    from_rate: u32,
+    to_rate: u32,
+    quality: ResamplerQuality,
+    capacity_frames: usize,
+    max_latency_frames: usize,
+) -> (Producer<f32>, RingSource) {
+    let channels = channels.max(1);
+    let (producer, consumer) = RingBuffer::new(capacity_frames * channels);
+    let max_frames = max_latency_frames.min(capacity_frames);
+    let source = RingSource {
+        consumer,
+        channels,
+        max_buffered: max_frames * channels,
+        target: max_frames / 2 * channels,
+        from_rate: from_rate as f64,
+        to_rate: to_rate as f64,
+        resampler: Resampler::new(channels, from_rate, to_rate, quality),
+        drift: DriftControl::new(max_frames as f64 / 2.0 / from_rate as f64),
+        primed: false,
+        underruns: 0,
+        dropped: 0,
+    };
+    (producer, source)
+}
+
+impl RingSource {
+    // Times the buffer ran dry after it had been filled
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    // Samples skipped because too much was queued
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Current ratio correction of the drift control
+    pub fn correction(&self) -> f64 {
+        self.drift.correction()
+    }
+}
+
+impl Source for RingSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, out: &mut [f32]) {
+        let frames = out.len() / self.channels;
+        let mut buffered = self.consumer.slots();
+        if buffered > self.max_buffered + out.len() {
+            // Keep whole frames when skipping
+            let skip = (buffered - self.max_buffered) / self.channels * self.channels;
+            if let Ok(chunk) = self.consumer.read_chunk(skip) {
+                chunk.commit_all();
+                self.dropped += skip as u64;
+                buffered -= skip;
+            }
+        }
+        let channels = self.channels as f64;
+        let seconds = |samples: usize, rate: f64| samples as f64 / channels / rate;
+        if !self.primed {
+            // Start (again) once the target level is there
+            if buffered < self.target {
+                out.fill(0.0);
+                return;
+            }
+            self.primed = true;
+            self.drift.reset(seconds(buffered, self.from_rate));
+        }
+        let correction = self.drift.update(seconds(buffered, self.from_rate), seconds(out.len(), self.to_rate));
+        self.resampler.set_correction(correction);
+        if self.resampler.input_needed(frames) * self.channels > buffered {
+            self.primed = false;
+            self.underruns += 1;
+            out.fill(0.0);
+            return;
+        }
+        let consumer = &mut self.consumer;
+        self.resampler.process(out, |input| {
+            let got = pop_samples(consumer, input);
+            input[got..].fill(0.0);
+        });
+    }
+}
+
+// Push as many samples as fit; returns how many were written
+pub fn push_samples(producer: &mut Producer<f32>, samples: &[f32]) -> usize {
+    let n = producer.slots().min(samples.len());
+    match producer.write_chunk_uninit(n) {
+        Ok(chunk) => chunk.fill_from_iter(samples[..n].iter().copied()),
+        Err(_) => 0,
+    }
+}
+
+// Pop up to `out.len()` samples; returns how many were read
+pub fn pop_samples(consumer: &mut Consumer<f32>, out: &mut [f32]) -> usize {
+    let n = consumer.slots().min(out.len());
+    match consumer.read_chunk(n) {
+        Ok(chunk) => {
+            let (a, b) = chunk.as_slices();
+            out[..a.len()].copy_from_slice(a);
+            out[a.len()..a.len() + b.len()].copy_from_slice(b);
+            chunk.commit_all();
+            n
+        }
+        Err(_) => 0,
+    }
+}
+
+// Sine test tone on every channel
+pub struct SineSource {
+    channels: usize,
+    phase: f64,
+    step: f64,
+    amplitude: f32,
+}
+
+impl SineSource {
+    pub fn new(channels: usize, sample_rate: u32, frequency: f64, amplitude: f32) -> Self {
+        SineSource {
+            channels: channels.max(1),
+            phase: 0.0,
+            step: std::f64::consts::TAU * frequency / sample_rate as f64,
+            amplitude,
+        }
+    }
+}
+
+impl Source for SineSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_mut(self.channels) {
+            let v = self.phase.sin() as f32 * self.amplitude;
+            frame.fill(v);
+            self.phase = (self.phase + self.step) % std::f64::consts::TAU;
+        }
+    }
+}
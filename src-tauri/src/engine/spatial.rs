@@ -0,0 +1,486 @@
+// Binaural virtual surround for the Game bus. Every input channel is treated as a
+// loudspeaker at its standard 5.1/7.1 position and convolved with the HRIR pair
+// measured closest to that direction; the results are summed to stereo for headphones.
+// HRIR sets come from SOFA files (AES69, SimpleFreeFieldHRIR) or from a built-in
+// spherical head model. Convolution is uniformly partitioned in the frequency domain,
+// which adds BLOCK frames of latency.
+use std::path::Path;
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+
+use super::buffer::AudioBuffer;
+use super::hdf5;
+use super::MAX_BLOCK_FRAMES;
+
+const BLOCK: usize = 256;
+const FFT_SIZE: usize = 2 * BLOCK;
+const BINS: usize = FFT_SIZE / 2 + 1;
+// HRIRs are cut to this many taps at the engine rate
+const PARTITIONS: usize = 4;
+const MAX_TAPS: usize = PARTITIONS * BLOCK;
+const MAX_CHANNELS: usize = 8;
+
+// Virtual loudspeakers, azimuth in degrees counter-clockwise from the front (the SOFA
+// convention, so left is positive), all at ear height
+const SPEAKERS: [f32; 9] = [30.0, -30.0, 0.0, 110.0, -110.0, 150.0, -150.0, 90.0, -90.0];
+const FRONT_LEFT: usize = 0;
+const FRONT_RIGHT: usize = 1;
+const CENTER: usize = 2;
+// Surrounds of a 5.1 layout
+const SURROUND_LEFT: usize = 3;
+const SURROUND_RIGHT: usize = 4;
+// Backs and sides of a 7.1 layout
+const BACK_LEFT: usize = 5;
+const BACK_RIGHT: usize = 6;
+const SIDE_LEFT: usize = 7;
+const SIDE_RIGHT: usize = 8;
+// LFE goes through the center HRIR at -3 dB
+const LFE_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct SpatialSettings {
+    pub enabled: bool,
+    // Name of an imported SOFA set, `None` for the built-in model
+    pub hrtf: Option<String>,
+}
+
+// Channel -> (speaker, gain) in WAVEFORMATEXTENSIBLE order (FL FR FC LFE BL BR SL SR)
+fn layout(channels: usize) -> [Option<(usize, f32)>; MAX_CHANNELS] {
+    let mut map = [None; MAX_CHANNELS];
+    let speakers: &[(usize, f32)] = match channels {
+        1 => &[(CENTER, 1.0)],
+        2 => &[(FRONT_LEFT, 1.0), (FRONT_RIGHT, 1.0)],
+        4 => &[(FRONT_LEFT, 1.0), (FRONT_RIGHT, 1.0), (SURROUND_LEFT, 1.0), (SURROUND_RIGHT, 1.0)],
+        6 => &[
+            (FRONT_LEFT, 1.0),
+            (FRONT_RIGHT, 1.0),
+            (CENTER, 1.0),
+            (CENTER, LFE_GAIN),
+            (SURROUND_LEFT, 1.0),
+            (SURROUND_RIGHT, 1.0),
+        ],
+        _ => &[
+            (FRONT_LEFT, 1.0),
+            (FRONT_RIGHT, 1.0),
+            (CENTER, 1.0),
+            (CENTER, LFE_GAIN),
+            (BACK_LEFT, 1.0),
+            (BACK_RIGHT, 1.0),
+            (SIDE_LEFT, 1.0),
+            (SIDE_RIGHT, 1.0),
+        ],
+    };
+    for (slot, s) in map.iter_mut().zip(speakers.iter().take(channels)) {
+        *slot = Some(*s);
+    }
+    map
+}
+
+// Plain stereo downmix of a speaker (left, right), used while the virtualizer is off
+fn downmix_gains(speaker: usize) -> (f32, f32) {
+    let h = std::f32::consts::FRAC_1_SQRT_2;
+    match speaker {
+        FRONT_LEFT => (1.0, 0.0),
+        FRONT_RIGHT => (0.0, 1.0),
+        CENTER => (h, h),
+        SURROUND_LEFT | BACK_LEFT | SIDE_LEFT => (h, 0.0),
+        _ => (0.0, h),
+    }
+}
+
+// A set of head-related impulse responses: one (left, right) pair per measured direction
+#[derive(Debug, Clone)]
+pub struct HrirSet {
+    pub name: String,
+    pub sample_rate: u32,
+    // (azimuth, elevation) in degrees
+    directions: Vec<(f32, f32)>,
+    irs: Vec<[Vec<f32>; 2]>,
+}
+
+impl HrirSet {
+    pub fn measurements(&self) -> usize {
+        self.directions.len()
+    }
+
+    // Load a SimpleFreeFieldHRIR SOFA file: Data.IR is M x 2 x N, SourcePosition
+    // M x 3 (spherical degrees/metres or cartesian)
+    pub fn from_sofa(path: &Path) -> Result<HrirSet, String> {
+        let file = hdf5::File::open(path)?;
+        let fail = |e: String| format!("{}: {}", path.display(), e);
+
+        let ir = file.dataset("Data.IR").map_err(fail)?;
+        let [m, r, n] = ir.dims[..] else {
+            return Err(fail("Data.IR must have 3 dimensions".into()));
+        };
+        let (m, n) = (m as usize, n as usize);
+        if r != 2 || m == 0 || n == 0 {
+            return Err(fail(format!("Data.IR is {}x{}x{}, expected M x 2 x N", m, r, n)));
+        }
+        let rate = file.dataset("Data.SamplingRate").map_err(fail)?.values.first().copied().unwrap_or(0.0);
+        if !(8000.0..=384_000.0).contains(&rate) {
+            return Err(fail(format!("unsupported sampling rate {}", rate)));
+        }
+
+        let pos = file.dataset("SourcePosition").map_err(fail)?;
+        if pos.dims.len() != 2 || pos.dims[1] != 3 || (pos.dims[0] as usize != m && pos.dims[0] != 1) {
+            return Err(fail("SourcePosition must be M x 3".into()));
+        }
+        let row = |i: usize| {
+            let i = if pos.dims[0] == 1 { 0 } else { i };
+            [pos.values[i * 3], pos.values[i * 3 + 1], pos.values[i * 3 + 2]]
+        };
+        let cartesian = match file.attribute_string("SourcePosition", "Type").map_err(fail)? {
+            Some(kind) => kind.eq_ignore_ascii_case("cartesian"),
+            // Without the attribute: angles in degrees easily exceed 10, metres don't
+            None => (0..m).all(|i| row(i)[0].abs() <= 10.0 && row(i)[1].abs() <= 10.0),
+        };
+        let directions = (0..m)
+            .map(|i| {
+                let [a, b, c] = row(i);
+                if cartesian {
+                    (b.atan2(a).to_degrees() as f32, c.atan2(a.hypot(b)).to_degrees() as f32)
+                } else {
+                    (a as f32, b as f32)
+                }
+            })
+            .collect();
+
+        // Optional broadband delays in samples, 1 x 2 or M x 2
+        let delay = file.dataset("Data.Delay").ok().filter(|d| d.values.len() == 2 || d.values.len() == 2 * m);
+        let irs = (0..m)
+            .map(|i| {
+                [0, 1].map(|ear| {
+                    let start = (i * 2 + ear) * n;
+                    let shift = delay
+                        .as_ref()
+                        .map(|d| d.values[if d.values.len() == 2 { ear } else { i * 2 + ear }].max(0.0).round() as usize)
+                        .unwrap_or(0)
+                        .min(MAX_TAPS);
+                    let mut taps = vec![0.0; shift];
+                    taps.extend(ir.values[start..start + n].iter().map(|&v| v as f32));
+                    taps
+                })
+            })
+            .collect();
+
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("SOFA").to_string();
+        Ok(HrirSet { name, sample_rate: rate.round() as u32, directions, irs })
+    }
+
+    // Spherical head model after Brown & Duda (1998): interaural delay (Woodworth) plus a
+    // one-pole/one-zero head shadow per ear, horizontal plane every 5 degrees. No pinna
+    // cues, so front/back separation is weak, but it works without any file.
+    pub fn builtin() -> HrirSet {
+        const RATE: u32 = 48_000;
+        const TAPS: usize = 256;
+        const HEAD_RADIUS: f64 = 0.0875;
+        const SPEED_OF_SOUND: f64 = 343.0;
+        let fs = RATE as f64;
+        let w0 = SPEED_OF_SOUND / HEAD_RADIUS;
+        let k = 2.0 * fs;
+
+        let ear = |azimuth: f64, ear_azimuth: f64| -> Vec<f32> {
+            // Angle between the source and the ear axis, 0..pi
+            let theta = ((azimuth - ear_azimuth + 540.0).rem_euclid(360.0) - 180.0).abs().to_radians();
+            let alpha = 1.05 + 0.95 * (theta / 150f64.to_radians() * std::f64::consts::PI).cos();
+            // Bilinear transform of (alpha*s + 2*w0) / (s + 2*w0)
+            let a0 = 2.0 * w0 + k;
+            let b0 = (2.0 * w0 + alpha * k) / a0;
+            let b1 = (2.0 * w0 - alpha * k) / a0;
+            let a1 = (2.0 * w0 - k) / a0;
+            let path = if theta < std::f64::consts::FRAC_PI_2 {
+                -theta.cos()
+            } else {
+                theta - std::f64::consts::FRAC_PI_2
+            };
+            let delay = path * HEAD_RADIUS / SPEED_OF_SOUND;
+            // Shift so the earliest ear arrives at sample 1 or later
+            let start = ((delay + HEAD_RADIUS / SPEED_OF_SOUND) * fs).round() as usize + 1;
+            let mut taps = vec![0.0f32; TAPS];
+            let (mut x1, mut y1) = (0.0, 0.0);
+            for (i, tap) in taps.iter_mut().enumerate().skip(start) {
+                let x = if i == start { 1.0 } else { 0.0 };
+                let y = b0 * x + b1 * x1 - a1 * y1;
+                x1 = x;
+                y1 = y;
+                *tap = y as f32;
+            }
+            taps
+        };
+
+        let mut directions = Vec::new();
+        let mut irs = Vec::new();
+        for step in 0..72 {
+            let azimuth = step as f64 * 5.0;
+            directions.push((azimuth as f32, 0.0));
+            irs.push([ear(azimuth, 90.0), ear(azimuth, -90.0)]);
+        }
+        HrirSet { name: "Built-in".into(), sample_rate: RATE, directions, irs }
+    }
+
+    // Measurement closest to a direction (great-circle distance)
+    fn nearest(&self, azimuth: f32, elevation: f32) -> usize {
+        let unit = |az: f32, el: f32| {
+            let (az, el) = (az.to_radians(), el.to_radians());
+            [el.cos() * az.cos(), el.cos() * az.sin(), el.sin()]
+        };
+        let target = unit(azimuth, elevation);
+        let mut best = (0, f32::MIN);
+        for (i, &(az, el)) in self.directions.iter().enumerate() {
+            let v = unit(az, el);
+            let dot = v[0] * target[0] + v[1] * target[1] + v[2] * target[2];
+            if dot > best.1 {
+                best = (i, dot);
+            }
+        }
+        best.0
+    }
+}
+
+// Windowed-sinc resampling of an impulse response (offline, short inputs only)
+fn resample_ir(ir: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to {
+        return ir.to_vec();
+    }
+    const HALF_WIDTH: f64 = 16.0;
+    let ratio = from as f64 / to as f64;
+    // Low-pass at the lower Nyquist frequency
+    let cutoff = (1.0 / ratio).min(1.0);
+    let len = ((ir.len() as f64 / ratio).ceil() as usize).min(MAX_TAPS);
+    (0..len)
+        .map(|n| {
+            let t = n as f64 * ratio;
+            let lo = (t - HALF_WIDTH / cutoff).floor().max(0.0) as usize;
+            let hi = ((t + HALF_WIDTH / cutoff).ceil() as usize).min(ir.len().saturating_sub(1));
+            let mut acc = 0.0;
+            for (k, &x) in ir.iter().enumerate().take(hi + 1).skip(lo) {
+                let d = (t - k as f64) * cutoff;
+                let sinc = if d == 0.0 { 1.0 } else { (std::f64::consts::PI * d).sin() / (std::f64::consts::PI * d) };
+                // Blackman window over the kernel
+                let w = 0.42 + 0.5 * (std::f64::consts::PI * d / HALF_WIDTH).cos()
+                    + 0.08 * (2.0 * std::f64::consts::PI * d / HALF_WIDTH).cos();
+                if d.abs() < HALF_WIDTH {
+                    acc += x as f64 * sinc * w;
+                }
+            }
+            (acc * cutoff) as f32
+        })
+        .collect()
+}
+
+// What the engine gets for a bus: the settings plus the loaded set
+#[derive(Debug, Clone)]
+pub struct SpatialConfig {
+    pub enabled: bool,
+    pub hrirs: Arc<HrirSet>,
+}
+
+// Filter spectra of every virtual speaker at the engine rate, [speaker][ear][partition]
+// flattened; built off the audio thread
+pub struct SpatialParams {
+    enabled: bool,
+    filters: Vec<Complex<f32>>,
+}
+
+impl SpatialParams {
+    pub fn new(config: &SpatialConfig, sample_rate: u32) -> Self {
+        let set = &config.hrirs;
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let picked: Vec<[Vec<f32>; 2]> = SPEAKERS
+            .iter()
+            .map(|&az| {
+                let [l, r] = &set.irs[set.nearest(az, 0.0)];
+                [resample_ir(l, set.sample_rate, sample_rate), resample_ir(r, set.sample_rate, sample_rate)]
+            })
+            .collect();
+        // Sets differ wildly in level: scale so the loudest ear response has unit energy
+        let energy = picked.iter().flatten().map(|ir| ir.iter().map(|x| x * x).sum::<f32>()).fold(0.0, f32::max);
+        let scale = if energy > 0.0 { 1.0 / energy.sqrt() } else { 0.0 };
+
+        let mut filters = vec![Complex::default(); SPEAKERS.len() * 2 * PARTITIONS * BINS];
+        let mut time = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+        for (s, pair) in picked.iter().enumerate() {
+            for (ear, ir) in pair.iter().enumerate() {
+                for p in 0..PARTITIONS {
+                    time.fill(0.0);
+                    for (t, x) in time.iter_mut().zip(ir.iter().skip(p * BLOCK).take(BLOCK)) {
+                        *t = x * scale;
+                    }
+                    if fft.process(&mut time, &mut spectrum).is_ok() {
+                        let at = ((s * 2 + ear) * PARTITIONS + p) * BINS;
+                        filters[at..at + BINS].copy_from_slice(&spectrum);
+                    }
+                }
+            }
+        }
+        SpatialParams { enabled: config.enabled, filters }
+    }
+
+    fn filter(&self, speaker: usize, ear: usize, partition: usize) -> &[Complex<f32>] {
+        let at = ((speaker * 2 + ear) * PARTITIONS + partition) * BINS;
+        &self.filters[at..at + BINS]
+    }
+}
+
+// Uniformly partitioned overlap-save convolution of up to 8 channels into stereo
+struct Convolver {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    time: Vec<f32>,
+    scratch_fwd: Vec<Complex<f32>>,
+    scratch_inv: Vec<Complex<f32>>,
+    // Per channel: previous and current input block
+    windows: Vec<f32>,
+    // Per channel: spectra of the last PARTITIONS blocks (frequency-domain delay line)
+    history: Vec<Complex<f32>>,
+    head: usize,
+    accum: [Vec<Complex<f32>>; 2],
+    // Finished stereo block being played out
+    ready: Vec<f32>,
+    pos: usize,
+}
+
+impl Convolver {
+    fn new() -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(FFT_SIZE);
+        let inverse = planner.plan_fft_inverse(FFT_SIZE);
+        Convolver {
+            time: forward.make_input_vec(),
+            scratch_fwd: forward.make_scratch_vec(),
+            scratch_inv: inverse.make_scratch_vec(),
+            accum: [inverse.make_input_vec(), inverse.make_input_vec()],
+            forward,
+            inverse,
+            windows: vec![0.0; MAX_CHANNELS * FFT_SIZE],
+            history: vec![Complex::default(); MAX_CHANNELS * PARTITIONS * BINS],
+            head: 0,
+            ready: vec![0.0; 2 * BLOCK],
+            pos: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.windows.fill(0.0);
+        self.history.fill(Complex::default());
+        self.ready.fill(0.0);
+        self.pos = 0;
+    }
+
+    fn process(&mut self, p: &SpatialParams, map: &[Option<(usize, f32)>], input: &[f32], channels: usize, out: &mut [f32]) {
+        for (frame, o) in input.chunks(channels).zip(out.chunks_mut(2)) {
+            for (ch, &x) in frame.iter().enumerate().take(MAX_CHANNELS) {
+                self.windows[ch * FFT_SIZE + BLOCK + self.pos] = x;
+            }
+            o[0] = self.ready[2 * self.pos];
+            o[1] = self.ready[2 * self.pos + 1];
+            self.pos += 1;
+            if self.pos == BLOCK {
+                self.convolve(p, map, channels.min(MAX_CHANNELS));
+                self.pos = 0;
+            }
+        }
+    }
+
+    fn convolve(&mut self, p: &SpatialParams, map: &[Option<(usize, f32)>], channels: usize) {
+        self.head = (self.head + 1) % PARTITIONS;
+        for ch in 0..channels {
+            let window = &mut self.windows[ch * FFT_SIZE..(ch + 1) * FFT_SIZE];
+            self.time.copy_from_slice(window);
+            window.copy_within(BLOCK.., 0);
+            let at = (ch * PARTITIONS + self.head) * BINS;
+            let spectrum = &mut self.history[at..at + BINS];
+            if self.forward.process_with_scratch(&mut self.time, spectrum, &mut self.scratch_fwd).is_err() {
+                spectrum.fill(Complex::default());
+            }
+        }
+
+        for (ear, accum) in self.accum.iter_mut().enumerate() {
+            accum.fill(Complex::default());
+            for (ch, route) in map.iter().enumerate().take(channels) {
+                let Some((speaker, gain)) = *route else { continue };
+                for part in 0..PARTITIONS {
+                    // Partition k of the filter meets the input from k blocks ago
+                    let slot = (self.head + PARTITIONS - part) % PARTITIONS;
+                    let at = (ch * PARTITIONS + slot) * BINS;
+                    let x = &self.history[at..at + BINS];
+                    for ((a, x), h) in accum.iter_mut().zip(x).zip(p.filter(speaker, ear, part)) {
+                        *a += x * h * gain;
+                    }
+                }
+            }
+            accum[0].im = 0.0;
+            accum[BINS - 1].im = 0.0;
+            if self.inverse.process_with_scratch(accum, &mut self.time, &mut self.scratch_inv).is_err() {
+                self.time.fill(0.0);
+            }
+            // Overlap-save: the second half is the valid output
+            let scale = 1.0 / FFT_SIZE as f32;
+            for (i, y) in self.time[BLOCK..].iter().enumerate() {
+                self.ready[2 * i + ear] = y * scale;
+            }
+        }
+    }
+}
+
+// Sits in front of the Game bus: sources are summed into `input` (at the capture
+// device's channel count) and rendered to the stereo bus buffer
+pub struct Virtualizer {
+    params: SpatialParams,
+    input: AudioBuffer,
+    map: [Option<(usize, f32)>; MAX_CHANNELS],
+    convolver: Convolver,
+}
+
+impl Virtualizer {
+    pub fn new(params: SpatialParams, channels: usize) -> Self {
+        Virtualizer {
+            params,
+            input: AudioBuffer::new(channels, MAX_BLOCK_FRAMES),
+            map: layout(channels),
+            convolver: Convolver::new(),
+        }
+    }
+
+    pub fn set_params(&mut self, params: SpatialParams) {
+        if params.enabled != self.params.enabled {
+            // Don't play stale audio from before the switch
+            self.convolver.reset();
+        }
+        self.params = params;
+    }
+
+    // Input buffer for the next block, zeroed
+    pub fn begin(&mut self, frames: usize) -> &mut AudioBuffer {
+        self.input.begin(frames);
+        &mut self.input
+    }
+
+    // Render the input block into `out` (stereo interleaved, same frame count)
+    pub fn process(&mut self, out: &mut [f32]) {
+        let channels = self.input.channels();
+        let input = self.input.samples();
+        if self.params.enabled {
+            self.convolver.process(&self.params, &self.map, input, channels, out);
+            return;
+        }
+        for (frame, o) in input.chunks(channels).zip(out.chunks_mut(2)) {
+            let (mut l, mut r) = (0.0, 0.0);
+            for (x, route) in frame.iter().zip(&self.map) {
+                if let Some((speaker, gain)) = *route {
+                    let (gl, gr) = downmix_gains(speaker);
+                    l += x * gl * gain;
+                    r += x * gr * gain;
+                }
+            }
+            o[0] = l;
+            o[1] = r;
+        }
+    }
+}
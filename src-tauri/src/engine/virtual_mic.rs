@@ -0,0 +1,194 @@
+// Feed of the virtual microphone: the processed mic, optionally with some of the Music bus
+// and the soundboard clips sent to it mixed in, taken from the engine like a recording and
+// written out as raw 32-bit float by a background thread. The receiving end runs on its own
+// clock; if it falls behind, the taps drop whole blocks like a recording would.
+use rtrb::Consumer;
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::record::{RecordSource, RecordTaps};
+use super::{BusId, StreamId};
+
+// Engine -> feed thread; also the most the feed can fall behind
+const RING_MS: usize = 200;
+const FEED_INTERVAL: Duration = Duration::from_millis(5);
+
+// Handle to a running feed; it stops when the engine drops the taps
+pub struct VirtualMicFeed {
+    music_level: Arc<AtomicU32>,
+}
+
+impl VirtualMicFeed {
+    pub fn set_music_level(&self, level: f32) {
+        self.music_level.store(level.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// A track mixed into the mic, at a level that can change while running
+struct Extra {
+    consumer: Consumer<f32>,
+    channels: usize,
+    level: Arc<AtomicU32>,
+}
+
+// `music_channels` and `soundboard_channels` are `None` if the engine has no such track;
+// the soundboard goes in at unity. `out` gets interleaved little-endian f32 with
+// `mic_channels` channels at `sample_rate`.
+pub fn start(
+    mic_channels: usize,
+    music_channels: Option<usize>,
+    music_level: f32,
+    soundboard_channels: Option<usize>,
+    sample_rate: u32,
+    out: Box<dyn Write + Send>,
+) -> Result<(VirtualMicFeed, RecordTaps), String> {
+    let level = Arc::new(AtomicU32::new(music_level.to_bits()));
+    let extras: Vec<(RecordSource, usize, Arc<AtomicU32>)> = [
+        music_channels.map(|c| (RecordSource::Bus(BusId::Stream(StreamId::Music)), c, level.clone())),
+        soundboard_channels.map(|c| (RecordSource::Soundboard, c, Arc::new(AtomicU32::new(1.0f32.to_bits())))),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let mut tracks = vec![(RecordSource::Mic, mic_channels)];
+    tracks.extend(extras.iter().map(|(source, channels, _)| (source.clone(), *channels)));
+    let (taps, mut consumers) = RecordTaps::new(&tracks, RING_MS * sample_rate as usize / 1000);
+    let mic = (consumers.remove(0), mic_channels);
+    let extras = consumers
+        .into_iter()
+        .zip(extras)
+        .map(|(consumer, (_, channels, level))| Extra { consumer, channels, level })
+        .collect();
+    thread::Builder::new()
+        .name("virtual-mic".into())
+        .spawn(move || feed(mic, extras, out))
+        .map_err(|e| format!("Spawn virtual mic thread failed: {e}"))?;
+    Ok((VirtualMicFeed { music_level: level }, taps))
+}
+
+fn feed((mut mic, mic_channels): (Consumer<f32>, usize), mut extras: Vec<Extra>, mut out: Box<dyn Write + Send>) {
+    let mut mixed = Vec::new();
+    let mut extra = Vec::new();
+    let mut bytes = Vec::new();
+    while !mic.is_abandoned() {
+        // All rings are fed from the same blocks, so equal frame counts line up
+        let mut frames = mic.slots() / mic_channels;
+        for e in &extras {
+            frames = frames.min(e.consumer.slots() / e.channels);
+        }
+        if frames == 0 {
+            thread::sleep(FEED_INTERVAL);
+            continue;
+        }
+        mixed.clear();
+        if let Ok(chunk) = mic.read_chunk(frames * mic_channels) {
+            let (a, b) = chunk.as_slices();
+            mixed.extend_from_slice(a);
+            mixed.extend_from_slice(b);
+            chunk.commit_all();
+        }
+        for e in extras.iter_mut() {
+            let gain = f32::from_bits(e.level.load(Ordering::Relaxed));
+            if let Ok(chunk) = e.consumer.read_chunk(frames * e.channels) {
+                let (a, b) = chunk.as_slices();
+                extra.clear();
+                extra.extend_from_slice(a);
+                extra.extend_from_slice(b);
+                chunk.commit_all();
+                if gain > 0.0 {
+                    mix_into(&mut mixed, mic_channels, &extra, e.channels, gain);
+                }
+            }
+        }
+        bytes.clear();
+        bytes.extend(mixed.iter().flat_map(|s| s.to_le_bytes()));
+        if let Err(e) = out.write_all(&bytes) {
+            log::error!("Virtual mic feed stopped: {}", e);
+            return;
+        }
+    }
+}
+
+// Add `src` at `gain`; channel counts that differ go through a mono downmix
+fn mix_into(dst: &mut [f32], dst_channels: usize, src: &[f32], src_channels: usize, gain: f32) {
+    if dst_channels == src_channels {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d += s * gain;
+        }
+        return;
+    }
+    let scale = gain / src_channels as f32;
+    for (d, s) in dst.chunks_exact_mut(dst_channels).zip(src.chunks_exact(src_channels)) {
+        let mono = s.iter().sum::<f32>() * scale;
+        d.iter_mut().for_each(|v| *v += mono);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    #[test]
+    fn same_width_tracks_add_per_channel() {
+        let mut dst = vec![0.1, 0.2, 0.3, 0.4];
+        mix_into(&mut dst, 2, &[1.0, -1.0, 0.5, 0.0], 2, 0.5);
+        assert_eq!(dst, [0.6, -0.3, 0.55, 0.4]);
+    }
+
+    #[test]
+    fn other_widths_are_downmixed() {
+        // Stereo music into a mono mic: the average of both sides
+        let mut mono = vec![0.0, 0.25];
+        mix_into(&mut mono, 1, &[1.0, 0.5, -0.5, -0.5], 2, 0.5);
+        assert_eq!(mono, [0.375, 0.0]);
+        // Mono into stereo: the same on both sides
+        let mut stereo = vec![0.0; 4];
+        mix_into(&mut stereo, 2, &[0.5, -1.0], 1, 1.0);
+        assert_eq!(stereo, [0.5, 0.5, -1.0, -1.0]);
+        // 5.1 into stereo, frame by frame
+        let mut stereo = vec![0.0; 4];
+        mix_into(&mut stereo, 2, &[0.6; 12], 6, 1.0);
+        assert!(stereo.iter().all(|s| (s - 0.6).abs() < 1e-6));
+    }
+
+    // Collects what the feed writes
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn feed_mixes_music_and_clips_into_the_mic() {
+        let sink = Sink::default();
+        let (_feed, mut taps) = start(1, Some(2), 0.5, Some(2), 48_000, Box::new(sink.clone())).unwrap();
+        taps.begin(4);
+        taps.push(|s| *s == RecordSource::Mic, &[0.1, 0.1, 0.1, 0.1]);
+        taps.push(|s| matches!(s, RecordSource::Bus(_)), &[0.2, 0.2, 0.4, 0.0, 0.0, 0.0, -0.2, -0.2]);
+        taps.push(|s| *s == RecordSource::Soundboard, &[0.0, 0.0, 0.0, 0.0, 0.3, 0.3, 0.0, 0.0]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while sink.0.lock().unwrap().len() < 16 && Instant::now() < deadline {
+            thread::sleep(FEED_INTERVAL);
+        }
+        drop(taps);
+        let bytes = sink.0.lock().unwrap().clone();
+        let samples: Vec<f32> = bytes.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        let expected = [0.2, 0.2, 0.4, 0.0];
+        assert_eq!(samples.len(), expected.len());
+        assert!(samples.iter().zip(expected).all(|(s, e)| (s - e).abs() < 1e-6), "{:?}", samples);
+    }
+}
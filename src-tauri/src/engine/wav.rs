@@ -0,0 +1,32 @@
+// WAV file helpers for offline processing (hound). Samples are interleaved f32.
+use std::path::Path;
+
+// Returns (samples, channels, sample rate)
+pub fn read(path: &Path) -> Result<(Vec<f32>, usize, u32), String> {
+    let mut reader = hound::WavReader::open(path).map_err(|e| format!("Open {} failed: {e}", path.display()))?;
+    let spec = reader.spec();
+    let samples: Result<Vec<f32>, _> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect(),
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|v| v as f32 * scale)).collect()
+        }
+    };
+    let samples = samples.map_err(|e| format!("Read {} failed: {e}", path.display()))?;
+    Ok((samples, spec.channels.max(1) as usize, spec.sample_rate))
+}
+
+pub fn write_f32(path: &Path, samples: &[f32], channels: usize, sample_rate: u32) -> Result<(), String> {
+    let spec = hound::WavSpec {
+        channels: channels as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let err = |e: hound::Error| format!("Write {} failed: {e}", path.display());
+    let mut writer = hound::WavWriter::create(path, spec).map_err(err)?;
+    for &s in samples {
+        writer.write_sample(s).map_err(err)?;
+    }
+    writer.finalize().map_err(err)
+}
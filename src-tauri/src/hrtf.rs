@@ -0,0 +1,88 @@
+// HRTF sets for the Game bus virtualizer: the built-in spherical head model plus SOFA
+// files imported into <config dir>/hrtf. A set is referenced by its file stem.
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::engine::spatial::{HrirSet, SpatialConfig, SpatialSettings};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HrtfSetInfo {
+    // `None` for the built-in model
+    pub name: Option<String>,
+    pub measurements: usize,
+    pub sample_rate: u32,
+}
+
+fn hrtf_dir() -> PathBuf {
+    let dir = crate::config_dir().join("hrtf");
+    let _ = std::fs::create_dir_all(&dir);
+    dir
+}
+
+fn set_path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("Invalid HRTF set name '{}'", name));
+    }
+    Ok(hrtf_dir().join(format!("{name}.sofa")))
+}
+
+pub fn load_set(name: Option<&str>) -> Result<HrirSet, String> {
+    match name {
+        None => Ok(HrirSet::builtin()),
+        Some(name) => HrirSet::from_sofa(&set_path(name)?),
+    }
+}
+
+fn info(name: Option<String>, set: &HrirSet) -> HrtfSetInfo {
+    HrtfSetInfo { name, measurements: set.measurements(), sample_rate: set.sample_rate }
+}
+
+// Engine configuration for the settings; a set that can't be loaded (file deleted or
+// damaged) falls back to the built-in model so the engine still starts
+pub fn spatial_config(settings: &SpatialSettings) -> SpatialConfig {
+    let hrirs = load_set(settings.hrtf.as_deref()).unwrap_or_else(|e| {
+        log::warn!("HRTF set {:?} unusable, using the built-in model: {}", settings.hrtf, e);
+        HrirSet::builtin()
+    });
+    SpatialConfig { enabled: settings.enabled, hrirs: Arc::new(hrirs) }
+}
+
+#[tauri::command]
+pub fn list_hrtf_sets() -> Vec<HrtfSetInfo> {
+    let mut sets = vec![info(None, &HrirSet::builtin())];
+    let Ok(entries) = std::fs::read_dir(hrtf_dir()) else { return sets };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("sofa")))
+        .collect();
+    paths.sort();
+    for path in paths {
+        match HrirSet::from_sofa(&path) {
+            Ok(set) => sets.push(info(Some(set.name.clone()), &set)),
+            Err(e) => log::warn!("Skipping HRTF set: {}", e),
+        }
+    }
+    sets
+}
+
+// Copy a SOFA file into the HRTF directory after checking that it loads
+#[tauri::command]
+pub fn import_hrtf_set(path: String) -> Result<HrtfSetInfo, String> {
+    let path = Path::new(&path);
+    let set = HrirSet::from_sofa(path)?;
+    let target = set_path(&set.name)?;
+    std::fs::copy(path, &target).map_err(|e| format!("Copying {} failed: {e}", path.display()))?;
+    log::info!("Imported HRTF set '{}' ({} measurements, {} Hz)", set.name, set.measurements(), set.sample_rate);
+    Ok(info(Some(set.name.clone()), &set))
+}
+
+#[tauri::command]
+pub fn delete_hrtf_set(name: String) -> Result<bool, String> {
+    let path = set_path(&name)?;
+    if !path.exists() {
+        return Ok(false);
+    }
+    std::fs::remove_file(&path).map_err(|e| format!("Deleting {} failed: {e}", path.display()))?;
+    Ok(true)
+}
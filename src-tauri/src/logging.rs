@@ -0,0 +1,331 @@
+// Structured logging: leveled records with module targets, written to a rotating
+// log file in the config dir and kept in memory so the UI can attach them to bug reports.
+// Release builds have no console, so the file is the only place diagnostics end up.
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+const LOG_FILE_NAME: &str = "audio-mixer.log";
+const MAX_LOG_FILE_BYTES: u64 = 2 * 1024 * 1024;
+const MAX_ROTATED_FILES: usize = 3;
+const RECENT_CAPACITY: usize = 1000;
+
+// Filter syntax (env var AUDIO_MIXER_LOG): "info" or "warn,audio_mixer::backend=debug"
+const LOG_ENV_VAR: &str = "AUDIO_MIXER_LOG";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: Option<File>,
+    written: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> Self {
+        let file = OpenOptions::new().create(true).append(true).open(&path).ok();
+        let written = file
+            .as_ref()
+            .and_then(|f| f.metadata().ok())
+            .map(|m| m.len())
+            .unwrap_or(0);
+        LogFile { path, file, written }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.written + line.len() as u64 > MAX_LOG_FILE_BYTES {
+            self.rotate();
+        }
+        if let Some(file) = self.file.as_mut() {
+            if file.write_all(line.as_bytes()).is_ok() {
+                self.written += line.len() as u64;
+            }
+        }
+    }
+
+    // audio-mixer.log -> audio-mixer.1.log -> ... -> audio-mixer.<MAX_ROTATED_FILES>.log (dropped)
+    fn rotate(&mut self) {
+        self.file = None;
+        for i in (1..MAX_ROTATED_FILES).rev() {
+            let _ = std::fs::rename(rotated_path(&self.path, i), rotated_path(&self.path, i + 1));
+        }
+        let _ = std::fs::rename(&self.path, rotated_path(&self.path, 1));
+        *self = LogFile::open(self.path.clone());
+    }
+}
+
+fn rotated_path(path: &std::path::Path, index: usize) -> PathBuf {
+    path.with_file_name(format!("audio-mixer.{index}.log"))
+}
+
+struct MixerLogger {
+    default_level: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+    echo_to_console: bool,
+    file: Mutex<Option<LogFile>>,
+    recent: Mutex<VecDeque<LogEntry>>,
+}
+
+impl MixerLogger {
+    // Longest matching target prefix wins, otherwise the default level applies
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default_level)
+    }
+}
+
+impl Log for MixerLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let entry = LogEntry {
+            timestamp: format_timestamp(SystemTime::now()),
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        let line = format!("{} {:<5} [{}] {}\n", entry.timestamp, entry.level, entry.target, entry.message);
+
+        if self.echo_to_console {
+            if record.level() <= Level::Warn {
+                eprint!("{line}");
+            } else {
+                print!("{line}");
+            }
+        }
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            file.write_line(&line);
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+
+    fn flush(&self) {
+        if let Some(LogFile { file: Some(file), .. }) = self.file.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+static LOGGER: std::sync::OnceLock<MixerLogger> = std::sync::OnceLock::new();
+
+fn parse_level(s: &str) -> Option<LevelFilter> {
+    s.trim().parse::<LevelFilter>().ok()
+}
+
+fn parse_filter(spec: &str, default_level: LevelFilter) -> (LevelFilter, Vec<(String, LevelFilter)>) {
+    let mut level = default_level;
+    let mut directives = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('=') {
+            Some((target, lvl)) => {
+                if let Some(lvl) = parse_level(lvl) {
+                    directives.push((target.trim().to_string(), lvl));
+                }
+            }
+            None => {
+                if let Some(lvl) = parse_level(part) {
+                    level = lvl;
+                }
+            }
+        }
+    }
+    (level, directives)
+}
+
+pub fn log_dir() -> PathBuf {
+    let dir = crate::config_dir().join("logs");
+    let _ = std::fs::create_dir_all(&dir);
+    dir
+}
+
+// Install the global logger. Safe to call once at startup; later calls are ignored.
+pub fn init() {
+    let fallback = if cfg!(debug_assertions) { LevelFilter::Debug } else { LevelFilter::Info };
+    let spec = std::env::var(LOG_ENV_VAR).unwrap_or_default();
+    let (default_level, directives) = parse_filter(&spec, fallback);
+    let max_level = directives
+        .iter()
+        .map(|(_, l)| *l)
+        .chain(std::iter::once(default_level))
+        .max()
+        .unwrap_or(default_level);
+
+    let logger = LOGGER.get_or_init(|| MixerLogger {
+        default_level,
+        directives,
+        echo_to_console: cfg!(debug_assertions),
+        file: Mutex::new(Some(LogFile::open(log_dir().join(LOG_FILE_NAME)))),
+        recent: Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+// Most recent entries (oldest first), optionally filtered to a minimum level
+pub fn recent_entries(limit: usize, min_level: Option<LevelFilter>) -> Vec<LogEntry> {
+    let Some(logger) = LOGGER.get() else { return Vec::new() };
+    let recent = logger.recent.lock().unwrap();
+    let mut out: Vec<LogEntry> = recent
+        .iter()
+        .rev()
+        .filter(|e| match (min_level, e.level.parse::<Level>()) {
+            (Some(min), Ok(level)) => level <= min,
+            _ => true,
+        })
+        .take(limit)
+        .cloned()
+        .collect();
+    out.reverse();
+    out
+}
+
+#[tauri::command]
+pub fn get_recent_logs(limit: Option<usize>, min_level: Option<String>) -> Vec<LogEntry> {
+    let min_level = min_level.as_deref().and_then(parse_level);
+    recent_entries(limit.unwrap_or(RECENT_CAPACITY), min_level)
+}
+
+#[tauri::command]
+pub fn get_log_file_path() -> String {
+    log_dir().join(LOG_FILE_NAME).to_string_lossy().into_owned()
+}
+
+// UTC "YYYY-MM-DDTHH:MM:SS.mmmZ" without pulling in a date crate
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let dur = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = dur.as_secs();
+    let millis = dur.subsec_millis();
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let (h, m, s) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+
+    // Civil-from-days (Howard Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}-{month:02}-{day:02}T{h:02}:{m:02}:{s:02}.{millis:03}Z")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn logger(spec: &str) -> MixerLogger {
+        let (default_level, directives) = parse_filter(spec, LevelFilter::Info);
+        MixerLogger {
+            default_level,
+            directives,
+            echo_to_console: false,
+            file: Mutex::new(None),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    #[test]
+    fn filter_spec() {
+        assert_eq!(parse_filter("", LevelFilter::Info), (LevelFilter::Info, vec![]));
+        assert_eq!(parse_filter("debug", LevelFilter::Info).0, LevelFilter::Debug);
+        let spec = " warn , audio_mixer::backend = debug,,bogus,x=nonsense";
+        let (level, directives) = parse_filter(spec, LevelFilter::Info);
+        assert_eq!(level, LevelFilter::Warn);
+        assert_eq!(directives, vec![("audio_mixer::backend".to_string(), LevelFilter::Debug)]);
+
+        // Longest prefix wins
+        let l = logger("error,audio_mixer=info,audio_mixer::backend=trace");
+        assert_eq!(l.level_for("audio_mixer::backend::wasapi"), LevelFilter::Trace);
+        assert_eq!(l.level_for("audio_mixer::engine"), LevelFilter::Info);
+        assert_eq!(l.level_for("cpal"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        // Leap day, and the last millisecond of a year
+        let at = |secs: u64, millis: u64| UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis);
+        assert_eq!(format_timestamp(at(951_782_400, 5)), "2000-02-29T00:00:00.005Z");
+        assert_eq!(format_timestamp(at(1_704_067_199, 999)), "2023-12-31T23:59:59.999Z");
+        assert_eq!(format_timestamp(at(1_718_454_896, 123)), "2024-06-15T12:34:56.123Z");
+        // Recording file names take the first 19 characters
+        let stamp = format_timestamp(SystemTime::now());
+        assert_eq!(stamp.len(), 24);
+        assert_eq!(&stamp[19..20], ".");
+        assert!(!stamp[..19].contains('.'));
+    }
+
+    #[test]
+    fn log_file_rotates() {
+        let dir = std::env::temp_dir().join(format!("audio-mixer-log-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOG_FILE_NAME);
+
+        let line = format!("{}\n", "x".repeat(64 * 1024 - 1));
+        let mut file = LogFile::open(path.clone());
+        // More rotations than are kept, and a current file that isn't full yet
+        for _ in 0..(MAX_LOG_FILE_BYTES as usize / line.len()) * (MAX_ROTATED_FILES + 3) - 1 {
+            file.write_line(&line);
+        }
+        drop(file);
+        for i in 1..=MAX_ROTATED_FILES {
+            assert_eq!(std::fs::metadata(rotated_path(&path, i)).unwrap().len(), MAX_LOG_FILE_BYTES);
+        }
+        assert!(!rotated_path(&path, MAX_ROTATED_FILES + 1).exists());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), MAX_LOG_FILE_BYTES - line.len() as u64);
+
+        // Reopening continues the size count of the existing file
+        let mut file = LogFile::open(path.clone());
+        let before = file.written;
+        assert_eq!(before, std::fs::metadata(&path).unwrap().len());
+        file.write_line("one more\n");
+        assert_eq!(file.written, before + 9);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn recent_entries_keep_the_last_thousand() {
+        let l = logger("info");
+        for i in 0..RECENT_CAPACITY + 25 {
+            l.log(&Record::builder().level(Level::Info).target("audio_mixer").args(format_args!("entry {i}")).build());
+        }
+        // Filtered out entries don't take up room
+        l.log(&Record::builder().level(Level::Debug).target("audio_mixer").args(format_args!("hidden")).build());
+        let recent = l.recent.lock().unwrap();
+        assert_eq!(recent.len(), RECENT_CAPACITY);
+        assert_eq!(recent.front().unwrap().message, "entry 25");
+        assert_eq!(recent.back().unwrap().message, format!("entry {}", RECENT_CAPACITY + 24));
+    }
+}
@@ -1,572 +1,1741 @@
-#![cfg(target_os = "windows")]
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 
-// cpal provides cross-platform audio backend detection and device listing; on Windows it uses WASAPI.
-use cpal::traits::{DeviceTrait, HostTrait};
 use tauri::Manager;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+mod backend;
+mod engine;
+mod hrtf;
+mod logging;
+mod plugins;
+mod presets;
+
+use backend::{AppSession, BackendWorker, DeviceInfo, FeedOpener, VirtualInput, VirtualOutput};
+use engine::latency::LatencyMeasurement;
+use engine::live::BusConfig;
+use engine::record;
+use engine::soundboard::{self, ClipAudio};
+use engine::{
+    AuxBusSettings, AutoGainSettings, BusId, DynamicsParams, DynamicsSettings, EngineCommand, EngineConfig, EngineStatus, EqDesign, EqSettings, LiveEngine,
+    MatrixSettings, MeterReading, MicParams, MicSettings, MixLevel, RecordFormat, RecordSource, ResamplerQuality, SendSettings, SpatialConfig,
+    SpatialParams, SpatialSettings, TapSpec,
+};
 
-// Windows COM / WASAPI imports for per-app session enumeration and volume control
-use windows::core::Interface;
-use windows::Win32::Media::Audio::{eMultimedia, eRender, IAudioSessionControl, IAudioSessionControl2, IAudioSessionEnumerator, IAudioSessionManager2, IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator, ISimpleAudioVolume, IMMDeviceCollection, DEVICE_STATE_ACTIVE};
-use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED};
-use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
-use windows::Win32::System::ProcessStatus::K32GetProcessImageFileNameW;
-use windows::Win32::Foundation::{HANDLE, BOOL, CloseHandle};
+// For simplicity, we define three logical streams. In a real app you would integrate with per-process audio sessions.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamId { Game, Voice, Music }
+
+impl StreamId {
+    pub const ALL: [StreamId; 3] = [StreamId::Game, StreamId::Voice, StreamId::Music];
+}
+
+// Where a stream plays. `device` carries the personal mix (what the user hears, at the
+// stream volume); the broadcast mix goes to `BroadcastSettings::output` at its own level.
+// Extra outputs duplicate the stream onto more devices. Mutes, broadcast and extra
+// outputs are rendered by the mixing engine.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "StoredRoute")]
+struct Route {
+    device: Option<String>,
+    muted: bool,
+    broadcast: MixLevel,
+    extra_outputs: Vec<TapSpec>,
+}
+
+// Older state files store just the device ID per stream
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRoute {
+    Device(Option<String>),
+    Full {
+        device: Option<String>,
+        #[serde(default)]
+        muted: bool,
+        #[serde(default)]
+        broadcast: MixLevel,
+        #[serde(default)]
+        extra_outputs: Vec<TapSpec>,
+    },
+}
+
+impl From<StoredRoute> for Route {
+    fn from(stored: StoredRoute) -> Self {
+        match stored {
+            StoredRoute::Device(device) => Route { device, ..Default::default() },
+            StoredRoute::Full { device, muted, broadcast, extra_outputs } => {
+                Route { device, muted, broadcast, extra_outputs }
+            }
+        }
+    }
+}
+
+type Routes = HashMap<StreamId, Route>;
+
+// Streamer mode: a second mix of all streams, rendered by the engine for OBS & co.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct BroadcastSettings {
+    enabled: bool,
+    // Device the broadcast mix plays on, typically a virtual cable; `None` = default output
+    output: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct RecordSettings {
+    // `None` = "Audio Mixer" in the user's music folder
+    folder: Option<String>,
+    format: RecordFormat,
+}
+
+fn record_folder(settings: &RecordSettings) -> std::path::PathBuf {
+    match &settings.folder {
+        Some(folder) => folder.into(),
+        None => dirs_next::audio_dir().map(|d| d.join("Audio Mixer")).unwrap_or_else(|| config_dir().join("recordings")),
+    }
+}
+
+// Instant replay: the last seconds of some streams, kept in memory and saved as one stem
+// per stream on demand
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ReplaySettings {
+    enabled: bool,
+    // Seconds kept per stream; streams left out aren't buffered
+    streams: BTreeMap<StreamId, f32>,
+    // Global shortcut that saves the buffer, e.g. "CommandOrControl+Shift+R"
+    hotkey: Option<String>,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        ReplaySettings { enabled: false, streams: StreamId::ALL.iter().map(|s| (s.clone(), 30.0)).collect(), hotkey: None }
+    }
+}
+
+impl ReplaySettings {
+    fn validate(&self) -> Result<(), String> {
+        let max = engine::replay::MAX_REPLAY_SECONDS;
+        for (stream, seconds) in &self.streams {
+            if !(seconds.is_finite() && (1.0..=max).contains(seconds)) {
+                return Err(format!("Replay length {seconds} s for {stream:?} out of range (1..{max})"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// "Audio Mixer - Microphone": the processed mic as a capture device chat apps can pick,
+// without a virtual cable
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+struct VirtualMicSettings {
+    enabled: bool,
+    // Music bus mixed in; 0 = mic only
+    music_level: f32,
+}
+
+impl VirtualMicSettings {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.music_level.is_finite() && (0.0..=1.0).contains(&self.music_level)) {
+            return Err(format!("Music level {} out of range (0..1)", self.music_level));
+        }
+        Ok(())
+    }
+}
+
+const VIRTUAL_MIC_ID: &str = "audio_mixer_mic";
+
+// A soundboard button: a short clip played into a stream bus (before its fader) and, if
+// wanted, into the virtual mic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct SoundboardSlot {
+    name: String,
+    // WAV, FLAC or Ogg Vorbis, at most `soundboard::MAX_CLIP_SECONDS`
+    file: String,
+    stream: StreamId,
+    to_mic: bool,
+    gain: f32,
+    // Presses of this slot playing at once; a further press cuts the oldest
+    polyphony: usize,
+    hotkey: Option<String>,
+}
+
+impl Default for SoundboardSlot {
+    fn default() -> Self {
+        SoundboardSlot {
+            name: String::new(),
+            file: String::new(),
+            stream: StreamId::Music,
+            to_mic: false,
+            gain: 1.0,
+            polyphony: 1,
+            hotkey: None,
+        }
+    }
+}
+
+const MAX_CLIP_POLYPHONY: usize = 8;
+// How often plugins get their requested main-thread callback
+const PLUGIN_IDLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(30);
+
+impl SoundboardSlot {
+    fn validate(&self) -> Result<(), String> {
+        if self.file.is_empty() {
+            return Err(format!("Soundboard slot {:?} has no file", self.name));
+        }
+        if !(self.gain.is_finite() && (0.0..=2.0).contains(&self.gain)) {
+            return Err(format!("Clip gain {} out of range (0..2)", self.gain));
+        }
+        if !(1..=MAX_CLIP_POLYPHONY).contains(&self.polyphony) {
+            return Err(format!("Clip polyphony {} out of range (1..{})", self.polyphony, MAX_CLIP_POLYPHONY));
+        }
+        Ok(())
+    }
+}
+
+// Delay on everything an output device plays, to line it up with slower devices (e.g.
+// wired headphones next to Bluetooth speakers)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OutputDelay {
+    // `None` = default output
+    output: Option<String>,
+    delay_ms: f32,
+}
+
+// Result of applying a route/volume/category change to the apps of a stream.
+// The UI uses `failed` to flag apps that did not follow the change.
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ApplyReport {
+    pub updated: Vec<u32>,
+    pub failed: Vec<ApplyFailure>,
+    // Set by `set_route` when the engine can't play on the new device
+    pub device_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApplyFailure {
+    pub pid: u32,
+    pub error: String,
+}
+
+impl ApplyReport {
+    fn record(&mut self, pid: u32, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.updated.push(pid),
+            Err(error) => self.failed.push(ApplyFailure { pid, error }),
+        }
+    }
+}
+
+#[tauri::command]
+fn list_audio_devices(worker: tauri::State<BackendWorker>) -> Vec<DeviceInfo> {
+    match worker.call(|b| b.list_devices()).and_then(|r| r) {
+        Ok(devices) => devices,
+        Err(e) => {
+            log::warn!("Listing audio devices failed: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Persistence helpers (module scope)
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct PersistedState {
+    routes: Routes,
+    volumes: HashMap<StreamId, f32>,
+    app_categories: HashMap<u32, StreamId>,
+    #[serde(default)]
+    engine: EngineSettings,
+    #[serde(default)]
+    eq: HashMap<StreamId, EqSettings>,
+    #[serde(default)]
+    dynamics: HashMap<StreamId, DynamicsSettings>,
+    #[serde(default)]
+    master_dynamics: DynamicsSettings,
+    #[serde(default)]
+    auto_gain: HashMap<StreamId, AutoGainSettings>,
+    #[serde(default)]
+    mic: MicSettings,
+    #[serde(default)]
+    spatial: SpatialSettings,
+    #[serde(default)]
+    matrix: HashMap<StreamId, MatrixSettings>,
+    #[serde(default)]
+    plugins: HashMap<BusId, Vec<plugins::PluginSlot>>,
+    #[serde(default)]
+    aux: Vec<AuxBusSettings>,
+    #[serde(default)]
+    broadcast: BroadcastSettings,
+    #[serde(default)]
+    recording: RecordSettings,
+    #[serde(default)]
+    replay: ReplaySettings,
+    #[serde(default)]
+    virtual_outputs: bool,
+    #[serde(default)]
+    virtual_mic: VirtualMicSettings,
+    #[serde(default)]
+    soundboard: Vec<SoundboardSlot>,
+}
+
+// Settings of the built-in mixing engine. When enabled, each stream bus captures its
+// input device and plays it on the stream's routed output.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct EngineSettings {
+    enabled: bool,
+    inputs: HashMap<StreamId, Option<String>>,
+    // Device receiving the processed microphone (Voice bus before its fader)
+    #[serde(default)]
+    processed_mic_output: Option<String>,
+    // Sample rate conversion for devices not at the engine rate
+    #[serde(default)]
+    resampler: ResamplerQuality,
+    #[serde(default)]
+    output_delays: Vec<OutputDelay>,
+}
+
+// <config dir>/audio-mixer, shared by state.json and the log files
+fn config_dir() -> std::path::PathBuf {
+    let base = dirs_next::config_dir()
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from(".")));
+    let dir = base.join("audio-mixer");
+    let _ = std::fs::create_dir_all(&dir);
+    dir
+}
+
+fn state_file_path() -> std::path::PathBuf {
+    config_dir().join("state.json")
+}
+
+fn load_state() -> MixerState {
+    let path = state_file_path();
+    if let Ok(data) = std::fs::read(&path) {
+        match serde_json::from_slice::<PersistedState>(&data) {
+            Ok(p) => {
+                return MixerState {
+                    routes: p.routes,
+                    volumes: p.volumes,
+                    app_categories: p.app_categories,
+                    engine: p.engine,
+                    eq: p.eq,
+                    dynamics: p.dynamics,
+                    master_dynamics: p.master_dynamics,
+                    auto_gain: p.auto_gain,
+                    mic: p.mic,
+                    spatial: p.spatial,
+                    // A hand-edited or older file may hold a matrix the engine can't use
+                    matrix: p
+                        .matrix
+                        .into_iter()
+                        .filter(|(stream, m)| {
+                            m.validate().map_err(|e| log::warn!("Ignoring channel matrix of {:?}: {}", stream, e)).is_ok()
+                        })
+                        .collect(),
+                    plugins: p.plugins,
+                    plugin_host: Default::default(),
+                    aux: p.aux,
+                    broadcast: p.broadcast,
+                    record_settings: p.recording,
+                    recording: None,
+                    replay_settings: p.replay,
+                    replay: None,
+                    virtual_outputs: p.virtual_outputs,
+                    virtual_mic: p.virtual_mic,
+                    virtual_mic_opener: None,
+                    virtual_mic_feed: None,
+                    soundboard: p.soundboard,
+                    soundboard_clips: Vec::new(),
+                    retired_clips: Vec::new(),
+                };
+            }
+            Err(e) => log::warn!("Ignoring unreadable state file {}: {}", path.display(), e),
+        }
+    }
+    MixerState::default()
+}
+
+fn save_state_snapshot(state: &std::sync::Mutex<MixerState>) {
+    let p = {
+        let mut s = state.lock().unwrap();
+        let s = &mut *s;
+        s.plugin_host.refresh_states(&mut s.plugins);
+        PersistedState {
+            routes: s.routes.clone(),
+            volumes: s.volumes.clone(),
+            app_categories: s.app_categories.clone(),
+            engine: s.engine.clone(),
+            eq: s.eq.clone(),
+            dynamics: s.dynamics.clone(),
+            master_dynamics: s.master_dynamics,
+            auto_gain: s.auto_gain.clone(),
+            mic: s.mic,
+            spatial: s.spatial.clone(),
+            matrix: s.matrix.clone(),
+            plugins: s.plugins.clone(),
+            aux: s.aux.clone(),
+            broadcast: s.broadcast.clone(),
+            recording: s.record_settings.clone(),
+            replay: s.replay_settings.clone(),
+            virtual_outputs: s.virtual_outputs,
+            virtual_mic: s.virtual_mic,
+            soundboard: s.soundboard.clone(),
+        }
+    };
+    if let Ok(json) = serde_json::to_vec_pretty(&p) {
+        if let Err(e) = std::fs::write(state_file_path(), json) {
+            log::warn!("Saving state failed: {}", e);
+        }
+    }
+}
+
+// In-memory routing/volume state. For a real app, persist to a file and drive actual audio pipelines.
+#[derive(Default)]
+struct MixerState {
+    routes: Routes,
+    volumes: HashMap<StreamId, f32>,
+    // Map process id -> assigned logical stream
+    app_categories: HashMap<u32, StreamId>,
+    engine: EngineSettings,
+    // EQ per stream bus
+    eq: HashMap<StreamId, EqSettings>,
+    // Compressor/limiter per stream bus and on the outputs
+    dynamics: HashMap<StreamId, DynamicsSettings>,
+    master_dynamics: DynamicsSettings,
+    // Loudness normalization per stream bus (meant for Music)
+    auto_gain: HashMap<StreamId, AutoGainSettings>,
+    // Gate/high-pass/de-esser on the Voice capture path
+    mic: MicSettings,
+    // HRTF virtual surround on the Game bus
+    spatial: SpatialSettings,
+    // Down/upmix from each bus to the channels of its output device
+    matrix: HashMap<StreamId, MatrixSettings>,
+    // CLAP effects per stream/aux bus, and their loaded instances
+    plugins: HashMap<BusId, Vec<plugins::PluginSlot>>,
+    plugin_host: plugins::PluginHost,
+    // Buses fed by sends from the streams; with the routes they form the routing graph
+    aux: Vec<AuxBusSettings>,
+    broadcast: BroadcastSettings,
+    record_settings: RecordSettings,
+    // Take in progress; ends when the engine restarts
+    recording: Option<record::Recording>,
+    replay_settings: ReplaySettings,
+    // History of the running engine, if replay is enabled
+    replay: Option<engine::replay::ReplayBuffer>,
+    // One virtual output device per stream, see `virtual_output`
+    virtual_outputs: bool,
+    virtual_mic: VirtualMicSettings,
+    // Set while the virtual mic device exists; the engine feeds it through this
+    virtual_mic_opener: Option<FeedOpener>,
+    virtual_mic_feed: Option<engine::virtual_mic::VirtualMicFeed>,
+    soundboard: Vec<SoundboardSlot>,
+    // Decoded clip per slot, at the engine rate once it has run; `None` if the file is unreadable
+    soundboard_clips: Vec<Option<std::sync::Arc<ClipAudio>>>,
+    // Replaced clips voices may still hold, kept until the audio thread lets go of them
+    retired_clips: Vec<std::sync::Arc<ClipAudio>>,
+}
+
+// The running engine, if enabled. Restarted whenever its configuration changes.
+type EngineSlot = std::sync::Mutex<Option<LiveEngine>>;
+
+// One bus per stream: input from the engine settings, output from the stream's route
+fn engine_config(state: &MixerState) -> EngineConfig {
+    let buses = StreamId::ALL
+        .iter()
+        .map(|stream| {
+            let route = state.routes.get(stream).cloned().unwrap_or_default();
+            BusConfig {
+                stream: stream.clone(),
+                input: state.engine.inputs.get(stream).cloned().flatten(),
+                output: route.device,
+                gain: state.volumes.get(stream).copied().unwrap_or(1.0),
+                muted: route.muted,
+                eq: state.eq.get(stream).cloned().unwrap_or_default(),
+                dynamics: state.dynamics.get(stream).copied().unwrap_or_default(),
+                auto_gain: state.auto_gain.get(stream).copied().unwrap_or_default(),
+                mic: (*stream == StreamId::Voice).then_some(state.mic),
+                direct_out: if *stream == StreamId::Voice { state.engine.processed_mic_output.clone() } else { None },
+                spatial: (*stream == StreamId::Game && state.spatial.enabled).then(|| hrtf::spatial_config(&state.spatial)),
+                matrix: state.matrix.get(stream).cloned().unwrap_or_default(),
+                broadcast: state.broadcast.enabled.then(|| TapSpec {
+                    output: state.broadcast.output.clone(),
+                    level: route.broadcast,
+                    delay_ms: 0.0,
+                    pre_fader: true,
+                }),
+                extra_outputs: route.extra_outputs,
+            }
+        })
+        .collect();
+    EngineConfig {
+        buses,
+        master: state.master_dynamics,
+        resampler: state.engine.resampler,
+        aux: state.aux.clone(),
+        output_delays: state.engine.output_delays.iter().map(|d| (d.output.clone(), d.delay_ms)).collect(),
+    }
+}
+
+// A restart would end the take in progress, so changes that need one wait for it
+fn check_restart_allowed(s: &MixerState) -> Result<(), String> {
+    if s.recording.is_some() {
+        return Err("Stop the recording first; this change restarts the audio engine".into());
+    }
+    Ok(())
+}
+
+// Stop the running engine and start it again from the current state if enabled
+fn restart_engine(state: &std::sync::Mutex<MixerState>, slot: &EngineSlot) -> EngineStatus {
+    let (enabled, config) = {
+        let s = state.lock().unwrap();
+        (s.engine.enabled, engine_config(&s))
+    };
+    let mut running = slot.lock().unwrap();
+    // Release the devices before opening them again
+    *running = None;
+    {
+        let mut s = state.lock().unwrap();
+        s.plugin_host.detach();
+        s.replay = None;
+        s.virtual_mic_feed = None;
+        if let Some(take) = s.recording.take() {
+            log::warn!("Engine restart ended the recording");
+            if let Err(e) = take.finish() {
+                log::error!("Recording: {}", e);
+            }
+        }
+    }
+    if !enabled {
+        return EngineStatus::default();
+    }
+    match LiveEngine::start(config) {
+        Ok(engine) => {
+            let status = engine.status();
+            {
+                let s = &mut *state.lock().unwrap();
+                s.plugin_host.attach(&s.plugins, &engine);
+                start_replay(s, &engine);
+                start_virtual_mic_feed(s, &engine);
+            }
+            *running = Some(engine);
+            drop(running);
+            prepare_soundboard(state, status.sample_rate);
+            status
+        }
+        Err(e) => {
+            log::error!("Starting audio engine failed: {}", e);
+            EngineStatus { error: Some(e), ..Default::default() }
+        }
+    }
+}
+
+// Give the running engine a fresh replay buffer (or none); the old history is dropped
+fn start_replay(s: &mut MixerState, engine: &LiveEngine) {
+    s.replay = None;
+    let mut taps = Default::default();
+    if s.replay_settings.enabled {
+        let tracks: Vec<_> = s
+            .replay_settings
+            .streams
+            .iter()
+            .filter_map(|(stream, seconds)| {
+                let source = RecordSource::Bus(BusId::Stream(stream.clone()));
+                engine.record_channels(&source).map(|channels| (source, channels, *seconds))
+            })
+            .collect();
+        match engine::replay::start(&tracks, engine.status().sample_rate) {
+            Ok((buffer, replay_taps)) => {
+                s.replay = Some(buffer);
+                taps = replay_taps;
+            }
+            Err(e) => log::error!("Starting replay buffer failed: {}", e),
+        }
+    }
+    engine.send(EngineCommand::SetReplay(taps));
+}
+
+// Feed the virtual mic device, if there is one, from the running engine
+fn start_virtual_mic_feed(s: &mut MixerState, engine: &LiveEngine) {
+    s.virtual_mic_feed = None;
+    let mut taps = Default::default();
+    if let (Some(open), Some(mic_channels)) = (s.virtual_mic_opener.clone(), engine.record_channels(&RecordSource::Mic)) {
+        let music_channels = engine.record_channels(&RecordSource::Bus(BusId::Stream(StreamId::Music)));
+        let soundboard_channels = engine.record_channels(&RecordSource::Soundboard);
+        let rate = engine.status().sample_rate;
+        let level = s.virtual_mic.music_level;
+        let started = open(rate, mic_channels).and_then(|out| {
+            engine::virtual_mic::start(mic_channels, music_channels, level, soundboard_channels, rate, out)
+        });
+        match started {
+            Ok((feed, feed_taps)) => {
+                s.virtual_mic_feed = Some(feed);
+                taps = feed_taps;
+            }
+            Err(e) => log::error!("Starting virtual mic feed failed: {}", e),
+        }
+    }
+    engine.send(EngineCommand::SetVirtualMic(taps));
+}
+
+#[tauri::command]
+fn get_engine_status(slot: tauri::State<EngineSlot>) -> EngineStatus {
+    slot.lock().unwrap().as_ref().map(|e| e.status()).unwrap_or_default()
+}
+
+#[tauri::command]
+fn set_engine_enabled(
+    enabled: bool,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> EngineStatus {
+    state.lock().unwrap().engine.enabled = enabled;
+    save_state_snapshot(&state);
+    restart_engine(&state, &slot)
+}
+
+#[tauri::command]
+fn set_stream_input(
+    stream: StreamId,
+    device_id: Option<String>,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<EngineStatus, String> {
+    {
+        let mut s = state.lock().unwrap();
+        check_restart_allowed(&s)?;
+        s.engine.inputs.insert(stream, device_id);
+    }
+    save_state_snapshot(&state);
+    Ok(restart_engine(&state, &slot))
+}
+
+#[tauri::command]
+fn get_resampler_quality(state: tauri::State<std::sync::Mutex<MixerState>>) -> ResamplerQuality {
+    state.lock().unwrap().engine.resampler
+}
+
+#[tauri::command]
+fn set_resampler_quality(
+    quality: ResamplerQuality,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<EngineStatus, String> {
+    {
+        let mut s = state.lock().unwrap();
+        check_restart_allowed(&s)?;
+        s.engine.resampler = quality;
+    }
+    save_state_snapshot(&state);
+    Ok(restart_engine(&state, &slot))
+}
+
+#[tauri::command]
+fn get_meters(slot: tauri::State<EngineSlot>) -> Vec<MeterReading> {
+    slot.lock().unwrap().as_ref().map(|e| e.meters().readings()).unwrap_or_default()
+}
+
+// Run the current configuration through an offline render with test tones and return
+// the peak level (dBFS) per output. Needs no audio device.
+#[tauri::command]
+fn engine_self_test(state: tauri::State<std::sync::Mutex<MixerState>>) -> BTreeMap<String, f32> {
+    let config = engine_config(&state.lock().unwrap());
+    engine::self_test(&config, 48_000)
+}
+
+#[tauri::command]
+fn get_stream_eq(stream: StreamId, state: tauri::State<std::sync::Mutex<MixerState>>) -> EqSettings {
+    state.lock().unwrap().eq.get(&stream).cloned().unwrap_or_default()
+}
+
+// Replace the EQ of a stream. Applied to the running engine without a restart.
+#[tauri::command]
+fn set_stream_eq(
+    stream: StreamId,
+    eq: EqSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    eq.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        let design = EqDesign::new(&eq, engine.status().sample_rate);
+        engine.send(EngineCommand::SetEq(stream.clone(), Box::new(design)));
+    }
+    state.lock().unwrap().eq.insert(stream, eq);
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+// EQ curve in dB at the given frequencies, for the UI graph
+#[tauri::command]
+fn get_eq_response(eq: EqSettings, frequencies: Vec<f32>) -> Vec<f32> {
+    engine::eq::response(&eq, 48_000, &frequencies)
+}
+
+#[tauri::command]
+fn get_stream_dynamics(stream: StreamId, state: tauri::State<std::sync::Mutex<MixerState>>) -> DynamicsSettings {
+    state.lock().unwrap().dynamics.get(&stream).copied().unwrap_or_default()
+}
+
+#[tauri::command]
+fn set_stream_dynamics(
+    stream: StreamId,
+    dynamics: DynamicsSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    dynamics.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        let params = DynamicsParams::new(&dynamics, engine.status().sample_rate);
+        engine.send(EngineCommand::SetDynamics(stream.clone(), params));
+    }
+    state.lock().unwrap().dynamics.insert(stream, dynamics);
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_master_dynamics(state: tauri::State<std::sync::Mutex<MixerState>>) -> DynamicsSettings {
+    state.lock().unwrap().master_dynamics
+}
+
+#[tauri::command]
+fn set_master_dynamics(
+    dynamics: DynamicsSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    dynamics.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        let params = DynamicsParams::new(&dynamics, engine.status().sample_rate);
+        engine.send(EngineCommand::SetMasterDynamics(params));
+    }
+    state.lock().unwrap().master_dynamics = dynamics;
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_stream_auto_gain(stream: StreamId, state: tauri::State<std::sync::Mutex<MixerState>>) -> AutoGainSettings {
+    state.lock().unwrap().auto_gain.get(&stream).copied().unwrap_or_default()
+}
+
+// Loudness normalization of a stream towards a target LUFS. The live loudness and the
+// applied correction are reported by `get_meters`.
+#[tauri::command]
+fn set_stream_auto_gain(
+    stream: StreamId,
+    auto_gain: AutoGainSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    auto_gain.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetAutoGain(stream.clone(), auto_gain));
+    }
+    state.lock().unwrap().auto_gain.insert(stream, auto_gain);
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_stream_matrix(stream: StreamId, state: tauri::State<std::sync::Mutex<MixerState>>) -> MatrixSettings {
+    state.lock().unwrap().matrix.get(&stream).cloned().unwrap_or_default()
+}
+
+// Channel matrix from a stream bus to its output device. Explicit gains only apply
+// while the bus and device channel counts match their dimensions.
+#[tauri::command]
+fn set_stream_matrix(
+    stream: StreamId,
+    matrix: MatrixSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    matrix.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetMatrix(stream.clone(), Box::new(matrix.clone())));
+    }
+    state.lock().unwrap().matrix.insert(stream, matrix);
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+// Standard down/upmix as a starting point for editing, channel counts as reported
+// in `DeviceInfo`
+#[tauri::command]
+fn get_standard_matrix(inputs: usize, outputs: usize) -> Vec<Vec<f32>> {
+    engine::matrix::standard_gains(inputs, outputs)
+}
+
+#[tauri::command]
+fn get_bus_plugins(bus: BusId, state: tauri::State<std::sync::Mutex<MixerState>>) -> Vec<plugins::PluginSlotInfo> {
+    let s = &mut *state.lock().unwrap();
+    let slots = s.plugins.get(&bus).map(Vec::as_slice).unwrap_or_default();
+    s.plugin_host.infos(&bus, slots)
+}
+
+// Append a plugin (path and ID from `list_plugins`) to the chain of a stream or aux bus
+#[tauri::command]
+fn add_bus_plugin(
+    bus: BusId,
+    path: String,
+    id: String,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<Vec<plugins::PluginSlotInfo>, String> {
+    let engine = slot.lock().unwrap();
+    let infos = {
+        let s = &mut *state.lock().unwrap();
+        if let BusId::Aux(id) = &bus {
+            aux_index(s, id)?;
+        }
+        let slots = s.plugins.entry(bus.clone()).or_default();
+        let new = plugins::PluginSlot { path, id, bypassed: false, state: None, params: Default::default() };
+        s.plugin_host.add(&bus, slots, new, engine.as_ref())?;
+        s.plugin_host.infos(&bus, slots)
+    };
+    save_state_snapshot(&state);
+    Ok(infos)
+}
+
+#[tauri::command]
+fn remove_bus_plugin(
+    bus: BusId,
+    index: usize,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<Vec<plugins::PluginSlotInfo>, String> {
+    let engine = slot.lock().unwrap();
+    let infos = {
+        let s = &mut *state.lock().unwrap();
+        let slots = s.plugins.entry(bus.clone()).or_default();
+        s.plugin_host.remove(&bus, slots, index, engine.as_ref())?;
+        s.plugin_host.infos(&bus, slots)
+    };
+    save_state_snapshot(&state);
+    Ok(infos)
+}
+
+#[tauri::command]
+fn move_bus_plugin(
+    bus: BusId,
+    from: usize,
+    to: usize,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<Vec<plugins::PluginSlotInfo>, String> {
+    let engine = slot.lock().unwrap();
+    let infos = {
+        let s = &mut *state.lock().unwrap();
+        let slots = s.plugins.entry(bus.clone()).or_default();
+        s.plugin_host.reorder(&bus, slots, from, to, engine.as_ref())?;
+        s.plugin_host.infos(&bus, slots)
+    };
+    save_state_snapshot(&state);
+    Ok(infos)
+}
+
+#[tauri::command]
+fn set_plugin_bypassed(
+    bus: BusId,
+    index: usize,
+    bypassed: bool,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+) -> Result<(), String> {
+    {
+        let s = &mut *state.lock().unwrap();
+        let slots = s.plugins.entry(bus.clone()).or_default();
+        s.plugin_host.set_bypassed(&bus, slots, index, bypassed)?;
+    }
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_plugin_params(
+    bus: BusId,
+    index: usize,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+) -> Result<Vec<engine::clap::ParamInfo>, String> {
+    let s = &mut *state.lock().unwrap();
+    let slots = s.plugins.get(&bus).map(Vec::as_slice).unwrap_or_default();
+    s.plugin_host.params(&bus, slots, index)
+}
+
+// Plugin state is captured when the state file is written, so the change is saved too
+#[tauri::command]
+fn set_plugin_param(
+    bus: BusId,
+    index: usize,
+    param_id: u32,
+    value: f64,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+) -> Result<(), String> {
+    {
+        let s = &mut *state.lock().unwrap();
+        let slots = s.plugins.entry(bus.clone()).or_default();
+        let change = engine::clap::ParamChange { id: param_id, value };
+        s.plugin_host.set_param(&bus, slots, index, change)?;
+    }
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+fn aux_index(state: &MixerState, id: &str) -> Result<usize, String> {
+    state.aux.iter().position(|a| a.id == id).ok_or_else(|| format!("No aux bus '{}'", id))
+}
+
+#[tauri::command]
+fn get_aux_buses(state: tauri::State<std::sync::Mutex<MixerState>>) -> Vec<AuxBusSettings> {
+    state.lock().unwrap().aux.clone()
+}
+
+// New aux bus without any sends. Adding or removing buses restarts a running engine.
+#[tauri::command]
+fn add_aux_bus(
+    name: String,
+    output: Option<String>,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<AuxBusSettings, String> {
+    let aux = {
+        let mut s = state.lock().unwrap();
+        check_restart_allowed(&s)?;
+        if s.aux.len() >= engine::sends::MAX_AUX_BUSES {
+            return Err(format!("At most {} aux buses", engine::sends::MAX_AUX_BUSES));
+        }
+        let aux = AuxBusSettings {
+            id: engine::sends::next_id(&s.aux),
+            name,
+            output,
+            gain: 1.0,
+            muted: false,
+            sends: Default::default(),
+        };
+        aux.validate()?;
+        s.aux.push(aux.clone());
+        aux
+    };
+    save_state_snapshot(&state);
+    if slot.lock().unwrap().is_some() {
+        restart_engine(&state, &slot);
+    }
+    Ok(aux)
+}
+
+#[tauri::command]
+fn remove_aux_bus(
+    id: String,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    {
+        let s = &mut *state.lock().unwrap();
+        check_restart_allowed(s)?;
+        let index = aux_index(s, &id)?;
+        s.aux.remove(index);
+        let bus = BusId::Aux(id);
+        s.plugins.remove(&bus);
+        s.plugin_host.forget(&bus);
+    }
+    save_state_snapshot(&state);
+    if slot.lock().unwrap().is_some() {
+        restart_engine(&state, &slot);
+    }
+    Ok(())
+}
+
+// Replace an aux bus's settings, sends included. Only a new output device needs an
+// engine restart; levels and mutes are applied live.
+#[tauri::command]
+fn set_aux_bus(
+    aux: AuxBusSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    aux.validate()?;
+    let output_changed = {
+        let mut s = state.lock().unwrap();
+        let index = aux_index(&s, &aux.id)?;
+        let changed = s.aux[index].output != aux.output;
+        if changed {
+            check_restart_allowed(&s)?;
+        }
+        s.aux[index] = aux.clone();
+        changed
+    };
+    save_state_snapshot(&state);
+    if output_changed {
+        if slot.lock().unwrap().is_some() {
+            restart_engine(&state, &slot);
+        }
+        return Ok(());
+    }
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetAuxGain(aux.id.clone(), aux.gain));
+        engine.send(EngineCommand::SetAuxMuted(aux.id.clone(), aux.muted));
+        for stream in StreamId::ALL.iter() {
+            engine.send(EngineCommand::SetSend(stream.clone(), aux.id.clone(), aux.send(stream)));
+        }
+    }
+    Ok(())
+}
+
+// Level and tap point of one stream's send into an aux bus
+#[tauri::command]
+fn set_stream_send(
+    stream: StreamId,
+    aux: String,
+    send: SendSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    send.validate()?;
+    {
+        let mut s = state.lock().unwrap();
+        let index = aux_index(&s, &aux)?;
+        s.aux[index].sends.insert(stream.clone(), send);
+    }
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetSend(stream, aux, send));
+    }
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_mic_settings(state: tauri::State<std::sync::Mutex<MixerState>>) -> MicSettings {
+    state.lock().unwrap().mic
+}
+
+#[tauri::command]
+fn set_mic_settings(
+    mic: MicSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    mic.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        let params = MicParams::new(&mic, engine.status().sample_rate);
+        engine.send(EngineCommand::SetMic(StreamId::Voice, Box::new(params)));
+    }
+    state.lock().unwrap().mic = mic;
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+// Send the processed microphone to a device, typically a virtual cable that chat apps
+// use as their input. `None` turns the processed capture stream off.
+#[tauri::command]
+fn set_processed_mic_output(
+    device_id: Option<String>,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<EngineStatus, String> {
+    {
+        let mut s = state.lock().unwrap();
+        check_restart_allowed(&s)?;
+        s.engine.processed_mic_output = device_id;
+    }
+    save_state_snapshot(&state);
+    Ok(restart_engine(&state, &slot))
+}
+
+#[tauri::command]
+fn get_spatial_settings(state: tauri::State<std::sync::Mutex<MixerState>>) -> SpatialSettings {
+    state.lock().unwrap().spatial.clone()
+}
+
+#[tauri::command]
+fn set_spatial_settings(
+    spatial: SpatialSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    // Unlike an engine restart, a set that doesn't load is an error here
+    let hrirs = hrtf::load_set(spatial.hrtf.as_deref())?;
+    let toggled = {
+        let mut s = state.lock().unwrap();
+        let toggled = s.spatial.enabled != spatial.enabled;
+        if toggled {
+            check_restart_allowed(&s)?;
+        }
+        s.spatial = spatial.clone();
+        toggled
+    };
+    save_state_snapshot(&state);
+    // The Game bus only has a virtualizer while enabled, so switching it rebuilds the bus
+    if toggled {
+        if slot.lock().unwrap().is_some() {
+            restart_engine(&state, &slot);
+        }
+    } else if spatial.enabled {
+        if let Some(engine) = slot.lock().unwrap().as_ref() {
+            let config = SpatialConfig { enabled: true, hrirs: std::sync::Arc::new(hrirs) };
+            let params = SpatialParams::new(&config, engine.status().sample_rate);
+            engine.send(EngineCommand::SetSpatial(StreamId::Game, Box::new(params)));
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_routes(state: tauri::State<std::sync::Mutex<MixerState>>) -> BTreeMap<StreamId, Option<String>> {
+    state
+        .lock()
+        .unwrap()
+        .routes
+        .iter()
+        .map(|(k, v)| (k.clone(), v.device.clone()))
+        .collect()
+}
+
+#[tauri::command]
+fn set_route(
+    stream: StreamId,
+    device_id: Option<String>,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    worker: tauri::State<BackendWorker>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<ApplyReport, String> {
+    // Store the route configuration
+    {
+        let mut s = state.lock().unwrap();
+        check_restart_allowed(&s)?;
+        s.routes.entry(stream.clone()).or_default().device = device_id.clone();
+    }
+    save_state_snapshot(&state);
+    let mut report = ApplyReport::default();
+    if slot.lock().unwrap().is_some() {
+        // The engine skips outputs it can't open (or fails to start if it's the first bus's)
+        report.device_error = engine::live::resolve_output(&cpal::default_host(), &device_id).err();
+        restart_engine(&state, &slot);
+    }
+    update_virtual_outputs(&state, &worker);
+    
+    // Apply the route to all apps currently assigned to this stream
+    let app_categories = state.lock().unwrap().app_categories.clone();
+    for (pid, app_stream) in app_categories.iter() {
+        if *app_stream == stream {
+            let result = route_app_to_device(&worker, *pid, device_id.clone());
+            if let Err(e) = &result {
+                log::warn!("Failed to route app {} to device: {}", pid, e);
+            }
+            report.record(*pid, result);
+        }
+    }
+    
+    Ok(report)
+}
+
+// Both mixes of a stream, for the streamer view
+#[derive(Debug, Clone, Serialize)]
+struct StreamMixes {
+    personal: MixLevel,
+    broadcast: MixLevel,
+}
+
+#[tauri::command]
+fn get_stream_mixes(state: tauri::State<std::sync::Mutex<MixerState>>) -> BTreeMap<StreamId, StreamMixes> {
+    let s = state.lock().unwrap();
+    StreamId::ALL
+        .iter()
+        .map(|stream| {
+            let route = s.routes.get(stream).cloned().unwrap_or_default();
+            let gain = s.volumes.get(stream).copied().unwrap_or(1.0);
+            let personal = MixLevel { gain, muted: route.muted };
+            (stream.clone(), StreamMixes { personal, broadcast: route.broadcast })
+        })
+        .collect()
+}
+
+// Mute a stream in the personal mix only; the broadcast mix keeps it
+#[tauri::command]
+fn set_stream_muted(
+    stream: StreamId,
+    muted: bool,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    worker: tauri::State<BackendWorker>,
+    slot: tauri::State<EngineSlot>,
+) {
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetMuted(stream.clone(), muted));
+    }
+    state.lock().unwrap().routes.entry(stream).or_default().muted = muted;
+    update_virtual_outputs(&state, &worker);
+    save_state_snapshot(&state);
+}
+
+#[tauri::command]
+fn set_broadcast_level(
+    stream: StreamId,
+    level: MixLevel,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    level.validate()?;
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetBroadcast(stream.clone(), level));
+    }
+    state.lock().unwrap().routes.entry(stream).or_default().broadcast = level;
+    save_state_snapshot(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_stream_extra_outputs(stream: StreamId, state: tauri::State<std::sync::Mutex<MixerState>>) -> Vec<TapSpec> {
+    state.lock().unwrap().routes.get(&stream).map(|r| r.extra_outputs.clone()).unwrap_or_default()
+}
+
+// Devices playing the stream besides its route. Level, delay and tap point changes are
+// applied live; adding or removing a device restarts the engine.
+#[tauri::command]
+fn set_stream_extra_outputs(
+    stream: StreamId,
+    outputs: Vec<TapSpec>,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    if outputs.len() > engine::routing::MAX_EXTRA_OUTPUTS {
+        return Err(format!("At most {} extra outputs per stream", engine::routing::MAX_EXTRA_OUTPUTS));
+    }
+    for (i, tap) in outputs.iter().enumerate() {
+        tap.validate()?;
+        if outputs[..i].iter().any(|t| t.output == tap.output) {
+            return Err(format!("Output {} listed twice", tap.output.as_deref().unwrap_or("default")));
+        }
+    }
+    let devices_changed = {
+        let mut s = state.lock().unwrap();
+        let route = s.routes.entry(stream.clone()).or_default();
+        if outputs.iter().any(|t| t.output == route.device) {
+            return Err("The stream's route can't also be an extra output".into());
+        }
+        let devices = |taps: &[TapSpec]| taps.iter().map(|t| t.output.clone()).collect::<Vec<_>>();
+        let changed = devices(&route.extra_outputs) != devices(&outputs);
+        if changed {
+            check_restart_allowed(&s)?;
+        }
+        let route = s.routes.entry(stream.clone()).or_default();
+        route.extra_outputs = outputs.clone();
+        changed
+    };
+    save_state_snapshot(&state);
+    if devices_changed {
+        if slot.lock().unwrap().is_some() {
+            restart_engine(&state, &slot);
+        }
+        return Ok(());
+    }
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        for tap in outputs {
+            engine.send(EngineCommand::SetTap(stream.clone(), tap));
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_broadcast_settings(state: tauri::State<std::sync::Mutex<MixerState>>) -> BroadcastSettings {
+    state.lock().unwrap().broadcast.clone()
+}
+
+// Turning the broadcast mix on/off or moving it to another device restarts the engine
+#[tauri::command]
+fn set_broadcast_settings(
+    broadcast: BroadcastSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<EngineStatus, String> {
+    {
+        let mut s = state.lock().unwrap();
+        check_restart_allowed(&s)?;
+        s.broadcast = broadcast;
+    }
+    save_state_snapshot(&state);
+    Ok(restart_engine(&state, &slot))
+}
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
-#[serde(rename_all = "snake_case")]
-pub enum DeviceKind {
-    Input,
-    Output,
+#[tauri::command]
+fn get_output_delays(state: tauri::State<std::sync::Mutex<MixerState>>) -> Vec<OutputDelay> {
+    state.lock().unwrap().engine.output_delays.clone()
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct DeviceInfo {
-    pub id: String,
-    pub name: String,
-    pub kind: DeviceKind,
-    pub is_default: bool,
-    pub backend: String,
+// Applied live to a running output; other outputs pick it up when the engine opens them
+#[tauri::command]
+fn set_output_delay(
+    output: Option<String>,
+    delay_ms: f32,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    let max = engine::routing::MAX_DELAY_MS;
+    if !(delay_ms.is_finite() && (0.0..=max).contains(&delay_ms)) {
+        return Err(format!("Delay {delay_ms} ms out of range (0..{max})"));
+    }
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetOutputDelay(output.clone(), delay_ms));
+    }
+    {
+        let mut s = state.lock().unwrap();
+        let delays = &mut s.engine.output_delays;
+        delays.retain(|d| d.output != output);
+        if delay_ms > 0.0 {
+            delays.push(OutputDelay { output, delay_ms });
+        }
+    }
+    save_state_snapshot(&state);
+    Ok(())
 }
 
-// For simplicity, we define three logical streams. In a real app you would integrate with per-process audio sessions.
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash, Ord, PartialOrd)]
-#[serde(rename_all = "snake_case")]
-pub enum StreamId { Game, Voice, Music }
+// Time a click on each output through `input` (a microphone or loopback capture) and
+// suggest the delays that line them up. Nothing is applied; the UI sets the delays.
+#[tauri::command(async)]
+fn measure_output_latency(outputs: Vec<Option<String>>, input: String) -> Result<Vec<LatencyMeasurement>, String> {
+    if outputs.is_empty() {
+        return Err("No outputs to measure".into());
+    }
+    let mut measurements =
+        outputs.iter().map(|output| engine::latency::measure(output, &input)).collect::<Result<Vec<_>, _>>()?;
+    engine::latency::compensate(&mut measurements);
+    Ok(measurements)
+}
 
-type Routes = HashMap<StreamId, Option<String>>; // route to device id
-
-#[tauri::command]
-fn list_audio_devices() -> Vec<DeviceInfo> {
-    // Enumerate devices via cpal (WASAPI on Windows)
-    let host = cpal::default_host();
-
-    let default_output = host.default_output_device().map(|d| d.name().unwrap_or_default());
-    let default_output_name = default_output.unwrap_or_default();
-
-    let mut out = Vec::new();
-
-    // To provide unique IDs for React keys and routing selections, we create
-    // a stable-in-session identifier of the shape: "<name>::<kind>#<n>".
-    use std::collections::hash_map::Entry;
-    let mut seen: HashMap<(DeviceKind, String), usize> = HashMap::new();
-    if let Ok(devices) = host.devices() {
-        for dev in devices {
-            let name = dev.name().unwrap_or_else(|_| "Unbekannt".into());
-            // Determine kind by probing supported configs
-            let is_output = dev.supported_output_configs().is_ok();
-            let kind = if is_output { DeviceKind::Output } else { DeviceKind::Input };
-            let is_default = is_output && name == default_output_name;
-            let key = (kind.clone(), name.clone());
-            let idx = match seen.entry(key) {
-                Entry::Occupied(mut e) => {
-                    *e.get_mut() += 1;
-                    *e.get()
-                }
-                Entry::Vacant(v) => {
-                    v.insert(0);
-                    0
-                }
-            };
-            let id = format!("{}::{:?}#{}", name, kind, idx);
+#[tauri::command]
+fn get_record_settings(state: tauri::State<std::sync::Mutex<MixerState>>) -> RecordSettings {
+    state.lock().unwrap().record_settings.clone()
+}
 
-            println!("Device {}: ID='{}', Name='{}', Default={}", idx, id, name, is_default);
+// Takes effect with the next recording
+#[tauri::command]
+fn set_record_settings(settings: RecordSettings, state: tauri::State<std::sync::Mutex<MixerState>>) {
+    state.lock().unwrap().record_settings = settings;
+    save_state_snapshot(&state);
+}
 
-            out.push(DeviceInfo {
-                id,
-                name,
-                kind,
-                is_default,
-                backend: "WASAPI".into(),
-            });
+// Record each source to its own file; several sources make a multitrack take whose files
+// stay sample-aligned
+#[tauri::command]
+fn start_recording(
+    sources: Vec<RecordSource>,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<record::RecordingStatus, String> {
+    if sources.is_empty() {
+        return Err("Nothing to record".into());
+    }
+    let running = slot.lock().unwrap();
+    let engine = running.as_ref().ok_or("Start the audio engine to record")?;
+    let mut s = state.lock().unwrap();
+    if s.recording.is_some() {
+        return Err("Already recording".into());
+    }
+    let mut tracks = Vec::new();
+    for source in sources {
+        if tracks.iter().any(|(t, _)| *t == source) {
+            continue;
         }
+        let channels = engine.record_channels(&source).ok_or_else(|| format!("{source:?} is not part of the engine"))?;
+        tracks.push((source, channels));
     }
+    let folder = record_folder(&s.record_settings);
+    let (take, taps) = record::start(&folder, s.record_settings.format, &tracks, engine.status().sample_rate)?;
+    engine.send(EngineCommand::SetRecording(taps));
+    let status = take.status();
+    s.recording = Some(take);
+    Ok(status)
+}
 
-    out
+// Returns the files written
+#[tauri::command]
+fn stop_recording(
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<Vec<String>, String> {
+    let running = slot.lock().unwrap();
+    let take = state.lock().unwrap().recording.take().ok_or("Not recording")?;
+    if let Some(engine) = running.as_ref() {
+        engine.send(EngineCommand::SetRecording(Default::default()));
+    }
+    take.finish()
 }
 
-// Persistence helpers (module scope)
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct PersistedState {
-    routes: Routes,
-    volumes: HashMap<StreamId, f32>,
-    app_categories: HashMap<u32, StreamId>,
+#[tauri::command]
+fn get_recording_status(state: tauri::State<std::sync::Mutex<MixerState>>) -> Option<record::RecordingStatus> {
+    state.lock().unwrap().recording.as_ref().map(|r| r.status())
 }
 
-fn state_file_path() -> std::path::PathBuf {
-    let base = dirs_next::config_dir()
-        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from(".")));
-    let dir = base.join("audio-mixer");
-    let _ = std::fs::create_dir_all(&dir);
-    dir.join("state.json")
+#[tauri::command]
+fn get_replay_settings(state: tauri::State<std::sync::Mutex<MixerState>>) -> ReplaySettings {
+    state.lock().unwrap().replay_settings.clone()
 }
 
-fn load_state() -> MixerState {
-    let path = state_file_path();
-    if let Ok(data) = std::fs::read(path) {
-        if let Ok(p) = serde_json::from_slice::<PersistedState>(&data) {
-            return MixerState { routes: p.routes, volumes: p.volumes, app_categories: p.app_categories };
+// Applied live; the history starts over
+#[tauri::command]
+fn set_replay_settings(
+    settings: ReplaySettings,
+    app: tauri::AppHandle,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    settings.validate()?;
+    let clips = state.lock().unwrap().soundboard.clone();
+    register_hotkeys(&app, &hotkey_actions(&settings, &clips))?;
+    {
+        let running = slot.lock().unwrap();
+        let mut s = state.lock().unwrap();
+        s.replay_settings = settings;
+        if let Some(engine) = running.as_ref() {
+            start_replay(&mut s, engine);
         }
     }
-    MixerState::default()
+    save_state_snapshot(&state);
+    Ok(())
 }
 
-fn save_state_snapshot(state: &std::sync::Mutex<MixerState>) {
-    let (routes, volumes, app_categories) = {
+// Write the last `seconds` (all that is buffered if `None`) of every replay stream to the
+// recordings folder; returns the files written
+#[tauri::command(async)]
+fn save_replay(seconds: Option<f32>, state: tauri::State<std::sync::Mutex<MixerState>>) -> Result<Vec<String>, String> {
+    write_replay(&state, seconds)
+}
+
+fn write_replay(state: &std::sync::Mutex<MixerState>, seconds: Option<f32>) -> Result<Vec<String>, String> {
+    let (replay, folder, format) = {
         let s = state.lock().unwrap();
-        (s.routes.clone(), s.volumes.clone(), s.app_categories.clone())
+        let replay = s.replay.clone().ok_or("The replay buffer is not running")?;
+        (replay, record_folder(&s.record_settings), s.record_settings.format)
     };
-    let p = PersistedState { routes, volumes, app_categories };
-    if let Ok(json) = serde_json::to_vec_pretty(&p) {
-        let _ = std::fs::write(state_file_path(), json);
-    }
+    replay.save(&folder, format, seconds)
 }
 
-// In-memory routing/volume state. For a real app, persist to a file and drive actual audio pipelines.
-#[derive(Default)]
-struct MixerState {
-    routes: Routes,
-    volumes: HashMap<StreamId, f32>,
-    // Map process id -> assigned logical stream
-    app_categories: HashMap<u32, StreamId>,
+// What a global shortcut does
+#[derive(Debug, Clone, Copy)]
+enum HotkeyAction {
+    SaveReplay,
+    PlayClip(usize),
 }
 
-#[tauri::command]
-fn get_routes(state: tauri::State<std::sync::Mutex<MixerState>>) -> BTreeMap<StreamId, Option<String>> {
-    state
-        .lock()
-        .unwrap()
-        .routes
+fn hotkey_actions<'a>(replay: &'a ReplaySettings, soundboard: &'a [SoundboardSlot]) -> Vec<(&'a str, HotkeyAction)> {
+    let replay = replay.hotkey.as_deref().map(|key| (key, HotkeyAction::SaveReplay));
+    let clips = soundboard
         .iter()
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .collect()
+        .enumerate()
+        .filter_map(|(i, c)| c.hotkey.as_deref().map(|key| (key, HotkeyAction::PlayClip(i))));
+    replay.into_iter().chain(clips).collect()
 }
 
-#[tauri::command]
-fn set_route(
-    stream: StreamId,
-    device_id: Option<String>,
-    state: tauri::State<std::sync::Mutex<MixerState>>,
-) -> bool {
-    // Store the route configuration
-    state.lock().unwrap().routes.insert(stream.clone(), device_id.clone());
-    save_state_snapshot(&state);
-    
-    // Apply the route to all apps currently assigned to this stream
-    let app_categories = state.lock().unwrap().app_categories.clone();
-    for (pid, app_stream) in app_categories.iter() {
-        if *app_stream == stream {
-            if let Err(e) = route_app_to_device(*pid, device_id.clone()) {
-                eprintln!("Failed to route app {} to device: {}", pid, e);
-            }
+// The replay and soundboard hotkeys are the app's only global shortcuts, so the whole set
+// is registered anew. Checked first, so a bad set leaves the current one in place.
+fn register_hotkeys(app: &tauri::AppHandle, actions: &[(&str, HotkeyAction)]) -> Result<(), String> {
+    let mut parsed: Vec<Shortcut> = Vec::new();
+    for &(hotkey, _) in actions {
+        let shortcut = hotkey.parse().map_err(|e| format!("Invalid hotkey {hotkey}: {e}"))?;
+        if parsed.contains(&shortcut) {
+            return Err(format!("Hotkey {hotkey} is assigned twice"));
         }
+        parsed.push(shortcut);
     }
-    
-    true
+    let shortcuts = app.global_shortcut();
+    shortcuts.unregister_all().map_err(|e| format!("Unregister hotkeys failed: {e}"))?;
+    for &(hotkey, _) in actions {
+        shortcuts.register(hotkey).map_err(|e| format!("Register hotkey {hotkey} failed: {e}"))?;
+    }
+    Ok(())
 }
 
-// Route a specific app (PID) to a specific audio device
-fn route_app_to_device(pid: u32, device_id: Option<String>) -> Result<(), String> {
-    unsafe {
-        let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
-        let need_uninit = hr.is_ok();
-        
-        let result = (|| -> Result<(), String> {
-            let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
-                .map_err(|e| format!("Create MMDeviceEnumerator failed: {e}"))?;
-            
-            // Get target device
-            let target_device = match &device_id {
-                Some(id) => {
-                    find_device_by_id(&enumerator, id)?
-                }
-                None => {
-                    enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)
-                        .map_err(|e| format!("Get default device failed: {e}"))?
-                }
-            };
-            
-            // Get the device endpoint ID string for policy routing
-            let device_endpoint_id = get_device_endpoint_id(&target_device)?;
-            
-            // Find the app's audio session
-            let session_found = find_and_log_app_session(pid, &enumerator)?;
-            
-            if session_found {
-                println!("Audio session found for PID {}", pid);
-                
-                // Try to route the app using Windows Policy Config API
-                match route_app_using_policy(pid, &device_endpoint_id) {
-                    Ok(_) => {
-                        if let Some(device_id) = &device_id {
-                            println!("Successfully routed PID {} to device {}", pid, device_id);
-                        } else {
-                            println!("Successfully routed PID {} to default device", pid);
-                        }
-                    }
-                    Err(e) => {
-                        println!("Policy routing failed for PID {}: {}, trying alternative method", pid, e);
-                        
-                        // Fallback: Try to set default device for the app's process
-                        try_set_app_default_device(pid, &target_device)?;
-                    }
-                }
-                
-                Ok(())
-            } else {
-                Err(format!("No audio session found for PID {}", pid))
-            }
-        })();
-        
-        if need_uninit {
-            CoUninitialize();
-        }
-        result
+fn on_hotkey(app: &tauri::AppHandle, shortcut: Shortcut) {
+    let state = app.state::<std::sync::Mutex<MixerState>>();
+    let action = {
+        let s = state.lock().unwrap();
+        hotkey_actions(&s.replay_settings, &s.soundboard)
+            .into_iter()
+            .find(|(key, _)| key.parse::<Shortcut>().is_ok_and(|k| k == shortcut))
+            .map(|(_, action)| action)
+    };
+    let result = match action {
+        Some(HotkeyAction::SaveReplay) => write_replay(&state, None).map(|_| ()),
+        Some(HotkeyAction::PlayClip(index)) => play_clip(&state, &app.state::<EngineSlot>(), index),
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        log::warn!("Hotkey {:?}: {}", action, e);
     }
 }
 
-// Get device endpoint ID for policy routing
-fn get_device_endpoint_id(device: &IMMDevice) -> Result<String, String> {
-    unsafe {
-        // Get the device ID string
-        let id_ptr = device.GetId()
-            .map_err(|e| format!("GetId failed: {e}"))?;
-        
-        let id_str = id_ptr.to_string()
-            .map_err(|e| format!("Convert ID to string failed: {e}"))?;
-        
-        // Free the allocated string
-        use windows::Win32::System::Com::CoTaskMemFree;
-        CoTaskMemFree(Some(id_ptr.0 as *mut _));
-        
-        Ok(id_str)
+fn load_clip(file: &str, sample_rate: Option<u32>) -> Result<ClipAudio, String> {
+    let clip = soundboard::decode(std::path::Path::new(file))?;
+    Ok(match sample_rate {
+        Some(rate) if rate != clip.sample_rate => soundboard::resample(&clip, rate),
+        _ => clip,
+    })
+}
+
+// Bring every clip to the engine rate; a slot whose file can't be read stays silent.
+// Decoding takes a while, so it runs without the locks, and always starts from the file
+// rather than from a clip converted for an earlier rate.
+fn prepare_soundboard(state: &std::sync::Mutex<MixerState>, sample_rate: u32) {
+    let pending: Vec<(usize, SoundboardSlot)> = {
+        let s = state.lock().unwrap();
+        s.soundboard
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| s.soundboard_clips.get(*i).cloned().flatten().is_none_or(|c| c.sample_rate != sample_rate))
+            .map(|(i, slot)| (i, slot.clone()))
+            .collect()
+    };
+    let loaded: Vec<_> = pending
+        .into_iter()
+        .map(|(i, slot)| {
+            let clip = load_clip(&slot.file, Some(sample_rate))
+                .map_err(|e| log::error!("Soundboard slot {:?}: {}", slot.name, e))
+                .ok()
+                .map(std::sync::Arc::new);
+            (i, slot.file, clip)
+        })
+        .collect();
+    let s = &mut *state.lock().unwrap();
+    let len = s.soundboard.len();
+    s.soundboard_clips.resize(len, None);
+    for (i, file, clip) in loaded {
+        // Unless the slot was changed in the meantime
+        if s.soundboard.get(i).is_some_and(|slot| slot.file == file) {
+            let old = std::mem::replace(&mut s.soundboard_clips[i], clip);
+            retire_clips(s, old);
+        }
     }
 }
 
-// Route app using Windows Policy Config API (requires elevated privileges)
-fn route_app_using_policy(pid: u32, device_endpoint_id: &str) -> Result<(), String> {
-    // Note: This requires the undocumented IPolicyConfig interface
-    // which is used by Windows Sound Control Panel
-    
-    // Create PolicyConfig instance (this may fail on some Windows versions)
-    // CLSID for PolicyConfig: {870af99c-171d-4f9e-af0d-e63df40c2bc9}
-    use windows::core::GUID;
-    let _policy_clsid = GUID::from("870af99c-171d-4f9e-af0d-e63df40c2bc9");
-    
-    println!("Attempting policy-based routing for PID {} to device {}", pid, device_endpoint_id);
-    
-    // This is an advanced technique that may not work on all systems
-    // For now, we'll log the attempt and return an error to trigger fallback
-    Err("Policy routing not implemented - using fallback".to_string())
+// Keep replaced clips until no voice plays them any more, so the audio thread never frees one
+fn retire_clips(s: &mut MixerState, clips: impl IntoIterator<Item = std::sync::Arc<ClipAudio>>) {
+    s.retired_clips.extend(clips);
+    s.retired_clips.retain(|c| std::sync::Arc::strong_count(c) > 1);
 }
 
-// Alternative method: Try to influence the app's default device
-fn try_set_app_default_device(pid: u32, target_device: &IMMDevice) -> Result<(), String> {
-    println!("Attempting alternative routing method for PID {}", pid);
-    
-    // Get device properties for logging
-    let device_endpoint_id = get_device_endpoint_id(target_device)?;
-    
-    println!("Alternative routing: PID {} should use device {}", pid, device_endpoint_id);
-    
-    // Method 1: Try to disconnect and reconnect the app's audio sessions
-    // This forces the app to recreate its audio sessions, potentially on the new default device
-    try_restart_app_audio_sessions(pid)?;
-    
-    println!("Audio session restart attempted for PID {}", pid);
-    
+fn play_clip(state: &std::sync::Mutex<MixerState>, slot: &EngineSlot, index: usize) -> Result<(), String> {
+    let running = slot.lock().unwrap();
+    let engine = running.as_ref().ok_or("The audio engine is not running")?;
+    let s = &mut *state.lock().unwrap();
+    retire_clips(s, None);
+    let settings = s.soundboard.get(index).ok_or_else(|| format!("No soundboard slot {index}"))?;
+    let clip =
+        s.soundboard_clips.get(index).cloned().flatten().ok_or_else(|| format!("{} isn't loaded", settings.file))?;
+    // Right after a restart, until prepare_soundboard has converted it
+    if clip.sample_rate != engine.status().sample_rate {
+        return Err(format!("{} isn't ready yet", settings.file));
+    }
+    engine.send(EngineCommand::PlayClip(soundboard::Trigger {
+        clip,
+        slot: index,
+        stream: settings.stream.clone(),
+        to_mic: settings.to_mic,
+        gain: settings.gain,
+        polyphony: settings.polyphony,
+    }));
     Ok(())
 }
 
-// Try to restart an app's audio sessions to force device reselection
-fn try_restart_app_audio_sessions(pid: u32) -> Result<(), String> {
-    unsafe {
-        let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
-            .map_err(|e| format!("Create MMDeviceEnumerator failed: {e}"))?;
-        
-        let devices: IMMDeviceCollection = enumerator
-            .EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)
-            .map_err(|e| format!("EnumAudioEndpoints failed: {e}"))?;
-        let dev_count = devices
-            .GetCount()
-            .map_err(|e| format!("GetCount(devices) failed: {e}"))? as i32;
-
-        for di in 0..dev_count {
-            let device: IMMDevice = devices
-                .Item(di as u32)
-                .map_err(|e| format!("Get device {di} failed: {e}"))?;
-
-            let mgr: IAudioSessionManager2 = device
-                .Activate::<IAudioSessionManager2>(CLSCTX_ALL, None)
-                .map_err(|e| format!("Activate IAudioSessionManager2 failed: {e}"))?;
-
-            let session_enumerator = mgr
-                .GetSessionEnumerator()
-                .map_err(|e| format!("GetSessionEnumerator failed: {e}"))?;
-
-            let count = session_enumerator
-                .GetCount()
-                .map_err(|e| format!("GetCount(sessions) failed: {e}"))?;
-
-            for i in 0..count {
-                let session: IAudioSessionControl = session_enumerator
-                    .GetSession(i)
-                    .map_err(|e| format!("GetSession {i} failed: {e}"))?;
-
-                let session2: IAudioSessionControl2 = session
-                    .cast()
-                    .map_err(|e| format!("Cast to IAudioSessionControl2 failed: {e}"))?;
-
-                let session_pid = session2
-                    .GetProcessId()
-                    .map_err(|e| format!("GetProcessId failed: {e}"))?;
-
-                if session_pid == pid {
-                    println!("Found audio session for PID {} on device {}, attempting disconnect", pid, di);
-                    
-                    // Try to disconnect the session
-                    // This may cause the app to recreate its audio session on the new default device
-                    match try_disconnect_session(&session2) {
-                        Ok(_) => println!("Successfully signaled session disconnect for PID {}", pid),
-                        Err(e) => println!("Session disconnect failed for PID {}: {}", pid, e),
-                    }
-                }
-            }
-        }
-        
-        Ok(())
-    }
+#[tauri::command]
+fn get_soundboard(state: tauri::State<std::sync::Mutex<MixerState>>) -> Vec<SoundboardSlot> {
+    state.lock().unwrap().soundboard.clone()
 }
 
-// Try to signal a session to disconnect (this may cause the app to restart audio)
-fn try_disconnect_session(session: &IAudioSessionControl2) -> Result<(), String> {
-    unsafe {
-        // Method 1: Try to set the session state to inactive
-        // This is a soft approach that may cause the app to reinitialize audio
-        
-        // Get the simple audio volume interface to manipulate the session
-        let simple_volume: ISimpleAudioVolume = session
-            .cast()
-            .map_err(|e| format!("Cast to ISimpleAudioVolume failed: {e}"))?;
-        
-        // Store current volume
-        let _current_volume = simple_volume
-            .GetMasterVolume()
-            .map_err(|e| format!("GetMasterVolume failed: {e}"))?;
-        
-        // Briefly mute and unmute to signal the session
-        simple_volume
-            .SetMute(true, std::ptr::null())
-            .map_err(|e| format!("SetMute(true) failed: {e}"))?;
-        
-        // Small delay
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        
-        simple_volume
-            .SetMute(false, std::ptr::null())
-            .map_err(|e| format!("SetMute(false) failed: {e}"))?;
-        
-        println!("Session signaling completed (mute/unmute cycle)");
-        
-        Ok(())
+// Decodes the files, so a missing or overlong one fails right here; files already loaded
+// aren't read again. Clips still playing are faded out since slot numbers may change.
+#[tauri::command(async)]
+fn set_soundboard(
+    slots: Vec<SoundboardSlot>,
+    app: tauri::AppHandle,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    for s in &slots {
+        s.validate()?;
+    }
+    let sample_rate = slot.lock().unwrap().as_ref().map(|e| e.status().sample_rate);
+    let (loaded, replay) = {
+        let s = state.lock().unwrap();
+        let loaded: HashMap<String, std::sync::Arc<ClipAudio>> = s
+            .soundboard
+            .iter()
+            .zip(&s.soundboard_clips)
+            .filter_map(|(slot, clip)| clip.clone().map(|c| (slot.file.clone(), c)))
+            .collect();
+        (loaded, s.replay_settings.clone())
+    };
+    let clips = slots
+        .iter()
+        .map(|s| match loaded.get(&s.file) {
+            Some(c) if sample_rate.is_none_or(|rate| rate == c.sample_rate) => Ok(Some(c.clone())),
+            _ => load_clip(&s.file, sample_rate).map(|c| Some(std::sync::Arc::new(c))),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    register_hotkeys(&app, &hotkey_actions(&replay, &slots))?;
+    {
+        let running = slot.lock().unwrap();
+        if let Some(engine) = running.as_ref() {
+            engine.send(EngineCommand::StopClips(None));
+        }
+        let s = &mut *state.lock().unwrap();
+        s.soundboard = slots;
+        let old = std::mem::replace(&mut s.soundboard_clips, clips);
+        retire_clips(s, old.into_iter().flatten());
     }
+    save_state_snapshot(&state);
+    Ok(())
 }
 
-// Helper function to find a device by ID
-fn find_device_by_id(enumerator: &IMMDeviceEnumerator, device_id: &str) -> Result<IMMDevice, String> {
-    unsafe {
-        let devices: IMMDeviceCollection = enumerator
-            .EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)
-            .map_err(|e| format!("EnumAudioEndpoints failed: {e}"))?;
-        let dev_count = devices
-            .GetCount()
-            .map_err(|e| format!("GetCount(devices) failed: {e}"))? as i32;
-
-        println!("Looking for device: {}", device_id);
-
-        // Extract device index from ID (format: "Name::Output#INDEX")
-        if let Some(index_part) = device_id.split('#').last() {
-            if let Ok(target_index) = index_part.parse::<i32>() {
-                if target_index >= 0 && target_index < dev_count {
-                    println!("Found device by index: {}", target_index);
-                    let device: IMMDevice = devices
-                        .Item(target_index as u32)
-                        .map_err(|e| format!("Get device {} failed: {e}", target_index))?;
-                    return Ok(device);
-                }
-            }
-        }
+#[tauri::command]
+fn play_soundboard_slot(
+    index: usize,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    play_clip(&state, &slot, index)
+}
 
-        // Fallback: try name matching
-        for di in 0..dev_count {
-            let device: IMMDevice = devices
-                .Item(di as u32)
-                .map_err(|e| format!("Get device {di} failed: {e}"))?;
-            
-            let device_name = format!("Device_{}", di); // Simplified for now
-            
-            println!("Checking device {}: '{}'", di, device_name);
-            
-            if device_id.contains(&device_name) {
-                println!("Found matching device by name: {}", device_name);
-                return Ok(device);
-            }
-        }
-        
-        Err(format!("Device not found: {}", device_id))
+// Fade out the clips of one slot, or all of them
+#[tauri::command]
+fn stop_soundboard(index: Option<usize>, slot: tauri::State<EngineSlot>) {
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::StopClips(index));
     }
 }
 
-// Helper function to get device name
-fn get_device_name(_device: &IMMDevice) -> Result<String, String> {
-    // This is a simplified version - would need proper property store access
-    Ok("Device".to_string())
+// "Audio Mixer - Game" etc.: an output device apps can pick directly, played on the
+// stream's routed device at the stream's volume. Bypasses the engine.
+fn virtual_output(state: &MixerState, stream: &StreamId) -> VirtualOutput {
+    let route = state.routes.get(stream).cloned().unwrap_or_default();
+    VirtualOutput {
+        id: virtual_output_id(stream),
+        name: format!("Audio Mixer - {stream:?}"),
+        target: route.device,
+        volume: state.volumes.get(stream).copied().unwrap_or(1.0),
+        muted: route.muted,
+    }
 }
 
-// Helper function to find and log an app's audio session
-fn find_and_log_app_session(target_pid: u32, enumerator: &IMMDeviceEnumerator) -> Result<bool, String> {
-    unsafe {
-        let devices: IMMDeviceCollection = enumerator
-            .EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)
-            .map_err(|e| format!("EnumAudioEndpoints failed: {e}"))?;
-        let dev_count = devices
-            .GetCount()
-            .map_err(|e| format!("GetCount(devices) failed: {e}"))? as i32;
-
-        for di in 0..dev_count {
-            let device: IMMDevice = devices
-                .Item(di as u32)
-                .map_err(|e| format!("Get device {di} failed: {e}"))?;
+fn virtual_output_id(stream: &StreamId) -> String {
+    format!("audio_mixer_{}", format!("{stream:?}").to_lowercase())
+}
 
-            let mgr: IAudioSessionManager2 = device
-                .Activate::<IAudioSessionManager2>(CLSCTX_ALL, None)
-                .map_err(|e| format!("Activate IAudioSessionManager2 failed: {e}"))?;
+// Create, update or remove the virtual outputs to match the stream configuration
+fn sync_virtual_outputs(state: &std::sync::Mutex<MixerState>, worker: &BackendWorker) -> Result<(), String> {
+    let (enabled, outputs): (bool, Vec<VirtualOutput>) = {
+        let s = state.lock().unwrap();
+        (s.virtual_outputs, StreamId::ALL.iter().map(|stream| virtual_output(&s, stream)).collect())
+    };
+    worker.call(move |b| {
+        let errors: Vec<String> = outputs
+            .iter()
+            .map(|o| if enabled { b.set_virtual_output(o) } else { b.remove_virtual_output(&o.id) })
+            .filter_map(Result::err)
+            .collect();
+        if errors.is_empty() { Ok(()) } else { Err(errors.join("; ")) }
+    })?
+}
 
-            let enumerator = mgr
-                .GetSessionEnumerator()
-                .map_err(|e| format!("GetSessionEnumerator failed: {e}"))?;
+// Changes to a stream's route, volume or mute carry over to its virtual output
+fn update_virtual_outputs(state: &std::sync::Mutex<MixerState>, worker: &BackendWorker) {
+    if state.lock().unwrap().virtual_outputs {
+        if let Err(e) = sync_virtual_outputs(state, worker) {
+            log::warn!("Updating virtual outputs failed: {}", e);
+        }
+    }
+}
 
-            let count = enumerator
-                .GetCount()
-                .map_err(|e| format!("GetCount(sessions) failed: {e}"))?;
+// Empty while they are disabled
+#[tauri::command]
+fn get_virtual_outputs(state: tauri::State<std::sync::Mutex<MixerState>>) -> Vec<VirtualOutput> {
+    let s = state.lock().unwrap();
+    if !s.virtual_outputs {
+        return Vec::new();
+    }
+    StreamId::ALL.iter().map(|stream| virtual_output(&s, stream)).collect()
+}
 
-            for i in 0..count {
-                let session: IAudioSessionControl = enumerator
-                    .GetSession(i)
-                    .map_err(|e| format!("GetSession {i} failed: {e}"))?;
+#[tauri::command]
+fn set_virtual_outputs_enabled(
+    enabled: bool,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    worker: tauri::State<BackendWorker>,
+) -> Result<Vec<VirtualOutput>, String> {
+    state.lock().unwrap().virtual_outputs = enabled;
+    let result = sync_virtual_outputs(&state, &worker);
+    if result.is_err() && enabled {
+        // Don't bring up half-working devices again on the next start
+        state.lock().unwrap().virtual_outputs = false;
+        let _ = sync_virtual_outputs(&state, &worker);
+    }
+    save_state_snapshot(&state);
+    result.map(|_| get_virtual_outputs(state))
+}
 
-                let session2: IAudioSessionControl2 = session
-                    .cast()
-                    .map_err(|e| format!("Cast to IAudioSessionControl2 failed: {e}"))?;
+// Create or remove the virtual mic device to match the settings
+fn sync_virtual_mic(state: &std::sync::Mutex<MixerState>, worker: &BackendWorker) -> Result<(), String> {
+    let enabled = state.lock().unwrap().virtual_mic.enabled;
+    let opener = if enabled {
+        let input = VirtualInput { id: VIRTUAL_MIC_ID.into(), name: "Audio Mixer - Microphone".into() };
+        Some(worker.call(move |b| b.set_virtual_input(&input))??)
+    } else {
+        worker.call(|b| b.remove_virtual_input(VIRTUAL_MIC_ID))??;
+        None
+    };
+    state.lock().unwrap().virtual_mic_opener = opener;
+    Ok(())
+}
 
-                let session_pid = session2
-                    .GetProcessId()
-                    .map_err(|e| format!("GetProcessId failed: {e}"))?;
+#[tauri::command]
+fn get_virtual_mic(state: tauri::State<std::sync::Mutex<MixerState>>) -> VirtualMicSettings {
+    state.lock().unwrap().virtual_mic
+}
 
-                if session_pid == target_pid {
-                    println!("Found audio session for PID {} on device {}", target_pid, di);
-                    return Ok(true);
-                }
-            }
+// Needs the engine with a microphone on the Voice bus; the device stays silent otherwise.
+// A new music level applies live.
+#[tauri::command]
+fn set_virtual_mic(
+    settings: VirtualMicSettings,
+    state: tauri::State<std::sync::Mutex<MixerState>>,
+    worker: tauri::State<BackendWorker>,
+    slot: tauri::State<EngineSlot>,
+) -> Result<(), String> {
+    settings.validate()?;
+    let was_enabled = {
+        let mut s = state.lock().unwrap();
+        std::mem::replace(&mut s.virtual_mic, settings).enabled
+    };
+    if settings.enabled == was_enabled {
+        if let Some(feed) = state.lock().unwrap().virtual_mic_feed.as_ref() {
+            feed.set_music_level(settings.music_level);
+        }
+        save_state_snapshot(&state);
+        return Ok(());
+    }
+    let result = sync_virtual_mic(&state, &worker);
+    if result.is_err() {
+        state.lock().unwrap().virtual_mic.enabled = false;
+    }
+    {
+        let running = slot.lock().unwrap();
+        let mut s = state.lock().unwrap();
+        match running.as_ref() {
+            Some(engine) => start_virtual_mic_feed(&mut s, engine),
+            None => s.virtual_mic_feed = None,
         }
-        
-        Ok(false)
     }
+    save_state_snapshot(&state);
+    result
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct AppSession {
-    pub pid: u32,
-    pub name: String,
-    pub process_name: String, // The actual executable name (e.g., "discord.exe")
-    pub volume: f32,
-    pub muted: bool,
-}
-
-fn process_name_from_pid(pid: u32) -> Option<String> {
-    unsafe {
-        let handle: HANDLE = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, BOOL(0), pid).ok()?;
-        if handle.is_invalid() { return None; }
-        let mut buf = [0u16; 32768];
-        let len = K32GetProcessImageFileNameW(handle, &mut buf);
-        let _ = CloseHandle(handle);
-        if len == 0 { return None; }
-        let path = String::from_utf16_lossy(&buf[..len as usize]);
-        let name = path.rsplit(['\\', '/']).next().unwrap_or(path.as_str()).to_string();
-        Some(name)
-    }
-}
-
-#[tauri::command]
-fn list_audio_apps() -> Result<Vec<AppSession>, String> {
-    unsafe {
-        let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
-        let need_uninit = hr.is_ok();
-        let result = (|| -> Result<Vec<AppSession>, String> {
-            let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
-                .map_err(|e| format!("Create MMDeviceEnumerator failed: {e}"))?;
-
-            let devices: IMMDeviceCollection = enumerator
-                .EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)
-                .map_err(|e| format!("EnumAudioEndpoints failed: {e}"))?;
-            let dev_count = devices
-                .GetCount()
-                .map_err(|e| format!("GetCount(devices) failed: {e}"))? as i32;
-
-            let mut out = Vec::new();
-            let mut seen: std::collections::HashSet<u32> = std::collections::HashSet::new();
-
-            for di in 0..dev_count {
-                let device: IMMDevice = devices
-                    .Item(di as u32)
-                    .map_err(|e| format!("Get device {di} failed: {e}"))?;
-
-                let mgr: IAudioSessionManager2 = device
-                    .Activate::<IAudioSessionManager2>(CLSCTX_ALL, None)
-                    .map_err(|e| format!("Activate IAudioSessionManager2 failed: {e}"))?;
-
-                let sessions: IAudioSessionEnumerator = mgr
-                    .GetSessionEnumerator()
-                    .map_err(|e| format!("GetSessionEnumerator failed: {e}"))?;
-                let count = sessions
-                    .GetCount()
-                    .map_err(|e| format!("GetCount(sessions) failed: {e}"))? as i32;
-
-                for i in 0..count {
-                    let ctrl: IAudioSessionControl = sessions
-                        .GetSession(i)
-                        .map_err(|e| format!("GetSession({i}) failed: {e}"))?;
-                    let ctrl2: IAudioSessionControl2 = ctrl
-                        .cast()
-                        .map_err(|e| format!("Query IAudioSessionControl2 failed: {e}"))?;
-                    let pid = ctrl2
-                        .GetProcessId()
-                        .map_err(|e| format!("GetProcessId failed: {e}"))?;
-                    if pid == 0 || seen.contains(&pid) { continue; }
-
-                    let simple: ISimpleAudioVolume = ctrl
-                        .cast()
-                        .map_err(|e| format!("Query ISimpleAudioVolume failed: {e}"))?;
-                    let volume = simple
-                        .GetMasterVolume()
-                        .map_err(|e| format!("GetMasterVolume failed: {e}"))?;
-                    let muted = simple
-                        .GetMute()
-                        .map_err(|e| format!("GetMute failed: {e}"))?
-                        .as_bool();
-
-                    let name = process_name_from_pid(pid).unwrap_or_else(|| format!("PID {pid}"));
-                    let process_name = process_name_from_pid(pid).unwrap_or_else(|| format!("unknown_process_{pid}.exe"));
-                    out.push(AppSession { pid, name, process_name, volume, muted });
-                    seen.insert(pid);
-                }
-            }
-            Ok(out)
-        })();
-        if need_uninit { CoUninitialize(); }
-        result
-    }
+// Route a specific app (PID) to a specific audio device
+fn route_app_to_device(worker: &BackendWorker, pid: u32, device_id: Option<String>) -> Result<(), String> {
+    worker.call(move |b| b.route_app(pid, device_id.as_deref()))?
+}
+
+#[tauri::command]
+fn list_audio_apps(worker: tauri::State<BackendWorker>) -> Result<Vec<AppSession>, String> {
+    worker.call(|b| b.list_apps())?
 }
 
 #[tauri::command]
@@ -585,18 +1754,22 @@ fn set_app_category(
     pid: u32,
     stream: StreamId,
     state: tauri::State<std::sync::Mutex<MixerState>>,
-) -> bool {
-    // Store the app category
-    state.lock().unwrap().app_categories.insert(pid, stream.clone());
-    save_state_snapshot(&state);
-    
-    // Get the device for this stream and route the app to it
-    let device_id = state.lock().unwrap().routes.get(&stream).cloned().flatten();
-    if let Err(e) = route_app_to_device(pid, device_id) {
-        eprintln!("Failed to route app {} to stream device: {}", pid, e);
+    worker: tauri::State<BackendWorker>,
+) -> ApplyReport {
+    // Route the app to the stream's device; the category is only stored once it got there
+    let device_id = state.lock().unwrap().routes.get(&stream).and_then(|r| r.device.clone());
+    let result = route_app_to_device(&worker, pid, device_id);
+    match &result {
+        Ok(()) => {
+            state.lock().unwrap().app_categories.insert(pid, stream);
+            save_state_snapshot(&state);
+        }
+        Err(e) => log::warn!("Failed to route app {} to stream device: {}", pid, e),
     }
-    
-    true
+
+    let mut report = ApplyReport::default();
+    report.record(pid, result);
+    report
 }
 
 #[tauri::command]
@@ -610,71 +1783,13 @@ fn clear_app_category(
 }
 
 #[tauri::command]
-fn set_app_volume(pid: u32, volume: f32) -> Result<bool, String> {
-    apply_volume_to_pid(pid, volume)
+fn set_app_volume(pid: u32, volume: f32, worker: tauri::State<BackendWorker>) -> Result<bool, String> {
+    apply_volume_to_pid(&worker, pid, volume)
 }
 
 // Hilfsfunktion: Volume auf eine spezifische PID anwenden
-fn apply_volume_to_pid(pid: u32, volume: f32) -> Result<bool, String> {
-    unsafe {
-        let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
-        let need_uninit = hr.is_ok();
-        let result = (|| -> Result<bool, String> {
-            let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
-                .map_err(|e| format!("Create MMDeviceEnumerator failed: {e}"))?;
-            
-            // Durchsuche alle aktiven Ausgabegeräte
-            let devices: IMMDeviceCollection = enumerator
-                .EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)
-                .map_err(|e| format!("EnumAudioEndpoints failed: {e}"))?;
-            let dev_count = devices
-                .GetCount()
-                .map_err(|e| format!("GetCount(devices) failed: {e}"))? as i32;
-
-            for di in 0..dev_count {
-                let device: IMMDevice = devices
-                    .Item(di as u32)
-                    .map_err(|e| format!("Get device {di} failed: {e}"))?;
-
-                let mgr: IAudioSessionManager2 = device
-                    .Activate::<IAudioSessionManager2>(CLSCTX_ALL, None)
-                    .map_err(|e| format!("Activate IAudioSessionManager2 failed: {e}"))?;
-
-                let sessions: IAudioSessionEnumerator = mgr
-                    .GetSessionEnumerator()
-                    .map_err(|e| format!("GetSessionEnumerator failed: {e}"))?;
-                let count = sessions
-                    .GetCount()
-                    .map_err(|e| format!("GetCount failed: {e}"))? as i32;
-
-                for i in 0..count {
-                    let ctrl: IAudioSessionControl = sessions
-                        .GetSession(i)
-                        .map_err(|e| format!("GetSession({i}) failed: {e}"))?;
-                    let ctrl2: IAudioSessionControl2 = ctrl
-                        .cast()
-                        .map_err(|e| format!("Query IAudioSessionControl2 failed: {e}"))?;
-                    let this_pid = ctrl2
-                        .GetProcessId()
-                        .map_err(|e| format!("GetProcessId failed: {e}"))?;
-                    
-                    if this_pid == pid {
-                        let simple: ISimpleAudioVolume = ctrl
-                            .cast()
-                            .map_err(|e| format!("Query ISimpleAudioVolume failed: {e}"))?;
-                        simple
-                            .SetMasterVolume(volume.clamp(0.0, 1.0), std::ptr::null())
-                            .map_err(|e| format!("SetMasterVolume failed: {e}"))?;
-                        if need_uninit { CoUninitialize(); }
-                        return Ok(true);
-                    }
-                }
-            }
-            Ok(false)
-        })();
-        if need_uninit { CoUninitialize(); }
-        result
-    }
+fn apply_volume_to_pid(worker: &BackendWorker, pid: u32, volume: f32) -> Result<bool, String> {
+    worker.call(move |b| b.set_app_volume(pid, volume))?
 }
 
 #[tauri::command]
@@ -682,13 +1797,24 @@ fn set_stream_volume(
     stream: StreamId,
     volume: f32,
     state: tauri::State<std::sync::Mutex<MixerState>>,
-) -> bool {
+    worker: tauri::State<BackendWorker>,
+    slot: tauri::State<EngineSlot>,
+) -> ApplyReport {
     let vol = volume.clamp(0.0, 1.0);
-    
     // Speichere den neuen Volume-Wert für den Stream
+    state.lock().unwrap().volumes.insert(stream.clone(), vol);
+    update_virtual_outputs(&state, &worker);
+
+    // Mit laufender Engine regelt der Bus den Stream; die App-Sessions bleiben unverändert,
+    // sonst würde die Lautstärke doppelt angewendet
+    if let Some(engine) = slot.lock().unwrap().as_ref() {
+        engine.send(EngineCommand::SetGain(stream, vol));
+        save_state_snapshot(&state);
+        return ApplyReport::default();
+    }
+    
     let pids_to_update: Vec<u32> = {
-        let mut s = state.lock().unwrap();
-        s.volumes.insert(stream.clone(), vol);
+        let s = state.lock().unwrap();
         
         // Finde alle PIDs, die diesem Stream zugeordnet sind
         s.app_categories
@@ -699,36 +1825,265 @@ fn set_stream_volume(
     };
     
     // Wende die Lautstärke auf alle zugeordneten Apps an
+    // Fehler landen im Report (App könnte beendet sein), der Stream-Wert bleibt gespeichert
+    let mut report = ApplyReport::default();
     for pid in pids_to_update {
-        let _ = apply_volume_to_pid(pid, vol); // Ignoriere Fehler (App könnte beendet sein)
+        let result = match apply_volume_to_pid(&worker, pid, vol) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("No audio session found for PID {}", pid)),
+            Err(e) => Err(e),
+        };
+        report.record(pid, result);
     }
     
     save_state_snapshot(&state);
-    true
+    report
+}
+
+// Offline harness for the noise suppression:
+//   audio-mixer --denoise <file.wav | fixture dir> [strength 0..1]
+fn run_denoise_harness(args: &[String]) -> i32 {
+    let Some(path) = args.first() else {
+        eprintln!("usage: audio-mixer --denoise <file.wav | dir> [strength]");
+        return 2;
+    };
+    let strength = args.get(1).and_then(|s| s.parse::<f32>().ok()).unwrap_or(0.6);
+    match engine::denoise::run_harness(std::path::Path::new(path), strength) {
+        Ok(reports) => {
+            for r in &reports {
+                println!(
+                    "{} -> {}: noise floor {:.1} dB -> {:.1} dB",
+                    r.input, r.output, r.noise_floor_before_db, r.noise_floor_after_db
+                );
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+// Offline check of the sample rate conversion (sine sweeps) and drift compensation
+// (simulated clocks); exits non-zero if a check fails:
+//   audio-mixer --resample-test [low|medium|high]
+fn run_resample_harness(args: &[String]) -> i32 {
+    let quality = match args.first().map(|q| serde_json::from_value(serde_json::Value::String(q.clone()))) {
+        None => ResamplerQuality::default(),
+        Some(Ok(q)) => q,
+        Some(Err(_)) => {
+            eprintln!("usage: audio-mixer --resample-test [low|medium|high]");
+            return 2;
+        }
+    };
+    let (sweeps, drifts) = engine::resample::run_harness(quality);
+    let mut passed = true;
+    for r in &sweeps {
+        println!(
+            "sweep {} -> {} Hz: passband {:.0} Hz, ripple {:.3} dB, aliasing {:.1} dB {}",
+            r.from,
+            r.to,
+            r.passband_hz,
+            r.ripple_db,
+            r.alias_db,
+            if r.passed { "ok" } else { "FAILED" }
+        );
+        passed &= r.passed;
+    }
+    for r in &drifts {
+        println!(
+            "drift {} -> {} Hz at {:+.0} ppm for {:.0} s: correction {:+.1} ppm, latency {:.1} ms, {} underruns, {} dropped {}",
+            r.from,
+            r.to,
+            r.drift_ppm,
+            r.seconds,
+            r.correction_ppm,
+            r.latency_ms,
+            r.underruns,
+            r.dropped,
+            if r.passed { "ok" } else { "FAILED" }
+        );
+        passed &= r.passed;
+    }
+    if passed { 0 } else { 1 }
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("--denoise") => std::process::exit(run_denoise_harness(&args[1..])),
+        Some("--resample-test") => std::process::exit(run_resample_harness(&args[1..])),
+        _ => {}
+    }
+
+    logging::init();
+    log::info!("Audio Mixer {} starting", env!("CARGO_PKG_VERSION"));
+
     tauri::Builder::default()
         .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(
+            tauri_plugin_global_shortcut::Builder::new()
+                .with_handler(|app, shortcut, event| {
+                    if event.state() != ShortcutState::Pressed {
+                        return;
+                    }
+                    // Off the event loop; saving the replay takes a moment and the engine
+                    // lock can be held by a restart
+                    let app = app.clone();
+                    let shortcut = *shortcut;
+                    std::thread::spawn(move || on_hotkey(&app, shortcut));
+                })
+                .build(),
+        )
         .setup(|app| {
             #[cfg(debug_assertions)]
             if let Some(win) = app.get_webview_window("main") {
                 let _ = win.open_devtools();
             }
+            let state = app.state::<std::sync::Mutex<MixerState>>();
+            let slot = app.state::<EngineSlot>();
+            let worker = app.state::<BackendWorker>();
+            if state.lock().unwrap().virtual_outputs {
+                if let Err(e) = sync_virtual_outputs(&state, &worker) {
+                    log::warn!("Creating virtual outputs failed: {}", e);
+                }
+            }
+            // Before the engine starts, which feeds it
+            if state.lock().unwrap().virtual_mic.enabled {
+                if let Err(e) = sync_virtual_mic(&state, &worker) {
+                    log::warn!("Creating virtual mic failed: {}", e);
+                }
+            }
+            if state.lock().unwrap().engine.enabled {
+                restart_engine(&state, &slot);
+            }
+            // Plugins request main-thread callbacks at any time, not just around plugin commands.
+            // A tick that finds the state busy is skipped; the request stays pending.
+            let handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(PLUGIN_IDLE_INTERVAL);
+                let app = handle.clone();
+                let _ = handle.run_on_main_thread(move || {
+                    if let Ok(s) = app.state::<std::sync::Mutex<MixerState>>().try_lock() {
+                        s.plugin_host.idle();
+                    }
+                });
+            });
+            {
+                let s = state.lock().unwrap();
+                if let Err(e) = register_hotkeys(app.handle(), &hotkey_actions(&s.replay_settings, &s.soundboard)) {
+                    log::warn!("{}", e);
+                }
+            }
             Ok(())
         })
         .manage(std::sync::Mutex::new(load_state()))
+        .manage(backend::spawn_default_worker())
+        .manage(EngineSlot::default())
         .invoke_handler(tauri::generate_handler![
             list_audio_devices,
             get_routes,
             set_route,
+            get_stream_mixes,
+            set_stream_muted,
+            set_broadcast_level,
+            get_broadcast_settings,
+            set_broadcast_settings,
+            get_stream_extra_outputs,
+            set_stream_extra_outputs,
+            get_output_delays,
+            set_output_delay,
+            measure_output_latency,
+            get_record_settings,
+            set_record_settings,
+            start_recording,
+            stop_recording,
+            get_recording_status,
+            get_replay_settings,
+            get_virtual_outputs,
+            set_virtual_outputs_enabled,
+            get_virtual_mic,
+            set_virtual_mic,
+            set_replay_settings,
+            save_replay,
+            get_soundboard,
+            set_soundboard,
+            play_soundboard_slot,
+            stop_soundboard,
             set_stream_volume,
             list_audio_apps,
             get_app_categories,
             set_app_category,
             clear_app_category,
-            set_app_volume
+            set_app_volume,
+            get_engine_status,
+            set_engine_enabled,
+            set_stream_input,
+            get_resampler_quality,
+            set_resampler_quality,
+            get_meters,
+            engine_self_test,
+            get_stream_eq,
+            set_stream_eq,
+            get_eq_response,
+            get_stream_dynamics,
+            set_stream_dynamics,
+            get_master_dynamics,
+            set_master_dynamics,
+            get_stream_auto_gain,
+            set_stream_auto_gain,
+            get_stream_matrix,
+            set_stream_matrix,
+            get_standard_matrix,
+            get_aux_buses,
+            add_aux_bus,
+            remove_aux_bus,
+            set_aux_bus,
+            set_stream_send,
+            plugins::list_plugins,
+            get_bus_plugins,
+            add_bus_plugin,
+            remove_bus_plugin,
+            move_bus_plugin,
+            set_plugin_bypassed,
+            get_plugin_params,
+            set_plugin_param,
+            get_mic_settings,
+            set_mic_settings,
+            set_processed_mic_output,
+            get_spatial_settings,
+            set_spatial_settings,
+            hrtf::list_hrtf_sets,
+            hrtf::import_hrtf_set,
+            hrtf::delete_hrtf_set,
+            presets::list_eq_presets,
+            presets::save_eq_preset,
+            presets::delete_eq_preset,
+            presets::import_eq_preset,
+            logging::get_recent_logs,
+            logging::get_log_file_path
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Close the engine so the take's files get everything, then finish them
+                let take = app.state::<std::sync::Mutex<MixerState>>().lock().unwrap().recording.take();
+                if let Some(take) = take {
+                    *app.state::<EngineSlot>().lock().unwrap() = None;
+                    if let Err(e) = take.finish() {
+                        log::error!("Recording: {}", e);
+                    }
+                }
+                // Virtual devices live in the sound server and would outlast the app
+                let ids: Vec<String> = StreamId::ALL.iter().map(virtual_output_id).collect();
+                let _ = app.state::<BackendWorker>().call(move |b| {
+                    for id in &ids {
+                        let _ = b.remove_virtual_output(id);
+                    }
+                    let _ = b.remove_virtual_input(VIRTUAL_MIC_ID);
+                });
+            }
+        });
 }
@@ -0,0 +1,318 @@
+// Third-party CLAP effects on the stream and aux buses. The chain per bus (plugin,
+// bypass, saved state) is part of the persisted state; instances are created on first use
+// and activated at the engine rate whenever the engine starts.
+use base64::Engine as _;
+use rtrb::{Producer, RingBuffer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::engine::clap::{ParamChange, ParamInfo, PluginInfo};
+use crate::engine::{BusId, ClapInstance, ClapProcessor, EngineCommand, LiveEngine};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginSlot {
+    pub path: String,
+    pub id: String,
+    #[serde(default)]
+    pub bypassed: bool,
+    // Plugin state (CLAP state extension), base64
+    #[serde(default)]
+    pub state: Option<String>,
+    // Values set from the UI, applied on top of the state (plugins without state support)
+    #[serde(default)]
+    pub params: BTreeMap<u32, f64>,
+}
+
+// A slot as the UI shows it
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginSlotInfo {
+    pub path: String,
+    pub id: String,
+    pub name: String,
+    pub bypassed: bool,
+    // Why the plugin isn't running, e.g. the file is gone
+    pub error: Option<String>,
+}
+
+// Parameter changes that may queue up between two audio blocks
+const PARAM_QUEUE: usize = 256;
+
+struct Loaded {
+    instance: Arc<ClapInstance>,
+    // Feeds the processor while the engine runs
+    changes: Option<Producer<ParamChange>>,
+}
+
+#[derive(Default)]
+pub struct PluginHost {
+    // Parallel to the slots of each bus; errors for plugins that didn't load
+    loaded: HashMap<BusId, Vec<Result<Loaded, String>>>,
+    // Removed instances, destroyed once the audio thread has let go of them
+    retired: Vec<Arc<ClapInstance>>,
+}
+
+// Plugin state as stored in `PluginSlot::state`
+fn encode_state(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+fn decode_state(name: &str, state: &str) -> Result<Vec<u8>, String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(state)
+        .map_err(|e| format!("Saved state of '{}' is damaged: {e}", name))
+}
+
+fn instantiate(slot: &PluginSlot) -> Result<Loaded, String> {
+    let instance = ClapInstance::new(Path::new(&slot.path), &slot.id)?;
+    if let Some(state) = &slot.state {
+        instance.load_state(&decode_state(instance.name(), state)?)?;
+    }
+    let changes: Vec<ParamChange> = slot.params.iter().map(|(&id, &value)| ParamChange { id, value }).collect();
+    instance.flush_params(&changes);
+    instance.set_bypassed(slot.bypassed);
+    Ok(Loaded { instance: Arc::new(instance), changes: None })
+}
+
+fn check_index<T>(items: &[T], index: usize) -> Result<(), String> {
+    if index >= items.len() {
+        return Err(format!("No plugin at position {}", index));
+    }
+    Ok(())
+}
+
+impl PluginHost {
+    fn ensure(&mut self, bus: &BusId, slots: &[PluginSlot]) -> &mut Vec<Result<Loaded, String>> {
+        self.loaded.entry(bus.clone()).or_insert_with(|| {
+            slots
+                .iter()
+                .map(|slot| {
+                    instantiate(slot).inspect_err(|e| log::warn!("Plugin {} on {:?} not loaded: {}", slot.id, bus, e))
+                })
+                .collect()
+        })
+    }
+
+    fn loaded(&mut self, bus: &BusId, slots: &[PluginSlot], index: usize) -> Result<&mut Loaded, String> {
+        let loaded = self.ensure(bus, slots);
+        check_index(loaded, index)?;
+        loaded[index].as_mut().map_err(|e| e.clone())
+    }
+
+    // Destroy retired instances the engine no longer holds
+    fn collect(&mut self) {
+        self.retired.retain(|i| Arc::strong_count(i) > 1);
+        self.idle();
+    }
+
+    // Main-thread callbacks the plugins asked for; called regularly from the main thread
+    pub fn idle(&self) {
+        for loaded in self.loaded.values().flatten().flatten() {
+            loaded.instance.idle();
+        }
+    }
+
+    // Activate the bus's instances at the engine rate and hand the engine a fresh
+    // processor chain
+    fn send(&mut self, bus: &BusId, slots: &[PluginSlot], engine: &LiveEngine) {
+        let rate = engine.status().sample_rate;
+        let mut processors = Vec::new();
+        for loaded in self.ensure(bus, slots).iter_mut().flatten() {
+            if let Err(e) = loaded.instance.activate(rate) {
+                log::warn!("Plugin on {:?} skipped: {}", bus, e);
+                continue;
+            }
+            let (producer, consumer) = RingBuffer::new(PARAM_QUEUE);
+            loaded.changes = Some(producer);
+            processors.push(ClapProcessor::new(loaded.instance.clone(), consumer));
+        }
+        engine.send(EngineCommand::SetPlugins(bus.clone(), processors));
+    }
+
+    // After the engine started
+    pub fn attach(&mut self, slots: &HashMap<BusId, Vec<PluginSlot>>, engine: &LiveEngine) {
+        for (bus, slots) in slots {
+            if !slots.is_empty() {
+                self.send(bus, slots, engine);
+            }
+        }
+    }
+
+    // After the engine stopped, when no processor is left
+    pub fn detach(&mut self) {
+        for loaded in self.loaded.values_mut().flatten().flatten() {
+            loaded.changes = None;
+            loaded.instance.deactivate();
+        }
+        self.collect();
+    }
+
+    // Drop the instances of a bus that was deleted
+    pub fn forget(&mut self, bus: &BusId) {
+        let removed = self.loaded.remove(bus).unwrap_or_default();
+        self.retired.extend(removed.into_iter().flatten().map(|l| l.instance));
+        self.collect();
+    }
+
+    pub fn infos(&mut self, bus: &BusId, slots: &[PluginSlot]) -> Vec<PluginSlotInfo> {
+        let loaded = self.ensure(bus, slots);
+        slots
+            .iter()
+            .zip(loaded.iter())
+            .map(|(slot, loaded)| PluginSlotInfo {
+                path: slot.path.clone(),
+                id: slot.id.clone(),
+                name: loaded.as_ref().map(|l| l.instance.name().to_string()).unwrap_or_else(|_| slot.id.clone()),
+                bypassed: slot.bypassed,
+                error: loaded.as_ref().err().cloned(),
+            })
+            .collect()
+    }
+
+    pub fn add(
+        &mut self,
+        bus: &BusId,
+        slots: &mut Vec<PluginSlot>,
+        slot: PluginSlot,
+        engine: Option<&LiveEngine>,
+    ) -> Result<(), String> {
+        self.collect();
+        self.ensure(bus, slots);
+        let loaded = instantiate(&slot)?;
+        log::info!("Added plugin '{}' to {:?}", loaded.instance.name(), bus);
+        self.ensure(bus, slots).push(Ok(loaded));
+        slots.push(slot);
+        if let Some(engine) = engine {
+            self.send(bus, slots, engine);
+        }
+        Ok(())
+    }
+
+    pub fn remove(
+        &mut self,
+        bus: &BusId,
+        slots: &mut Vec<PluginSlot>,
+        index: usize,
+        engine: Option<&LiveEngine>,
+    ) -> Result<(), String> {
+        self.collect();
+        check_index(slots, index)?;
+        let loaded = self.ensure(bus, slots);
+        if let Ok(l) = loaded.remove(index) {
+            self.retired.push(l.instance);
+        }
+        slots.remove(index);
+        if let Some(engine) = engine {
+            self.send(bus, slots, engine);
+        }
+        Ok(())
+    }
+
+    pub fn reorder(
+        &mut self,
+        bus: &BusId,
+        slots: &mut Vec<PluginSlot>,
+        from: usize,
+        to: usize,
+        engine: Option<&LiveEngine>,
+    ) -> Result<(), String> {
+        check_index(slots, from)?;
+        check_index(slots, to)?;
+        let loaded = self.ensure(bus, slots);
+        let l = loaded.remove(from);
+        loaded.insert(to, l);
+        let slot = slots.remove(from);
+        slots.insert(to, slot);
+        if let Some(engine) = engine {
+            self.send(bus, slots, engine);
+        }
+        Ok(())
+    }
+
+    pub fn set_bypassed(&mut self, bus: &BusId, slots: &mut [PluginSlot], index: usize, bypassed: bool) -> Result<(), String> {
+        check_index(slots, index)?;
+        if let Ok(l) = self.loaded(bus, slots, index) {
+            l.instance.set_bypassed(bypassed);
+        }
+        slots[index].bypassed = bypassed;
+        Ok(())
+    }
+
+    pub fn params(&mut self, bus: &BusId, slots: &[PluginSlot], index: usize) -> Result<Vec<ParamInfo>, String> {
+        self.collect();
+        Ok(self.loaded(bus, slots, index)?.instance.params())
+    }
+
+    pub fn set_param(&mut self, bus: &BusId, slots: &mut [PluginSlot], index: usize, change: ParamChange) -> Result<(), String> {
+        let loaded = self.loaded(bus, slots, index)?;
+        if !change.value.is_finite() {
+            return Err(format!("Invalid value {} for parameter {}", change.value, change.id));
+        }
+        match loaded.changes.as_mut() {
+            Some(queue) if loaded.instance.is_active() => {
+                queue.push(change).map_err(|_| "Too many parameter changes queued".to_string())?
+            }
+            _ => loaded.instance.flush_params(&[change]),
+        }
+        slots[index].params.insert(change.id, change.value);
+        Ok(())
+    }
+
+    // Copy the current plugin states into the slots before they are saved
+    pub fn refresh_states(&self, slots: &mut HashMap<BusId, Vec<PluginSlot>>) {
+        for (bus, loaded) in &self.loaded {
+            let Some(slots) = slots.get_mut(bus) else { continue };
+            for (slot, loaded) in slots.iter_mut().zip(loaded) {
+                let Ok(loaded) = loaded else { continue };
+                match loaded.instance.save_state() {
+                    Ok(Some(data)) => slot.state = Some(encode_state(&data)),
+                    Ok(None) => {}
+                    Err(e) => log::warn!("{}", e),
+                }
+            }
+        }
+    }
+}
+
+// Plugins found in the CLAP search paths
+#[tauri::command]
+pub fn list_plugins() -> Vec<PluginInfo> {
+    crate::engine::clap::scan()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_state_survives_a_save() {
+        // Arbitrary binary, as plugins write it
+        let data: Vec<u8> = (0..=255u8).chain([0, 0, 255, b'=', b'\n']).collect();
+        let slot = PluginSlot {
+            path: "/usr/lib/clap/reverb.clap".into(),
+            id: "com.example.reverb".into(),
+            bypassed: true,
+            state: Some(encode_state(&data)),
+            params: BTreeMap::from([(3, 0.25)]),
+        };
+        let saved = serde_json::to_string(&slot).unwrap();
+        let loaded: PluginSlot = serde_json::from_str(&saved).unwrap();
+        assert_eq!(decode_state("Reverb", loaded.state.as_deref().unwrap()).unwrap(), data);
+        assert_eq!(loaded.params, slot.params);
+        assert!(loaded.bypassed);
+        assert_eq!(decode_state("Reverb", &encode_state(&[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn damaged_state_names_the_plugin() {
+        let err = decode_state("Reverb", "not base64!").unwrap_err();
+        assert!(err.contains("'Reverb'"), "{}", err);
+    }
+
+    #[test]
+    fn older_slots_load_without_state() {
+        let slot: PluginSlot = serde_json::from_str(r#"{"path":"a.clap","id":"x"}"#).unwrap();
+        assert!(slot.state.is_none() && slot.params.is_empty() && !slot.bypassed);
+    }
+}
@@ -0,0 +1,292 @@
+// EQ presets: a few built-in curves plus user presets stored in
+// <config dir>/eq-presets.json. User presets can also be imported from Equalizer APO /
+// AutoEQ `ParametricEQ.txt` files.
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::engine::eq::{EqBand, EqBandType, EqSettings};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EqPreset {
+    pub name: String,
+    #[serde(flatten)]
+    pub eq: EqSettings,
+    // Built-in presets can't be overwritten or deleted
+    #[serde(default, skip_deserializing)]
+    pub builtin: bool,
+}
+
+fn band(kind: EqBandType, frequency: f32, gain_db: f32, q: f32) -> EqBand {
+    EqBand { kind, frequency, gain_db, q, enabled: true }
+}
+
+fn builtin_presets() -> Vec<EqPreset> {
+    use EqBandType::*;
+    let preset = |name: &str, preamp_db: f32, bands: Vec<EqBand>| EqPreset {
+        name: name.into(),
+        eq: EqSettings { preamp_db, bands },
+        builtin: true,
+    };
+    vec![
+        preset("Flat", 0.0, Vec::new()),
+        // Pull down rumble and explosions, lift the 2-5 kHz range where steps sit
+        preset(
+            "Footsteps",
+            -3.0,
+            vec![
+                band(LowShelf, 200.0, -6.0, 0.7),
+                band(Peaking, 3000.0, 5.0, 1.0),
+                band(HighShelf, 9000.0, -2.0, 0.7),
+            ],
+        ),
+        // Remove rumble and boxiness, add presence
+        preset(
+            "Voice clarity",
+            -2.0,
+            vec![
+                band(HighPass, 80.0, 0.0, 0.7),
+                band(Peaking, 300.0, -3.0, 1.0),
+                band(Peaking, 3000.0, 4.0, 1.2),
+                band(HighShelf, 10000.0, 2.0, 0.7),
+            ],
+        ),
+        preset("Bass boost", -6.0, vec![band(LowShelf, 100.0, 6.0, 0.7), band(HighPass, 25.0, 0.0, 0.7)]),
+    ]
+}
+
+fn presets_file_path() -> PathBuf {
+    crate::config_dir().join("eq-presets.json")
+}
+
+fn load_user_presets() -> Vec<EqPreset> {
+    let path = presets_file_path();
+    let Ok(data) = std::fs::read(&path) else { return Vec::new() };
+    match serde_json::from_slice(&data) {
+        Ok(presets) => presets,
+        Err(e) => {
+            log::warn!("Ignoring unreadable preset file {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+fn save_user_presets(presets: &[EqPreset]) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(presets).map_err(|e| e.to_string())?;
+    std::fs::write(presets_file_path(), json).map_err(|e| format!("Saving presets failed: {e}"))
+}
+
+fn all_presets() -> Vec<EqPreset> {
+    let mut presets = builtin_presets();
+    presets.extend(load_user_presets());
+    presets
+}
+
+fn store_preset(name: String, eq: EqSettings) -> Result<EqPreset, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Preset name is empty".into());
+    }
+    if builtin_presets().iter().any(|p| p.name.eq_ignore_ascii_case(&name)) {
+        return Err(format!("'{}' is a built-in preset", name));
+    }
+    eq.validate()?;
+    let preset = EqPreset { name, eq, builtin: false };
+    let mut presets = load_user_presets();
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(existing) => *existing = preset.clone(),
+        None => presets.push(preset.clone()),
+    }
+    save_user_presets(&presets)?;
+    Ok(preset)
+}
+
+// Map an Equalizer APO filter type to a band. Shelves given without Q use the
+// Equalizer APO default slope, which matches Q 0.707.
+fn apo_filter_kind(kind: &str) -> Option<EqBandType> {
+    Some(match kind {
+        "PK" | "PEQ" | "Modal" => EqBandType::Peaking,
+        "LS" | "LSC" | "LSQ" => EqBandType::LowShelf,
+        "HS" | "HSC" | "HSQ" => EqBandType::HighShelf,
+        "LP" | "LPQ" => EqBandType::LowPass,
+        "HP" | "HPQ" => EqBandType::HighPass,
+        "NO" => EqBandType::Notch,
+        _ => return None,
+    })
+}
+
+// Bandwidth in octaves to Q
+fn q_from_bandwidth(octaves: f32) -> f32 {
+    let p = 2f32.powf(octaves);
+    p.sqrt() / (p - 1.0)
+}
+
+// Parse the Equalizer APO config subset written by AutoEQ:
+//   Preamp: -6.2 dB
+//   Filter 1: ON PK Fc 105 Hz Gain -3.1 dB Q 0.70
+// Unknown commands are skipped; unsupported filter types are an error so a curve is
+// never imported half.
+pub fn parse_parametric_eq(text: &str) -> Result<EqSettings, String> {
+    let mut eq = EqSettings::default();
+    for (no, raw) in text.lines().enumerate() {
+        let line = raw.split('#').next().unwrap_or("").trim();
+        let Some((command, rest)) = line.split_once(':') else { continue };
+        let command = command.trim();
+        let tokens: Vec<&str> = rest.split_whitespace().collect();
+        let number = |i: usize| -> Result<f32, String> {
+            tokens
+                .get(i)
+                .and_then(|t| t.replace(',', ".").parse::<f32>().ok())
+                .ok_or_else(|| format!("Line {}: expected a number in '{}'", no + 1, line))
+        };
+
+        if command.eq_ignore_ascii_case("Preamp") {
+            // Several Preamp lines add up in Equalizer APO
+            eq.preamp_db += number(0)?;
+        } else if command.starts_with("Filter") {
+            let enabled = match tokens.first() {
+                Some(t) if t.eq_ignore_ascii_case("ON") => true,
+                Some(t) if t.eq_ignore_ascii_case("OFF") => false,
+                _ => return Err(format!("Line {}: expected ON/OFF in '{}'", no + 1, line)),
+            };
+            let type_name = tokens.get(1).copied().unwrap_or("");
+            // "LS 6dB"/"LS 12dB" shelves carry the slope as an extra token; "None" is a placeholder
+            if type_name == "None" {
+                continue;
+            }
+            let kind = apo_filter_kind(type_name)
+                .ok_or_else(|| format!("Line {}: unsupported filter type '{}'", no + 1, type_name))?;
+
+            let mut frequency = None;
+            let mut gain_db = 0.0;
+            let mut q = None;
+            let mut i = 2;
+            while i < tokens.len() {
+                match tokens[i] {
+                    "Fc" => frequency = Some(number(i + 1)?),
+                    "Gain" => gain_db = number(i + 1)?,
+                    "Q" => q = Some(number(i + 1)?),
+                    "BW" => {
+                        // "BW Oct 1.5"
+                        q = Some(q_from_bandwidth(number(i + 2)?));
+                        i += 1;
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            let frequency = frequency.ok_or_else(|| format!("Line {}: filter without Fc", no + 1))?;
+            eq.bands.push(EqBand { kind, frequency, gain_db, q: q.unwrap_or(0.707), enabled });
+        }
+    }
+    if eq.bands.is_empty() && eq.preamp_db == 0.0 {
+        return Err("No Preamp or Filter lines found".into());
+    }
+    eq.validate()?;
+    Ok(eq)
+}
+
+pub fn import_file(path: &Path, name: Option<String>) -> Result<EqPreset, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Reading {} failed: {e}", path.display()))?;
+    let eq = parse_parametric_eq(&text)?;
+    // AutoEQ names its files "<Headphone> ParametricEQ.txt"
+    let name = name.unwrap_or_else(|| {
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("Imported");
+        stem.trim_end_matches("ParametricEQ").trim().to_string()
+    });
+    let preset = store_preset(name, eq)?;
+    log::info!("Imported EQ preset '{}' from {} ({} bands)", preset.name, path.display(), preset.eq.bands.len());
+    Ok(preset)
+}
+
+#[tauri::command]
+pub fn list_eq_presets() -> Vec<EqPreset> {
+    all_presets()
+}
+
+#[tauri::command]
+pub fn save_eq_preset(name: String, eq: EqSettings) -> Result<EqPreset, String> {
+    store_preset(name, eq)
+}
+
+#[tauri::command]
+pub fn delete_eq_preset(name: String) -> Result<bool, String> {
+    let mut presets = load_user_presets();
+    let before = presets.len();
+    presets.retain(|p| p.name != name);
+    if presets.len() == before {
+        return Ok(false);
+    }
+    save_user_presets(&presets)?;
+    Ok(true)
+}
+
+#[tauri::command]
+pub fn import_eq_preset(path: String, name: Option<String>) -> Result<EqPreset, String> {
+    import_file(Path::new(&path), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As exported by AutoEQ (CRLF line ends included)
+    const AUTOEQ: &str = include_str!("../tests/fixtures/HD 600 ParametricEQ.txt");
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn parses_autoeq_file() {
+        let eq = parse_parametric_eq(AUTOEQ).unwrap();
+        assert!(close(eq.preamp_db, -6.6));
+        assert_eq!(eq.bands.len(), 10);
+        assert_eq!(eq.bands[0], band(EqBandType::LowShelf, 105.0, 6.5, 0.70));
+        assert_eq!(eq.bands[4], band(EqBandType::Peaking, 5439.0, 4.6, 3.09));
+        assert_eq!(eq.bands[9], band(EqBandType::HighShelf, 10000.0, -3.1, 0.70));
+        assert!(eq.bands[1..9].iter().all(|b| b.kind == EqBandType::Peaking && b.enabled));
+    }
+
+    #[test]
+    fn parses_equalizer_apo_variants() {
+        let text = "\
+# Exported by hand
+Device: Speakers
+Preamp: -3 dB
+Preamp: -1,5 dB   # adds up
+Filter 1: ON PK Fc 1000 Hz Gain 3 dB BW Oct 1.0
+Filter 2: OFF PK Fc 2000 Hz Gain -4 dB Q 2
+Filter 3: ON HS Fc 8000 Hz Gain 2 dB
+Filter 4: ON None
+Filter: ON HP Fc 30 Hz
+";
+        let eq = parse_parametric_eq(text).unwrap();
+        assert!(close(eq.preamp_db, -4.5));
+        assert_eq!(eq.bands.len(), 4);
+        // One octave is Q sqrt(2)
+        assert_eq!(eq.bands[0].kind, EqBandType::Peaking);
+        assert!(close(eq.bands[0].q, std::f32::consts::SQRT_2), "{}", eq.bands[0].q);
+        assert!(!eq.bands[1].enabled);
+        assert!(close(eq.bands[1].gain_db, -4.0));
+        // Shelves without Q get the Equalizer APO default slope
+        assert_eq!(eq.bands[2], band(EqBandType::HighShelf, 8000.0, 2.0, 0.707));
+        assert_eq!(eq.bands[3].kind, EqBandType::HighPass);
+        assert!(close(eq.bands[3].frequency, 30.0));
+    }
+
+    #[test]
+    fn rejects_unsupported_and_broken_filters() {
+        let err = parse_parametric_eq("Preamp: -2 dB\nFilter 1: ON BP Fc 1000 Hz Q 1").unwrap_err();
+        assert!(err.contains("Line 2") && err.contains("unsupported filter type 'BP'"), "{err}");
+        let err = parse_parametric_eq("Filter 1: ON PK Gain 3 dB Q 1").unwrap_err();
+        assert!(err.contains("without Fc"), "{err}");
+        let err = parse_parametric_eq("Filter 1: MAYBE PK Fc 100 Hz").unwrap_err();
+        assert!(err.contains("ON/OFF"), "{err}");
+        let err = parse_parametric_eq("Filter 1: ON PK Fc loud").unwrap_err();
+        assert!(err.contains("expected a number"), "{err}");
+        // Parses, but the band is outside what the EQ supports
+        let err = parse_parametric_eq("Filter 1: ON PK Fc 1000 Hz Gain 40 dB Q 1").unwrap_err();
+        assert!(err.contains("Band 1"), "{err}");
+        assert!(parse_parametric_eq("# nothing here\nDevice: all").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+# Writes the two minimal SOFA (HDF5) files used by the hdf5.rs tests, laid out
+# by hand from the HDF5 file format spec so no HDF5 library is needed:
+#   tiny-v0.sofa  superblock 0, symbol-table group, chunked Data.IR (shuffle +
+#                 deflate, float32), version 1 attribute
+#   tiny-v2.sofa  superblock 2, dense links (fractal heap + v2 B-tree),
+#                 contiguous/compact float64 data, version 3 attribute
+# Both hold Data.IR (4 x 2 x 8, value (m*100 + r*10 + n) / 1000),
+# Data.SamplingRate and SourcePosition with a Type attribute.
+import os
+import struct, zlib, sys
+UND = b'\xff'*8
+def pad8(b): return b + b'\0'*((-len(b))%8)
+M, N = 4, 8
+AZ = [0.0, 90.0, 180.0, 270.0]
+ir = [[[ (m*100 + e*10 + n) * 0.001 for n in range(N)] for e in range(2)] for m in range(M)]
+ir_flat = [v for m in ir for e in m for v in e]
+def f64_type():
+    return struct.pack('<BBBBI', 0x11, 0x20, 0x3f, 0, 8) + struct.pack('<HHBBBBI', 0, 64, 52, 11, 0, 52, 1023)
+def f32_type():
+    return struct.pack('<BBBBI', 0x11, 0x20, 0x1f, 0, 4) + struct.pack('<HHBBBBI', 0, 32, 23, 8, 0, 23, 127)
+def str_type(n): return struct.pack('<BBBBI', 0x13, 0, 0, 0, n)
+
+class F:
+    def __init__(s, start): s.b = bytearray(start)
+    def alloc(s, data):
+        while len(s.b) % 8: s.b.append(0)
+        a = len(s.b); s.b += data; return a
+    def reserve(s, n): return s.alloc(b'\0'*n)
+    def put(s, a, data): s.b[a:a+len(data)] = data
+
+def v1_file(path):
+    f = F(0)
+    sb = f.reserve(24 + 32 + 40)  # superblock v0
+    def space(dims): return struct.pack('<BBB5x', 1, len(dims), 0) + b''.join(struct.pack('<Q', d) for d in dims)
+    def ohdr(msgs):
+        body = b''.join(struct.pack('<HHB3x', t, len(pad8(d)), 0) + pad8(d) for t, d in msgs)
+        return struct.pack('<BBHII', 1, 0, len(msgs), 1, len(body)) + b'\0'*4 + body
+    # Data.IR: chunked (2 measurements per chunk), shuffle + deflate, float32
+    raw_chunks = []
+    for c0 in range(0, M, 2):
+        vals = ir_flat[c0*2*N:(c0+2)*2*N]
+        data = struct.pack('<%df' % len(vals), *vals)
+        n = len(vals)
+        sh = bytes(data[i*4+b] for b in range(4) for i in range(n))
+        raw_chunks.append((c0, zlib.compress(sh)))
+    chunk_addrs = [(c0, f.alloc(d), len(d)) for c0, d in raw_chunks]
+    tree = b'TREE' + struct.pack('<BBH', 1, 0, len(chunk_addrs)) + UND + UND
+    for c0, a, n in chunk_addrs:
+        tree += struct.pack('<II', n, 0) + struct.pack('<4Q', c0, 0, 0, 0) + struct.pack('<Q', a)
+    tree += struct.pack('<II', 0, 0) + struct.pack('<4Q', M, 0, 0, 0)
+    tree_addr = f.alloc(tree)
+    layout = struct.pack('<BBB', 3, 2, 4) + struct.pack('<Q', tree_addr) + struct.pack('<4I', 2, 2, N, 4)
+    filt = struct.pack('<BB6x', 1, 2) + struct.pack('<HHHH', 2, 0, 1, 1) + struct.pack('<I', 4) + b'\0'*4 \
+         + struct.pack('<HHHH', 1, 0, 1, 1) + struct.pack('<I', 6) + b'\0'*4
+    ir_obj = f.alloc(ohdr([(1, space([M, 2, N])), (3, f32_type()), (0x0B, filt), (8, layout)]))
+    # SamplingRate contiguous
+    sr = f.alloc(struct.pack('<d', 44100.0))
+    sr_obj = f.alloc(ohdr([(1, space([1])), (3, f64_type()), (8, struct.pack('<BB', 3, 1) + struct.pack('<QQ', sr, 8))]))
+    # SourcePosition contiguous + Type attribute
+    pos = [v for az in AZ for v in (az, 0.0, 1.2)]
+    pa = f.alloc(struct.pack('<%dd' % len(pos), *pos))
+    attr_val = b'spherical\0'
+    attr = struct.pack('<BBHHH', 1, 0, 5, 8, 8) + pad8(b'Type\0') + pad8(str_type(len(attr_val))) + pad8(struct.pack('<BBB5x', 1, 0, 0)) + attr_val
+    pos_obj = f.alloc(ohdr([(1, space([M, 3])), (3, f64_type()), (8, struct.pack('<BB', 3, 1) + struct.pack('<QQ', pa, len(pos)*8)), (0x0C, attr)]))
+    # root group: local heap + SNOD + TREE
+    names = [('Data.IR', ir_obj), ('Data.SamplingRate', sr_obj), ('SourcePosition', pos_obj)]
+    heapdata = bytearray(b'\0'*8); offs = []
+    for n_, _ in names:
+        offs.append(len(heapdata)); heapdata += pad8(n_.encode() + b'\0')
+    hd = f.alloc(bytes(heapdata))
+    heap = f.alloc(b'HEAP' + struct.pack('<B3x', 0) + struct.pack('<QQQ', len(heapdata), 0xffffffffffffffff, hd))
+    snod = b'SNOD' + struct.pack('<BBH', 1, 0, len(names))
+    for (n_, a), o in zip(names, offs):
+        snod += struct.pack('<QQII', o, a, 0, 0) + b'\0'*16
+    snod_addr = f.alloc(snod)
+    gt = f.alloc(b'TREE' + struct.pack('<BBH', 0, 0, 1) + UND + UND + struct.pack('<QQQ', 0, snod_addr, offs[-1]))
+    root = f.alloc(ohdr([(0x11, struct.pack('<QQ', gt, heap))]))
+    sbd = b'\x89HDF\r\n\x1a\n' + bytes([0,0,0,0,0,8,8,0]) + struct.pack('<HHI', 4, 16, 0) \
+        + struct.pack('<QQQQ', 0, 0xffffffffffffffff, len(f.b), 0xffffffffffffffff) \
+        + struct.pack('<QQII', 0, root, 1, 0) + struct.pack('<QQ', gt, heap)
+    f.put(sb, sbd)
+    open(path, 'wb').write(f.b)
+
+def v2_file(path):
+    f = F(0)
+    sb = f.reserve(48)
+    def space(dims): return struct.pack('<BBBB', 2, len(dims), 0, 1) + b''.join(struct.pack('<Q', d) for d in dims)
+    def ohdr(msgs):
+        body = b''.join(struct.pack('<BHB', t, len(d), 0) + d for t, d in msgs)
+        return b'OHDR' + struct.pack('<BB', 2, 2) + struct.pack('<I', len(body)) + body + b'\0'*4
+    irdata = struct.pack('<%dd' % len(ir_flat), *ir_flat)
+    ia = f.alloc(irdata)
+    ir_obj = f.alloc(ohdr([(1, space([M, 2, N])), (3, f64_type()), (8, struct.pack('<BB', 3, 1) + struct.pack('<QQ', ia, len(irdata)))]))
+    sr_obj = f.alloc(ohdr([(1, space([1])), (3, f64_type()), (8, struct.pack('<BBH', 3, 0, 8) + struct.pack('<d', 48000.0))]))
+    # cartesian positions, attribute v3
+    import math
+    pos = [v for az in AZ for v in (math.cos(math.radians(az)), math.sin(math.radians(az)), 0.0)]
+    pa = f.alloc(struct.pack('<%dd' % len(pos), *pos))
+    val = b'cartesian'
+    attr = struct.pack('<BBHHHB', 3, 0, 5, 8, 4, 0) + b'Type\0' + str_type(len(val)) + struct.pack('<BBBB', 2, 0, 0, 0) + val
+    pos_obj = f.alloc(ohdr([(1, space([M, 3])), (3, f64_type()), (8, struct.pack('<BB', 3, 1) + struct.pack('<QQ', pa, len(pos)*8)), (0x0C, attr)]))
+    names = [('Data.IR', ir_obj), ('Data.SamplingRate', sr_obj), ('SourcePosition', pos_obj)]
+    # fractal heap with a root direct block
+    hdr_size = 4 + 1 + 8 + 4
+    objs = b''; ids = []
+    for n_, a in names:
+        msg = struct.pack('<BBB', 1, 0, len(n_)) + n_.encode() + struct.pack('<Q', a)
+        ids.append(struct.pack('<B', 0) + struct.pack('<I', hdr_size + len(objs)) + struct.pack('<H', len(msg)))
+        objs += msg
+    heap_hdr_addr = f.reserve(200)
+    db = f.alloc(b'FHDB' + b'\0' + struct.pack('<Q', heap_hdr_addr) + struct.pack('<I', 0) + objs + b'\0'*(512 - hdr_size - len(objs)))
+    hh = b'FRHP' + struct.pack('<BHHBI', 0, 7, 0, 0, 4096) + struct.pack('<Q', 0) + UND + struct.pack('<Q', 0) + UND \
+        + struct.pack('<8Q', 512, 512, 0, len(names), 0, 0, 0, 0) + struct.pack('<H', 4) + struct.pack('<QQ', 512, 65536) \
+        + struct.pack('<HH', 32, 0) + struct.pack('<Q', db) + struct.pack('<H', 0) + b'\0'*4
+    f.put(heap_hdr_addr, hh)
+    leaf = b'BTLF' + struct.pack('<BB', 0, 5) + b''.join(struct.pack('<I', i) + id_ for i, id_ in enumerate(ids)) + b'\0'*4
+    la = f.alloc(leaf)
+    bt = f.alloc(b'BTHD' + struct.pack('<BBIHHBB', 0, 5, 512, 11, 0, 100, 40) + struct.pack('<QHQ', la, len(names), len(names)) + b'\0'*4)
+    root = f.alloc(ohdr([(2, struct.pack('<BB', 0, 0) + struct.pack('<QQ', heap_hdr_addr, bt))]))
+    f.put(sb, b'\x89HDF\r\n\x1a\n' + struct.pack('<BBBB', 2, 8, 8, 0) + struct.pack('<QQQQ', 0, 0xffffffffffffffff, len(f.b), root) + b'\0'*4)
+    open(path, 'wb').write(f.b)
+
+here = os.path.dirname(os.path.abspath(__file__))
+v1_file(os.path.join(here, "tiny-v0.sofa"))
+v2_file(os.path.join(here, "tiny-v2.sofa"))
@@ -1,5 +1,5 @@
 import React, { useEffect, useMemo, useState, useCallback, useRef } from 'react'
-import { getDevices, setRoute, setStreamVolume, type DeviceInfo, type StreamId, getRoutes, listAudioApps, type AppSession, getAppCategories, setAppCategory, clearAppCategory, getAppIcon, setAppVolume } from './bridge'
+import { getDevices, setRoute, setStreamVolume, type DeviceInfo, type StreamId, getRoutes, listAudioApps, type AppSession, getAppCategories, setAppCategory, clearAppCategory, getAppIcon, setAppVolume, type ApplyReport } from './bridge'
 import { invoke } from '@tauri-apps/api/core'
 import { check as checkUpdate } from '@tauri-apps/plugin-updater'
 
@@ -68,6 +68,8 @@ export default function App() {
   const [volumes, setVolumes] = useState<Record<StreamId, number>>({ game: 0.8, voice: 0.8, music: 0.8 })
   const [customMixerVolumes, setCustomMixerVolumes] = useState<Record<string, number>>({})
   const [customMixerRoutes, setCustomMixerRoutes] = useState<Record<string, string | null>>({})
+  // Apps that did not follow the last route/volume/category change, with the reason
+  const [applyFailures, setApplyFailures] = useState<Record<number, string>>({})
   // Load icon cache from localStorage on startup
   const loadIconCache = (): Record<string, string> => {
     try {
@@ -886,10 +888,29 @@ export default function App() {
 
   // Note: Volume updates are now handled live in onVolume() function
 
+  // Clear the flag of apps a change reached and flag the ones it didn't
+  const noteApplyReport = (report: ApplyReport) => {
+    setApplyFailures(prev => {
+      const next = { ...prev }
+      report.updated.forEach(pid => { delete next[pid] })
+      report.failed.forEach(f => { next[f.pid] = f.error })
+      return next
+    })
+    report.failed.forEach(f => console.error(`App ${f.pid} not updated: ${f.error}`))
+  }
+
   const onRoute = async (stream: StreamId, deviceId: string | null) => {
-    const ok = await setRoute(stream, deviceId);
-    if (ok) {
+    try {
+      // The stream keeps its new route even if some of its apps could not be moved
+      const report = await setRoute(stream, deviceId);
       setRoutesState(prev => ({ ...prev, [stream]: deviceId }));
+      noteApplyReport(report);
+      if (report.device_error) {
+        alert(`The audio engine can't play ${stream} on this device: ${report.device_error}`);
+      }
+    } catch (error) {
+      console.error(`Error routing ${stream}:`, error);
+      alert(`Couldn't route ${stream}: ${error}`);
     }
   };
 
@@ -900,13 +921,9 @@ export default function App() {
       const clampedVolume = volume <= 0.001 ? 0.0 : Math.min(1.0, Math.max(0.0, volume))
       
       console.log(`Live setting volume for ${stream} to ${clampedVolume} (original: ${volume})`);
-      const ok = await setStreamVolume(stream, clampedVolume);
-      if (ok) {
-        setVolumes(prev => ({ ...prev, [stream]: clampedVolume }));
-        console.log(`Volume updated successfully for ${stream}`);
-      } else {
-        console.error(`Failed to update volume for ${stream}`);
-      }
+      const report = await setStreamVolume(stream, clampedVolume);
+      setVolumes(prev => ({ ...prev, [stream]: clampedVolume }));
+      noteApplyReport(report);
     } catch (error) {
       console.error(`Error updating volume for ${stream}:`, error);
     }
@@ -935,11 +952,9 @@ export default function App() {
     if (volume <= 0.001) {
       try {
         console.log(`Immediate silence setting for ${stream} to 0.0`);
-        const ok = await setStreamVolume(stream, 0.0);
-        if (ok) {
-          setVolumes(prev => ({ ...prev, [stream]: 0.0 }));
-          console.log(`Silence applied immediately for ${stream}`);
-        }
+        const report = await setStreamVolume(stream, 0.0);
+        setVolumes(prev => ({ ...prev, [stream]: 0.0 }));
+        noteApplyReport(report);
       } catch (error) {
         console.error(`Error applying immediate silence for ${stream}:`, error);
       }
@@ -982,9 +997,11 @@ export default function App() {
         if (ok) setAppCategoriesState(prev => { const n = { ...prev }; delete n[pid]; return n })
       } else if (validStreamIds.includes(value as StreamId)) {
         console.log('Setting app category for pid:', pid, 'to backend stream:', value)
-        const ok = await setAppCategory(pid, value as StreamId)
-        console.log('Set category result:', ok)
-        if (ok) setAppCategoriesState(prev => ({ ...prev, [pid]: value }))
+        const report = await setAppCategory(pid, value as StreamId)
+        console.log('Set category result:', report)
+        // The backend only keeps the category if the app could be routed to the stream
+        if (report.updated.includes(pid)) setAppCategoriesState(prev => ({ ...prev, [pid]: value }))
+        noteApplyReport(report)
       } else {
         console.log('Custom category detected - storing locally:', value)
         // Custom categories are stored locally only
@@ -1157,13 +1174,9 @@ export default function App() {
         const clampedVolume = volume <= 0.001 ? 0.0 : Math.min(1.0, Math.max(0.0, volume))
         
         console.log(`Live setting volume for ${streamId} to ${clampedVolume} (original: ${volume})`);
-        const ok = await setStreamVolume(streamId as StreamId, clampedVolume);
-        if (ok) {
-          setVolumes(prev => ({ ...prev, [streamId as StreamId]: clampedVolume }));
-          console.log(`Volume updated successfully for ${streamId}`);
-        } else {
-          console.error(`Failed to update volume for ${streamId}`);
-        }
+        const report = await setStreamVolume(streamId as StreamId, clampedVolume);
+        setVolumes(prev => ({ ...prev, [streamId as StreamId]: clampedVolume }));
+        noteApplyReport(report);
       } catch (error) {
         console.error(`Error updating volume for ${streamId}:`, error);
       }
@@ -1235,13 +1248,9 @@ export default function App() {
       if (isTauri) {
         try {
           console.log(`Setting backend volume for ${streamId} to ${originalVolume}`)
-          const ok = await setStreamVolume(streamId as StreamId, originalVolume)
-          if (ok) {
-            setVolumes(prev => ({ ...prev, [streamId as StreamId]: originalVolume }))
-            console.log(`✅ Backend volume restored successfully for ${streamId}`)
-          } else {
-            console.error(`❌ Failed to set backend volume for ${streamId}`)
-          }
+          const report = await setStreamVolume(streamId as StreamId, originalVolume)
+          setVolumes(prev => ({ ...prev, [streamId as StreamId]: originalVolume }))
+          noteApplyReport(report)
         } catch (error) {
           console.error(`❌ Error restoring backend volume for ${streamId}:`, error)
         }
@@ -1287,13 +1296,9 @@ export default function App() {
       if (isTauri) {
         try {
           console.log(`Setting backend volume for ${streamId} to 0`)
-          const ok = await setStreamVolume(streamId as StreamId, 0)
-          if (ok) {
-            setVolumes(prev => ({ ...prev, [streamId as StreamId]: 0 }))
-            console.log(`✅ Backend volume muted successfully for ${streamId}`)
-          } else {
-            console.error(`❌ Failed to mute backend volume for ${streamId}`)
-          }
+          const report = await setStreamVolume(streamId as StreamId, 0)
+          setVolumes(prev => ({ ...prev, [streamId as StreamId]: 0 }))
+          noteApplyReport(report)
         } catch (error) {
           console.error(`❌ Error muting backend volume for ${streamId}:`, error)
         }
@@ -1764,8 +1769,8 @@ export default function App() {
                             return app ? (
                               <div 
                                 key={pid} 
-                                className="wavelink-app-icon" 
-                                title={app.name}
+                                className={`wavelink-app-icon${applyFailures[app.pid] ? ' wavelink-app-failed' : ''}`}
+                                title={applyFailures[app.pid] ? `${app.name}: ${applyFailures[app.pid]}` : app.name}
                                 draggable
                                 onDragStart={(e) => handleDragStart(e, app)}
                                 onDragEnd={handleDragEnd}
@@ -1955,8 +1960,8 @@ export default function App() {
                           return app ? (
                             <div 
                               key={pid} 
-                              className="wavelink-app-icon" 
-                              title={app.name}
+                              className={`wavelink-app-icon${applyFailures[app.pid] ? ' wavelink-app-failed' : ''}`}
+                              title={applyFailures[app.pid] ? `${app.name}: ${applyFailures[app.pid]}` : app.name}
                               draggable
                               onDragStart={(e) => handleDragStart(e, app)}
                               onDragEnd={handleDragEnd}
@@ -2064,8 +2069,9 @@ export default function App() {
                         onDragStart={(e) => handleDragStart(e, app)}
                         onDragEnd={handleDragEnd}
                         className="wavelink-app-item"
+                        title={applyFailures[app.pid]}
                       >
-                        <div className="wavelink-app-icon">
+                        <div className={`wavelink-app-icon${applyFailures[app.pid] ? ' wavelink-app-failed' : ''}`}>
                           <AppIcon app={app} />
                         </div>
                         <div className="wavelink-app-info">
@@ -2,6 +2,8 @@ import { invoke } from '@tauri-apps/api/core'
 
 export type DeviceKind = 'input' | 'output'
 export type StreamId = 'game' | 'voice' | 'music'
+// A stream bus, or an aux bus by its ID ("aux-1", ...)
+export type BusId = StreamId | string
 
 export interface DeviceInfo {
   id: string
@@ -9,6 +11,8 @@ export interface DeviceInfo {
   kind: DeviceKind
   is_default: boolean
   backend: string
+  // 0 if unknown
+  channels: number
 }
 
 export async function getDevices(): Promise<DeviceInfo[]> {
@@ -19,20 +23,283 @@ export async function getRoutes(): Promise<Record<StreamId, string | null>> {
   return await invoke('get_routes')
 }
 
-export async function setRoute(stream: StreamId, device_id: string | null): Promise<boolean> {
+// Per-app outcome of a route/volume/category change; `failed` lists apps that did not follow it
+export interface ApplyFailure {
+  pid: number
+  error: string
+}
+
+export interface ApplyReport {
+  updated: number[]
+  failed: ApplyFailure[]
+  // set_route only: the audio engine can't play on the chosen device
+  device_error: string | null
+}
+
+export async function setRoute(stream: StreamId, device_id: string | null): Promise<ApplyReport> {
   return await invoke('set_route', { stream, deviceId: device_id })
 }
 
-export async function setStreamVolume(stream: StreamId, volume: number): Promise<boolean> {
+export async function setStreamVolume(stream: StreamId, volume: number): Promise<ApplyReport> {
   return await invoke('set_stream_volume', { stream, volume })
 }
 
+// "Audio Mixer - Game" etc. (Linux only): output devices apps can pick directly, played on
+// the stream's routed device; they follow the stream's route, volume and mute
+export interface VirtualOutput {
+  // Device ID, usable like any other output
+  id: string
+  name: string
+  // null = default output
+  target: string | null
+  volume: number
+  muted: boolean
+}
+
+// Empty while disabled
+export async function getVirtualOutputs(): Promise<VirtualOutput[]> {
+  return await invoke('get_virtual_outputs')
+}
+
+export async function setVirtualOutputsEnabled(enabled: boolean): Promise<VirtualOutput[]> {
+  return await invoke('set_virtual_outputs_enabled', { enabled })
+}
+
+// "Audio Mixer - Microphone" (Linux only): the processed mic as an input device for chat
+// apps. Needs the engine with a microphone on the Voice bus.
+export interface VirtualMicSettings {
+  enabled: boolean
+  // Music bus mixed in, 0..1; 0 = mic only. Soundboard slots are sent separately.
+  music_level: number
+}
+
+export async function getVirtualMic(): Promise<VirtualMicSettings> {
+  return await invoke('get_virtual_mic')
+}
+
+// A new music level applies live
+export async function setVirtualMic(settings: VirtualMicSettings): Promise<void> {
+  return await invoke('set_virtual_mic', { settings })
+}
+
+// Level of a stream in one mix; gain 0..1
+export interface MixLevel {
+  gain: number
+  muted: boolean
+}
+
+// Personal mix = what the user hears on the routed device (gain is the stream volume),
+// broadcast mix = what goes to the broadcast output
+export interface StreamMixes {
+  personal: MixLevel
+  broadcast: MixLevel
+}
+
+export interface BroadcastSettings {
+  enabled: boolean
+  // null = default output
+  output: string | null
+}
+
+export async function getStreamMixes(): Promise<Record<StreamId, StreamMixes>> {
+  return await invoke('get_stream_mixes')
+}
+
+export async function setStreamMuted(stream: StreamId, muted: boolean): Promise<void> {
+  return await invoke('set_stream_muted', { stream, muted })
+}
+
+export async function setBroadcastLevel(stream: StreamId, level: MixLevel): Promise<void> {
+  return await invoke('set_broadcast_level', { stream, level })
+}
+
+export async function getBroadcastSettings(): Promise<BroadcastSettings> {
+  return await invoke('get_broadcast_settings')
+}
+
+export async function setBroadcastSettings(broadcast: BroadcastSettings): Promise<EngineStatus> {
+  return await invoke('set_broadcast_settings', { broadcast })
+}
+
+// A further device playing a stream besides its route
+export interface TapSpec {
+  // null = default output
+  output: string | null
+  level: MixLevel
+  // 0..1000, to line up devices with different latency
+  delay_ms: number
+  // Tap before the stream's fader and mute
+  pre_fader: boolean
+}
+
+export async function getStreamExtraOutputs(stream: StreamId): Promise<TapSpec[]> {
+  return await invoke('get_stream_extra_outputs', { stream })
+}
+
+export async function setStreamExtraOutputs(stream: StreamId, outputs: TapSpec[]): Promise<void> {
+  return await invoke('set_stream_extra_outputs', { stream, outputs })
+}
+
+// Delay on everything an output device plays; outputs not listed have none
+export interface OutputDelay {
+  // null = default output
+  output: string | null
+  delay_ms: number
+}
+
+export interface LatencyMeasurement {
+  output: string | null
+  round_trip_ms: number
+  clicks_heard: number
+  // Suggested delay lining this output up with the slowest one measured
+  delay_ms: number
+}
+
+export async function getOutputDelays(): Promise<OutputDelay[]> {
+  return await invoke('get_output_delays')
+}
+
+// 0..1000 ms; 0 removes the delay
+export async function setOutputDelay(output: string | null, delayMs: number): Promise<void> {
+  return await invoke('set_output_delay', { output, delayMs })
+}
+
+// Plays clicks on each output and listens on `input` (a microphone near the speakers or a
+// loopback capture); takes about four seconds per output. Apply the suggested delays
+// with setOutputDelay.
+export async function measureOutputLatency(outputs: (string | null)[], input: string): Promise<LatencyMeasurement[]> {
+  return await invoke('measure_output_latency', { outputs, input })
+}
+
+// WAV is 32-bit float, FLAC 24-bit
+export type RecordFormat = 'wav' | 'flac'
+
+export interface RecordSettings {
+  // null = "Audio Mixer" in the music folder
+  folder: string | null
+  format: RecordFormat
+}
+
+// A stream or aux bus after its fader, what an output device plays (master), the
+// processed microphone, or the soundboard clips sent to the virtual mic
+export type RecordSource =
+  | { kind: 'bus'; id: BusId }
+  | { kind: 'output'; id: string | null }
+  | { kind: 'mic' }
+  | { kind: 'soundboard' }
+
+export interface RecordingStatus {
+  active: boolean
+  sources: RecordSource[]
+  files: string[]
+  seconds: number
+  dropped_seconds: number
+  error: string | null
+}
+
+// One track per stream, for editing clips
+export const MULTITRACK_SOURCES: RecordSource[] = [
+  { kind: 'bus', id: 'game' },
+  { kind: 'bus', id: 'voice' },
+  { kind: 'bus', id: 'music' },
+]
+
+export async function getRecordSettings(): Promise<RecordSettings> {
+  return await invoke('get_record_settings')
+}
+
+export async function setRecordSettings(settings: RecordSettings): Promise<void> {
+  return await invoke('set_record_settings', { settings })
+}
+
+// Several sources record in sync, one file each
+export async function startRecording(sources: RecordSource[]): Promise<RecordingStatus> {
+  return await invoke('start_recording', { sources })
+}
+
+// Resolves to the files written
+export async function stopRecording(): Promise<string[]> {
+  return await invoke('stop_recording')
+}
+
+export async function getRecordingStatus(): Promise<RecordingStatus | null> {
+  return await invoke('get_recording_status')
+}
+
+export interface ReplaySettings {
+  enabled: boolean
+  // Seconds kept per stream (1..120); streams left out aren't buffered
+  streams: Partial<Record<StreamId, number>>
+  // Global shortcut that saves the buffer, e.g. "CommandOrControl+Shift+R"
+  hotkey: string | null
+}
+
+export async function getReplaySettings(): Promise<ReplaySettings> {
+  return await invoke('get_replay_settings')
+}
+
+// Restarts the history; fails if the hotkey can't be registered
+export async function setReplaySettings(settings: ReplaySettings): Promise<void> {
+  return await invoke('set_replay_settings', { settings })
+}
+
+// Saves the last `seconds` (everything buffered if null) of each stream to the recordings
+// folder; resolves to the files written
+export async function saveReplay(seconds: number | null = null): Promise<string[]> {
+  return await invoke('save_replay', { seconds })
+}
+
+// A soundboard button. Clips play into the stream bus before its fader; `to_mic` also
+// sends them to the virtual mic.
+export interface SoundboardSlot {
+  name: string
+  // WAV, FLAC or Ogg Vorbis, at most 30 s
+  file: string
+  stream: StreamId
+  to_mic: boolean
+  // 0..2
+  gain: number
+  // Presses playing at once (1..8); a further press cuts the oldest
+  polyphony: number
+  hotkey: string | null
+}
+
+export async function getSoundboard(): Promise<SoundboardSlot[]> {
+  return await invoke('get_soundboard')
+}
+
+// Loads the files; fails if one can't be decoded or a hotkey can't be registered
+export async function setSoundboard(slots: SoundboardSlot[]): Promise<void> {
+  return await invoke('set_soundboard', { slots })
+}
+
+// Needs the engine running
+export async function playSoundboardSlot(index: number): Promise<void> {
+  return await invoke('play_soundboard_slot', { index })
+}
+
+// Fades out the clips of one slot, or all of them
+export async function stopSoundboard(index: number | null = null): Promise<void> {
+  return await invoke('stop_soundboard', { index })
+}
+
+// One audio session; a process can have several (e.g. browser tabs on different devices)
+export interface SessionInfo {
+  instance_id: string
+  device_id: string
+  display_name: string
+  icon_path: string
+  volume: number
+  muted: boolean
+}
+
 export interface AppSession {
   pid: number
   name: string
   process_name: string // The actual executable name (e.g., "discord.exe")
-  volume: number
-  muted: boolean
+  volume: number // loudest session
+  muted: boolean // true if all sessions are muted
+  sessions: SessionInfo[]
 }
 
 export async function listAudioApps(): Promise<AppSession[]> {
@@ -43,7 +310,7 @@ export async function getAppCategories(): Promise<Record<number, StreamId>> {
   return await invoke('get_app_categories')
 }
 
-export async function setAppCategory(pid: number, stream: StreamId): Promise<boolean> {
+export async function setAppCategory(pid: number, stream: StreamId): Promise<ApplyReport> {
   return await invoke('set_app_category', { pid, stream })
 }
 
@@ -79,3 +346,356 @@ export async function getAppIcon(processName: string): Promise<string | null> {
     return null;
   }
 }
+
+export interface LogEntry {
+  timestamp: string
+  level: string
+  target: string
+  message: string
+}
+
+export async function getRecentLogs(limit?: number, minLevel?: string): Promise<LogEntry[]> {
+  return await invoke('get_recent_logs', { limit, minLevel })
+}
+
+export async function getLogFilePath(): Promise<string> {
+  return await invoke('get_log_file_path')
+}
+
+export interface EngineStatus {
+  running: boolean
+  sample_rate: number
+  outputs: string[]
+  inputs: string[]
+  // Devices running at another rate than the engine, "<device>: <rate> Hz"
+  resampled: string[]
+  warnings: string[]
+  error: string | null
+}
+
+export interface MeterReading {
+  bus: BusId
+  peak_db: number
+  rms_db: number
+  gain_reduction_db: number
+  momentary_lufs: number
+  short_term_lufs: number
+  // Correction applied by loudness normalization
+  auto_gain_db: number
+  // Noise gate of the mic chain; always true for buses without one
+  gate_open: boolean
+}
+
+export async function getEngineStatus(): Promise<EngineStatus> {
+  return await invoke('get_engine_status')
+}
+
+export async function setEngineEnabled(enabled: boolean): Promise<EngineStatus> {
+  return await invoke('set_engine_enabled', { enabled })
+}
+
+export async function setStreamInput(stream: StreamId, deviceId: string | null): Promise<EngineStatus> {
+  return await invoke('set_stream_input', { stream, deviceId })
+}
+
+export type ResamplerQuality = 'low' | 'medium' | 'high'
+
+export async function getResamplerQuality(): Promise<ResamplerQuality> {
+  return await invoke('get_resampler_quality')
+}
+
+export async function setResamplerQuality(quality: ResamplerQuality): Promise<EngineStatus> {
+  return await invoke('set_resampler_quality', { quality })
+}
+
+export async function getMeters(): Promise<MeterReading[]> {
+  return await invoke('get_meters')
+}
+
+export async function engineSelfTest(): Promise<Record<string, number>> {
+  return await invoke('engine_self_test')
+}
+
+export type EqBandType = 'peaking' | 'low_shelf' | 'high_shelf' | 'low_pass' | 'high_pass' | 'notch'
+
+export interface EqBand {
+  kind: EqBandType
+  frequency: number
+  gain_db: number
+  q: number
+  enabled: boolean
+}
+
+export interface EqSettings {
+  preamp_db: number
+  bands: EqBand[]
+}
+
+export async function getStreamEq(stream: StreamId): Promise<EqSettings> {
+  return await invoke('get_stream_eq', { stream })
+}
+
+export async function setStreamEq(stream: StreamId, eq: EqSettings): Promise<void> {
+  return await invoke('set_stream_eq', { stream, eq })
+}
+
+export async function getEqResponse(eq: EqSettings, frequencies: number[]): Promise<number[]> {
+  return await invoke('get_eq_response', { eq, frequencies })
+}
+
+export interface EqPreset extends EqSettings {
+  name: string
+  builtin: boolean
+}
+
+export async function listEqPresets(): Promise<EqPreset[]> {
+  return await invoke('list_eq_presets')
+}
+
+export async function saveEqPreset(name: string, eq: EqSettings): Promise<EqPreset> {
+  return await invoke('save_eq_preset', { name, eq })
+}
+
+export async function deleteEqPreset(name: string): Promise<boolean> {
+  return await invoke('delete_eq_preset', { name })
+}
+
+// Import an Equalizer APO / AutoEQ ParametricEQ.txt file as a user preset
+export async function importEqPreset(path: string, name?: string): Promise<EqPreset> {
+  return await invoke('import_eq_preset', { path, name })
+}
+
+export interface CompressorSettings {
+  enabled: boolean
+  threshold_db: number
+  ratio: number
+  knee_db: number
+  attack_ms: number
+  release_ms: number
+  makeup_db: number
+}
+
+export interface LimiterSettings {
+  enabled: boolean
+  ceiling_db: number
+  lookahead_ms: number
+  release_ms: number
+}
+
+export interface DynamicsSettings {
+  compressor: CompressorSettings
+  limiter: LimiterSettings
+}
+
+export async function getStreamDynamics(stream: StreamId): Promise<DynamicsSettings> {
+  return await invoke('get_stream_dynamics', { stream })
+}
+
+export async function setStreamDynamics(stream: StreamId, dynamics: DynamicsSettings): Promise<void> {
+  return await invoke('set_stream_dynamics', { stream, dynamics })
+}
+
+export async function getMasterDynamics(): Promise<DynamicsSettings> {
+  return await invoke('get_master_dynamics')
+}
+
+export async function setMasterDynamics(dynamics: DynamicsSettings): Promise<void> {
+  return await invoke('set_master_dynamics', { dynamics })
+}
+
+export interface AutoGainSettings {
+  enabled: boolean
+  target_lufs: number
+  max_boost_db: number
+  max_cut_db: number
+}
+
+export async function getStreamAutoGain(stream: StreamId): Promise<AutoGainSettings> {
+  return await invoke('get_stream_auto_gain', { stream })
+}
+
+export async function setStreamAutoGain(stream: StreamId, autoGain: AutoGainSettings): Promise<void> {
+  return await invoke('set_stream_auto_gain', { stream, autoGain })
+}
+
+export interface MatrixSettings {
+  // [output channel][bus channel]; empty = standard down/upmix
+  gains: number[][]
+  mono: boolean
+  swap_left_right: boolean
+  channel_gains_db: number[]
+}
+
+export async function getStreamMatrix(stream: StreamId): Promise<MatrixSettings> {
+  return await invoke('get_stream_matrix', { stream })
+}
+
+export async function setStreamMatrix(stream: StreamId, matrix: MatrixSettings): Promise<void> {
+  return await invoke('set_stream_matrix', { stream, matrix })
+}
+
+export async function getStandardMatrix(inputs: number, outputs: number): Promise<number[][]> {
+  return await invoke('get_standard_matrix', { inputs, outputs })
+}
+
+// CLAP plugin found in the search paths
+export interface PluginInfo {
+  path: string
+  id: string
+  name: string
+  vendor: string
+  version: string
+  features: string[]
+}
+
+// Entry of a bus's effect chain
+export interface PluginSlotInfo {
+  path: string
+  id: string
+  name: string
+  bypassed: boolean
+  // Set when the plugin couldn't be loaded
+  error: string | null
+}
+
+export interface ParamInfo {
+  id: number
+  name: string
+  module: string
+  min: number
+  max: number
+  default: number
+  value: number
+  stepped: boolean
+  read_only: boolean
+}
+
+export async function listPlugins(): Promise<PluginInfo[]> {
+  return await invoke('list_plugins')
+}
+
+export async function getBusPlugins(bus: BusId): Promise<PluginSlotInfo[]> {
+  return await invoke('get_bus_plugins', { bus })
+}
+
+export async function addBusPlugin(bus: BusId, path: string, id: string): Promise<PluginSlotInfo[]> {
+  return await invoke('add_bus_plugin', { bus, path, id })
+}
+
+export async function removeBusPlugin(bus: BusId, index: number): Promise<PluginSlotInfo[]> {
+  return await invoke('remove_bus_plugin', { bus, index })
+}
+
+export async function moveBusPlugin(bus: BusId, from: number, to: number): Promise<PluginSlotInfo[]> {
+  return await invoke('move_bus_plugin', { bus, from, to })
+}
+
+export async function setPluginBypassed(bus: BusId, index: number, bypassed: boolean): Promise<void> {
+  return await invoke('set_plugin_bypassed', { bus, index, bypassed })
+}
+
+export async function getPluginParams(bus: BusId, index: number): Promise<ParamInfo[]> {
+  return await invoke('get_plugin_params', { bus, index })
+}
+
+export async function setPluginParam(bus: BusId, index: number, paramId: number, value: number): Promise<void> {
+  return await invoke('set_plugin_param', { bus, index, paramId, value })
+}
+
+export interface SendSettings {
+  // 0..1
+  level: number
+  // Tap before the stream's fader and mute
+  pre_fader: boolean
+}
+
+// Bus fed by sends from the streams, e.g. a shared reverb or a stream mix
+export interface AuxBusSettings {
+  id: string
+  name: string
+  // Output device; null = default
+  output: string | null
+  gain: number
+  muted: boolean
+  sends: Partial<Record<StreamId, SendSettings>>
+}
+
+export async function getAuxBuses(): Promise<AuxBusSettings[]> {
+  return await invoke('get_aux_buses')
+}
+
+export async function addAuxBus(name: string, output: string | null): Promise<AuxBusSettings> {
+  return await invoke('add_aux_bus', { name, output })
+}
+
+export async function removeAuxBus(id: string): Promise<void> {
+  return await invoke('remove_aux_bus', { id })
+}
+
+export async function setAuxBus(aux: AuxBusSettings): Promise<void> {
+  return await invoke('set_aux_bus', { aux })
+}
+
+export async function setStreamSend(stream: StreamId, aux: string, send: SendSettings): Promise<void> {
+  return await invoke('set_stream_send', { stream, aux, send })
+}
+
+export interface MicSettings {
+  high_pass: { enabled: boolean; frequency: number }
+  // strength 0 (gentle) .. 1 (aggressive)
+  noise_suppression: { enabled: boolean; strength: number }
+  gate: {
+    enabled: boolean
+    threshold_db: number
+    hysteresis_db: number
+    hold_ms: number
+    attack_ms: number
+    release_ms: number
+    range_db: number
+  }
+  de_esser: { enabled: boolean; frequency: number; threshold_db: number; max_reduction_db: number }
+}
+
+export async function getMicSettings(): Promise<MicSettings> {
+  return await invoke('get_mic_settings')
+}
+
+export async function setMicSettings(mic: MicSettings): Promise<void> {
+  return await invoke('set_mic_settings', { mic })
+}
+
+export async function setProcessedMicOutput(deviceId: string | null): Promise<EngineStatus> {
+  return await invoke('set_processed_mic_output', { deviceId })
+}
+
+export interface SpatialSettings {
+  enabled: boolean
+  // Imported SOFA set, null for the built-in model
+  hrtf: string | null
+}
+
+export interface HrtfSetInfo {
+  name: string | null
+  measurements: number
+  sample_rate: number
+}
+
+export async function getSpatialSettings(): Promise<SpatialSettings> {
+  return await invoke('get_spatial_settings')
+}
+
+export async function setSpatialSettings(spatial: SpatialSettings): Promise<void> {
+  return await invoke('set_spatial_settings', { spatial })
+}
+
+export async function listHrtfSets(): Promise<HrtfSetInfo[]> {
+  return await invoke('list_hrtf_sets')
+}
+
+export async function importHrtfSet(path: string): Promise<HrtfSetInfo> {
+  return await invoke('import_hrtf_set', { path })
+}
+
+export async function deleteHrtfSet(name: string): Promise<boolean> {
+  return await invoke('delete_hrtf_set', { name })
+}